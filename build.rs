@@ -0,0 +1,29 @@
+// With the ffi feature, generates nessie.h from the functions in
+// src/ffi.rs into OUT_DIR, where a test compares it against the copy in
+// include/ so that can't fall behind them.
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    #[cfg(feature = "ffi")]
+    {
+        println!("cargo:rerun-if-changed=src/ffi.rs");
+        println!("cargo:rerun-if-changed=cbindgen.toml");
+        if let Err(e) = write_header() {
+            println!("cargo:warning=can't generate nessie.h: {}", e);
+        }
+    }
+}
+
+#[cfg(feature = "ffi")]
+fn write_header() -> Result<(), String> {
+    let dir = std::path::PathBuf::from(std::env::var("CARGO_MANIFEST_DIR").map_err(|e| e.to_string())?);
+    let out = std::path::PathBuf::from(std::env::var("OUT_DIR").map_err(|e| e.to_string())?);
+    let config = cbindgen::Config::from_file(dir.join("cbindgen.toml"))?;
+    let bindings = cbindgen::Builder::new()
+        .with_config(config)
+        .with_src(dir.join("src/ffi.rs"))
+        .generate()
+        .map_err(|e| e.to_string())?;
+    bindings.write_to_file(out.join("nessie.h"));
+    Ok(())
+}
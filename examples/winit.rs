@@ -0,0 +1,190 @@
+//! A frontend in pure Rust, for when SDL2 isn't around:
+//! `cargo run --release --example winit --features winit -- game.nes [--config nessie.toml]`.
+//!
+//! The whole loop is: hand `Nes::run_for` the time that passed, copy
+//! `Nes::frame` into the window, move `Nes::audio` to the sound card and
+//! tell the APU how full its queue is. Keyboard controls are those of the
+//! config's input profile, by default `InputProfile::default()`; F1
+//! resets, P pauses, Tab fast forwards while held and Escape quits.
+//! Battery saves go where the config says, by default next to the game.
+
+use nessie::audio::AudioOutput;
+use nessie::cartridge::Cartridge;
+use nessie::config::Config;
+use nessie::frame::{HEIGHT, WIDTH};
+use nessie::input::mapping::{HostInput, HostKey};
+use nessie::nes::{Nes, ResetKind, Speed};
+use pixels::{Pixels, SurfaceTexture};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use winit::dpi::LogicalSize;
+use winit::event::{ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent};
+use winit::event_loop::EventLoop;
+use winit::window::WindowBuilder;
+
+const SCALE: u32 = 3;
+const FAST_FORWARD: f64 = 4.0;
+
+fn main() {
+    let mut args = std::env::args_os().skip(1);
+    let mut path = None;
+    let mut config = None;
+    while let Some(arg) = args.next() {
+        if arg == "--config" {
+            config = Some(args.next().map(PathBuf::from).unwrap_or_else(|| usage()));
+        } else if path.is_none() {
+            path = Some(PathBuf::from(arg));
+        } else {
+            usage();
+        }
+    }
+    let Some(path) = path else { usage() };
+    let result = config.map_or(Ok(Config::default()), Config::load).and_then(|config| run(path, config));
+    if let Err(err) = result {
+        eprintln!("winit: {}", err);
+        std::process::exit(1);
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("usage: winit <game.nes> [--config FILE]");
+    std::process::exit(2);
+}
+
+fn run(path: PathBuf, config: Config) -> Result<(), String> {
+    let mut nes = Nes::new();
+    config.apply(&mut nes)?;
+    nes.insert_cartridge(Cartridge::load(&path)?);
+    let battery = config.battery_path(&path);
+    if nes.cpu().bus.has_battery() && battery.exists() {
+        nes.load_battery(&battery)?;
+    }
+    let profile = config.input;
+
+    let event_loop = EventLoop::new();
+    let window = WindowBuilder::new()
+        .with_title("NESsie")
+        .with_inner_size(LogicalSize::new(WIDTH as u32 * SCALE, HEIGHT as u32 * SCALE))
+        .with_min_inner_size(LogicalSize::new(WIDTH as u32, HEIGHT as u32))
+        .build(&event_loop)
+        .map_err(|err| err.to_string())?;
+    let size = window.inner_size();
+    let mut pixels = Pixels::new(WIDTH as u32, HEIGHT as u32, SurfaceTexture::new(size.width, size.height, &window)).map_err(|err| err.to_string())?;
+
+    // a game is still playable without sound
+    let mut audio = AudioOutput::open().map_err(|err| eprintln!("no audio: {}", err)).ok();
+    if let Some(audio) = &audio {
+        let apu = &mut nes.cpu_mut().bus.apu;
+        apu.set_sample_rate(audio.sample_rate());
+        apu.set_dynamic_rate_control(Some(0.005));
+    }
+    let mut samples = Vec::new();
+
+    let mut last = Instant::now();
+    event_loop.run(move |event, _, control_flow| {
+        control_flow.set_poll();
+        match event {
+            Event::WindowEvent { event, .. } => match event {
+                WindowEvent::CloseRequested | WindowEvent::KeyboardInput { input: KeyboardInput { state: ElementState::Pressed, virtual_keycode: Some(VirtualKeyCode::Escape), .. }, .. } => {
+                    if let Err(err) = quit(&mut nes, &battery) {
+                        eprintln!("winit: {}", err);
+                    }
+                    control_flow.set_exit();
+                }
+                WindowEvent::Resized(size) => {
+                    if let Err(err) = pixels.resize_surface(size.width, size.height) {
+                        eprintln!("winit: {}", err);
+                        control_flow.set_exit();
+                    }
+                }
+                WindowEvent::KeyboardInput { input: KeyboardInput { state, virtual_keycode: Some(key), .. }, .. } => {
+                    let pressed = state == ElementState::Pressed;
+                    if !(pressed && hotkey(&mut nes, key)) {
+                        if key == VirtualKeyCode::Tab {
+                            nes.set_speed(Speed::Multiplier(1.0));
+                        }
+                        if let Some(code) = key_code(key) {
+                            profile.handle(&mut nes.cpu_mut().bus, &HostInput::Key(code), pressed);
+                        }
+                    }
+                }
+                _ => {}
+            },
+            Event::MainEventsCleared => {
+                let now = Instant::now();
+                nes.run_for(now - last);
+                last = now;
+
+                nes.audio(&mut samples);
+                if let Some(audio) = &mut audio {
+                    audio.push(&samples);
+                    nes.cpu_mut().bus.apu.update_buffer_fill(audio.fill());
+                }
+                samples.clear();
+                window.request_redraw();
+            }
+            Event::RedrawRequested(_) => {
+                pixels.frame_mut().copy_from_slice(nes.frame().pixels());
+                if let Err(err) = pixels.render() {
+                    eprintln!("winit: {}", err);
+                    control_flow.set_exit();
+                }
+            }
+            _ => {}
+        }
+    })
+}
+
+fn quit(nes: &mut Nes, battery: &Path) -> Result<(), String> {
+    if nes.cpu().bus.has_battery() {
+        nes.save_battery(battery)?;
+    }
+    Ok(())
+}
+
+// true if `key` was a hotkey, which games don't see
+fn hotkey(nes: &mut Nes, key: VirtualKeyCode) -> bool {
+    match key {
+        VirtualKeyCode::F1 => nes.reset(ResetKind::Soft),
+        VirtualKeyCode::P if nes.is_paused() => nes.resume(),
+        VirtualKeyCode::P => nes.pause(),
+        VirtualKeyCode::Tab => nes.set_speed(Speed::Multiplier(FAST_FORWARD)),
+        _ => return false,
+    }
+    true
+}
+
+fn key_code(key: VirtualKeyCode) -> Option<String> {
+    let name = format!("{:?}", key);
+    let key = match key {
+        _ if name.len() == 1 && name.as_bytes()[0].is_ascii_uppercase() => HostKey::Letter(name.as_bytes()[0] as char),
+        _ if name.len() == 4 && name.starts_with("Key") => HostKey::Digit(name[3..].parse().ok()?),
+        _ if name.len() <= 3 && name.starts_with('F') => HostKey::Function(name[1..].parse().ok()?),
+        VirtualKeyCode::Up => HostKey::ArrowUp,
+        VirtualKeyCode::Down => HostKey::ArrowDown,
+        VirtualKeyCode::Left => HostKey::ArrowLeft,
+        VirtualKeyCode::Right => HostKey::ArrowRight,
+        VirtualKeyCode::Return => HostKey::Enter,
+        VirtualKeyCode::Space => HostKey::Space,
+        VirtualKeyCode::Back => HostKey::Backspace,
+        VirtualKeyCode::LShift => HostKey::ShiftLeft,
+        VirtualKeyCode::RShift => HostKey::ShiftRight,
+        VirtualKeyCode::LControl => HostKey::ControlLeft,
+        VirtualKeyCode::RControl => HostKey::ControlRight,
+        VirtualKeyCode::LAlt => HostKey::AltLeft,
+        VirtualKeyCode::RAlt => HostKey::AltRight,
+        VirtualKeyCode::Minus => HostKey::Minus,
+        VirtualKeyCode::Equals => HostKey::Equal,
+        VirtualKeyCode::LBracket => HostKey::BracketLeft,
+        VirtualKeyCode::RBracket => HostKey::BracketRight,
+        VirtualKeyCode::Backslash => HostKey::Backslash,
+        VirtualKeyCode::Semicolon => HostKey::Semicolon,
+        VirtualKeyCode::Apostrophe => HostKey::Quote,
+        VirtualKeyCode::Grave => HostKey::Backquote,
+        VirtualKeyCode::Comma => HostKey::Comma,
+        VirtualKeyCode::Period => HostKey::Period,
+        VirtualKeyCode::Slash => HostKey::Slash,
+        _ => return None,
+    };
+    Some(key.code())
+}
@@ -0,0 +1,102 @@
+/*
+ * C API of the NESsie NES emulator, see src/ffi.rs. Build the library with
+ *
+ *     cargo rustc --release --lib --features ffi --crate-type cdylib
+ *
+ * and link against libnessie. Every function accepts a null console and
+ * does nothing with it. Functions returning int return 0 on success and -1
+ * on failure, with the reason in nessie_last_error().
+ */
+
+#ifndef NESSIE_H
+#define NESSIE_H
+
+/* Generated from src/ffi.rs by cbindgen, don't edit. */
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#define NESSIE_WIDTH 256
+
+#define NESSIE_HEIGHT 240
+
+// Bits of nessie_set_buttons(), the order the controller reports them in.
+#define NESSIE_BUTTON_A 1
+
+#define NESSIE_BUTTON_B 2
+
+#define NESSIE_BUTTON_SELECT 4
+
+#define NESSIE_BUTTON_START 8
+
+#define NESSIE_BUTTON_UP 16
+
+#define NESSIE_BUTTON_DOWN 32
+
+#define NESSIE_BUTTON_LEFT 64
+
+#define NESSIE_BUTTON_RIGHT 128
+
+// The console behind a `NessieConsole *`.
+typedef struct NessieConsole NessieConsole;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// A console with nothing plugged in. Free it with nessie_free().
+NessieConsole *nessie_new(void);
+
+void nessie_free(NessieConsole *console);
+
+// Why the last call that returned -1 failed. Owned by the console and
+// valid until the next failure.
+const char *nessie_last_error(const NessieConsole *console);
+
+// Plugs in an iNES or NES 2.0 image and powers on. The data is copied.
+int nessie_load_rom(NessieConsole *console, const uint8_t *data, size_t len);
+
+void nessie_reset(NessieConsole *console, bool hard);
+
+// Runs until the next frame is done.
+void nessie_run_frame(NessieConsole *console);
+
+// Frames completed since power on.
+uint64_t nessie_frame_number(const NessieConsole *console);
+
+// The last frame as NESSIE_WIDTH x NESSIE_HEIGHT RGBA pixels, 4 bytes
+// each. Valid until the next nessie_run_frame().
+const uint8_t *nessie_framebuffer(const NessieConsole *console);
+
+// Buttons held on controller 0 to 3, NESSIE_BUTTON_* or'ed together.
+void nessie_set_buttons(NessieConsole *console, unsigned int player, uint8_t buttons);
+
+// Rate nessie_audio() delivers samples at, in Hz.
+void nessie_set_sample_rate(NessieConsole *console, unsigned int rate);
+
+// Moves up to `capacity` mono samples from 0.0 to 1.0 into `out` and
+// returns how many. Audio not collected stays queued, up to about a
+// second of it.
+size_t nessie_audio(NessieConsole *console, float *out, size_t capacity);
+
+// Returns the size of the state, writing it to `out` only if it fits, so
+// a call with a null `out` finds out how much room to make.
+size_t nessie_save_state(const NessieConsole *console, uint8_t *out, size_t capacity);
+
+int nessie_load_state(NessieConsole *console, const uint8_t *data, size_t len);
+
+// For RetroAchievements runtimes like rcheevos. Copies up to `len` bytes
+// of the CPU address space from `address` on into `out` without side
+// effects and returns how many; registers read as 0xFF.
+size_t nessie_read_memory(const NessieConsole *console, uint32_t address, uint8_t *out, size_t len);
+
+// Writes the RetroAchievements hash of an NES file, 32 hex digits and a
+// NUL, to `out`, which needs room for 33 bytes.
+int nessie_rom_hash(const uint8_t *data, size_t len, char *out);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* NESSIE_H */
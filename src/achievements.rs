@@ -0,0 +1,178 @@
+//! What a RetroAchievements runtime like rcheevos needs from an emulator:
+//! the hash it identifies a game by, and side-effect-free reads of the
+//! console's memory at the addresses achievement definitions use, which
+//! for the NES are the CPU's.
+
+use alloc::format;
+use alloc::string::String;
+use crate::nes::Nes;
+
+/// What a stretch of the address space holds, as rcheevos classifies it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryKind {
+    SystemRam,
+    SaveRam,
+    /// Another view of memory listed elsewhere, like the RAM mirrors.
+    VirtualRam,
+    HardwareController,
+    ReadOnly,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryRegion {
+    pub start: u32,
+    pub end: u32,
+    pub kind: MemoryKind,
+    pub description: &'static str,
+}
+
+const fn region(start: u32, end: u32, kind: MemoryKind, description: &'static str) -> MemoryRegion {
+    MemoryRegion { start, end, kind, description }
+}
+
+/// The NES memory map the way rcheevos lays it out, covering $0000-$FFFF.
+pub static MEMORY_REGIONS: &[MemoryRegion] = &[
+    region(0x0000, 0x07ff, MemoryKind::SystemRam, "System RAM"),
+    region(0x0800, 0x0fff, MemoryKind::VirtualRam, "Mirror RAM"),
+    region(0x1000, 0x17ff, MemoryKind::VirtualRam, "Mirror RAM"),
+    region(0x1800, 0x1fff, MemoryKind::VirtualRam, "Mirror RAM"),
+    region(0x2000, 0x2007, MemoryKind::HardwareController, "PPU Register"),
+    region(0x2008, 0x3fff, MemoryKind::VirtualRam, "Mirrored PPU Register"),
+    region(0x4000, 0x4017, MemoryKind::HardwareController, "APU and I/O register"),
+    region(0x4018, 0x401f, MemoryKind::HardwareController, "APU and I/O test register"),
+    region(0x4020, 0x5fff, MemoryKind::ReadOnly, "Cartridge data"),
+    region(0x6000, 0x7fff, MemoryKind::SaveRam, "Cartridge RAM"),
+    region(0x8000, 0xffff, MemoryKind::ReadOnly, "Cartridge ROM"),
+];
+
+/// The RetroAchievements hash of an NES file: the MD5 of everything after
+/// the 16 byte iNES header, or of the whole file without one, as 32
+/// lowercase hex digits.
+pub fn rom_hash(rom: &[u8]) -> String {
+    let data = match rom {
+        [b'N', b'E', b'S', 0x1a, ..] if rom.len() >= 16 => &rom[16..],
+        _ => rom,
+    };
+    md5(data).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Fills `buffer` with memory from `address` on, for an rcheevos
+/// `read_memory` callback, and returns how many bytes it read: fewer than
+/// asked for at the end of the address space. Registers read as $FF since
+/// reading them would change them.
+pub fn read_memory(nes: &Nes, address: u32, buffer: &mut [u8]) -> usize {
+    let bus = &nes.cpu().bus;
+    let count = buffer.len().min(0x10000usize.saturating_sub(address as usize));
+    for (i, byte) in buffer[..count].iter_mut().enumerate() {
+        *byte = bus.peek((address as usize + i) as u16);
+    }
+    count
+}
+
+// per-round shift amounts and the sines MD5 mixes in (RFC 1321)
+const SHIFTS: [u32; 16] = [7, 12, 17, 22, 5, 9, 14, 20, 4, 11, 16, 23, 6, 10, 15, 21];
+const SINES: [u32; 64] = [
+    0xd76aa478, 0xe8c7b756, 0x242070db, 0xc1bdceee, 0xf57c0faf, 0x4787c62a, 0xa8304613, 0xfd469501,
+    0x698098d8, 0x8b44f7af, 0xffff5bb1, 0x895cd7be, 0x6b901122, 0xfd987193, 0xa679438e, 0x49b40821,
+    0xf61e2562, 0xc040b340, 0x265e5a51, 0xe9b6c7aa, 0xd62f105d, 0x02441453, 0xd8a1e681, 0xe7d3fbc8,
+    0x21e1cde6, 0xc33707d6, 0xf4d50d87, 0x455a14ed, 0xa9e3e905, 0xfcefa3f8, 0x676f02d9, 0x8d2a4c8a,
+    0xfffa3942, 0x8771f681, 0x6d9d6122, 0xfde5380c, 0xa4beea44, 0x4bdecfa9, 0xf6bb4b60, 0xbebfbc70,
+    0x289b7ec6, 0xeaa127fa, 0xd4ef3085, 0x04881d05, 0xd9d4d039, 0xe6db99e5, 0x1fa27cf8, 0xc4ac5665,
+    0xf4292244, 0x432aff97, 0xab9423a7, 0xfc93a039, 0x655b59c3, 0x8f0ccc92, 0xffeff47d, 0x85845dd1,
+    0x6fa87e4f, 0xfe2ce6e0, 0xa3014314, 0x4e0811a1, 0xf7537e82, 0xbd3af235, 0x2ad7d2bb, 0xeb86d391,
+];
+
+fn md5(data: &[u8]) -> [u8; 16] {
+    let mut state: [u32; 4] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476];
+    // the data, a 1 bit, zeros up to 8 bytes short of a whole block and
+    // the length in bits
+    let bits = (data.len() as u64).wrapping_mul(8);
+    let padding = 64 - (data.len() + 8) % 64;
+    let mut tail = [0u8; 128];
+    let rest = data.len() % 64;
+    tail[..rest].copy_from_slice(&data[data.len() - rest..]);
+    tail[rest] = 0x80;
+    let tail_len = rest + padding + 8;
+    tail[tail_len - 8..tail_len].copy_from_slice(&bits.to_le_bytes());
+
+    for block in data[..data.len() - rest].chunks_exact(64).chain(tail[..tail_len].chunks_exact(64)) {
+        let words: [u32; 16] = core::array::from_fn(|i| u32::from_le_bytes(block[i * 4..i * 4 + 4].try_into().unwrap()));
+        let [mut a, mut b, mut c, mut d] = state;
+        for i in 0..64 {
+            let (f, g) = match i / 16 {
+                0 => ((b & c) | (!b & d), i),
+                1 => ((d & b) | (!d & c), (5 * i + 1) % 16),
+                2 => (b ^ c ^ d, (3 * i + 5) % 16),
+                _ => (c ^ (b | !d), (7 * i) % 16),
+            };
+            let f = f.wrapping_add(a).wrapping_add(SINES[i]).wrapping_add(words[g]);
+            a = d;
+            d = c;
+            c = b;
+            b = b.wrapping_add(f.rotate_left(SHIFTS[i / 16 * 4 + i % 4]));
+        }
+        for (word, add) in state.iter_mut().zip([a, b, c, d]) {
+            *word = word.wrapping_add(add);
+        }
+    }
+
+    let mut digest = [0; 16];
+    for (chunk, word) in digest.chunks_exact_mut(4).zip(state) {
+        chunk.copy_from_slice(&word.to_le_bytes());
+    }
+    digest
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::test_rom;
+    use crate::cartridge::Cartridge;
+
+    #[test]
+    fn test_md5() {
+        let hex = |data: &[u8]| md5(data).iter().map(|byte| format!("{:02x}", byte)).collect::<String>();
+        assert_eq!(hex(b""), "d41d8cd98f00b204e9800998ecf8427e");
+        assert_eq!(hex(b"abc"), "900150983cd24fb0d6963f7d28e17f72");
+        assert_eq!(hex(b"The quick brown fox jumps over the lazy dog"), "9e107d9d372bb6826bd81d3542a419d6");
+        // padding spills into a second block
+        assert_eq!(hex(&[b'a'; 56]), "3b0c8ac703f828b04c6c197006d17218");
+        assert_eq!(hex(&[b'a'; 64]), "014842d480b571495a4a0363793f7367");
+    }
+
+    #[test]
+    fn test_rom_hash() {
+        let rom = test_rom(&[0xea; 0x4000]);
+        assert_eq!(rom_hash(&rom), rom_hash(&rom[16..]));
+        assert_ne!(rom_hash(&rom), rom_hash(&rom[1..]));
+        assert_eq!(rom_hash(&rom).len(), 32);
+    }
+
+    #[test]
+    fn test_memory_regions_cover_the_address_space() {
+        assert_eq!(MEMORY_REGIONS[0].start, 0);
+        assert_eq!(MEMORY_REGIONS.last().unwrap().end, 0xffff);
+        for pair in MEMORY_REGIONS.windows(2) {
+            assert_eq!(pair[0].end + 1, pair[1].start);
+        }
+    }
+
+    #[test]
+    fn test_read_memory() {
+        let mut prg = vec![0xea; 0x4000];
+        prg[0x3fff] = 0xc0;
+        let mut nes = Nes::new();
+        nes.insert_cartridge(Cartridge::new(&test_rom(&prg)).unwrap());
+        nes.cpu_mut().bus.ram_mut()[0x10] = 0x42;
+        nes.cpu_mut().bus.prg_ram_mut()[0x20] = 0x99;
+
+        let mut buffer = [0; 4];
+        assert_eq!(read_memory(&nes, 0x0810, &mut buffer[..1]), 1);
+        assert_eq!(buffer[0], 0x42);
+        assert_eq!(read_memory(&nes, 0x6020, &mut buffer[..1]), 1);
+        assert_eq!(buffer[0], 0x99);
+        assert_eq!(read_memory(&nes, 0xfffe, &mut buffer), 2);
+        assert_eq!(buffer[..2], [0xea, 0xc0]);
+        assert_eq!(read_memory(&nes, 0x10000, &mut buffer), 0);
+    }
+}
@@ -0,0 +1,1613 @@
+#[cfg(feature = "std")]
+use alloc::boxed::Box;
+#[cfg(feature = "std")]
+use alloc::format;
+#[cfg(feature = "std")]
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use crate::blip::BlipBuffer;
+use crate::region::Region;
+#[cfg(feature = "std")]
+use crate::wav::WavWriter;
+#[cfg(feature = "std")]
+use std::fs::File;
+#[cfg(feature = "std")]
+use std::io::BufWriter;
+#[cfg(feature = "std")]
+use std::path::Path;
+
+// lengths loaded into a length counter by the top 5 bits of $4003/$4007/$400B/$400F
+static LENGTH_TABLE: [u8; 32] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14,
+    12, 16, 24, 18, 48, 20, 96, 22, 192, 24, 72, 26, 16, 28, 32, 30,
+];
+
+static DUTY_TABLE: [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0],
+    [0, 1, 1, 0, 0, 0, 0, 0],
+    [0, 1, 1, 1, 1, 0, 0, 0],
+    [1, 0, 0, 1, 1, 1, 1, 1],
+];
+
+static TRIANGLE_SEQUENCE: [u8; 32] = [
+    15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0,
+    0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15,
+];
+
+// noise timer periods in CPU cycles
+static NOISE_PERIODS_NTSC: [u16; 16] = [4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068];
+static NOISE_PERIODS_PAL: [u16; 16] = [4, 8, 14, 30, 60, 88, 118, 148, 188, 236, 354, 472, 708, 944, 1890, 3778];
+
+// DMC output rates in CPU cycles per bit
+static DMC_RATES_NTSC: [u16; 16] = [428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54];
+static DMC_RATES_PAL: [u16; 16] = [398, 354, 316, 298, 276, 236, 210, 198, 176, 148, 132, 118, 98, 78, 66, 50];
+
+// the region tables aren't saved, set_region puts the right ones back
+#[cfg(feature = "serde")]
+fn ntsc_noise_periods() -> &'static [u16; 16] {
+    &NOISE_PERIODS_NTSC
+}
+
+#[cfg(feature = "serde")]
+fn ntsc_dmc_rates() -> &'static [u16; 16] {
+    &DMC_RATES_NTSC
+}
+
+pub const DEFAULT_SAMPLE_RATE: u32 = 44100;
+
+// CPU cycles into the sequence at which the frame counter clocks the channels;
+// the 4-step one raises its IRQ around the last step, the 5-step one never does
+const FRAME_STEP_1: u32 = 7457;
+const FRAME_STEP_2: u32 = 14913;
+const FRAME_STEP_3: u32 = 22371;
+const FRAME_STEP_4: u32 = 29829;
+const FRAME_LENGTH: u32 = 29830;
+const FRAME_STEP_5: u32 = 37281;
+const FIVE_STEP_FRAME_LENGTH: u32 = 37282;
+
+/// Volume of a channel: either a constant or a sawtooth decaying from 15.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Envelope {
+    start: bool,
+    looping: bool,
+    constant: bool,
+    // the constant volume, or the divider period
+    volume: u8,
+    divider: u8,
+    decay: u8,
+}
+
+impl Envelope {
+    fn write(&mut self, data: u8) {
+        self.looping = data & 0b0010_0000 != 0;
+        self.constant = data & 0b0001_0000 != 0;
+        self.volume = data & 0b1111;
+    }
+
+    // quarter frame
+    fn clock(&mut self) {
+        if self.start {
+            self.start = false;
+            self.decay = 15;
+            self.divider = self.volume;
+        } else if self.divider == 0 {
+            self.divider = self.volume;
+            if self.decay > 0 {
+                self.decay -= 1;
+            } else if self.looping {
+                self.decay = 15;
+            }
+        } else {
+            self.divider -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.constant {
+            self.volume
+        } else {
+            self.decay
+        }
+    }
+}
+
+/// Silences a channel once it has played for the loaded number of half frames.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LengthCounter {
+    enabled: bool,
+    halt: bool,
+    counter: u8,
+}
+
+impl LengthCounter {
+    fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.counter = 0;
+        }
+    }
+
+    fn load(&mut self, index: u8) {
+        if self.enabled {
+            self.counter = LENGTH_TABLE[(index >> 3) as usize];
+        }
+    }
+
+    // half frame
+    fn clock(&mut self) {
+        if !self.halt && self.counter > 0 {
+            self.counter -= 1;
+        }
+    }
+
+    pub fn active(&self) -> bool {
+        self.counter > 0
+    }
+}
+
+/// One of the two square wave channels at $4000-$4003 and $4004-$4007.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Pulse {
+    // pulse 1 negates its sweep with ones' complement, pulse 2 with two's
+    ones_complement: bool,
+    duty: u8,
+    sequence: u8,
+    timer_period: u16,
+    timer: u16,
+    pub length: LengthCounter,
+    pub envelope: Envelope,
+
+    sweep_enabled: bool,
+    sweep_period: u8,
+    sweep_negate: bool,
+    sweep_shift: u8,
+    sweep_reload: bool,
+    sweep_divider: u8,
+}
+
+impl Pulse {
+    pub fn new(channel: u8) -> Self {
+        Pulse {
+            ones_complement: channel == 1,
+            duty: 0,
+            sequence: 0,
+            timer_period: 0,
+            timer: 0,
+            length: LengthCounter::default(),
+            envelope: Envelope::default(),
+            sweep_enabled: false,
+            sweep_period: 0,
+            sweep_negate: false,
+            sweep_shift: 0,
+            sweep_reload: false,
+            sweep_divider: 0,
+        }
+    }
+
+    pub fn timer_period(&self) -> u16 {
+        self.timer_period
+    }
+
+    pub fn write_control(&mut self, data: u8) {
+        self.duty = data >> 6;
+        self.length.halt = data & 0b0010_0000 != 0;
+        self.envelope.write(data);
+    }
+
+    pub fn write_sweep(&mut self, data: u8) {
+        self.sweep_enabled = data & 0b1000_0000 != 0;
+        self.sweep_period = (data >> 4) & 0b111;
+        self.sweep_negate = data & 0b0000_1000 != 0;
+        self.sweep_shift = data & 0b111;
+        self.sweep_reload = true;
+    }
+
+    pub fn write_timer_lo(&mut self, data: u8) {
+        self.timer_period = (self.timer_period & 0xff00) | data as u16;
+    }
+
+    pub fn write_timer_hi(&mut self, data: u8) {
+        self.timer_period = (self.timer_period & 0x00ff) | ((data as u16 & 0b111) << 8);
+        self.length.load(data);
+        self.sequence = 0;
+        self.envelope.start = true;
+    }
+
+    fn sweep_target(&self) -> u16 {
+        let change = self.timer_period >> self.sweep_shift;
+        if !self.sweep_negate {
+            self.timer_period + change
+        } else if self.ones_complement {
+            self.timer_period.saturating_sub(change + 1)
+        } else {
+            self.timer_period.saturating_sub(change)
+        }
+    }
+
+    // the sweep unit mutes the channel even while it's disabled
+    fn sweep_muting(&self) -> bool {
+        self.timer_period < 8 || self.sweep_target() > 0x7ff
+    }
+
+    // every APU cycle, which is every other CPU cycle
+    fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period;
+            self.sequence = (self.sequence + 1) % 8;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn clock_quarter_frame(&mut self) {
+        self.envelope.clock();
+    }
+
+    fn clock_half_frame(&mut self) {
+        self.length.clock();
+        if self.sweep_divider == 0 && self.sweep_enabled && self.sweep_shift > 0 && !self.sweep_muting() {
+            self.timer_period = self.sweep_target();
+        }
+        if self.sweep_divider == 0 || self.sweep_reload {
+            self.sweep_divider = self.sweep_period;
+            self.sweep_reload = false;
+        } else {
+            self.sweep_divider -= 1;
+        }
+    }
+
+    /// Current level of the channel, 0-15.
+    pub fn output(&self) -> u8 {
+        if !self.length.active() || self.sweep_muting() || DUTY_TABLE[self.duty as usize][self.sequence as usize] == 0 {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+}
+
+/// The triangle wave channel at $4008-$400B.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Triangle {
+    sequence: u8,
+    timer_period: u16,
+    timer: u16,
+    pub length: LengthCounter,
+
+    // the control flag doubles as the length counter halt flag
+    control: bool,
+    linear_reload_value: u8,
+    linear_counter: u8,
+    linear_reload: bool,
+}
+
+impl Triangle {
+    pub fn new() -> Self {
+        Triangle::default()
+    }
+
+    pub fn timer_period(&self) -> u16 {
+        self.timer_period
+    }
+
+    pub fn write_linear_counter(&mut self, data: u8) {
+        self.control = data & 0b1000_0000 != 0;
+        self.length.halt = self.control;
+        self.linear_reload_value = data & 0b0111_1111;
+    }
+
+    pub fn write_timer_lo(&mut self, data: u8) {
+        self.timer_period = (self.timer_period & 0xff00) | data as u16;
+    }
+
+    pub fn write_timer_hi(&mut self, data: u8) {
+        self.timer_period = (self.timer_period & 0x00ff) | ((data as u16 & 0b111) << 8);
+        self.length.load(data);
+        self.linear_reload = true;
+    }
+
+    // every CPU cycle, twice as fast as the pulse timers
+    fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period;
+            // periods below 2 would play an ultrasonic tone that the TV filters down
+            // to a pop, so the sequencer is held where it is instead, like most games expect
+            if self.linear_counter > 0 && self.length.active() && self.timer_period >= 2 {
+                self.sequence = (self.sequence + 1) % 32;
+            }
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn clock_quarter_frame(&mut self) {
+        if self.linear_reload {
+            self.linear_counter = self.linear_reload_value;
+        } else if self.linear_counter > 0 {
+            self.linear_counter -= 1;
+        }
+        if !self.control {
+            self.linear_reload = false;
+        }
+    }
+
+    fn clock_half_frame(&mut self) {
+        self.length.clock();
+    }
+
+    /// Current level of the channel, 0-15. A silenced triangle keeps
+    /// outputting the step it stopped on.
+    pub fn output(&self) -> u8 {
+        TRIANGLE_SEQUENCE[self.sequence as usize]
+    }
+}
+
+/// The pseudo-random noise channel at $400C-$400F.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Noise {
+    #[cfg_attr(feature = "serde", serde(skip, default = "ntsc_noise_periods"))]
+    periods: &'static [u16; 16],
+    // feedback from bit 6 instead of bit 1, giving a short 93 step loop
+    short_mode: bool,
+    shift: u16,
+    timer_period: u16,
+    timer: u16,
+    pub length: LengthCounter,
+    pub envelope: Envelope,
+}
+
+impl Default for Noise {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Noise {
+    pub fn new() -> Self {
+        Noise {
+            periods: &NOISE_PERIODS_NTSC,
+            short_mode: false,
+            shift: 1,
+            timer_period: NOISE_PERIODS_NTSC[0],
+            timer: 0,
+            length: LengthCounter::default(),
+            envelope: Envelope::default(),
+        }
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        self.periods = match region {
+            Region::Pal => &NOISE_PERIODS_PAL,
+            Region::Ntsc | Region::Dendy => &NOISE_PERIODS_NTSC,
+        };
+    }
+
+    pub fn write_control(&mut self, data: u8) {
+        self.length.halt = data & 0b0010_0000 != 0;
+        self.envelope.write(data);
+    }
+
+    pub fn write_period(&mut self, data: u8) {
+        self.short_mode = data & 0b1000_0000 != 0;
+        self.timer_period = self.periods[(data & 0b1111) as usize];
+    }
+
+    pub fn write_length(&mut self, data: u8) {
+        self.length.load(data);
+        self.envelope.start = true;
+    }
+
+    // every CPU cycle, the periods are in CPU cycles
+    fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period - 1;
+            let tap = if self.short_mode { 6 } else { 1 };
+            let feedback = (self.shift ^ (self.shift >> tap)) & 1;
+            self.shift = (self.shift >> 1) | (feedback << 14);
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn clock_quarter_frame(&mut self) {
+        self.envelope.clock();
+    }
+
+    fn clock_half_frame(&mut self) {
+        self.length.clock();
+    }
+
+    /// Current level of the channel, 0-15.
+    pub fn output(&self) -> u8 {
+        if !self.length.active() || self.shift & 1 != 0 {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+}
+
+/// The delta modulation channel at $4010-$4013, playing 1-bit delta
+/// encoded samples straight out of CPU memory.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Dmc {
+    #[cfg_attr(feature = "serde", serde(skip, default = "ntsc_dmc_rates"))]
+    rates: &'static [u16; 16],
+    irq_enabled: bool,
+    looping: bool,
+    timer_period: u16,
+    timer: u16,
+    level: u8,
+
+    sample_address: u16,
+    sample_length: u16,
+    current_address: u16,
+    bytes_remaining: u16,
+    sample_buffer: Option<u8>,
+
+    shift: u8,
+    bits_remaining: u8,
+    silence: bool,
+    irq: bool,
+}
+
+impl Default for Dmc {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Dmc {
+    pub fn new() -> Self {
+        Dmc {
+            rates: &DMC_RATES_NTSC,
+            irq_enabled: false,
+            looping: false,
+            timer_period: DMC_RATES_NTSC[0],
+            timer: 0,
+            level: 0,
+            sample_address: 0xc000,
+            sample_length: 1,
+            current_address: 0xc000,
+            bytes_remaining: 0,
+            sample_buffer: None,
+            shift: 0,
+            bits_remaining: 8,
+            silence: true,
+            irq: false,
+        }
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        self.rates = match region {
+            Region::Pal => &DMC_RATES_PAL,
+            Region::Ntsc | Region::Dendy => &DMC_RATES_NTSC,
+        };
+    }
+
+    pub fn write_control(&mut self, data: u8) {
+        self.irq_enabled = data & 0b1000_0000 != 0;
+        self.looping = data & 0b0100_0000 != 0;
+        self.timer_period = self.rates[(data & 0b1111) as usize];
+        if !self.irq_enabled {
+            self.irq = false;
+        }
+    }
+
+    pub fn write_level(&mut self, data: u8) {
+        self.level = data & 0b0111_1111;
+    }
+
+    pub fn write_sample_address(&mut self, data: u8) {
+        self.sample_address = 0xc000 | ((data as u16) << 6);
+    }
+
+    pub fn write_sample_length(&mut self, data: u8) {
+        self.sample_length = ((data as u16) << 4) + 1;
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        self.irq = false;
+        if !enabled {
+            self.bytes_remaining = 0;
+        } else if self.bytes_remaining == 0 {
+            self.restart();
+        }
+    }
+
+    fn restart(&mut self) {
+        self.current_address = self.sample_address;
+        self.bytes_remaining = self.sample_length;
+    }
+
+    pub fn active(&self) -> bool {
+        self.bytes_remaining > 0
+    }
+
+    pub fn irq(&self) -> bool {
+        self.irq
+    }
+
+    /// Address the memory reader wants to fetch from, once the sample buffer
+    /// has been emptied and there are bytes left to play.
+    pub fn pending_fetch(&self) -> Option<u16> {
+        if self.sample_buffer.is_none() && self.bytes_remaining > 0 {
+            Some(self.current_address)
+        } else {
+            None
+        }
+    }
+
+    /// Hands the byte read from `pending_fetch()` to the channel.
+    pub fn load_sample(&mut self, data: u8) {
+        self.sample_buffer = Some(data);
+        // the address wraps around to $8000, not $0000
+        self.current_address = if self.current_address == 0xffff { 0x8000 } else { self.current_address + 1 };
+        self.bytes_remaining -= 1;
+        if self.bytes_remaining == 0 {
+            if self.looping {
+                self.restart();
+            } else if self.irq_enabled {
+                self.irq = true;
+            }
+        }
+    }
+
+    // every CPU cycle, the rates are in CPU cycles
+    fn clock_timer(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.timer_period - 1;
+
+        if !self.silence {
+            if self.shift & 1 != 0 {
+                if self.level <= 125 {
+                    self.level += 2;
+                }
+            } else if self.level >= 2 {
+                self.level -= 2;
+            }
+        }
+        self.shift >>= 1;
+
+        self.bits_remaining -= 1;
+        if self.bits_remaining == 0 {
+            self.bits_remaining = 8;
+            match self.sample_buffer.take() {
+                Some(data) => {
+                    self.silence = false;
+                    self.shift = data;
+                }
+                None => self.silence = true,
+            }
+        }
+    }
+
+    /// Current level of the channel, 0-127.
+    pub fn output(&self) -> u8 {
+        self.level
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Channel {
+    Pulse1,
+    Pulse2,
+    Triangle,
+    Noise,
+    Dmc,
+}
+
+/// Combines the channel levels the way the resistor network on the 2A03's
+/// outputs does: louder channels add less, and the triangle, noise and DMC
+/// share an output and hold each other down. Gives 0.0 to about 1.0.
+pub fn mix(pulse1: u8, pulse2: u8, triangle: u8, noise: u8, dmc: u8) -> f32 {
+    let pulse = (pulse1 + pulse2) as f32;
+    let pulse_out = if pulse == 0.0 { 0.0 } else { 95.88 / (8128.0 / pulse + 100.0) };
+
+    let tnd = triangle as f32 / 8227.0 + noise as f32 / 12241.0 + dmc as f32 / 22638.0;
+    let tnd_out = if tnd == 0.0 { 0.0 } else { 159.79 / (1.0 / tnd + 100.0) };
+
+    pulse_out + tnd_out
+}
+
+// a copy of the samples made since it was last taken, for recording
+// without taking them from the frontend; copies of the console don't record
+#[derive(Default)]
+pub(crate) struct Tap(pub(crate) Option<Vec<f32>>);
+
+impl Clone for Tap {
+    fn clone(&self) -> Self {
+        Tap(None)
+    }
+}
+
+impl Channel {
+    #[cfg(feature = "std")]
+    const ALL: [Channel; 5] = [Channel::Pulse1, Channel::Pulse2, Channel::Triangle, Channel::Noise, Channel::Dmc];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Channel::Pulse1 => "pulse1",
+            Channel::Pulse2 => "pulse2",
+            Channel::Triangle => "triangle",
+            Channel::Noise => "noise",
+            Channel::Dmc => "dmc",
+        }
+    }
+
+    // the mixer's output with only this channel at `level`
+    #[cfg(feature = "std")]
+    fn alone(self, level: u8) -> f32 {
+        let mut levels = [0; 5];
+        levels[self as usize] = level;
+        mix(levels[0], levels[1], levels[2], levels[3], levels[4])
+    }
+}
+
+// a .wav capture in progress: the mixed output as the frontend gets it, and
+// with stems every channel on its own through a band-limiter of its own
+#[cfg(feature = "std")]
+struct WavCapture {
+    mix: WavWriter<BufWriter<File>>,
+    stems: Vec<(Channel, BlipBuffer, WavWriter<BufWriter<File>>)>,
+    buffer: Vec<f32>,
+    // the first write that failed; nothing more is written after it
+    error: Option<String>,
+}
+
+#[cfg(feature = "std")]
+impl WavCapture {
+    fn clock_stems(&mut self, levels: [u8; 5]) {
+        for (channel, blip, wav) in &mut self.stems {
+            blip.set_level(channel.alone(levels[*channel as usize]));
+            blip.clock(1);
+            if blip.available() > 0 && self.error.is_none() {
+                self.buffer.clear();
+                blip.read_samples(&mut self.buffer);
+                self.error = wav.write(&self.buffer).err();
+            }
+        }
+    }
+
+    fn write_mix(&mut self, samples: &[f32]) {
+        if self.error.is_none() {
+            self.error = self.mix.write(samples).err();
+        }
+    }
+
+    fn finish(self) -> Result<(), String> {
+        let mut result = self.mix.finish().map(|_| ());
+        for (_, _, wav) in self.stems {
+            result = result.and(wav.finish().map(|_| ()));
+        }
+        match self.error {
+            Some(err) => Err(err),
+            None => result,
+        }
+    }
+}
+
+// copies of the console don't capture
+#[cfg(feature = "std")]
+#[derive(Default)]
+pub(crate) struct Capture(Option<Box<WavCapture>>);
+
+#[cfg(feature = "std")]
+impl Clone for Capture {
+    fn clone(&self) -> Self {
+        Capture(None)
+    }
+}
+
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct APU {
+    pub pulse1: Pulse,
+    pub pulse2: Pulse,
+    pub triangle: Triangle,
+    pub noise: Noise,
+    pub dmc: Dmc,
+
+    // frame counter: CPU cycles into the current sequence, the mode and IRQ
+    // set through $4017, and the cycles until a write to it takes effect
+    frame_cycle: u32,
+    five_step: bool,
+    irq_inhibit: bool,
+    frame_irq: bool,
+    frame_reset_delay: u8,
+    cycles: u64,
+
+    // output is band-limited down to the sample rate and buffered until collected
+    sample_rate: u32,
+    clock_rate: f64,
+    blip: BlipBuffer,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    samples: Vec<f32>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub(crate) tap: Tap,
+    #[cfg(feature = "std")]
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub(crate) capture: Capture,
+    // dynamic rate control: largest allowed change of the rate, and the current factor
+    rate_control: Option<f32>,
+    rate_adjustment: f64,
+
+    // indexed by Channel; while anything is soloed only soloed channels play
+    muted: [bool; 5],
+    soloed: [bool; 5],
+}
+
+impl Default for APU {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl APU {
+    pub fn new() -> Self {
+        APU {
+            pulse1: Pulse::new(1),
+            pulse2: Pulse::new(2),
+            triangle: Triangle::new(),
+            noise: Noise::new(),
+            dmc: Dmc::new(),
+            frame_cycle: 0,
+            five_step: false,
+            irq_inhibit: false,
+            frame_irq: false,
+            frame_reset_delay: 0,
+            cycles: 0,
+            sample_rate: DEFAULT_SAMPLE_RATE,
+            clock_rate: Region::Ntsc.cpu_clock_hz(),
+            blip: BlipBuffer::new(Region::Ntsc.cpu_clock_hz(), DEFAULT_SAMPLE_RATE as f64),
+            rate_control: None,
+            rate_adjustment: 1.0,
+            samples: Vec::new(),
+            tap: Tap::default(),
+            #[cfg(feature = "std")]
+            capture: Capture::default(),
+            muted: [false; 5],
+            soloed: [false; 5],
+        }
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        self.noise.set_region(region);
+        self.dmc.set_region(region);
+        self.clock_rate = region.cpu_clock_hz();
+        self.update_rates();
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Sets the rate `samples()` delivers audio at, in Hz.
+    pub fn set_sample_rate(&mut self, rate: u32) {
+        self.sample_rate = rate.max(1);
+        self.update_rates();
+    }
+
+    /// Turns on dynamic rate control: the sample rate gets nudged by up to
+    /// `max_delta` (0.005 is a good start) to keep the frontend's audio
+    /// buffer half full, so audio can follow a video-synced frame rate
+    /// without drifting. `None` turns it off.
+    pub fn set_dynamic_rate_control(&mut self, max_delta: Option<f32>) {
+        self.rate_control = max_delta;
+        self.rate_adjustment = 1.0;
+        self.update_rates();
+    }
+
+    /// Reports how full the frontend's audio buffer is, from 0.0 to 1.0.
+    /// Only has an effect with dynamic rate control on.
+    pub fn update_buffer_fill(&mut self, fill: f32) {
+        if let Some(max_delta) = self.rate_control {
+            let fill = fill.clamp(0.0, 1.0);
+            // a filling buffer gets fewer samples, an emptying one more
+            self.rate_adjustment = 1.0 + ((1.0 - 2.0 * fill) * max_delta) as f64;
+            self.update_rates();
+        }
+    }
+
+    /// The rate samples are actually produced at right now.
+    pub fn effective_sample_rate(&self) -> f64 {
+        self.sample_rate as f64 * self.rate_adjustment
+    }
+
+    fn update_rates(&mut self) {
+        self.blip.set_rates(self.clock_rate, self.effective_sample_rate());
+    }
+
+    /// Puts sample rate, rate control and muting back to their defaults and
+    /// drops the audio being resampled, keeping the channels as they are.
+    #[cfg(feature = "serde")]
+    pub(crate) fn clear_output(&mut self) {
+        self.sample_rate = DEFAULT_SAMPLE_RATE;
+        self.blip = BlipBuffer::new(self.clock_rate, DEFAULT_SAMPLE_RATE as f64);
+        self.rate_control = None;
+        self.rate_adjustment = 1.0;
+        self.muted = [false; 5];
+        self.soloed = [false; 5];
+    }
+
+    /// Moves the audio produced since the last call into `out`, as mono
+    /// samples between 0.0 and 1.0 at the configured sample rate. The
+    /// output is band-limited, so it runs 8 samples behind the APU.
+    pub fn samples(&mut self, out: &mut Vec<f32>) {
+        out.append(&mut self.samples);
+    }
+
+    /// Throws away audio produced but not collected yet.
+    pub fn flush_samples(&mut self) {
+        self.samples.clear();
+    }
+
+    /// Samples produced but not collected yet.
+    pub fn pending_samples(&self) -> usize {
+        self.samples.len()
+    }
+
+    /// Keeps only the oldest `len` samples not collected yet.
+    pub fn truncate_samples(&mut self, len: usize) {
+        self.samples.truncate(len);
+    }
+
+    /// Starts writing the mixed output, as `samples` gives it, to a .wav
+    /// file at `path`. With `stems`, each channel is also written on its
+    /// own, unmuted, to a file named after it: `song-pulse1.wav` and so on
+    /// next to `song.wav`.
+    #[cfg(feature = "std")]
+    pub fn start_wav_capture<P: AsRef<Path>>(&mut self, path: P, stems: bool) -> Result<(), String> {
+        if self.capture.0.is_some() {
+            return Err("already capturing".to_string());
+        }
+        let path = path.as_ref();
+        let mix = WavWriter::create(path, self.sample_rate, 1)?;
+        let mut capture = WavCapture { mix, stems: Vec::new(), buffer: Vec::new(), error: None };
+        if stems {
+            let name = path.file_stem().and_then(|stem| stem.to_str()).unwrap_or("capture");
+            for channel in Channel::ALL {
+                let stem = path.with_file_name(format!("{}-{}.wav", name, channel.name()));
+                let blip = BlipBuffer::new(self.clock_rate, self.effective_sample_rate());
+                capture.stems.push((channel, blip, WavWriter::create(stem, self.sample_rate, 1)?));
+            }
+        }
+        self.capture.0 = Some(Box::new(capture));
+        Ok(())
+    }
+
+    /// Finishes the .wav files, reporting any write that failed on the way.
+    #[cfg(feature = "std")]
+    pub fn stop_wav_capture(&mut self) -> Result<(), String> {
+        let capture = self.capture.0.take().ok_or("not capturing")?;
+        capture.finish()
+    }
+
+    #[cfg(feature = "std")]
+    pub fn is_capturing_wav(&self) -> bool {
+        self.capture.0.is_some()
+    }
+
+    /// Soft reset: every channel is silenced as if $4015 was cleared, and
+    /// the frame IRQ is acknowledged. The frame counter mode stays.
+    pub fn reset(&mut self) {
+        self.write_register(0x4015, 0);
+        self.frame_irq = false;
+    }
+
+    /// Power cycle: channels and frame counter go back to their power-on
+    /// state, while the audio output settings are kept.
+    pub fn power_cycle(&mut self, region: Region) {
+        self.pulse1 = Pulse::new(1);
+        self.pulse2 = Pulse::new(2);
+        self.triangle = Triangle::new();
+        self.noise = Noise::new();
+        self.dmc = Dmc::new();
+        self.frame_cycle = 0;
+        self.five_step = false;
+        self.irq_inhibit = false;
+        self.frame_irq = false;
+        self.frame_reset_delay = 0;
+        self.samples.clear();
+        self.set_region(region);
+    }
+
+    /// Starts the APU `cycles` CPU cycles after power on: that decides which
+    /// half of an APU cycle the CPU lands on and how far the frame counter
+    /// has got, which differs from one power on to the next.
+    pub fn set_phase(&mut self, cycles: u32) {
+        self.cycles = cycles as u64;
+        self.frame_cycle = cycles % FRAME_STEP_1;
+    }
+
+    pub fn write_register(&mut self, addr: u16, data: u8) {
+        diag!(trace, register = format_args!("${:04X}", addr), value = format_args!("${:02X}", data), "register write");
+        match addr {
+            0x4000 => self.pulse1.write_control(data),
+            0x4001 => self.pulse1.write_sweep(data),
+            0x4002 => self.pulse1.write_timer_lo(data),
+            0x4003 => self.pulse1.write_timer_hi(data),
+            0x4004 => self.pulse2.write_control(data),
+            0x4005 => self.pulse2.write_sweep(data),
+            0x4006 => self.pulse2.write_timer_lo(data),
+            0x4007 => self.pulse2.write_timer_hi(data),
+            0x4008 => self.triangle.write_linear_counter(data),
+            0x400a => self.triangle.write_timer_lo(data),
+            0x400b => self.triangle.write_timer_hi(data),
+            0x400c => self.noise.write_control(data),
+            0x400e => self.noise.write_period(data),
+            0x400f => self.noise.write_length(data),
+            0x4010 => self.dmc.write_control(data),
+            0x4011 => self.dmc.write_level(data),
+            0x4012 => self.dmc.write_sample_address(data),
+            0x4013 => self.dmc.write_sample_length(data),
+            0x4015 => {
+                self.pulse1.length.set_enabled(data & 0b0001 != 0);
+                self.pulse2.length.set_enabled(data & 0b0010 != 0);
+                self.triangle.length.set_enabled(data & 0b0100 != 0);
+                self.noise.length.set_enabled(data & 0b1000 != 0);
+                self.dmc.set_enabled(data & 0b1_0000 != 0);
+            }
+            0x4017 => self.write_frame_counter(data),
+            _ => {}
+        }
+    }
+
+    fn write_frame_counter(&mut self, data: u8) {
+        self.five_step = data & 0b1000_0000 != 0;
+        self.irq_inhibit = data & 0b0100_0000 != 0;
+        if self.irq_inhibit {
+            self.frame_irq = false;
+        }
+        // the sequence restarts 3 or 4 cycles later, depending on where in
+        // the APU cycle the write lands
+        self.frame_reset_delay = if self.cycles % 2 == 1 { 4 } else { 3 };
+    }
+
+    /// Leaves the channel out of the mix. It keeps running, so unmuting it
+    /// picks up wherever it is.
+    pub fn set_muted(&mut self, channel: Channel, muted: bool) {
+        self.muted[channel as usize] = muted;
+    }
+
+    pub fn is_muted(&self, channel: Channel) -> bool {
+        self.muted[channel as usize]
+    }
+
+    /// Soloed channels are the only ones heard, as long as any channel is soloed.
+    pub fn set_solo(&mut self, channel: Channel, solo: bool) {
+        self.soloed[channel as usize] = solo;
+    }
+
+    pub fn is_solo(&self, channel: Channel) -> bool {
+        self.soloed[channel as usize]
+    }
+
+    pub fn audible(&self, channel: Channel) -> bool {
+        if self.soloed.iter().any(|s| *s) {
+            self.soloed[channel as usize]
+        } else {
+            !self.muted[channel as usize]
+        }
+    }
+
+    /// Current mixed output level.
+    pub fn output(&self) -> f32 {
+        let level = |channel: Channel, level: u8| if self.audible(channel) { level } else { 0 };
+        mix(
+            level(Channel::Pulse1, self.pulse1.output()),
+            level(Channel::Pulse2, self.pulse2.output()),
+            level(Channel::Triangle, self.triangle.output()),
+            level(Channel::Noise, self.noise.output()),
+            level(Channel::Dmc, self.dmc.output()),
+        )
+    }
+
+    /// The frame counter's IRQ flag.
+    pub fn frame_irq(&self) -> bool {
+        self.frame_irq
+    }
+
+    /// The IRQ line, held low by the frame counter or the DMC.
+    pub fn irq_pending(&self) -> bool {
+        self.frame_irq || self.dmc.irq()
+    }
+
+    /// Reads $4015, which acknowledges the frame IRQ.
+    pub fn read_status(&mut self) -> u8 {
+        let frame_irq = self.frame_irq;
+        self.frame_irq = false;
+        (self.pulse1.length.active() as u8)
+            | (self.pulse2.length.active() as u8) << 1
+            | (self.triangle.length.active() as u8) << 2
+            | (self.noise.length.active() as u8) << 3
+            | (self.dmc.active() as u8) << 4
+            | (frame_irq as u8) << 6
+            | (self.dmc.irq() as u8) << 7
+    }
+
+    /// Runs the APU for the given number of CPU cycles.
+    pub fn tick(&mut self, cycles: u16) {
+        for _ in 0..cycles {
+            self.step();
+        }
+    }
+
+    fn step(&mut self) {
+        self.cycles += 1;
+        self.triangle.clock_timer();
+        self.noise.clock_timer();
+        self.dmc.clock_timer();
+        if self.cycles.is_multiple_of(2) {
+            self.pulse1.clock_timer();
+            self.pulse2.clock_timer();
+        }
+        self.sample();
+
+        if self.frame_reset_delay > 0 {
+            self.frame_reset_delay -= 1;
+            if self.frame_reset_delay == 0 {
+                self.frame_cycle = 0;
+                // switching to 5-step mode clocks everything right away
+                if self.five_step {
+                    self.clock_quarter_frame();
+                    self.clock_half_frame();
+                }
+                return;
+            }
+        }
+
+        self.frame_cycle += 1;
+        if !self.five_step && !self.irq_inhibit && self.frame_cycle >= FRAME_STEP_4 - 1 {
+            if !self.frame_irq {
+                diag!(debug, "frame IRQ");
+            }
+            self.frame_irq = true;
+        }
+        match (self.frame_cycle, self.five_step) {
+            (FRAME_STEP_1, _) | (FRAME_STEP_3, _) => self.clock_quarter_frame(),
+            (FRAME_STEP_2, _) | (FRAME_STEP_4, false) | (FRAME_STEP_5, true) => {
+                self.clock_quarter_frame();
+                self.clock_half_frame();
+            }
+            (FRAME_LENGTH, false) | (FIVE_STEP_FRAME_LENGTH, true) => self.frame_cycle = 0,
+            _ => {}
+        }
+    }
+
+    fn sample(&mut self) {
+        self.blip.set_level(self.output());
+        self.blip.clock(1);
+        #[cfg(feature = "std")]
+        if let Some(capture) = &mut self.capture.0 {
+            capture.clock_stems([self.pulse1.output(), self.pulse2.output(), self.triangle.output(), self.noise.output(), self.dmc.output()]);
+        }
+        if self.blip.available() > 0 {
+            let start = self.samples.len();
+            self.blip.read_samples(&mut self.samples);
+            if let Some(tap) = &mut self.tap.0 {
+                tap.extend_from_slice(&self.samples[start..]);
+            }
+            #[cfg(feature = "std")]
+            if let Some(capture) = &mut self.capture.0 {
+                capture.write_mix(&self.samples[start..]);
+            }
+            // nobody is collecting them, keep about a second around
+            if self.samples.len() > self.sample_rate as usize {
+                let excess = self.samples.len() - self.sample_rate as usize;
+                self.samples.drain(..excess);
+            }
+        }
+    }
+
+    fn clock_quarter_frame(&mut self) {
+        self.pulse1.clock_quarter_frame();
+        self.pulse2.clock_quarter_frame();
+        self.triangle.clock_quarter_frame();
+        self.noise.clock_quarter_frame();
+    }
+
+    fn clock_half_frame(&mut self) {
+        self.pulse1.clock_half_frame();
+        self.pulse2.clock_half_frame();
+        self.triangle.clock_half_frame();
+        self.noise.clock_half_frame();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn run_frames(apu: &mut APU, frames: u32) {
+        for _ in 0..frames * FRAME_LENGTH {
+            apu.step();
+        }
+    }
+
+    #[test]
+    fn test_length_counter_runs_out() {
+        let mut apu = APU::new();
+        apu.write_register(0x4015, 0b01);
+        apu.write_register(0x4000, 0b0001_1111);
+        apu.write_register(0x4002, 0x80);
+        // index 1 loads 254 half frames
+        apu.write_register(0x4003, 0b0000_1000);
+        assert_eq!(apu.read_status(), 0b01);
+        run_frames(&mut apu, 126);
+        assert_eq!(apu.read_status() & 0b1111, 0b01);
+        run_frames(&mut apu, 1);
+        assert_eq!(apu.read_status() & 0b1111, 0);
+    }
+
+    #[test]
+    fn test_disabled_channel_ignores_length_load() {
+        let mut apu = APU::new();
+        apu.write_register(0x4007, 0b0000_1000);
+        assert_eq!(apu.read_status(), 0);
+        apu.write_register(0x4015, 0b10);
+        apu.write_register(0x4007, 0b0000_1000);
+        assert_eq!(apu.read_status(), 0b10);
+        apu.write_register(0x4015, 0);
+        assert_eq!(apu.read_status(), 0);
+    }
+
+    #[test]
+    fn test_envelope_decays() {
+        let mut apu = APU::new();
+        apu.write_register(0x4015, 0b01);
+        // 50% duty, envelope period 0 so it drops once per quarter frame
+        apu.write_register(0x4000, 0b1000_0000);
+        apu.write_register(0x4002, 0x80);
+        apu.write_register(0x4003, 0b0000_1000);
+        assert_eq!(apu.pulse1.envelope.output(), 0);
+        apu.tick(FRAME_STEP_1 as u16);
+        assert_eq!(apu.pulse1.envelope.output(), 15);
+        apu.tick((FRAME_STEP_3 - FRAME_STEP_1) as u16);
+        assert_eq!(apu.pulse1.envelope.output(), 13);
+    }
+
+    #[test]
+    fn test_duty_sequence() {
+        let mut pulse = Pulse::new(1);
+        pulse.length.set_enabled(true);
+        pulse.write_control(0b0101_1010);
+        pulse.write_timer_lo(8);
+        pulse.write_timer_hi(0b0000_1000);
+
+        let mut levels = Vec::new();
+        for _ in 0..8 {
+            levels.push(pulse.output());
+            for _ in 0..9 {
+                pulse.clock_timer();
+            }
+        }
+        assert_eq!(levels, vec![0, 10, 10, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_sweep_negate_differs_between_channels() {
+        for (channel, expected) in [(1, 0x100 - 0x40 - 1), (2, 0x100 - 0x40)] {
+            let mut pulse = Pulse::new(channel);
+            pulse.write_timer_lo(0x00);
+            pulse.write_timer_hi(0x01);
+            // enabled, period 0, negate, shift 2
+            pulse.write_sweep(0b1000_1010);
+            pulse.clock_half_frame();
+            assert_eq!(pulse.timer_period(), expected);
+        }
+    }
+
+    fn playing_triangle(period: u16) -> Triangle {
+        let mut triangle = Triangle::new();
+        triangle.length.set_enabled(true);
+        triangle.write_linear_counter(0x7f);
+        triangle.write_timer_lo(period as u8);
+        triangle.write_timer_hi(0b0000_1000 | (period >> 8) as u8);
+        triangle.clock_quarter_frame();
+        triangle
+    }
+
+    #[test]
+    fn test_triangle_sequence() {
+        let mut triangle = playing_triangle(2);
+        let mut levels = Vec::new();
+        for _ in 0..32 {
+            levels.push(triangle.output());
+            for _ in 0..3 {
+                triangle.clock_timer();
+            }
+        }
+        assert_eq!(levels, TRIANGLE_SEQUENCE.to_vec());
+    }
+
+    #[test]
+    fn test_triangle_linear_counter() {
+        let mut apu = APU::new();
+        apu.write_register(0x4015, 0b100);
+        // length halted, only the linear counter of 3 quarter frames stops it
+        apu.write_register(0x4008, 0b0000_0011);
+        apu.write_register(0x400a, 0x40);
+        apu.write_register(0x400b, 0b0000_1000);
+        apu.tick(FRAME_STEP_1 as u16);
+        assert_eq!(apu.triangle.linear_counter, 3);
+        apu.tick((FRAME_STEP_4 - FRAME_STEP_1) as u16);
+        assert_eq!(apu.triangle.linear_counter, 0);
+
+        let level = apu.triangle.output();
+        apu.tick(1000);
+        assert_eq!(apu.triangle.output(), level);
+    }
+
+    #[test]
+    fn test_triangle_holds_at_ultrasonic_periods() {
+        let mut triangle = playing_triangle(1);
+        let level = triangle.output();
+        for _ in 0..100 {
+            triangle.clock_timer();
+        }
+        assert_eq!(triangle.output(), level);
+    }
+
+    // steps until the shift register gets back to its starting value
+    fn noise_loop_length(short_mode: bool) -> usize {
+        let mut noise = Noise::new();
+        noise.write_period(if short_mode { 0b1000_0000 } else { 0 });
+        let start = noise.shift;
+        let mut steps = 0;
+        loop {
+            for _ in 0..noise.timer_period {
+                noise.clock_timer();
+            }
+            steps += 1;
+            if noise.shift == start {
+                return steps;
+            }
+        }
+    }
+
+    #[test]
+    fn test_noise_sequence_lengths() {
+        assert_eq!(noise_loop_length(false), 32767);
+        assert_eq!(noise_loop_length(true), 93);
+    }
+
+    #[test]
+    fn test_noise_period_tables() {
+        let mut apu = APU::new();
+        apu.write_register(0x400e, 0x0f);
+        assert_eq!(apu.noise.timer_period, 4068);
+        apu.set_region(Region::Pal);
+        apu.write_register(0x400e, 0x0f);
+        assert_eq!(apu.noise.timer_period, 3778);
+    }
+
+    #[test]
+    fn test_noise_status_and_output() {
+        let mut apu = APU::new();
+        apu.write_register(0x4015, 0b1000);
+        apu.write_register(0x400c, 0b0001_1001);
+        apu.write_register(0x400f, 0b0000_1000);
+        assert_eq!(apu.read_status(), 0b1000);
+
+        // the output follows bit 0 of the shift register
+        let mut levels = std::collections::HashSet::new();
+        for _ in 0..1000 {
+            apu.tick(1);
+            levels.insert(apu.noise.output());
+        }
+        assert_eq!(levels.len(), 2);
+        assert!(levels.contains(&9));
+    }
+
+    fn run_cycles(apu: &mut APU, cycles: u32) {
+        for _ in 0..cycles {
+            apu.step();
+        }
+    }
+
+    #[test]
+    fn test_samples_at_sample_rate() {
+        let mut apu = APU::new();
+        apu.write_register(0x4011, 64);
+        let mut out = Vec::new();
+        run_cycles(&mut apu, 1_789_773 / 10);
+        apu.samples(&mut out);
+        assert!((4409..=4411).contains(&out.len()), "{}", out.len());
+        // once past the start-up step
+        assert!(out[20..].iter().all(|s| (s - apu.output()).abs() < 0.0001));
+
+        // collected samples are gone from the APU
+        apu.samples(&mut out);
+        assert!((4409..=4411).contains(&out.len()));
+
+        apu.set_sample_rate(48000);
+        out.clear();
+        run_cycles(&mut apu, 1_789_773 / 10);
+        apu.samples(&mut out);
+        assert!((4799..=4801).contains(&out.len()), "{}", out.len());
+    }
+
+    #[test]
+    fn test_pulse_edges_are_band_limited() {
+        let mut apu = APU::new();
+        apu.write_register(0x4015, 0b0001);
+        // 50% duty at constant volume 15, a period of 144 CPU cycles is about 12.4kHz
+        apu.write_register(0x4000, 0b1011_1111);
+        apu.write_register(0x4002, 8);
+        apu.write_register(0x4003, 0b0000_1000);
+        run_cycles(&mut apu, 20_000);
+        let mut out = Vec::new();
+        apu.samples(&mut out);
+
+        let high = mix(15, 0, 15, 0, 0);
+        let low = mix(0, 0, 15, 0, 0);
+        let levels = &out[20..];
+        // a square wave sampled naively only ever shows its two levels
+        assert!(levels.iter().filter(|s| (*s - high).abs() > 0.01 && (*s - low).abs() > 0.01).count() > levels.len() / 2);
+        assert!(levels.iter().all(|s| *s > low - 0.05 && *s < high + 0.05));
+    }
+
+    #[test]
+    fn test_dynamic_rate_control() {
+        let mut apu = APU::new();
+        apu.update_buffer_fill(1.0);
+        assert_eq!(apu.effective_sample_rate(), 44100.0);
+
+        apu.set_dynamic_rate_control(Some(0.01));
+        apu.update_buffer_fill(0.5);
+        assert_eq!(apu.effective_sample_rate(), 44100.0);
+        apu.update_buffer_fill(0.0);
+        assert!((apu.effective_sample_rate() - 44541.0).abs() < 0.01);
+
+        apu.update_buffer_fill(1.0);
+        let mut out = Vec::new();
+        run_cycles(&mut apu, 1_789_773 / 10);
+        apu.samples(&mut out);
+        assert!((4365..=4367).contains(&out.len()), "{}", out.len());
+
+        apu.set_dynamic_rate_control(None);
+        assert_eq!(apu.effective_sample_rate(), 44100.0);
+    }
+
+    #[test]
+    fn test_unread_samples_are_capped() {
+        let mut apu = APU::new();
+        apu.set_sample_rate(1000);
+        run_cycles(&mut apu, 1_789_773 * 2);
+        let mut out = Vec::new();
+        apu.samples(&mut out);
+        assert_eq!(out.len(), 1000);
+    }
+
+    #[test]
+    fn test_mute_and_solo() {
+        let mut apu = APU::new();
+        apu.write_register(0x4011, 100);
+        let dmc = mix(0, 0, 0, 0, 100);
+        let triangle = mix(0, 0, 15, 0, 0);
+        assert_eq!(apu.output(), mix(0, 0, 15, 0, 100));
+
+        apu.set_muted(Channel::Triangle, true);
+        assert_eq!(apu.output(), dmc);
+        assert!(!apu.audible(Channel::Triangle));
+
+        // solo wins over mute, and silences everything else
+        apu.set_solo(Channel::Triangle, true);
+        assert_eq!(apu.output(), triangle);
+        apu.set_solo(Channel::Dmc, true);
+        assert_eq!(apu.output(), mix(0, 0, 15, 0, 100));
+
+        apu.set_solo(Channel::Triangle, false);
+        apu.set_solo(Channel::Dmc, false);
+        assert_eq!(apu.output(), dmc);
+        apu.set_muted(Channel::Triangle, false);
+        assert!(apu.audible(Channel::Triangle));
+    }
+
+    #[test]
+    fn test_wav_capture() {
+        let dir = std::env::temp_dir().join(format!("nessie-wav-capture-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut apu = APU::new();
+        apu.start_wav_capture(dir.join("song.wav"), true).unwrap();
+        assert!(apu.start_wav_capture(dir.join("other.wav"), false).is_err());
+        apu.write_register(0x4011, 100);
+        run_frames(&mut apu, 2);
+        let mut samples = Vec::new();
+        apu.samples(&mut samples);
+        apu.stop_wav_capture().unwrap();
+        assert!(!apu.is_capturing_wav());
+        assert!(apu.stop_wav_capture().is_err());
+
+        let read = |name: &str| std::fs::read(dir.join(name)).unwrap()[44..].chunks(4).map(|bytes| f32::from_le_bytes(bytes.try_into().unwrap())).collect::<Vec<_>>();
+        assert_eq!(read("song.wav"), samples);
+        assert!(read("song-pulse1.wav").iter().all(|&sample| sample == 0.0));
+        let dmc = read("song-dmc.wav");
+        assert!((dmc[dmc.len() - 1] - mix(0, 0, 0, 0, 100)).abs() < 0.01);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_mixer_range() {
+        assert_eq!(mix(0, 0, 0, 0, 0), 0.0);
+        let loudest = mix(15, 15, 15, 15, 127);
+        assert!(loudest > 0.99 && loudest < 1.01, "{}", loudest);
+    }
+
+    #[test]
+    fn test_mixer_is_non_linear() {
+        let one = mix(15, 0, 0, 0, 0);
+        let both = mix(15, 15, 0, 0, 0);
+        assert!((one - 0.1494).abs() < 0.001);
+        assert!(both < one * 2.0);
+
+        // a loud DMC makes the triangle quieter
+        let triangle = mix(0, 0, 15, 0, 0);
+        let with_dmc = mix(0, 0, 15, 0, 127) - mix(0, 0, 0, 0, 127);
+        assert!(with_dmc < triangle);
+    }
+
+    #[test]
+    fn test_frame_irq_in_four_step_mode() {
+        let mut apu = APU::new();
+        apu.tick((FRAME_STEP_4 - 2) as u16);
+        assert!(!apu.irq_pending());
+        apu.tick(1);
+        assert!(apu.irq_pending());
+        assert_eq!(apu.read_status() & 0b0100_0000, 0b0100_0000);
+        // still raised for the last two cycles of the sequence
+        apu.tick(1);
+        assert!(apu.irq_pending());
+        apu.tick(2);
+        apu.read_status();
+        assert!(!apu.irq_pending());
+        assert_eq!(apu.read_status() & 0b0100_0000, 0);
+    }
+
+    #[test]
+    fn test_irq_inhibit() {
+        let mut apu = APU::new();
+        apu.tick(FRAME_LENGTH as u16);
+        assert!(apu.irq_pending());
+        apu.write_register(0x4017, 0b0100_0000);
+        assert!(!apu.irq_pending());
+        apu.tick(FRAME_LENGTH as u16);
+        assert!(!apu.irq_pending());
+    }
+
+    #[test]
+    fn test_five_step_mode() {
+        let mut apu = APU::new();
+        apu.write_register(0x4015, 0b0001);
+        apu.write_register(0x4003, 0b0001_1000);
+        assert_eq!(apu.pulse1.length.counter, 2);
+
+        // even cycle, so the reset lands 3 cycles later with an immediate half frame
+        apu.write_register(0x4017, 0b1000_0000);
+        apu.tick(2);
+        assert_eq!(apu.pulse1.length.counter, 2);
+        apu.tick(1);
+        assert_eq!(apu.pulse1.length.counter, 1);
+
+        // no half frame at step 4, the next one is at step 5 and there's no IRQ
+        apu.tick(FRAME_STEP_2 as u16);
+        assert_eq!(apu.pulse1.length.counter, 0);
+        apu.write_register(0x4003, 0b0001_1000);
+        apu.tick((FRAME_STEP_5 - FRAME_STEP_2 - 1) as u16);
+        assert_eq!(apu.pulse1.length.counter, 2);
+        apu.tick(1);
+        assert_eq!(apu.pulse1.length.counter, 1);
+        assert!(!apu.irq_pending());
+    }
+
+    #[test]
+    fn test_dmc_fetches_and_decodes_sample() {
+        let mut dmc = Dmc::new();
+        // fastest rate, sample at $c040, 17 bytes
+        dmc.write_control(0x0f);
+        dmc.write_level(64);
+        dmc.write_sample_address(0x01);
+        dmc.write_sample_length(0x01);
+        dmc.set_enabled(true);
+        assert_eq!(dmc.pending_fetch(), Some(0xc040));
+        dmc.load_sample(0b0000_1111);
+        assert_eq!(dmc.pending_fetch(), None);
+        assert_eq!(dmc.bytes_remaining, 16);
+
+        // the byte only starts playing once the current (silent) one runs out
+        for _ in 0..8 * 54 {
+            dmc.clock_timer();
+        }
+        assert_eq!(dmc.output(), 64);
+        assert_eq!(dmc.pending_fetch(), Some(0xc041));
+        for _ in 0..4 * 54 {
+            dmc.clock_timer();
+        }
+        assert_eq!(dmc.output(), 72);
+        for _ in 0..4 * 54 {
+            dmc.clock_timer();
+        }
+        assert_eq!(dmc.output(), 64);
+    }
+
+    #[test]
+    fn test_dmc_irq_and_looping() {
+        let mut apu = APU::new();
+        apu.write_register(0x4010, 0b1000_0000);
+        apu.write_register(0x4013, 0x00);
+        apu.write_register(0x4015, 0b1_0000);
+        assert_eq!(apu.read_status(), 0b0001_0000);
+        apu.dmc.load_sample(0);
+        assert!(apu.irq_pending());
+        assert_eq!(apu.read_status(), 0b1000_0000);
+        // writing $4015 acknowledges it
+        apu.write_register(0x4015, 0);
+        assert!(!apu.irq_pending());
+
+        apu.write_register(0x4010, 0b0100_0000);
+        apu.write_register(0x4015, 0b1_0000);
+        apu.dmc.load_sample(0);
+        assert!(!apu.irq_pending());
+        assert_eq!(apu.dmc.bytes_remaining, 1);
+        assert_eq!(apu.dmc.current_address, 0xc000);
+    }
+
+    #[test]
+    fn test_dmc_enable_only_restarts_finished_samples() {
+        let mut apu = APU::new();
+        apu.write_register(0x4012, 0x01);
+        apu.write_register(0x4013, 0x01);
+        apu.write_register(0x4015, 0b1_0000);
+        apu.dmc.load_sample(0);
+        apu.dmc.sample_buffer = None;
+        assert_eq!(apu.dmc.current_address, 0xc041);
+
+        apu.write_register(0x4015, 0b1_0000);
+        assert_eq!((apu.dmc.current_address, apu.dmc.bytes_remaining), (0xc041, 16));
+        apu.write_register(0x4015, 0);
+        assert_eq!(apu.read_status() & 0b1_0000, 0);
+        apu.write_register(0x4015, 0b1_0000);
+        assert_eq!((apu.dmc.current_address, apu.dmc.bytes_remaining), (0xc040, 17));
+    }
+
+    #[test]
+    fn test_dmc_address_wraps_to_8000() {
+        let mut dmc = Dmc::new();
+        dmc.write_sample_address(0xff);
+        dmc.write_sample_length(0xff);
+        dmc.set_enabled(true);
+        for _ in 0..0x40 {
+            let data = dmc.pending_fetch().map(|_| 0).unwrap();
+            dmc.load_sample(data);
+            dmc.sample_buffer = None;
+        }
+        assert_eq!(dmc.pending_fetch(), Some(0x8000));
+    }
+
+    #[test]
+    fn test_sweep_mutes_low_and_overflowing_periods() {
+        let mut pulse = Pulse::new(2);
+        pulse.length.set_enabled(true);
+        pulse.write_control(0b1101_1111);
+        pulse.write_timer_lo(7);
+        pulse.write_timer_hi(0b0000_1000);
+        assert_eq!(pulse.output(), 0);
+
+        pulse.write_timer_lo(0xff);
+        pulse.write_timer_hi(0b0000_1111);
+        // shift 0 adds the whole period, going past $7ff
+        assert_eq!(pulse.output(), 0);
+        pulse.write_sweep(0b0000_1001);
+        assert_eq!(pulse.output(), 15);
+    }
+}
@@ -0,0 +1,409 @@
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use crate::cpu::AddressingMode;
+use crate::ops::{OpCode, CPU_OPS_CODES};
+use crate::symbols::Symbols;
+
+// where code goes without an `.org`, which is where `CPU::load` puts it
+const DEFAULT_ORIGIN: u16 = 0x8000;
+
+/// Machine code from `assemble`, to load at `origin`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Assembly {
+    pub origin: u16,
+    pub bytes: Vec<u8>,
+    pub labels: BTreeMap<String, u16>,
+}
+
+impl Assembly {
+    /// The labels as symbols for the debugger, disassembler and traces.
+    pub fn symbols(&self) -> Symbols {
+        let mut symbols = Symbols::new();
+        for (name, &addr) in &self.labels {
+            symbols.insert(addr, name);
+        }
+        symbols
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Part {
+    Whole,
+    Low,
+    High,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Term {
+    Number(i64),
+    Label(String),
+}
+
+// terms added or subtracted, then maybe cut to a byte with `<` or `>`
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Expr {
+    terms: Vec<(bool, Term)>,
+    part: Part,
+}
+
+impl Expr {
+    fn parse(text: &str) -> Result<Expr, String> {
+        let text = text.trim();
+        let (part, text) = match text.chars().next() {
+            Some('<') => (Part::Low, &text[1..]),
+            Some('>') => (Part::High, &text[1..]),
+            _ => (Part::Whole, text),
+        };
+        let mut terms = Vec::new();
+        let mut rest = text.trim_start();
+        let mut negative = rest.starts_with('-');
+        if negative {
+            rest = &rest[1..];
+        }
+        loop {
+            let len = rest.find(['+', '-']).unwrap_or(rest.len());
+            let term = rest[..len].trim();
+            if term.is_empty() {
+                return Err(format!("expected a value in {:?}", text));
+            }
+            terms.push((negative, parse_term(term)?));
+            if len == rest.len() {
+                break;
+            }
+            negative = rest[len..].starts_with('-');
+            rest = &rest[len + 1..];
+        }
+        Ok(Expr { terms, part })
+    }
+
+    // None while a label isn't known yet
+    fn eval(&self, labels: &BTreeMap<String, u16>) -> Result<Option<i64>, String> {
+        let mut value: i64 = 0;
+        for (negative, term) in &self.terms {
+            let term = match term {
+                Term::Number(number) => *number,
+                Term::Label(name) => match labels.get(name) {
+                    Some(&addr) => addr as i64,
+                    None => return Ok(None),
+                },
+            };
+            value = if *negative { value - term } else { value + term };
+        }
+        Ok(Some(match self.part {
+            Part::Whole => value,
+            Part::Low => value & 0xff,
+            Part::High => (value >> 8) & 0xff,
+        }))
+    }
+
+    fn resolve(&self, labels: &BTreeMap<String, u16>) -> Result<i64, String> {
+        self.eval(labels)?.ok_or_else(|| {
+            let unknown = self.terms.iter().find_map(|(_, term)| match term {
+                Term::Label(name) if !labels.contains_key(name) => Some(name.as_str()),
+                _ => None,
+            });
+            format!("unknown label {:?}", unknown.unwrap_or(""))
+        })
+    }
+}
+
+fn parse_term(text: &str) -> Result<Term, String> {
+    let number = if let Some(hex) = text.strip_prefix('$') {
+        i64::from_str_radix(hex, 16)
+    } else if let Some(binary) = text.strip_prefix('%') {
+        i64::from_str_radix(binary, 2)
+    } else if text.starts_with(|c: char| c.is_ascii_digit()) {
+        text.parse()
+    } else if is_name(text) {
+        return Ok(Term::Label(text.to_string()));
+    } else {
+        return Err(format!("bad value {:?}", text));
+    };
+    number.map(Term::Number).map_err(|_| format!("bad number {:?}", text))
+}
+
+fn is_name(text: &str) -> bool {
+    text.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') && text.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+enum Item {
+    Instruction { addr: u16, op: &'static OpCode, operand: Option<Expr> },
+    Bytes { addr: u16, values: Vec<Expr>, size: u16 },
+}
+
+/// Assembles a small 6502 dialect, so tests can be written as assembly:
+///
+/// ```text
+///         .org $C000
+/// PPUCTRL = $2000
+/// reset:  ldx #$10        ; comments run to the end of the line
+/// loop:   dex
+///         bne loop
+///         lda table,x
+///         sta PPUCTRL
+///         jmp (vector)
+/// table:  .byte 1, 2, %11, <reset
+/// vector: .word reset
+/// ```
+///
+/// Mnemonics are the official ones, in either case. Numbers are decimal,
+/// `$` hex or `%` binary, and operands can add and subtract labels, with
+/// `<` and `>` for the low and high byte. Addresses known by the time an
+/// instruction is reached that fit in a byte use zero page. Code starts at
+/// $8000 without an `.org`; gaps between `.org`s are filled with BRK.
+pub fn assemble(source: &str) -> Result<Assembly, String> {
+    let mut labels = BTreeMap::new();
+    let mut items = Vec::new();
+    // one past the end of the code so far, which can be $10000
+    let mut addr = DEFAULT_ORIGIN as u32;
+    let mut origin = None;
+
+    for (number, line) in source.lines().enumerate() {
+        let at = |err: String| format!("line {}: {}", number + 1, err);
+        let mut line = line.split(';').next().unwrap_or("").trim();
+
+        if let Some((name, value)) = line.split_once('=') {
+            let name = name.trim();
+            if !is_name(name) {
+                return Err(at(format!("bad constant name {:?}", name)));
+            }
+            let value = Expr::parse(value).and_then(|value| value.resolve(&labels)).map_err(at)?;
+            define(&mut labels, name, value as u16).map_err(at)?;
+            continue;
+        }
+        while let Some((label, rest)) = line.split_once(':') {
+            let label = label.trim();
+            if !is_name(label) {
+                break;
+            }
+            define(&mut labels, label, addr as u16).map_err(at)?;
+            line = rest.trim();
+        }
+        if line.is_empty() {
+            continue;
+        }
+
+        let (word, operand) = line.split_once(char::is_whitespace).map_or((line, ""), |(word, operand)| (word, operand.trim()));
+        let start = addr as u16;
+        let item = match word.to_ascii_lowercase().as_str() {
+            ".org" => {
+                let target = Expr::parse(operand).and_then(|target| target.resolve(&labels)).map_err(at)? as u16 as u32;
+                if origin.is_some() && target < addr {
+                    return Err(at(format!(".org ${:04X} is behind ${:04X}", target, addr)));
+                }
+                addr = target;
+                continue;
+            }
+            ".byte" | ".db" => Item::Bytes { addr: start, values: parse_list(operand).map_err(at)?, size: 1 },
+            ".word" | ".dw" => Item::Bytes { addr: start, values: parse_list(operand).map_err(at)?, size: 2 },
+            mnemonic if mnemonic.starts_with('.') => return Err(at(format!("unknown directive {:?}", word))),
+            mnemonic => {
+                let (op, operand) = choose_opcode(&mnemonic.to_ascii_uppercase(), operand, &labels).map_err(at)?;
+                Item::Instruction { addr: start, op, operand }
+            }
+        };
+        origin.get_or_insert(start);
+        let len = match &item {
+            Item::Instruction { op, .. } => op.len as u32,
+            Item::Bytes { values, size, .. } => values.len() as u32 * *size as u32,
+        };
+        addr += len;
+        if addr > 0x10000 {
+            return Err(at("code runs past $FFFF".to_string()));
+        }
+        items.push((number, item));
+    }
+
+    let origin = origin.unwrap_or(DEFAULT_ORIGIN);
+    let mut bytes = vec![0; (addr - origin as u32) as usize];
+    for (number, item) in &items {
+        let at = |err: String| format!("line {}: {}", number + 1, err);
+        let (start, encoded) = match item {
+            Item::Instruction { addr, op, operand } => (*addr, encode(*addr, op, operand.as_ref(), &labels).map_err(at)?),
+            Item::Bytes { addr, values, size } => {
+                let mut encoded = Vec::new();
+                for value in values {
+                    let value = value.resolve(&labels).map_err(at)?;
+                    if *size == 1 {
+                        encoded.push(byte(value).map_err(at)?);
+                    } else {
+                        encoded.extend_from_slice(&(value as u16).to_le_bytes());
+                    }
+                }
+                (*addr, encoded)
+            }
+        };
+        let offset = (start - origin) as usize;
+        bytes[offset..offset + encoded.len()].copy_from_slice(&encoded);
+    }
+    Ok(Assembly { origin, bytes, labels })
+}
+
+fn define(labels: &mut BTreeMap<String, u16>, name: &str, value: u16) -> Result<(), String> {
+    if labels.insert(name.to_string(), value).is_some() {
+        return Err(format!("{:?} is defined twice", name));
+    }
+    Ok(())
+}
+
+fn parse_list(text: &str) -> Result<Vec<Expr>, String> {
+    text.split(',').map(Expr::parse).collect()
+}
+
+fn byte(value: i64) -> Result<u8, String> {
+    if !(-128..=255).contains(&value) {
+        return Err(format!("{} doesn't fit in a byte", value));
+    }
+    Ok(value as u8)
+}
+
+fn find(mnemonic: &str, mode: AddressingMode) -> Option<&'static OpCode> {
+    CPU_OPS_CODES.iter().find(|op| !op.unofficial && op.name == mnemonic && op.mode == mode)
+}
+
+// the opcode for an operand written as `text`, with its value if it has one
+fn choose_opcode(mnemonic: &str, text: &str, labels: &BTreeMap<String, u16>) -> Result<(&'static OpCode, Option<Expr>), String> {
+    if !CPU_OPS_CODES.iter().any(|op| !op.unofficial && op.name == mnemonic) {
+        return Err(format!("unknown instruction {:?}", mnemonic));
+    }
+    let upper = text.to_ascii_uppercase().replace(' ', "");
+    let (modes, inner): (&[AddressingMode], &str) = if text.is_empty() || upper == "A" {
+        (&[AddressingMode::Implied, AddressingMode::Accumulator], "")
+    } else if let Some(value) = text.strip_prefix('#') {
+        (&[AddressingMode::Immediate], value)
+    } else if upper.starts_with('(') && upper.ends_with(",X)") {
+        (&[AddressingMode::Indirect_X], &text[1..text.rfind(',').unwrap()])
+    } else if upper.starts_with('(') && upper.ends_with("),Y") {
+        (&[AddressingMode::Indirect_Y], &text[1..text.rfind(')').unwrap()])
+    } else if upper.starts_with('(') && upper.ends_with(')') {
+        (&[AddressingMode::Indirect], &text[1..text.len() - 1])
+    } else if upper.ends_with(",X") {
+        (&[AddressingMode::ZeroPage_X, AddressingMode::Absolute_X], &text[..text.rfind(',').unwrap()])
+    } else if upper.ends_with(",Y") {
+        (&[AddressingMode::ZeroPage_Y, AddressingMode::Absolute_Y], &text[..text.rfind(',').unwrap()])
+    } else {
+        (&[AddressingMode::Relative, AddressingMode::ZeroPage, AddressingMode::Absolute], text)
+    };
+    let operand = if inner.is_empty() { None } else { Some(Expr::parse(inner)?) };
+    let candidates: Vec<&'static OpCode> = modes.iter().filter_map(|mode| find(mnemonic, *mode)).collect();
+    let op = match candidates[..] {
+        [] => return Err(format!("{} can't take {:?}", mnemonic, text)),
+        [op] => op,
+        // zero page when the address is known to fit, absolute otherwise
+        [zero_page, absolute, ..] => {
+            let value = operand.as_ref().map(|operand| operand.eval(labels)).transpose()?.flatten();
+            if zero_page.mode == AddressingMode::Relative || value.is_some_and(|value| (0..=0xff).contains(&value)) {
+                zero_page
+            } else {
+                absolute
+            }
+        }
+    };
+    Ok((op, operand))
+}
+
+fn encode(addr: u16, op: &OpCode, operand: Option<&Expr>, labels: &BTreeMap<String, u16>) -> Result<Vec<u8>, String> {
+    let mut bytes = vec![op.code];
+    let Some(operand) = operand else {
+        return Ok(bytes);
+    };
+    let value = operand.resolve(labels)?;
+    match op.len {
+        _ if op.mode == AddressingMode::Relative => {
+            let offset = value - (addr as i64 + 2);
+            if !(-128..=127).contains(&offset) {
+                return Err(format!("branch to ${:04X} is {} bytes away, more than a branch reaches", value, offset));
+            }
+            bytes.push(offset as u8);
+        }
+        2 if op.mode == AddressingMode::Immediate => bytes.push(byte(value)?),
+        2 if !(0..=0xff).contains(&value) => return Err(format!("${:X} isn't a zero page address", value)),
+        2 => bytes.push(value as u8),
+        _ => bytes.extend_from_slice(&(value as u16).to_le_bytes()),
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cpu::CPU;
+
+    #[test]
+    fn test_assemble() {
+        let source = "
+            PPUCTRL = $2000
+                    .org $C000
+            reset:  ldx #$10        ; count down
+            loop:   dex
+                    bne loop
+                    lda table,x
+                    sta PPUCTRL
+                    sta $10,X
+                    asl A
+                    jmp (vector)
+            table:  .byte 1, 2, %11, <reset, -1
+            vector: .word reset, later
+                    .org $C020
+            later:  rts
+        ";
+        let assembly = assemble(source).unwrap();
+        assert_eq!(assembly.origin, 0xc000);
+        #[rustfmt::skip]
+        let expected = vec![
+            0xa2, 0x10,
+            0xca,
+            0xd0, 0xfd,
+            0xbd, 0x11, 0xc0,
+            0x8d, 0x00, 0x20,
+            0x95, 0x10,
+            0x0a,
+            0x6c, 0x16, 0xc0,
+            0x01, 0x02, 0x03, 0x00, 0xff,
+            0x00, 0xc0, 0x20, 0xc0,
+        ];
+        assert_eq!(assembly.bytes[..expected.len()], expected[..]);
+        assert_eq!(assembly.bytes.len(), 0x21);
+        assert_eq!(assembly.bytes[0x20], 0x60);
+        assert_eq!(assembly.labels["later"], 0xc020);
+        assert_eq!(assembly.symbols().label(0xc002), Some("loop"));
+
+        let vectors = assemble(".org $FFFA\nnmi: .word nmi, $8000, nmi").unwrap();
+        assert_eq!(vectors.bytes, vec![0xfa, 0xff, 0x00, 0x80, 0xfa, 0xff]);
+    }
+
+    #[test]
+    fn test_zero_page_and_forward_labels() {
+        // `ahead` isn't known yet, so its LDA takes the absolute form
+        let assembly = assemble("lda $10\nlda ahead\nahead = $20\nlda ahead\nlda >$1234+1").unwrap();
+        assert_eq!(assembly.bytes, vec![0xa5, 0x10, 0xad, 0x20, 0x00, 0xa5, 0x20, 0xa5, 0x12]);
+    }
+
+    #[test]
+    fn test_runs() {
+        let assembly = assemble("ldx #3\nloop: inx\ncpx #5\nbne loop\nstx $00\nbrk").unwrap();
+        let mut cpu = CPU::new();
+        cpu.load_and_run(assembly.bytes);
+        assert_eq!(cpu.bus.peek(0x00), 5);
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(assemble("lda").unwrap_err().contains("line 1"));
+        assert!(assemble("nop\nfoo $10").unwrap_err().starts_with("line 2: unknown instruction"));
+        assert!(assemble("lax $10").unwrap_err().contains("unknown instruction"));
+        assert!(assemble("nop #$10").is_err());
+        assert!(assemble("jmp nowhere").unwrap_err().contains("unknown label \"nowhere\""));
+        assert!(assemble("a: nop\na: nop").is_err());
+        assert!(assemble("lda #$100").is_err());
+        assert!(assemble("ldx ($10),Y").is_err());
+        assert!(assemble(".org $9000\nnop\n.org $8000").is_err());
+        assert!(assemble(".fill 3").is_err());
+        assert!(assemble(".org $FFFE\n.word 1, 2").unwrap_err().contains("past $FFFF"));
+        let far = format!("start: nop\n{}beq start", "nop\n".repeat(200));
+        assert!(assemble(&far).unwrap_err().contains("branch"));
+    }
+}
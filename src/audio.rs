@@ -0,0 +1,109 @@
+use crate::blip::DcBlocker;
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{FromSample, SampleFormat, SizedSample, Stream, StreamConfig};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+// how much audio may pile up before the oldest samples are thrown away
+const MAX_QUEUED_SECONDS: f32 = 0.25;
+
+/// Plays APU output on the default audio device.
+///
+/// Feed it with what `APU::samples()` returns, after setting the APU to
+/// `sample_rate()`. When the queue runs dry the last sample is held, so an
+/// underrun is heard as a short gap instead of a click.
+pub struct AudioOutput {
+    stream: Stream,
+    queue: Arc<Mutex<VecDeque<f32>>>,
+    sample_rate: u32,
+    dc: DcBlocker,
+}
+
+impl AudioOutput {
+    pub fn open() -> Result<AudioOutput, String> {
+        let host = cpal::default_host();
+        let device = host.default_output_device().ok_or("no audio output device")?;
+        let supported = device
+            .default_output_config()
+            .map_err(|e| format!("can't get audio output config: {}", e))?;
+        let format = supported.sample_format();
+        let config: StreamConfig = supported.into();
+
+        let queue = Arc::new(Mutex::new(VecDeque::new()));
+        let stream = match format {
+            SampleFormat::F32 => build_stream::<f32>(&device, &config, queue.clone()),
+            SampleFormat::I16 => build_stream::<i16>(&device, &config, queue.clone()),
+            SampleFormat::U16 => build_stream::<u16>(&device, &config, queue.clone()),
+            other => Err(format!("unsupported audio sample format {:?}", other)),
+        }?;
+        stream.play().map_err(|e| format!("can't start audio stream: {}", e))?;
+
+        Ok(AudioOutput {
+            stream,
+            queue,
+            sample_rate: config.sample_rate.0,
+            dc: DcBlocker::default(),
+        })
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Queues mono samples in the 0.0-1.0 range the APU produces.
+    pub fn push(&mut self, samples: &[f32]) {
+        let mut queue = self.queue.lock().unwrap();
+        for sample in samples {
+            queue.push_back(self.dc.filter(*sample));
+        }
+        let max = (self.sample_rate as f32 * MAX_QUEUED_SECONDS) as usize;
+        if queue.len() > max {
+            let excess = queue.len() - max;
+            queue.drain(..excess);
+        }
+    }
+
+    /// Samples waiting to be played.
+    pub fn queued(&self) -> usize {
+        self.queue.lock().unwrap().len()
+    }
+
+    /// How full the queue is, from 0.0 to 1.0, for `APU::update_buffer_fill()`.
+    pub fn fill(&self) -> f32 {
+        self.queued() as f32 / (self.sample_rate as f32 * MAX_QUEUED_SECONDS)
+    }
+
+    pub fn pause(&self) -> Result<(), String> {
+        self.stream.pause().map_err(|e| format!("can't pause audio stream: {}", e))
+    }
+
+    pub fn resume(&self) -> Result<(), String> {
+        self.stream.play().map_err(|e| format!("can't resume audio stream: {}", e))
+    }
+}
+
+fn build_stream<T>(device: &cpal::Device, config: &StreamConfig, queue: Arc<Mutex<VecDeque<f32>>>) -> Result<Stream, String>
+where
+    T: SizedSample + FromSample<f32>,
+{
+    let channels = config.channels as usize;
+    let mut last = 0.0;
+    device
+        .build_output_stream(
+            config,
+            move |data: &mut [T], _| {
+                let mut queue = queue.lock().unwrap();
+                for frame in data.chunks_mut(channels) {
+                    if let Some(sample) = queue.pop_front() {
+                        last = sample;
+                    }
+                    for out in frame.iter_mut() {
+                        *out = T::from_sample(last);
+                    }
+                }
+            },
+            |e| eprintln!("audio stream error: {}", e),
+            None,
+        )
+        .map_err(|e| format!("can't open audio stream: {}", e))
+}
@@ -0,0 +1,241 @@
+//! Plays a game in a window: `nessie-sdl game.nes [--config nessie.toml]`.
+//!
+//! Keyboard controls are those of the config's input profile, by default
+//! `InputProfile::default()`, and unless the profile binds gamepad buttons
+//! the first gamepad is player 1 too. Hotkeys:
+//!
+//! | Key       | Does                                  |
+//! |-----------|---------------------------------------|
+//! | F1        | reset                                 |
+//! | F2        | power cycle                           |
+//! | F5        | save state to the selected slot       |
+//! | F6        | select the next slot, 0 to 9          |
+//! | F7        | load state from the selected slot     |
+//! | P         | pause and resume                      |
+//! | Tab       | fast forward while held               |
+//! | Escape    | quit                                  |
+//!
+//! Battery saves and save states go where the config's `[paths]` say, by
+//! default next to the game as a `.sav` and in a `states` directory.
+
+use nessie::blip::DcBlocker;
+use nessie::cartridge::Cartridge;
+use nessie::config::Config;
+use nessie::frame::{HEIGHT, WIDTH};
+use nessie::input::mapping::{HostInput, HostKey, InputProfile, Target};
+use nessie::joypad::Button;
+use nessie::nes::{Nes, ResetKind, Speed};
+use nessie::slots::SlotManager;
+use sdl2::audio::{AudioQueue, AudioSpecDesired};
+use sdl2::controller::{Button as PadButton, GameController};
+use sdl2::event::Event;
+use sdl2::keyboard::Scancode;
+use sdl2::pixels::PixelFormatEnum;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+const SCALE: u32 = 3;
+// audio queued ahead of what's playing; dynamic rate control aims for half
+const QUEUED_SECONDS: f32 = 0.1;
+const FAST_FORWARD: f64 = 4.0;
+const SLOTS: u8 = 10;
+
+fn main() {
+    let mut args = std::env::args_os().skip(1);
+    let mut path = None;
+    let mut config = None;
+    while let Some(arg) = args.next() {
+        if arg == "--config" {
+            config = Some(args.next().map(PathBuf::from).unwrap_or_else(|| usage()));
+        } else if path.is_none() {
+            path = Some(PathBuf::from(arg));
+        } else {
+            usage();
+        }
+    }
+    let Some(path) = path else { usage() };
+    let result = config.map_or(Ok(Config::default()), Config::load).and_then(|config| run(&path, &config));
+    if let Err(err) = result {
+        eprintln!("nessie-sdl: {}", err);
+        std::process::exit(1);
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("usage: nessie-sdl <game.nes> [--config FILE]");
+    std::process::exit(2);
+}
+
+fn run(path: &Path, config: &Config) -> Result<(), String> {
+    let mut nes = Nes::new();
+    config.apply(&mut nes)?;
+    nes.insert_cartridge(Cartridge::load(path)?);
+    let battery = config.battery_path(path);
+    if nes.cpu().bus.has_battery() && battery.exists() {
+        nes.load_battery(&battery)?;
+    }
+    let mut slots = SlotManager::new(config.states_dir(path));
+    slots.set_auto_save(None);
+
+    let sdl = sdl2::init()?;
+    let window = sdl
+        .video()?
+        .window("NESsie", WIDTH as u32 * SCALE, HEIGHT as u32 * SCALE)
+        .position_centered()
+        .resizable()
+        .build()
+        .map_err(|err| err.to_string())?;
+    let mut canvas = window.into_canvas().present_vsync().build().map_err(|err| err.to_string())?;
+    canvas.set_logical_size(WIDTH as u32, HEIGHT as u32).map_err(|err| err.to_string())?;
+    let textures = canvas.texture_creator();
+    let mut texture = textures.create_texture_streaming(PixelFormatEnum::RGBA32, WIDTH as u32, HEIGHT as u32).map_err(|err| err.to_string())?;
+
+    let audio: AudioQueue<f32> = sdl.audio()?.open_queue(None, &AudioSpecDesired { freq: Some(config.audio.sample_rate as i32), channels: Some(1), samples: Some(512) })?;
+    let sample_rate = audio.spec().freq as u32;
+    let apu = &mut nes.cpu_mut().bus.apu;
+    apu.set_sample_rate(sample_rate);
+    apu.set_dynamic_rate_control(Some(0.005));
+    audio.resume();
+    let max_queued = (sample_rate as f32 * QUEUED_SECONDS) as u32 * 4;
+    let mut dc = DcBlocker::default();
+    let mut samples = Vec::new();
+
+    let pads = sdl.game_controller()?;
+    let mut controllers: Vec<GameController> = Vec::new();
+    let mut profile = config.input.clone();
+    if !profile.bindings.iter().any(|(input, _)| matches!(input, HostInput::GamepadButton { .. })) {
+        for (pad, button) in default_pad() {
+            profile.bind(HostInput::GamepadButton { gamepad: 0, button: pad as u8 }, Target::Joypad { player: 0, button });
+        }
+    }
+
+    let mut events = sdl.event_pump()?;
+    let mut last = Instant::now();
+    'running: loop {
+        for event in events.poll_iter() {
+            match event {
+                Event::Quit { .. } | Event::KeyDown { scancode: Some(Scancode::Escape), .. } => break 'running,
+                Event::KeyDown { scancode: Some(scancode), repeat: false, .. } => {
+                    let handled = hotkey(&mut nes, &mut slots, scancode);
+                    if !handled {
+                        press(&mut nes, &profile, key_code(scancode), true);
+                    }
+                }
+                Event::KeyUp { scancode: Some(Scancode::Tab), .. } => nes.set_speed(Speed::Multiplier(1.0)),
+                Event::KeyUp { scancode: Some(scancode), .. } => press(&mut nes, &profile, key_code(scancode), false),
+                Event::ControllerDeviceAdded { which, .. } => controllers.push(pads.open(which).map_err(|err| err.to_string())?),
+                Event::ControllerDeviceRemoved { which, .. } => controllers.retain(|controller| controller.instance_id() != which),
+                Event::ControllerButtonDown { which, button, .. } | Event::ControllerButtonUp { which, button, .. } => {
+                    let pressed = matches!(event, Event::ControllerButtonDown { .. });
+                    if let Some(gamepad) = controllers.iter().position(|controller| controller.instance_id() == which) {
+                        let input = HostInput::GamepadButton { gamepad: gamepad as u8, button: button as u8 };
+                        profile.handle(&mut nes.cpu_mut().bus, &input, pressed);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let now = Instant::now();
+        nes.run_for(now - last);
+        last = now;
+
+        texture.update(None, nes.frame().pixels(), WIDTH * 4).map_err(|err| err.to_string())?;
+        canvas.clear();
+        canvas.copy(&texture, None, None)?;
+        canvas.present();
+
+        nes.audio(&mut samples);
+        dc.filter_slice(&mut samples);
+        if audio.size() < max_queued {
+            audio.queue_audio(&samples)?;
+        }
+        samples.clear();
+        let fill = audio.size() as f32 / max_queued as f32;
+        nes.cpu_mut().bus.apu.update_buffer_fill(fill);
+    }
+
+    if nes.cpu().bus.has_battery() {
+        nes.save_battery(&battery)?;
+    }
+    Ok(())
+}
+
+// true if `scancode` was a hotkey, which games don't see
+fn hotkey(nes: &mut Nes, slots: &mut SlotManager, scancode: Scancode) -> bool {
+    match scancode {
+        Scancode::F1 => nes.reset(ResetKind::Soft),
+        Scancode::F2 => nes.reset(ResetKind::Hard),
+        Scancode::F5 => report(slots.save_selected(nes).map(|_| format!("saved to slot {}", slots.selected()))),
+        Scancode::F6 => {
+            slots.select((slots.selected() + 1) % SLOTS);
+            eprintln!("slot {}", slots.selected());
+        }
+        Scancode::F7 => report(slots.load_selected(nes).map(|_| format!("loaded slot {}", slots.selected()))),
+        Scancode::P if nes.is_paused() => nes.resume(),
+        Scancode::P => nes.pause(),
+        Scancode::Tab => nes.set_speed(Speed::Multiplier(FAST_FORWARD)),
+        _ => return false,
+    }
+    true
+}
+
+fn report(result: Result<String, String>) {
+    let (Ok(message) | Err(message)) = result;
+    eprintln!("{}", message);
+}
+
+fn press(nes: &mut Nes, profile: &InputProfile, code: Option<String>, pressed: bool) {
+    if let Some(code) = code {
+        profile.handle(&mut nes.cpu_mut().bus, &HostInput::Key(code), pressed);
+    }
+}
+
+// NES A and B on the east and south buttons, as on a SNES pad
+fn default_pad() -> [(PadButton, Button); 8] {
+    [
+        (PadButton::B, Button::A),
+        (PadButton::A, Button::B),
+        (PadButton::Back, Button::Select),
+        (PadButton::Start, Button::Start),
+        (PadButton::DPadUp, Button::Up),
+        (PadButton::DPadDown, Button::Down),
+        (PadButton::DPadLeft, Button::Left),
+        (PadButton::DPadRight, Button::Right),
+    ]
+}
+
+fn key_code(scancode: Scancode) -> Option<String> {
+    let name = scancode.name();
+    let key = match scancode {
+        _ if name.len() == 1 && name.as_bytes()[0].is_ascii_uppercase() => HostKey::Letter(name.as_bytes()[0] as char),
+        _ if name.len() == 1 && name.as_bytes()[0].is_ascii_digit() => HostKey::Digit(name.as_bytes()[0] - b'0'),
+        _ if name.len() <= 3 && name.starts_with('F') => HostKey::Function(name[1..].parse().ok()?),
+        Scancode::Up => HostKey::ArrowUp,
+        Scancode::Down => HostKey::ArrowDown,
+        Scancode::Left => HostKey::ArrowLeft,
+        Scancode::Right => HostKey::ArrowRight,
+        Scancode::Return => HostKey::Enter,
+        Scancode::Space => HostKey::Space,
+        Scancode::Backspace => HostKey::Backspace,
+        Scancode::LShift => HostKey::ShiftLeft,
+        Scancode::RShift => HostKey::ShiftRight,
+        Scancode::LCtrl => HostKey::ControlLeft,
+        Scancode::RCtrl => HostKey::ControlRight,
+        Scancode::LAlt => HostKey::AltLeft,
+        Scancode::RAlt => HostKey::AltRight,
+        Scancode::Minus => HostKey::Minus,
+        Scancode::Equals => HostKey::Equal,
+        Scancode::LeftBracket => HostKey::BracketLeft,
+        Scancode::RightBracket => HostKey::BracketRight,
+        Scancode::Backslash => HostKey::Backslash,
+        Scancode::Semicolon => HostKey::Semicolon,
+        Scancode::Apostrophe => HostKey::Quote,
+        Scancode::Grave => HostKey::Backquote,
+        Scancode::Comma => HostKey::Comma,
+        Scancode::Period => HostKey::Period,
+        Scancode::Slash => HostKey::Slash,
+        _ => return None,
+    };
+    Some(key.code())
+}
@@ -0,0 +1,295 @@
+//! Plays a game in a terminal: `nessie-tui game.nes [--frames N] [--config nessie.toml]`.
+//!
+//! Every character cell shows two pixels as an upper half block, its
+//! foreground the top one and its background the bottom one, so it needs a
+//! terminal with 24-bit color. There's no sound. Keyboard controls are
+//! those of the config's input profile, by default `InputProfile::default()`,
+//! with space for select as well unless the profile binds it, since most
+//! terminals can't tell right shift apart. Hotkeys:
+//!
+//! | Key       | Does                                  |
+//! |-----------|---------------------------------------|
+//! | F1        | reset                                 |
+//! | F2        | power cycle                           |
+//! | P         | pause and resume                      |
+//! | Tab       | fast forward on and off               |
+//! | Escape    | quit, as does ctrl+C                  |
+//!
+//! Terminals speaking the kitty keyboard protocol report key releases;
+//! elsewhere a key is held for as long as it keeps repeating. With
+//! `--frames N` it quits by itself after frame N and prints that frame's
+//! hash, which makes for a quick smoke test over SSH.
+
+use nessie::cartridge::Cartridge;
+use nessie::config::Config;
+use nessie::frame::{Frame, HEIGHT, WIDTH};
+use nessie::input::mapping::{HostInput, HostKey, InputProfile, Target};
+use nessie::joypad::Button;
+use nessie::nes::{Nes, ResetKind, Speed};
+use ratatui::buffer::Buffer;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers, KeyboardEnhancementFlags, ModifierKeyCode, PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags};
+use ratatui::crossterm::{execute, terminal};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::Color;
+use ratatui::text::Line;
+use ratatui::widgets::Widget;
+use ratatui::DefaultTerminal;
+use std::collections::HashMap;
+use std::io::stdout;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+const FAST_FORWARD: f64 = 4.0;
+// how often the screen is redrawn
+const TICK: Duration = Duration::from_millis(16);
+// without release events a key is let go once it stops repeating; the first
+// repeat comes about half a second after the press on most systems
+const HOLD: Duration = Duration::from_millis(550);
+
+fn main() {
+    let mut args = std::env::args_os().skip(1);
+    let mut path = None;
+    let mut frames = None;
+    let mut config = Config::default();
+    while let Some(arg) = args.next() {
+        if arg == "--frames" {
+            frames = args.next().and_then(|count| count.to_str()?.parse::<u64>().ok());
+            if frames.is_none() {
+                usage();
+            }
+        } else if arg == "--config" {
+            let Some(file) = args.next() else { usage() };
+            config = Config::load(file).unwrap_or_else(|err| {
+                eprintln!("nessie-tui: {}", err);
+                std::process::exit(1);
+            });
+        } else if path.is_none() {
+            path = Some(PathBuf::from(arg));
+        } else {
+            usage();
+        }
+    }
+    let Some(path) = path else { usage() };
+
+    let mut terminal = ratatui::init();
+    let releases = terminal::supports_keyboard_enhancement().unwrap_or(false);
+    if releases {
+        let flags = KeyboardEnhancementFlags::DISAMBIGUATE_ESCAPE_CODES | KeyboardEnhancementFlags::REPORT_EVENT_TYPES | KeyboardEnhancementFlags::REPORT_ALL_KEYS_AS_ESCAPE_CODES;
+        let _ = execute!(stdout(), PushKeyboardEnhancementFlags(flags));
+    }
+    let result = run(&mut terminal, &path, &config, frames, releases);
+    if releases {
+        let _ = execute!(stdout(), PopKeyboardEnhancementFlags);
+    }
+    ratatui::restore();
+
+    match result {
+        Ok(Some(hash)) => println!("{:08x}", hash),
+        Ok(None) => {}
+        Err(err) => {
+            eprintln!("nessie-tui: {}", err);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("usage: nessie-tui <game.nes> [--frames N] [--config FILE]");
+    std::process::exit(2);
+}
+
+// the hash of the last frame if it stopped after `frames`
+fn run(terminal: &mut DefaultTerminal, path: &Path, config: &Config, frames: Option<u64>, releases: bool) -> Result<Option<u32>, String> {
+    let mut nes = Nes::new();
+    config.apply(&mut nes)?;
+    nes.insert_cartridge(Cartridge::load(path)?);
+    let battery = config.battery_path(path);
+    if nes.cpu().bus.has_battery() && battery.exists() {
+        nes.load_battery(&battery)?;
+    }
+    let mut profile = config.input.clone();
+    let space = HostInput::Key("Space".to_string());
+    if profile.targets(&space).next().is_none() {
+        profile.bind(space, Target::Joypad { player: 0, button: Button::Select });
+    }
+    let mut keys = Keys { releases, held: HashMap::new() };
+
+    let mut last = Instant::now();
+    let mut fps = Fps::default();
+    let hash = loop {
+        let deadline = last + TICK;
+        while event::poll(deadline.saturating_duration_since(Instant::now())).map_err(|err| err.to_string())? {
+            let Event::Key(key) = event::read().map_err(|err| err.to_string())? else { continue };
+            match (key.code, key.kind) {
+                (KeyCode::Esc, KeyEventKind::Press) => return quit(&mut nes, &battery).map(|_| None),
+                (KeyCode::Char('c'), KeyEventKind::Press) if key.modifiers.contains(KeyModifiers::CONTROL) => return quit(&mut nes, &battery).map(|_| None),
+                (_, KeyEventKind::Press) if hotkey(&mut nes, key.code) => {}
+                (_, KeyEventKind::Release) => keys.release(&mut nes, &profile, key.code),
+                _ => keys.press(&mut nes, &profile, key.code),
+            }
+        }
+
+        let now = Instant::now();
+        keys.expire(&mut nes, &profile, now);
+        fps.add(nes.run_for(now - last), now);
+        last = now;
+
+        let frame = nes.frame();
+        if frames.is_some_and(|frames| frame.number() >= frames) {
+            break frame.hash();
+        }
+        let status = format!(
+            " frame {}  {} fps{}{}  F1 reset  F2 power  P pause  Tab fast  Esc quit",
+            frame.number(),
+            fps.rate,
+            if nes.is_paused() { "  paused" } else { "" },
+            if nes.speed() == Speed::Multiplier(1.0) { "" } else { "  fast" }
+        );
+        terminal
+            .draw(|f| {
+                let [screen, status_line] = Layout::vertical([Constraint::Min(1), Constraint::Length(1)]).areas(f.area());
+                f.render_widget(Screen(frame), screen);
+                f.render_widget(Line::raw(status), status_line);
+            })
+            .map_err(|err| err.to_string())?;
+    };
+    quit(&mut nes, &battery)?;
+    Ok(Some(hash))
+}
+
+fn quit(nes: &mut Nes, battery: &Path) -> Result<(), String> {
+    if nes.cpu().bus.has_battery() {
+        nes.save_battery(battery)?;
+    }
+    Ok(())
+}
+
+// true if `code` was a hotkey, which games don't see
+fn hotkey(nes: &mut Nes, code: KeyCode) -> bool {
+    match code {
+        KeyCode::F(1) => nes.reset(ResetKind::Soft),
+        KeyCode::F(2) => nes.reset(ResetKind::Hard),
+        KeyCode::Char('p' | 'P') if nes.is_paused() => nes.resume(),
+        KeyCode::Char('p' | 'P') => nes.pause(),
+        KeyCode::Tab if nes.speed() == Speed::Multiplier(1.0) => nes.set_speed(Speed::Multiplier(FAST_FORWARD)),
+        KeyCode::Tab => nes.set_speed(Speed::Multiplier(1.0)),
+        _ => return false,
+    }
+    true
+}
+
+// keys games see as held, and when each was last pressed or repeated
+struct Keys {
+    releases: bool,
+    held: HashMap<String, Instant>,
+}
+
+impl Keys {
+    fn press(&mut self, nes: &mut Nes, profile: &InputProfile, code: KeyCode) {
+        let Some(code) = key_code(code) else { return };
+        if !self.held.contains_key(&code) {
+            profile.handle(&mut nes.cpu_mut().bus, &HostInput::Key(code.clone()), true);
+        }
+        self.held.insert(code, Instant::now());
+    }
+
+    fn release(&mut self, nes: &mut Nes, profile: &InputProfile, code: KeyCode) {
+        let Some(code) = key_code(code) else { return };
+        if self.held.remove(&code).is_some() {
+            profile.handle(&mut nes.cpu_mut().bus, &HostInput::Key(code), false);
+        }
+    }
+
+    fn expire(&mut self, nes: &mut Nes, profile: &InputProfile, now: Instant) {
+        if self.releases {
+            return;
+        }
+        let expired: Vec<String> = self.held.iter().filter(|(_, pressed)| now - **pressed > HOLD).map(|(code, _)| code.clone()).collect();
+        for code in expired {
+            self.held.remove(&code);
+            profile.handle(&mut nes.cpu_mut().bus, &HostInput::Key(code), false);
+        }
+    }
+}
+
+// frames emulated over the last second
+#[derive(Default)]
+struct Fps {
+    frames: usize,
+    since: Option<Instant>,
+    rate: usize,
+}
+
+impl Fps {
+    fn add(&mut self, frames: usize, now: Instant) {
+        self.frames += frames;
+        let since = *self.since.get_or_insert(now);
+        if now - since >= Duration::from_secs(1) {
+            self.rate = self.frames;
+            self.frames = 0;
+            self.since = Some(now);
+        }
+    }
+}
+
+/// The picture scaled to fit, keeping its shape, two pixels to a cell.
+struct Screen<'a>(&'a Frame);
+
+impl Widget for Screen<'_> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        // cells are about twice as tall as wide, so a cell is a square pair
+        let scale = (area.width as f32 / WIDTH as f32).min(area.height as f32 * 2.0 / HEIGHT as f32);
+        if scale <= 0.0 {
+            return;
+        }
+        let width = (WIDTH as f32 * scale) as u16;
+        let height = (HEIGHT as f32 * scale / 2.0) as u16;
+        let left = area.x + (area.width - width) / 2;
+        let top = area.y + (area.height - height) / 2;
+        let color = |x: u16, y: u16| {
+            let (r, g, b) = self.0.pixel(((x as f32 / scale) as usize).min(WIDTH - 1), ((y as f32 / scale) as usize).min(HEIGHT - 1));
+            Color::Rgb(r, g, b)
+        };
+        for row in 0..height {
+            for column in 0..width {
+                if let Some(cell) = buf.cell_mut((left + column, top + row)) {
+                    cell.set_char('▀').set_fg(color(column, row * 2)).set_bg(color(column, row * 2 + 1));
+                }
+            }
+        }
+    }
+}
+
+fn key_code(code: KeyCode) -> Option<String> {
+    let key = match code {
+        KeyCode::Char(c) if c.is_ascii_alphabetic() => HostKey::Letter(c),
+        KeyCode::Char(c) if c.is_ascii_digit() => HostKey::Digit(c as u8 - b'0'),
+        KeyCode::F(n) => HostKey::Function(n),
+        KeyCode::Up => HostKey::ArrowUp,
+        KeyCode::Down => HostKey::ArrowDown,
+        KeyCode::Left => HostKey::ArrowLeft,
+        KeyCode::Right => HostKey::ArrowRight,
+        KeyCode::Enter => HostKey::Enter,
+        KeyCode::Char(' ') => HostKey::Space,
+        KeyCode::Backspace => HostKey::Backspace,
+        KeyCode::Modifier(ModifierKeyCode::LeftShift) => HostKey::ShiftLeft,
+        KeyCode::Modifier(ModifierKeyCode::RightShift) => HostKey::ShiftRight,
+        KeyCode::Modifier(ModifierKeyCode::LeftControl) => HostKey::ControlLeft,
+        KeyCode::Modifier(ModifierKeyCode::RightControl) => HostKey::ControlRight,
+        KeyCode::Modifier(ModifierKeyCode::LeftAlt) => HostKey::AltLeft,
+        KeyCode::Modifier(ModifierKeyCode::RightAlt) => HostKey::AltRight,
+        KeyCode::Char('-') => HostKey::Minus,
+        KeyCode::Char('=') => HostKey::Equal,
+        KeyCode::Char('[') => HostKey::BracketLeft,
+        KeyCode::Char(']') => HostKey::BracketRight,
+        KeyCode::Char('\\') => HostKey::Backslash,
+        KeyCode::Char(';') => HostKey::Semicolon,
+        KeyCode::Char('\'') => HostKey::Quote,
+        KeyCode::Char('`') => HostKey::Backquote,
+        KeyCode::Char(',') => HostKey::Comma,
+        KeyCode::Char('.') => HostKey::Period,
+        KeyCode::Char('/') => HostKey::Slash,
+        _ => return None,
+    };
+    Some(key.code())
+}
@@ -0,0 +1,266 @@
+//! The emulator as a command line tool, without a window:
+//!
+//! ```text
+//! nessie run <game.nes> [--frames N]
+//! nessie disasm <game.nes> [--range C000-C0FF] [--symbols game.nl]
+//! nessie trace <game.nes> [--frames N] [--symbols game.nl]
+//! nessie checkrom <game.nes>...
+//! nessie test <dir or rom>... [--frames N]
+//! ```
+//!
+//! `run` plays the first N frames as fast as it can and prints the last
+//! one's hash, keeping battery saves next to the game like the frontends.
+//! `disasm` disassembles the address range from the cartridge as the CPU
+//! sees it after power on, `trace` logs every instruction of the first N
+//! frames in nestest.log format, `checkrom` tells what the header of each
+//! file says and whether it loads, and `test` runs blargg-style test ROMs
+//! and exits with 1 if any of them fails.
+
+use nessie::cartridge::Cartridge;
+use nessie::disasm;
+use nessie::nes::Nes;
+use nessie::ppu::Mirroring;
+use nessie::region::Region;
+use nessie::symbols::Symbols;
+use nessie::testing::blargg;
+use std::fs;
+use std::io::{stdout, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process::exit;
+
+const USAGE: &str = "usage: nessie run <game.nes> [--frames N]
+       nessie disasm <game.nes> [--range START-END] [--symbols FILE]
+       nessie trace <game.nes> [--frames N] [--symbols FILE]
+       nessie checkrom <game.nes>...
+       nessie test <dir or rom>... [--frames N]";
+
+// frames `run` plays when not told, 10 seconds of NTSC
+const RUN_FRAMES: u64 = 600;
+// frames a test ROM gets to report its result
+const TEST_FRAMES: u64 = 60 * 60;
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let Some(command) = args.next() else { usage() };
+    let args = Args::parse(args);
+    let result = match command.as_str() {
+        "run" => run(&args),
+        "disasm" => disassemble(&args),
+        "trace" => trace(&args),
+        "checkrom" => check_roms(&args),
+        "test" => test(&args),
+        "help" | "--help" | "-h" => {
+            println!("{}", USAGE);
+            Ok(true)
+        }
+        _ => usage(),
+    };
+    match result {
+        Ok(true) => {}
+        Ok(false) => exit(1),
+        Err(err) => {
+            eprintln!("nessie: {}", err);
+            exit(1);
+        }
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    exit(2);
+}
+
+// the options of a subcommand, and the paths between them
+#[derive(Default)]
+struct Args {
+    paths: Vec<PathBuf>,
+    frames: Option<u64>,
+    range: Option<(u16, u16)>,
+    symbols: Option<PathBuf>,
+}
+
+impl Args {
+    fn parse(mut args: impl Iterator<Item = String>) -> Args {
+        let mut parsed = Args::default();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--frames" => parsed.frames = Some(args.next().and_then(|count| count.parse().ok()).unwrap_or_else(|| usage())),
+                "--range" => parsed.range = Some(args.next().and_then(|range| parse_range(&range)).unwrap_or_else(|| usage())),
+                "--symbols" => parsed.symbols = Some(args.next().map(PathBuf::from).unwrap_or_else(|| usage())),
+                _ if arg.starts_with("--") => usage(),
+                _ => parsed.paths.push(PathBuf::from(arg)),
+            }
+        }
+        parsed
+    }
+
+    // the one game the subcommand works on
+    fn game(&self) -> &Path {
+        match self.paths.as_slice() {
+            [path] => path,
+            _ => usage(),
+        }
+    }
+}
+
+// "C000-C0FF", either side with or without a $
+fn parse_range(range: &str) -> Option<(u16, u16)> {
+    let (start, end) = range.split_once('-')?;
+    let hex = |addr: &str| u16::from_str_radix(addr.trim_start_matches('$'), 16).ok();
+    let (start, end) = (hex(start)?, hex(end)?);
+    (start <= end).then_some((start, end))
+}
+
+// the console with the game in, and the labels `--symbols` names for it
+fn power_on(args: &Args) -> Result<(Nes, Option<Symbols>), String> {
+    let cartridge = Cartridge::load(args.game())?;
+    let symbols = args.symbols.as_deref().map(|path| Symbols::load(path, cartridge.prg_rom.len())).transpose()?;
+    let mut nes = Nes::new();
+    nes.insert_cartridge(cartridge);
+    Ok((nes, symbols))
+}
+
+fn run(args: &Args) -> Result<bool, String> {
+    let (mut nes, _) = power_on(args)?;
+    let battery = args.game().with_extension("sav");
+    let has_battery = nes.cpu().bus.has_battery();
+    if has_battery && battery.exists() {
+        nes.load_battery(&battery)?;
+    }
+    nes.run_frames(args.frames.unwrap_or(RUN_FRAMES));
+    if has_battery {
+        nes.save_battery(&battery)?;
+    }
+    println!("{:08x}", nes.frame().hash());
+    Ok(true)
+}
+
+fn disassemble(args: &Args) -> Result<bool, String> {
+    let (nes, symbols) = power_on(args)?;
+    let (start, end) = args.range.unwrap_or((0x8000, 0xffff));
+    let mut out = BufWriter::new(stdout().lock());
+    let mut addr = start as u32;
+    while addr <= end as u32 {
+        let mut instruction = disasm::decode_with(|at| nes.cpu().bus.peek(at), addr as u16);
+        if let Some(symbols) = &symbols {
+            if let Some(label) = symbols.label(instruction.addr) {
+                writeln!(out, "{}:", label).map_err(|err| err.to_string())?;
+            }
+            instruction.symbolize(symbols);
+        }
+        let bytes: Vec<String> = instruction.bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+        writeln!(out, "{:04X}  {:<8}  {}", instruction.addr, bytes.join(" "), instruction).map_err(|err| err.to_string())?;
+        addr += instruction.len() as u32;
+    }
+    out.flush().map_err(|err| err.to_string())?;
+    Ok(true)
+}
+
+fn trace(args: &Args) -> Result<bool, String> {
+    let (mut nes, symbols) = power_on(args)?;
+    if let Some(symbols) = symbols {
+        nes.set_symbols(symbols);
+    }
+    let mut out = BufWriter::new(stdout());
+    nes.start_trace(move |line| {
+        // a closed pipe, as from `| head`, ends the output but not the run
+        let _ = writeln!(out, "{}", line);
+    });
+    nes.run_frames(args.frames.unwrap_or(1));
+    // dropping the tracer flushes what's left
+    nes.stop_trace();
+    Ok(true)
+}
+
+fn check_roms(args: &Args) -> Result<bool, String> {
+    if args.paths.is_empty() {
+        usage();
+    }
+    let mut all_load = true;
+    for (i, path) in args.paths.iter().enumerate() {
+        if i > 0 {
+            println!();
+        }
+        println!("{}", path.display());
+        let raw = fs::read(path).map_err(|err| format!("can't read {}: {}", path.display(), err))?;
+        all_load &= check_rom(&raw);
+    }
+    Ok(all_load)
+}
+
+// prints what the header says, which is there even for boards the
+// emulator doesn't have, then whether it loads
+fn check_rom(raw: &[u8]) -> bool {
+    if raw.len() >= 16 && raw[0..4] == *b"NES\x1a" {
+        let nes2 = raw[7] & 0b1100 == 0b1000;
+        println!("  format     {}", if nes2 { "NES 2.0" } else { "iNES" });
+        println!("  mapper     {}", (raw[7] & 0b1111_0000) | (raw[6] >> 4));
+        println!("  PRG ROM    {} KB", raw[4] as usize * 16);
+        println!("  CHR ROM    {}", if raw[5] == 0 { "none, 8 KB of CHR RAM".to_string() } else { format!("{} KB", raw[5] as usize * 8) });
+        println!("  trainer    {}", yes_no(raw[6] & 0b100 != 0));
+    }
+    let cartridge = match Cartridge::new(raw) {
+        Ok(cartridge) => cartridge,
+        Err(err) => {
+            println!("  loads      no, {}", err);
+            return false;
+        }
+    };
+    let mirroring = match cartridge.mirroring {
+        Mirroring::Vertical => "vertical",
+        Mirroring::Horizontal => "horizontal",
+        Mirroring::FourScreen => "four screen",
+    };
+    println!("  mirroring  {}", mirroring);
+    println!("  battery    {}", yes_no(cartridge.battery));
+    let region = match cartridge.region {
+        Some(Region::Ntsc) => "NTSC",
+        Some(Region::Pal) => "PAL",
+        Some(Region::Dendy) => "Dendy",
+        None => "any",
+    };
+    println!("  region     {}", region);
+    println!("  CRC32      {:08X}", cartridge.crc32());
+    println!("  loads      yes");
+    true
+}
+
+fn yes_no(flag: bool) -> &'static str {
+    if flag {
+        "yes"
+    } else {
+        "no"
+    }
+}
+
+fn test(args: &Args) -> Result<bool, String> {
+    let mut roms = Vec::new();
+    for path in &args.paths {
+        if path.is_dir() {
+            let entries = fs::read_dir(path).map_err(|err| format!("can't read {}: {}", path.display(), err))?;
+            let mut found: Vec<PathBuf> = entries.filter_map(|entry| Some(entry.ok()?.path())).filter(|path| path.extension().is_some_and(|ext| ext == "nes")).collect();
+            found.sort();
+            roms.extend(found);
+        } else {
+            roms.push(path.clone());
+        }
+    }
+    if roms.is_empty() {
+        usage();
+    }
+
+    let frames = args.frames.unwrap_or(TEST_FRAMES);
+    let mut failed = 0;
+    for path in &roms {
+        let outcome = Cartridge::load(path).and_then(|cartridge| blargg::check(cartridge, frames));
+        match outcome {
+            Ok(()) => println!("pass  {}", path.display()),
+            Err(err) => {
+                failed += 1;
+                println!("FAIL  {}: {}", path.display(), err.trim_end().replace('\n', " / "));
+            }
+        }
+    }
+    println!("{} passed, {} failed", roms.len() - failed, failed);
+    Ok(failed == 0)
+}
@@ -0,0 +1,186 @@
+use alloc::vec;
+use alloc::vec::Vec;
+use core::f64::consts::PI;
+use crate::math;
+
+// taps of the band-limited step, and how finely its position within a sample is resolved
+const KERNEL_WIDTH: usize = 16;
+const KERNEL_PHASES: usize = 64;
+// fraction of the Nyquist frequency that is let through
+const CUTOFF: f64 = 0.9;
+
+/// Resamples a signal that changes at a high clock rate into output
+/// samples without aliasing, blip_buf style: every change in level is
+/// spread out as a windowed sinc over the neighbouring samples, and the
+/// output is the running sum of those.
+///
+/// Output lags the input by half the kernel width, 8 samples.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BlipBuffer {
+    kernels: Vec<[f32; KERNEL_WIDTH]>,
+    samples_per_clock: f64,
+    // position of the current clock in output samples, counted from deltas[0]
+    time: f64,
+    deltas: Vec<f32>,
+    integrator: f32,
+    level: f32,
+}
+
+impl BlipBuffer {
+    pub fn new(clock_rate: f64, sample_rate: f64) -> Self {
+        let kernels = (0..KERNEL_PHASES)
+            .map(|phase| {
+                let offset = phase as f64 / KERNEL_PHASES as f64;
+                let mut kernel = [0.0; KERNEL_WIDTH];
+                let mut taps = [0.0f64; KERNEL_WIDTH];
+                for (k, tap) in taps.iter_mut().enumerate() {
+                    let t = k as f64 - (KERNEL_WIDTH / 2) as f64 - offset;
+                    let x = PI * CUTOFF * t;
+                    let sinc = if x == 0.0 { 1.0 } else { math::sin(x) / x };
+                    // blackman window over the width of the kernel
+                    let w = (t + (KERNEL_WIDTH / 2) as f64) / KERNEL_WIDTH as f64;
+                    let window = 0.42 - 0.5 * math::cos(2.0 * PI * w) + 0.08 * math::cos(4.0 * PI * w);
+                    *tap = sinc * window;
+                }
+                let sum: f64 = taps.iter().sum();
+                for (out, tap) in kernel.iter_mut().zip(taps.iter()) {
+                    *out = (tap / sum) as f32;
+                }
+                kernel
+            })
+            .collect();
+
+        BlipBuffer {
+            kernels,
+            samples_per_clock: sample_rate / clock_rate,
+            time: 0.0,
+            deltas: vec![0.0; KERNEL_WIDTH],
+            integrator: 0.0,
+            level: 0.0,
+        }
+    }
+
+    pub fn set_rates(&mut self, clock_rate: f64, sample_rate: f64) {
+        self.samples_per_clock = sample_rate / clock_rate;
+    }
+
+    /// Changes the input level at the current clock.
+    pub fn set_level(&mut self, level: f32) {
+        let delta = level - self.level;
+        if delta == 0.0 {
+            return;
+        }
+        self.level = level;
+
+        let start = self.time as usize;
+        let phase = ((self.time - start as f64) * KERNEL_PHASES as f64) as usize;
+        if self.deltas.len() < start + KERNEL_WIDTH {
+            self.deltas.resize(start + KERNEL_WIDTH, 0.0);
+        }
+        for (out, tap) in self.deltas[start..].iter_mut().zip(self.kernels[phase].iter()) {
+            *out += delta * tap;
+        }
+    }
+
+    pub fn clock(&mut self, clocks: u32) {
+        self.time += clocks as f64 * self.samples_per_clock;
+    }
+
+    /// Samples that are complete and can be read.
+    pub fn available(&self) -> usize {
+        self.time as usize
+    }
+
+    /// Moves the complete samples into `out`.
+    pub fn read_samples(&mut self, out: &mut Vec<f32>) {
+        let count = self.available();
+        if self.deltas.len() < count {
+            self.deltas.resize(count, 0.0);
+        }
+        for delta in self.deltas.drain(..count) {
+            self.integrator += delta;
+            out.push(self.integrator);
+        }
+        self.time -= count as f64;
+    }
+}
+
+/// High-pass filter for the APU's output, which sits well above zero:
+/// played as is, speakers pop when it starts and stops.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DcBlocker {
+    last_in: f32,
+    last_out: f32,
+}
+
+impl DcBlocker {
+    pub fn filter(&mut self, sample: f32) -> f32 {
+        self.last_out = sample - self.last_in + 0.995 * self.last_out;
+        self.last_in = sample;
+        self.last_out
+    }
+
+    pub fn filter_slice(&mut self, samples: &mut [f32]) {
+        for sample in samples {
+            *sample = self.filter(*sample);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_constant_level() {
+        let mut blip = BlipBuffer::new(1000.0, 100.0);
+        blip.set_level(0.5);
+        blip.clock(1000);
+        let mut out = Vec::new();
+        blip.read_samples(&mut out);
+        assert_eq!(out.len(), 100);
+        assert!(out[20..].iter().all(|s| (s - 0.5).abs() < 0.001));
+    }
+
+    #[test]
+    fn test_step_is_smoothed() {
+        let mut blip = BlipBuffer::new(1000.0, 100.0);
+        blip.clock(105);
+        blip.set_level(1.0);
+        blip.clock(895);
+        let mut out = Vec::new();
+        blip.read_samples(&mut out);
+        // the step lands 8 samples late and spreads over a few samples either side
+        assert!(out[..12].iter().all(|s| s.abs() < 0.05));
+        assert!(out[18] > 0.2 && out[18] < 0.8);
+        assert!(out[30..].iter().all(|s| (s - 1.0).abs() < 0.001));
+    }
+
+    #[test]
+    fn test_frequencies_above_nyquist_are_removed() {
+        // a square wave at 0.83 of the sample rate would alias to a loud tone
+        let mut blip = BlipBuffer::new(100_000.0, 10_000.0);
+        for i in 0..100_000 {
+            blip.set_level(if (i / 6) % 2 == 0 { 1.0 } else { 0.0 });
+            blip.clock(1);
+        }
+        let mut out = Vec::new();
+        blip.read_samples(&mut out);
+        let middle = &out[100..9900];
+        let max = middle.iter().cloned().fold(f32::MIN, f32::max);
+        let min = middle.iter().cloned().fold(f32::MAX, f32::min);
+        assert!(max - min < 0.1, "{} to {}", min, max);
+    }
+
+    #[test]
+    fn test_dc_blocker() {
+        let mut dc = DcBlocker::default();
+        let mut samples = vec![0.5; 2000];
+        dc.filter_slice(&mut samples);
+        assert_eq!(samples[0], 0.5);
+        assert!(samples[1999].abs() < 0.001);
+        // changes still get through
+        assert!(dc.filter(1.0) > 0.49);
+    }
+}
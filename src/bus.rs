@@ -0,0 +1,815 @@
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use crate::apu::APU;
+use crate::cartridge::Cartridge;
+use crate::cheats::Cheats;
+use crate::clock::Clock;
+use crate::debugger::{Access, Attached};
+use crate::events::{ConsoleEvent, Subscribers};
+use crate::hooks::Hooks;
+use crate::input::four_score::FourScore;
+use crate::input::keyboard::Keyboard;
+use crate::input::microphone::Microphone;
+use crate::bytes;
+use crate::input::paddle::Paddle;
+use crate::input::power_pad::PowerPad;
+use crate::input::zapper::Zapper;
+use crate::input::{ExpansionDevice, InputConfig, PortDevice};
+use crate::joypad::Joypad;
+use crate::ppu::events::PpuEventKind;
+use crate::ppu::registers::STATUS_VBLANK;
+use crate::ppu::PPU;
+use crate::region::Region;
+use crate::rng::Rng;
+
+//  _______________ $10000  _______________
+// | PRG-ROM       |       |               |
+// | Upper Bank    |       |               |
+// |_ _ _ _ _ _ _ _| $C000 | PRG-ROM       |
+// | PRG-ROM       |       |               |
+// | Lower Bank    |       |               |
+// |_______________| $8000 |_______________|
+// | SRAM          |       | SRAM          |
+// |_______________| $6000 |_______________|
+// | Expansion ROM |       | Expansion ROM |
+// |_______________| $4020 |_______________|
+// | I/O Registers |       |               |
+// |_ _ _ _ _ _ _ _| $4000 |               |
+// | Mirrors       |       | I/O Registers |
+// | $2000-$2007   |       |               |
+// |_ _ _ _ _ _ _ _| $2008 |               |
+// | I/O Registers |       |               |
+// |_______________| $2000 |_______________|
+// | Mirrors       |       |               |
+// | $0000-$07FF   |       |               |
+// |_ _ _ _ _ _ _ _| $0800 |               |
+// | RAM           |       | RAM           |
+// |_ _ _ _ _ _ _ _| $0200 |               |
+// | Stack         |       |               |
+// |_ _ _ _ _ _ _ _| $0100 |               |
+// | Zero Page     |       |               |
+// |_______________| $0000 |_______________|
+
+const RAM: u16 = 0x0000;
+const RAM_MIRRORS_END: u16 = 0x1fff;
+const PPU_REGISTERS: u16 = 0x2000;
+const PPU_REGISTERS_MIRRORS_END: u16 = 0x3fff;
+
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Bus {
+    #[cfg_attr(feature = "serde", serde(with = "serde_big_array::BigArray"))]
+    cpu_vram: [u8; 2048],
+    #[cfg_attr(feature = "serde", serde(with = "serde_big_array::BigArray"))]
+    prg_ram: [u8; 0x2000],
+    prg_rom: Vec<u8>,
+    // without a cartridge the PRG area is plain RAM that programs can be loaded into
+    prg_writable: bool,
+    // the cartridge keeps PRG RAM alive with a battery
+    battery: bool,
+    pub ppu: PPU,
+    pub apu: APU,
+    pub joypad1: Joypad,
+    pub joypad2: Joypad,
+    // only plugged in through a Four Score
+    pub joypad3: Joypad,
+    pub joypad4: Joypad,
+    pub zapper: Zapper,
+    pub paddle: Paddle,
+    pub power_pad: PowerPad,
+    pub keyboard: Keyboard,
+    pub microphone: Microphone,
+    input: InputConfig,
+    expansion: ExpansionDevice,
+    four_score: FourScore,
+    // a controller port was read, frames without a read are lag frames
+    input_polled: bool,
+    // the same, since the start of the current frame
+    frame_polled: bool,
+    region: Region,
+    // last value seen on the CPU data bus, which is what unmapped reads return
+    open_bus: u8,
+    clock: Clock,
+    // where power-on RAM contents and chip phases come from, zeroed without one
+    seed: Option<u64>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub(crate) subscribers: Subscribers,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub(crate) debugger: Attached,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub(crate) hooks: Hooks,
+    // a frontend setting rather than console state, so states don't carry them
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub(crate) cheats: Cheats,
+}
+
+impl Default for Bus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Bus {
+    pub fn new() -> Self {
+        Bus {
+            cpu_vram: [0; 2048],
+            prg_ram: [0; 0x2000],
+            prg_rom: vec![0; 0x8000],
+            prg_writable: true,
+            battery: false,
+            ppu: PPU::new_empty_rom(),
+            apu: APU::new(),
+            joypad1: Joypad::new(),
+            joypad2: Joypad::new(),
+            joypad3: Joypad::new(),
+            joypad4: Joypad::new(),
+            zapper: Zapper::new(),
+            paddle: Paddle::new(),
+            power_pad: PowerPad::new(),
+            keyboard: Keyboard::new(),
+            microphone: Microphone::new(),
+            input: InputConfig::default(),
+            expansion: ExpansionDevice::default(),
+            four_score: FourScore::new(),
+            input_polled: false,
+            frame_polled: false,
+            region: Region::Ntsc,
+            open_bus: 0,
+            clock: Clock::new(Region::Ntsc),
+            seed: None,
+            subscribers: Subscribers::default(),
+            debugger: Attached::default(),
+            hooks: Hooks::default(),
+            cheats: Cheats::default(),
+        }
+    }
+
+    /// Maps a game's PRG ROM into $8000-$FFFF and its CHR into the PPU.
+    pub fn insert_cartridge(&mut self, cartridge: Cartridge) {
+        // NROM never switches banks, so this is all there is to say about the mapper
+        diag!(info, target: "nessie::cartridge", mapper = cartridge.mapper, prg_rom = cartridge.prg_rom.len(), chr_rom = cartridge.chr_rom.len(), chr_ram = cartridge.chr_ram, mirroring = ?cartridge.mirroring, battery = cartridge.battery, "cartridge inserted");
+        self.prg_rom = cartridge.prg_rom;
+        self.prg_writable = false;
+        self.prg_ram = [0; 0x2000];
+        self.battery = cartridge.battery;
+        self.ppu.chr_rom = cartridge.chr_rom;
+        self.ppu.chr_ram = cartridge.chr_ram;
+        self.ppu.mirroring = cartridge.mirroring;
+    }
+
+    /// Clears RAM and brings the PPU and APU back to their power-on state.
+    /// The cartridge, PRG RAM, plugged in devices and region stay.
+    pub fn power_cycle(&mut self) {
+        self.cpu_vram = [0; 2048];
+        self.open_bus = 0;
+        self.ppu.power_cycle();
+        self.apu.power_cycle(self.region);
+        if let Some(seed) = self.seed {
+            let mut rng = Rng::new(seed);
+            rng.fill(&mut self.cpu_vram);
+            self.open_bus = rng.next_u64() as u8;
+            self.ppu.randomize(&mut rng);
+            self.apu.set_phase(rng.below(16) as u32);
+            self.clock.set_phase(rng.next_u64());
+        }
+    }
+
+    /// Moves RAM, ROM and the PPU's memory out into `out`, for keeping them
+    /// compressed.
+    pub(crate) fn take_memory(&mut self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.cpu_vram);
+        out.extend_from_slice(&self.prg_ram);
+        bytes::write_block(out, &core::mem::take(&mut self.prg_rom));
+        self.ppu.take_memory(out);
+    }
+
+    /// Puts back what `take_memory` took.
+    pub(crate) fn put_memory(&mut self, data: &mut &[u8]) {
+        bytes::read_into(data, &mut self.cpu_vram);
+        bytes::read_into(data, &mut self.prg_ram);
+        self.prg_rom = bytes::read_block(data).to_vec();
+        self.ppu.put_memory(data);
+    }
+
+    /// Writes what the RAM cheats that are on and frozen addresses hold RAM at.
+    pub(crate) fn apply_ram_cheats(&mut self) {
+        for cheat in self.cheats.ram_writes() {
+            let byte = match cheat.addr {
+                0x0000..=0x1fff => &mut self.cpu_vram[(cheat.addr & 0x07ff) as usize],
+                0x6000..=0x7fff => &mut self.prg_ram[(cheat.addr - 0x6000) as usize],
+                _ => continue,
+            };
+            if cheat.compare.is_none_or(|compare| compare == *byte) {
+                *byte = cheat.value;
+            }
+        }
+        // a freeze also puts back what was there before it was set
+        for (&addr, &value) in self.cheats.frozen() {
+            match addr {
+                0x0000..=0x07ff => self.cpu_vram[addr as usize] = value,
+                _ => self.prg_ram[(addr - 0x6000) as usize] = value,
+            }
+        }
+    }
+
+    /// The 2KB of internal RAM at $0000-$07FF.
+    pub fn ram(&self) -> &[u8] {
+        &self.cpu_vram
+    }
+
+    /// Internal RAM for frontends that read and write it in place, like a
+    /// libretro core's.
+    pub fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.cpu_vram
+    }
+
+    /// PRG RAM at $6000-$7FFF, which is what a battery keeps.
+    pub fn prg_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    pub fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.prg_ram
+    }
+
+    /// Fills PRG RAM from a plain .sav file, the raw 8KB other emulators
+    /// like FCEUX and Mesen use. A shorter file fills the start of it.
+    pub fn load_prg_ram(&mut self, data: &[u8]) -> Result<(), String> {
+        if data.len() > self.prg_ram.len() {
+            return Err(format!("save is {} bytes, PRG RAM only holds {}", data.len(), self.prg_ram.len()));
+        }
+        self.prg_ram[..data.len()].copy_from_slice(data);
+        Ok(())
+    }
+
+    /// The cartridge has battery-backed PRG RAM, so its contents are worth saving.
+    pub fn has_battery(&self) -> bool {
+        self.battery
+    }
+
+    pub fn seed(&self) -> Option<u64> {
+        self.seed
+    }
+
+    /// Powers up with RAM contents, open bus and APU/PPU phases made up from
+    /// `seed`, the way hardware comes up differently every time, yet the same
+    /// on every run. `None` powers up with everything zeroed. Either way
+    /// nothing depends on the host, so the same inputs give the same state.
+    /// Takes effect at the next power cycle.
+    pub fn set_seed(&mut self, seed: Option<u64>) {
+        self.seed = seed;
+    }
+
+    pub fn region(&self) -> Region {
+        self.region
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+        self.clock.set_region(region);
+        self.ppu.set_region(region);
+        self.apu.set_region(region);
+    }
+
+    pub fn input_config(&self) -> InputConfig {
+        self.input
+    }
+
+    pub fn set_input_config(&mut self, config: InputConfig) {
+        self.input = config;
+    }
+
+    pub fn expansion_device(&self) -> ExpansionDevice {
+        self.expansion
+    }
+
+    pub fn set_expansion_device(&mut self, device: ExpansionDevice) {
+        self.expansion = device;
+    }
+
+    /// Controller of player 0 to 3.
+    pub fn joypad_mut(&mut self, player: usize) -> Option<&mut Joypad> {
+        match player {
+            0 => Some(&mut self.joypad1),
+            1 => Some(&mut self.joypad2),
+            2 => Some(&mut self.joypad3),
+            3 => Some(&mut self.joypad4),
+            _ => None,
+        }
+    }
+
+    /// CPU cycles elapsed since power on.
+    pub fn cycles(&self) -> usize {
+        self.clock.cpu_cycles() as usize
+    }
+
+    pub fn clock(&self) -> &Clock {
+        &self.clock
+    }
+
+    /// Advances the rest of the system by the given number of CPU cycles,
+    /// one at a time: the PPU runs the dots the master clock has due, three
+    /// per cycle or 3.2 on PAL, and the APU follows the CPU.
+    pub fn tick(&mut self, cycles: u16) {
+        let frame = self.ppu.frame().number();
+        for _ in 0..cycles {
+            let dots = self.clock.advance_cpu(1);
+            // overclock scanlines are CPU time only, audio keeps its pace
+            let overclocking = self.ppu.is_overclocking();
+            if self.subscribers.is_empty() {
+                self.ppu.tick(dots as u16);
+                if !overclocking {
+                    self.apu.tick(1);
+                }
+            } else {
+                self.tick_with_events(dots, overclocking);
+            }
+        }
+        if self.ppu.frame().number() != frame {
+            for joypad in [&mut self.joypad1, &mut self.joypad2, &mut self.joypad3, &mut self.joypad4] {
+                joypad.end_frame();
+            }
+            self.frame_polled = false;
+        }
+
+        if let Some(addr) = self.apu.dmc.pending_fetch() {
+            let data = self.mem_read(addr);
+            self.apu.dmc.load_sample(data);
+            // the CPU is halted while the DMC reads
+            self.tick(4);
+        }
+    }
+
+    // one CPU cycle of ticking, telling subscribers about what changed
+    fn tick_with_events(&mut self, dots: u32, overclocking: bool) {
+        let frame = self.ppu.frame().number();
+        let vblank = self.ppu.status & STATUS_VBLANK != 0;
+        let frame_irq = self.apu.frame_irq();
+        self.ppu.tick(dots as u16);
+        if !overclocking {
+            self.apu.tick(1);
+        }
+        if !vblank && self.ppu.status & STATUS_VBLANK != 0 {
+            self.subscribers.emit(ConsoleEvent::VblankStart);
+        }
+        if !frame_irq && self.apu.frame_irq() {
+            self.subscribers.emit(ConsoleEvent::ApuFrameIrq);
+        }
+        let number = self.ppu.frame().number();
+        if number != frame {
+            self.subscribers.emit(ConsoleEvent::FrameComplete(number));
+            if !self.frame_polled {
+                self.subscribers.emit(ConsoleEvent::LagFrame(number));
+            }
+        }
+    }
+
+    pub fn poll_nmi_status(&mut self) -> bool {
+        self.ppu.poll_nmi()
+    }
+
+    pub fn irq_pending(&self) -> bool {
+        self.apu.irq_pending()
+    }
+
+    /// Whether a controller port has been read since the last call.
+    pub fn take_input_polled(&mut self) -> bool {
+        core::mem::take(&mut self.input_polled)
+    }
+
+    /// The 8K PRG ROM bank mapped at `addr`, if ROM is there.
+    pub fn prg_bank(&self, addr: u16) -> Option<u16> {
+        if addr < 0x8000 || self.prg_writable {
+            return None;
+        }
+        let mut offset = addr as usize - 0x8000;
+        if self.prg_rom.len() == 0x4000 {
+            offset %= 0x4000;
+        }
+        Some((offset / 0x2000) as u16)
+    }
+
+    fn read_prg_rom(&self, addr: u16) -> u8 {
+        let mut offset = addr as usize - 0x8000;
+        if self.prg_rom.len() == 0x4000 {
+            // NROM-128 mirrors its only bank
+            offset %= 0x4000;
+        }
+        let value = self.prg_rom[offset];
+        if self.cheats.is_empty() {
+            return value;
+        }
+        // codes patch a CPU address, like the real thing on the cartridge bus
+        self.cheats.patch(addr, value)
+    }
+
+    // low bits of $4016 or $4017, from whatever is plugged into that port
+    fn read_port(&mut self, port: usize) -> u8 {
+        self.input_polled = true;
+        self.frame_polled = true;
+        let device = match self.input {
+            InputConfig::FourScore => return self.four_score.read(port),
+            InputConfig::Ports(first, second) => if port == 0 { first } else { second },
+        };
+        match device {
+            PortDevice::Empty => 0,
+            PortDevice::Joypad => if port == 0 { self.joypad1.read() } else { self.joypad2.read() },
+            PortDevice::Zapper => {
+                let light = self.zapper.aimed_at().is_some_and(|(x, y)| self.ppu.light_at(x, y));
+                self.zapper.read(light)
+            }
+            PortDevice::ArkanoidPaddle => self.paddle.read(),
+            PortDevice::PowerPad => self.power_pad.read(),
+        }
+    }
+
+    // bits the expansion port device drives on $4016 or $4017
+    fn read_expansion(&mut self, port: usize) -> u8 {
+        // the Famicom's second controller has its microphone wired to $4016
+        let mic = if port == 0 { self.microphone.read() } else { 0 };
+        mic | match (self.expansion, port) {
+            (ExpansionDevice::FamilyTrainer, 1) => self.power_pad.read_family_trainer(),
+            (ExpansionDevice::FamilyKeyboard, 1) => self.keyboard.read(),
+            _ => 0,
+        }
+    }
+
+    fn write_ports(&mut self, data: u8) {
+        self.joypad1.write(data);
+        self.joypad2.write(data);
+        self.paddle.write(data);
+        self.power_pad.write(data);
+        self.keyboard.write(data);
+        let buttons = [self.joypad1.report(), self.joypad2.report(), self.joypad3.report(), self.joypad4.report()];
+        self.four_score.write(data, buttons);
+    }
+
+    fn oam_dma(&mut self, page: u8) {
+        let mut buffer = [0u8; 256];
+        let hi = (page as u16) << 8;
+        for (i, byte) in buffer.iter_mut().enumerate() {
+            *byte = self.mem_read(hi + i as u16);
+        }
+        self.ppu.write_oam_dma(&buffer);
+        // the CPU is halted while the transfer runs, one extra cycle on odd cycles
+        let stall = if self.clock.cpu_cycles() % 2 == 1 { 514 } else { 513 };
+        self.tick(stall);
+    }
+
+    /// Reads memory without side effects, for debuggers and trace logs.
+    /// Registers can't be read that way and come back as $FF.
+    pub fn peek(&self, addr: u16) -> u8 {
+        match addr {
+            RAM..=RAM_MIRRORS_END => self.cpu_vram[(addr & 0b0000_0111_1111_1111) as usize],
+            0x6000..=0x7fff => self.prg_ram[(addr - 0x6000) as usize],
+            0x8000..=0xffff => self.read_prg_rom(addr),
+            _ => 0xff,
+        }
+    }
+
+    pub fn mem_read(&mut self, addr: u16) -> u8 {
+        let mut data = self.read(addr);
+        if self.hooks.has_memory() {
+            data = self.hooks.on_access(addr, data, Access::Read);
+        }
+        if let Some(debugger) = &mut self.debugger.0 {
+            debugger.on_access(addr, data, Access::Read);
+        }
+        data
+    }
+
+    fn read(&mut self, addr: u16) -> u8 {
+        let data = match addr {
+            RAM..=RAM_MIRRORS_END => {
+                let mirror_down_addr = addr & 0b0000_0111_1111_1111;
+                self.cpu_vram[mirror_down_addr as usize]
+            }
+            PPU_REGISTERS..=PPU_REGISTERS_MIRRORS_END => {
+                match addr & 0x2007 {
+                    0x2002 => self.ppu.read_status(),
+                    0x2004 => self.ppu.read_oam_data(),
+                    0x2007 => self.ppu.read_data(),
+                    // write-only registers
+                    _ => self.ppu.open_bus(),
+                }
+            }
+            // read inside the CPU, so bit 5 isn't driven and the bus keeps its value
+            0x4015 => return self.apu.read_status() | (self.open_bus & 0b0010_0000),
+            // controllers only drive the low bits
+            0x4016 => self.read_port(0) | self.read_expansion(0) | (self.open_bus & 0b1110_0000),
+            0x4017 => self.read_port(1) | self.read_expansion(1) | (self.open_bus & 0b1110_0000),
+            0x6000..=0x7fff => self.prg_ram[(addr - 0x6000) as usize],
+            0x8000..=0xffff => self.read_prg_rom(addr),
+            _ => self.open_bus,
+        };
+        self.open_bus = data;
+        data
+    }
+
+    pub fn mem_write(&mut self, addr: u16, mut data: u8) {
+        if self.hooks.has_memory() {
+            data = self.hooks.on_access(addr, data, Access::Write);
+        }
+        if let Some(debugger) = &mut self.debugger.0 {
+            debugger.on_access(addr, data, Access::Write);
+        }
+        self.open_bus = data;
+        if self.ppu.events.is_some() && (0x2000..=0x4017).contains(&addr) {
+            let addr = if addr < 0x4000 { 0x2000 | (addr & 7) } else { addr };
+            self.ppu.record_event(PpuEventKind::RegisterWrite { addr, value: data });
+        }
+        match addr {
+            RAM..=RAM_MIRRORS_END => {
+                let mirror_down_addr = addr & 0b0000_0111_1111_1111;
+                self.cpu_vram[mirror_down_addr as usize] = self.cheats.write(mirror_down_addr, data);
+            }
+            PPU_REGISTERS..=PPU_REGISTERS_MIRRORS_END => {
+                diag!(trace, target: "nessie::ppu", register = format_args!("${:04X}", 0x2000 | (addr & 7)), value = format_args!("${:02X}", data), scanline = self.ppu.scanline(), dot = self.ppu.dot(), "register write");
+                self.ppu.latch_open_bus(data);
+                match addr & 0x2007 {
+                    0x2000 => self.ppu.write_to_ctrl(data),
+                    0x2001 => self.ppu.write_to_mask(data),
+                    0x2003 => self.ppu.write_to_oam_addr(data),
+                    0x2004 => self.ppu.write_to_oam_data(data),
+                    0x2005 => self.ppu.write_to_scroll(data),
+                    0x2006 => self.ppu.write_to_ppu_addr(data),
+                    0x2007 => self.ppu.write_to_data(data),
+                    // PPUSTATUS is read-only
+                    _ => {}
+                }
+            }
+            0x4000..=0x4013 | 0x4015 | 0x4017 => self.apu.write_register(addr, data),
+            0x4014 => self.oam_dma(data),
+            // one strobe line goes to both ports, $4017 writes are the APU's
+            0x4016 => self.write_ports(data),
+            0x6000..=0x7fff => self.prg_ram[(addr - 0x6000) as usize] = self.cheats.write(addr, data),
+            0x8000..=0xffff if self.prg_writable => {
+                self.prg_rom[(addr - 0x8000) as usize] = data;
+            }
+            _ => {}
+        }
+    }
+
+    pub fn mem_read_u16(&mut self, pos: u16) -> u16 {
+        let lo = self.mem_read(pos) as u16;
+        let hi = self.mem_read(pos.wrapping_add(1)) as u16;
+        (hi << 8) | lo
+    }
+
+    pub fn mem_write_u16(&mut self, pos: u16, data: u16) {
+        let hi = (data >> 8) as u8;
+        let lo = (data & 0xff) as u8;
+        self.mem_write(pos, lo);
+        self.mem_write(pos.wrapping_add(1), hi);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::test_rom;
+    use crate::input::keyboard::Key;
+
+    #[test]
+    fn test_ram_is_mirrored() {
+        let mut bus = Bus::new();
+        bus.mem_write(0x0001, 0x55);
+        assert_eq!(bus.mem_read(0x0801), 0x55);
+        assert_eq!(bus.mem_read(0x1801), 0x55);
+    }
+
+    #[test]
+    fn test_ppu_registers_are_mirrored() {
+        let mut bus = Bus::new();
+        bus.mem_write(0x3ffe, 0x21);
+        bus.mem_write(0x3ffe, 0x00);
+        bus.mem_write(0x2007, 0x66);
+        assert_eq!(bus.ppu.vram[0x0100], 0x66);
+    }
+
+    #[test]
+    fn test_pattern_table_writes_need_chr_ram() {
+        let write_pattern = |bus: &mut Bus| {
+            bus.mem_write(0x2006, 0x00);
+            bus.mem_write(0x2006, 0x10);
+            bus.mem_write(0x2007, 0x5a);
+        };
+
+        let mut bus = Bus::new();
+        bus.insert_cartridge(Cartridge::new(&test_rom(&[0; 0x4000])).unwrap());
+        write_pattern(&mut bus);
+        assert_eq!(bus.ppu.chr_rom[0x10], 0);
+
+        let mut bus = Bus::new();
+        bus.insert_cartridge(Cartridge::from_prg(&[0; 0x4000]).unwrap());
+        write_pattern(&mut bus);
+        assert_eq!(bus.ppu.chr_rom[0x10], 0x5a);
+    }
+
+    #[test]
+    fn test_write_only_ppu_registers_read_last_written_value() {
+        let mut bus = Bus::new();
+        bus.mem_write(0x2003, 0x3c);
+        assert_eq!(bus.mem_read(0x2000), 0x3c);
+        assert_eq!(bus.mem_read(0x2005), 0x3c);
+    }
+
+    #[test]
+    fn test_tick_runs_three_dots_per_cycle() {
+        let mut bus = Bus::new();
+        bus.tick(100);
+        assert_eq!(bus.cycles(), 100);
+        assert_eq!(bus.ppu.dot(), 300);
+        bus.tick(14);
+        assert_eq!(bus.ppu.scanline(), 1);
+        assert_eq!(bus.ppu.dot(), 1);
+    }
+
+    #[test]
+    fn test_pal_runs_sixteen_dots_per_five_cycles() {
+        let mut bus = Bus::new();
+        bus.set_region(Region::Pal);
+        bus.tick(1);
+        assert_eq!(bus.ppu.dot(), 3);
+        bus.tick(4);
+        assert_eq!(bus.ppu.dot(), 16);
+        bus.tick(100);
+        assert_eq!(bus.ppu.dot(), 336);
+    }
+
+    #[test]
+    fn test_long_tick_runs_a_whole_frame() {
+        let mut bus = Bus::new();
+        bus.tick(30_000);
+        assert_eq!(bus.ppu.frame().number(), 1);
+        assert_eq!(bus.clock().ppu_dots(), 90_000);
+    }
+
+    #[test]
+    fn test_dmc_fetch_stalls_cpu() {
+        let mut bus = Bus::new();
+        bus.mem_write(0xc000, 0xaa);
+        bus.mem_write(0x4013, 0x00);
+        bus.mem_write(0x4015, 0b1_0000);
+        bus.tick(1);
+        assert_eq!(bus.apu.dmc.pending_fetch(), None);
+        assert_eq!(bus.cycles(), 5);
+        assert!(!bus.apu.dmc.active());
+    }
+
+    #[test]
+    fn test_apu_status_register() {
+        let mut bus = Bus::new();
+        bus.mem_write(0x4017, 0b0100_0000);
+        bus.mem_write(0x4015, 0b0_0101);
+        bus.mem_write(0x4003, 0b0000_1000);
+        bus.mem_write(0x4007, 0b0000_1000);
+        bus.mem_write(0x400b, 0b0000_1000);
+        // pulse 2 is disabled, and bit 5 comes from the last value on the bus
+        bus.mem_write(0x0000, 0xff);
+        assert_eq!(bus.mem_read(0x4015), 0b0010_0101);
+        bus.mem_write(0x0000, 0x00);
+        assert_eq!(bus.mem_read(0x4015), 0b0000_0101);
+
+        bus.mem_write(0x4015, 0b0_0100);
+        assert_eq!(bus.mem_read(0x4015), 0b0000_0100);
+    }
+
+    #[test]
+    fn test_unmapped_reads_return_open_bus() {
+        let mut bus = Bus::new();
+        bus.mem_write(0x0000, 0x5a);
+        bus.mem_read(0x0000);
+        assert_eq!(bus.mem_read(0x5000), 0x5a);
+        assert_eq!(bus.mem_read(0x4000), 0x5a);
+    }
+
+    #[test]
+    fn test_joypad_reads() {
+        let mut bus = Bus::new();
+        bus.joypad1.set_button(crate::joypad::Button::B, true);
+        bus.mem_write(0x4016, 1);
+        bus.mem_write(0x4016, 0);
+        // the upper bits are left over from the high byte of the address
+        bus.open_bus = 0x40;
+        assert_eq!(bus.mem_read(0x4016), 0x40);
+        assert_eq!(bus.mem_read(0x4016), 0x41);
+    }
+
+    #[test]
+    fn test_two_joypads() {
+        let mut bus = Bus::new();
+        bus.joypad1.set_button(crate::joypad::Button::A, true);
+        bus.joypad2.set_button(crate::joypad::Button::B, true);
+        bus.mem_write(0x4016, 1);
+        bus.mem_write(0x4016, 0);
+        let one: Vec<u8> = (0..2).map(|_| bus.mem_read(0x4016) & 1).collect();
+        let two: Vec<u8> = (0..2).map(|_| bus.mem_read(0x4017) & 1).collect();
+        assert_eq!(one, vec![1, 0]);
+        assert_eq!(two, vec![0, 1]);
+
+        // $4017 writes still reach the frame counter
+        bus.mem_write(0x4017, 0b0100_0000);
+        bus.tick(30_000);
+        assert!(!bus.irq_pending());
+    }
+
+    #[test]
+    fn test_four_score() {
+        let mut bus = Bus::new();
+        bus.set_input_config(InputConfig::FourScore);
+        bus.joypad3.set_button(crate::joypad::Button::Start, true);
+        bus.mem_write(0x4016, 1);
+        bus.mem_write(0x4016, 0);
+        let bits: Vec<u8> = (0..24).map(|_| bus.mem_read(0x4016) & 1).collect();
+        assert_eq!(bits[8..16], [0, 0, 0, 1, 0, 0, 0, 0]);
+        assert_eq!(bits[16..24], [0, 0, 0, 0, 1, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_empty_port() {
+        let mut bus = Bus::new();
+        bus.set_input_config(InputConfig::Ports(PortDevice::Joypad, PortDevice::Empty));
+        bus.mem_write(0x4016, 1);
+        bus.mem_write(0x4016, 0);
+        assert!((0..10).all(|_| bus.mem_read(0x4017) & 1 == 0));
+    }
+
+    #[test]
+    fn test_zapper() {
+        let mut bus = Bus::new();
+        bus.set_input_config(InputConfig::Ports(PortDevice::Joypad, PortDevice::Zapper));
+        bus.ppu.palette_table[0] = 0x30;
+        bus.mem_write(0x2001, 0b0000_1000);
+        bus.zapper.aim(128, 20);
+        assert_eq!(bus.mem_read(0x4017) & 0b0001_1000, 0b0000_1000);
+
+        bus.zapper.set_trigger(true);
+        // run until the beam is a couple of lines past the aimed pixel
+        while bus.ppu.scanline() != 22 {
+            bus.tick(1);
+        }
+        assert_eq!(bus.mem_read(0x4017) & 0b0001_1000, 0b0001_0000);
+        bus.zapper.aim_offscreen();
+        assert_eq!(bus.mem_read(0x4017) & 0b0001_1000, 0b0001_1000);
+    }
+
+    #[test]
+    fn test_arkanoid_paddle() {
+        let mut bus = Bus::new();
+        bus.set_input_config(InputConfig::Ports(PortDevice::Joypad, PortDevice::ArkanoidPaddle));
+        bus.paddle.set_position(0x80);
+        bus.mem_write(0x4016, 1);
+        bus.mem_write(0x4016, 0);
+        assert_eq!(bus.mem_read(0x4017) & 0b0001_1000, 0b0000_0000);
+        assert_eq!(bus.mem_read(0x4017) & 0b0001_1000, 0b0001_0000);
+    }
+
+    #[test]
+    fn test_power_pad_and_family_trainer() {
+        let mut bus = Bus::new();
+        bus.set_input_config(InputConfig::Ports(PortDevice::Joypad, PortDevice::PowerPad));
+        bus.power_pad.set_button(2, true);
+        bus.mem_write(0x4016, 1);
+        bus.mem_write(0x4016, 0);
+        assert_eq!(bus.mem_read(0x4017) & 0b0001_1000, 0b0000_1000);
+
+        bus.set_input_config(InputConfig::Ports(PortDevice::Joypad, PortDevice::Empty));
+        bus.set_expansion_device(ExpansionDevice::FamilyTrainer);
+        bus.mem_write(0x4016, 0b110);
+        assert_eq!(bus.mem_read(0x4017) & 0b0001_1110, 0b0001_0110);
+    }
+
+    #[test]
+    fn test_family_keyboard() {
+        let mut bus = Bus::new();
+        bus.set_input_config(InputConfig::Ports(PortDevice::Joypad, PortDevice::Empty));
+        bus.set_expansion_device(ExpansionDevice::FamilyKeyboard);
+        bus.keyboard.set_key(Key::F8, true);
+        bus.mem_write(0x4016, 0b101);
+        assert_eq!(bus.mem_read(0x4017) & 0b0001_1110, 0b0000_1110);
+    }
+
+    #[test]
+    fn test_microphone() {
+        let mut bus = Bus::new();
+        assert_eq!(bus.mem_read(0x4016) & 0b100, 0);
+        bus.microphone.set_triggered(true);
+        assert_eq!(bus.mem_read(0x4016) & 0b100, 0b100);
+        assert_eq!(bus.mem_read(0x4017) & 0b100, 0);
+    }
+
+    #[test]
+    fn test_oam_dma_copies_page_and_stalls() {
+        let mut bus = Bus::new();
+        for i in 0..256u16 {
+            bus.mem_write(0x0200 + i, i as u8);
+        }
+        bus.mem_write(0x4014, 0x02);
+        assert_eq!(bus.ppu.oam_data[0x10], 0x10);
+        assert_eq!(bus.ppu.oam_data[0xff], 0xff);
+        assert_eq!(bus.cycles(), 513);
+    }
+}
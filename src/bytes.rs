@@ -0,0 +1,28 @@
+//! Reading and writing the flat byte buffers rewind snapshots and frames
+//! are packed into.
+
+use alloc::vec::Vec;
+
+/// Appends `bytes` with its length in front.
+pub(crate) fn write_block(out: &mut Vec<u8>, bytes: &[u8]) {
+    out.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+    out.extend_from_slice(bytes);
+}
+
+/// Reads back a block written by `write_block`.
+pub(crate) fn read_block<'a>(data: &mut &'a [u8]) -> &'a [u8] {
+    let len = u32::from_le_bytes(read_bytes(data, 4).try_into().unwrap()) as usize;
+    read_bytes(data, len)
+}
+
+/// Reads the next `len` bytes.
+pub(crate) fn read_bytes<'a>(data: &mut &'a [u8], len: usize) -> &'a [u8] {
+    let (bytes, rest) = data.split_at(len);
+    *data = rest;
+    bytes
+}
+
+/// Fills `bytes` from the next bytes of `data`.
+pub(crate) fn read_into(data: &mut &[u8], bytes: &mut [u8]) {
+    bytes.copy_from_slice(read_bytes(data, bytes.len()));
+}
@@ -0,0 +1,216 @@
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use crate::ppu::Mirroring;
+use crate::region::Region;
+#[cfg(feature = "std")]
+use std::fs;
+#[cfg(feature = "std")]
+use std::path::Path;
+
+const NES_TAG: [u8; 4] = [0x4e, 0x45, 0x53, 0x1a];
+const PRG_ROM_PAGE_SIZE: usize = 16384;
+const CHR_ROM_PAGE_SIZE: usize = 8192;
+
+/// A game loaded from an iNES file.
+#[derive(Debug, Clone)]
+pub struct Cartridge {
+    pub prg_rom: Vec<u8>,
+    pub chr_rom: Vec<u8>,
+    // the board has CHR RAM instead of ROM
+    pub chr_ram: bool,
+    pub mapper: u8,
+    pub mirroring: Mirroring,
+    // PRG RAM is kept alive by a battery
+    pub battery: bool,
+    // TV system the header says the game is for, if it says
+    pub region: Option<Region>,
+}
+
+impl Cartridge {
+    /// Parses an iNES or NES 2.0 image. Only NROM (mapper 0) boards are supported.
+    pub fn new(raw: &[u8]) -> Result<Cartridge, String> {
+        if raw.len() < 16 || raw[0..4] != NES_TAG {
+            return Err("not an iNES file".to_string());
+        }
+        let mapper = (raw[7] & 0b1111_0000) | (raw[6] >> 4);
+        if mapper != 0 {
+            return Err(format!("mapper {} isn't supported", mapper));
+        }
+
+        let four_screen = raw[6] & 0b1000 != 0;
+        let vertical_mirroring = raw[6] & 0b1 != 0;
+        let mirroring = match (four_screen, vertical_mirroring) {
+            (true, _) => Mirroring::FourScreen,
+            (false, true) => Mirroring::Vertical,
+            (false, false) => Mirroring::Horizontal,
+        };
+
+        let prg_rom_size = raw[4] as usize * PRG_ROM_PAGE_SIZE;
+        let chr_rom_size = raw[5] as usize * CHR_ROM_PAGE_SIZE;
+        // a 512 byte trainer sits between the header and PRG ROM
+        let skip_trainer = raw[6] & 0b100 != 0;
+        let prg_rom_start = 16 + if skip_trainer { 512 } else { 0 };
+        let chr_rom_start = prg_rom_start + prg_rom_size;
+        if prg_rom_size == 0 {
+            return Err("iNES file has no PRG ROM".to_string());
+        }
+        if raw.len() < chr_rom_start + chr_rom_size {
+            return Err(format!("iNES file is truncated, expected {} bytes, got {}", chr_rom_start + chr_rom_size, raw.len()));
+        }
+
+        let nes2 = raw[7] & 0b1100 == 0b1000;
+        let region = if nes2 {
+            match raw[12] & 0b11 {
+                0 => Some(Region::Ntsc),
+                1 => Some(Region::Pal),
+                3 => Some(Region::Dendy),
+                // runs on either
+                _ => None,
+            }
+        } else if raw[9] & 1 != 0 {
+            Some(Region::Pal)
+        } else {
+            None
+        };
+
+        let chr_ram = chr_rom_size == 0;
+        Ok(Cartridge {
+            prg_rom: raw[prg_rom_start..chr_rom_start].to_vec(),
+            chr_rom: if chr_ram { vec![0; CHR_ROM_PAGE_SIZE] } else { raw[chr_rom_start..chr_rom_start + chr_rom_size].to_vec() },
+            chr_ram,
+            mapper,
+            mirroring,
+            battery: raw[6] & 0b10 != 0,
+            region,
+        })
+    }
+
+    /// A bare PRG ROM image without a header, like ld65 writes for a
+    /// config without one: 16 or 32KB on an NROM board with CHR RAM.
+    pub fn from_prg(prg_rom: &[u8]) -> Result<Cartridge, String> {
+        if prg_rom.len() != PRG_ROM_PAGE_SIZE && prg_rom.len() != 2 * PRG_ROM_PAGE_SIZE {
+            return Err(format!("a raw binary has to be 16 or 32KB, not {} bytes", prg_rom.len()));
+        }
+        Ok(Cartridge {
+            prg_rom: prg_rom.to_vec(),
+            chr_rom: vec![0; CHR_ROM_PAGE_SIZE],
+            chr_ram: true,
+            mapper: 0,
+            mirroring: Mirroring::Horizontal,
+            battery: false,
+            region: None,
+        })
+    }
+
+    /// Reads an iNES file, or a raw binary if it ends in `.bin`.
+    #[cfg(feature = "std")]
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Cartridge, String> {
+        let raw = fs::read(path.as_ref())
+            .map_err(|e| format!("can't read {}: {}", path.as_ref().display(), e))?;
+        if path.as_ref().extension().is_some_and(|ext| ext.eq_ignore_ascii_case("bin")) && !raw.starts_with(&NES_TAG) {
+            return Cartridge::from_prg(&raw);
+        }
+        Cartridge::new(&raw)
+    }
+
+    /// CRC-32 of PRG ROM followed by CHR ROM, the way ROM databases
+    /// identify games regardless of the header.
+    pub fn crc32(&self) -> u32 {
+        let chr: &[u8] = if self.chr_ram { &[] } else { &self.chr_rom };
+        crc32(self.prg_rom.iter().chain(chr).copied())
+    }
+}
+
+/// CRC-32 (IEEE) of a run of bytes.
+pub fn crc32<I: IntoIterator<Item = u8>>(bytes: I) -> u32 {
+    !bytes.into_iter().fold(!0, |crc, byte| {
+        (0..8).fold(crc ^ byte as u32, |crc, _| if crc & 1 != 0 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 })
+    })
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use crate::asm::assemble;
+    use crate::nes::Nes;
+
+    /// An NROM image with the given PRG banks and one CHR bank.
+    pub fn test_rom(prg_rom: &[u8]) -> Vec<u8> {
+        let banks = prg_rom.len() / PRG_ROM_PAGE_SIZE;
+        let mut raw = vec![0x4e, 0x45, 0x53, 0x1a, banks as u8, 1, 0b1, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        raw.extend_from_slice(prg_rom);
+        raw.extend(vec![0; CHR_ROM_PAGE_SIZE]);
+        raw
+    }
+
+    /// A console with `source` assembled into an NROM cartridge. The
+    /// source runs from `.org $C000` through the vectors at $FFFA.
+    pub fn test_nes(source: &str) -> Nes {
+        let mut nes = Nes::new();
+        nes.insert_cartridge(Cartridge::new(&test_rom(&assemble(source).unwrap().bytes)).unwrap());
+        nes
+    }
+
+    #[test]
+    fn test_raw_binary() {
+        let mut prg = vec![0; 2 * PRG_ROM_PAGE_SIZE];
+        prg[0] = 0x42;
+        let cartridge = Cartridge::from_prg(&prg).unwrap();
+        assert_eq!(cartridge.prg_rom[0], 0x42);
+        assert!(cartridge.chr_ram);
+        assert!(Cartridge::from_prg(&prg[..100]).is_err());
+
+        let path = std::env::temp_dir().join(format!("nessie-raw-{}.bin", std::process::id()));
+        fs::write(&path, &prg).unwrap();
+        let loaded = Cartridge::load(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.unwrap().prg_rom.len(), 2 * PRG_ROM_PAGE_SIZE);
+    }
+
+    #[test]
+    fn test_parse_header() {
+        let mut prg = vec![0; PRG_ROM_PAGE_SIZE];
+        prg[0] = 0x42;
+        let cartridge = Cartridge::new(&test_rom(&prg)).unwrap();
+        assert_eq!(cartridge.prg_rom.len(), PRG_ROM_PAGE_SIZE);
+        assert_eq!(cartridge.prg_rom[0], 0x42);
+        assert_eq!(cartridge.chr_rom.len(), CHR_ROM_PAGE_SIZE);
+        assert!(!cartridge.chr_ram);
+        assert_eq!(cartridge.mirroring, Mirroring::Vertical);
+    }
+
+    #[test]
+    fn test_region_from_header() {
+        let mut raw = test_rom(&vec![0; PRG_ROM_PAGE_SIZE]);
+        assert_eq!(Cartridge::new(&raw).unwrap().region, None);
+        raw[9] = 1;
+        assert_eq!(Cartridge::new(&raw).unwrap().region, Some(Region::Pal));
+        // NES 2.0 keeps it in byte 12 instead
+        raw[7] |= 0b1000;
+        raw[9] = 0;
+        raw[12] = 3;
+        assert_eq!(Cartridge::new(&raw).unwrap().region, Some(Region::Dendy));
+        raw[12] = 2;
+        assert_eq!(Cartridge::new(&raw).unwrap().region, None);
+    }
+
+    #[test]
+    fn test_crc32() {
+        let mut cartridge = Cartridge::new(&test_rom(&vec![0; PRG_ROM_PAGE_SIZE])).unwrap();
+        cartridge.prg_rom = b"123456789".to_vec();
+        cartridge.chr_ram = true;
+        assert_eq!(cartridge.crc32(), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn test_reject_bad_images() {
+        let raw = test_rom(&vec![0; PRG_ROM_PAGE_SIZE]);
+        assert!(Cartridge::new(&raw[..100]).is_err());
+        assert!(Cartridge::new(&raw[1..]).is_err());
+        let mut mmc1 = raw.clone();
+        mmc1[6] |= 0x10;
+        assert_eq!(Cartridge::new(&mmc1).unwrap_err(), "mapper 1 isn't supported");
+    }
+}
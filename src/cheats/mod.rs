@@ -0,0 +1,368 @@
+pub mod search;
+
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+#[cfg(feature = "std")]
+use std::fs;
+#[cfg(feature = "std")]
+use std::path::{Path, PathBuf};
+
+// Game Genie letters, in the order of the nibbles they stand for
+const LETTERS: &[u8; 16] = b"APZLGITYEOXUKSVN";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheatKind {
+    /// Changes what the CPU reads from the cartridge, like a Game Genie.
+    GameGenie,
+    /// Writes RAM or PRG RAM at the end of every frame.
+    Ram,
+}
+
+/// A patch on what the CPU reads from the cartridge, `value` instead of
+/// what's at `addr`, or a value written to RAM every frame. Either only
+/// happens when what's there is `compare`, if there is one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cheat {
+    /// The code it came from.
+    pub code: String,
+    pub kind: CheatKind,
+    pub addr: u16,
+    pub value: u8,
+    pub compare: Option<u8>,
+    /// What it does, for showing in a list.
+    pub label: String,
+    pub group: Option<String>,
+}
+
+impl Cheat {
+    /// Decodes a 6 or 8 letter Game Genie code, the longer ones with a compare value.
+    pub fn from_game_genie(code: &str) -> Result<Cheat, String> {
+        let code = code.trim().to_ascii_uppercase();
+        let n: Vec<u16> = code
+            .bytes()
+            .map(|letter| LETTERS.iter().position(|&l| l == letter).map(|n| n as u16).ok_or_else(|| format!("{:?} isn't a Game Genie letter", letter as char)))
+            .collect::<Result<_, _>>()?;
+        if n.len() != 6 && n.len() != 8 {
+            return Err(format!("Game Genie codes are 6 or 8 letters, {:?} has {}", code, n.len()));
+        }
+        let addr = 0x8000 | ((n[3] & 7) << 12) | ((n[5] & 7) << 8) | ((n[4] & 8) << 8) | ((n[2] & 7) << 4) | ((n[1] & 8) << 4) | (n[4] & 7) | (n[3] & 8);
+        let value = ((n[1] & 7) << 4) | ((n[0] & 8) << 4) | (n[0] & 7);
+        let (value, compare) = if n.len() == 6 {
+            (value | (n[5] & 8), None)
+        } else {
+            (value | (n[7] & 8), Some(((n[7] & 7) << 4) | ((n[6] & 8) << 4) | (n[6] & 7) | (n[5] & 8)))
+        };
+        Ok(Cheat { code, kind: CheatKind::GameGenie, addr, value: value as u8, compare: compare.map(|compare| compare as u8), label: String::new(), group: None })
+    }
+
+    /// A RAM cheat from `AAAA:VV` or `AAAA:VV:CC` with a compare value, or
+    /// the six digits `AAAAVV` of Action Replay style codes, all hex.
+    /// Only internal RAM and PRG RAM can be written.
+    pub fn from_raw(code: &str) -> Result<Cheat, String> {
+        let code = code.trim().to_ascii_uppercase();
+        let fields: Vec<&str> = if code.contains(':') {
+            code.split(':').collect()
+        } else if code.len() == 6 && code.is_ascii() {
+            vec![&code[..4], &code[4..]]
+        } else {
+            return Err(format!("{:?} isn't AAAA:VV or AAAAVV", code));
+        };
+        let hex = |field: &str, digits: usize| {
+            if field.len() > digits {
+                return Err(format!("{:?} has more than {} hex digits", field, digits));
+            }
+            u16::from_str_radix(field, 16).map_err(|_| format!("{:?} isn't hex", field))
+        };
+        let (addr, value, compare) = match fields[..] {
+            [addr, value] => (hex(addr, 4)?, hex(value, 2)?, None),
+            [addr, value, compare] => (hex(addr, 4)?, hex(value, 2)?, Some(hex(compare, 2)? as u8)),
+            _ => return Err(format!("{:?} isn't AAAA:VV or AAAA:VV:CC", code)),
+        };
+        if !matches!(addr, 0x0000..=0x1fff | 0x6000..=0x7fff) {
+            return Err(format!("${:04X} isn't in RAM or PRG RAM", addr));
+        }
+        Ok(Cheat { code, kind: CheatKind::Ram, addr, value: value as u8, compare, label: String::new(), group: None })
+    }
+
+    /// Either kind of code, by its shape.
+    pub fn parse(code: &str) -> Result<Cheat, String> {
+        let trimmed = code.trim();
+        if trimmed.len() == 8 || trimmed.len() == 6 && trimmed.bytes().any(|letter| !letter.is_ascii_hexdigit()) {
+            Cheat::from_game_genie(trimmed)
+        } else {
+            Cheat::from_raw(trimmed)
+        }
+    }
+
+    pub fn with_label(mut self, label: &str) -> Cheat {
+        self.label = label.to_string();
+        self
+    }
+
+    pub fn with_group(mut self, group: Option<&str>) -> Cheat {
+        self.group = group.map(str::to_string);
+        self
+    }
+}
+
+/// The cheats on a console, each of which can be turned off and on again.
+///
+/// They're kept per game in a text file, by default `<crc>.cht` in a
+/// directory of them, with a line for each code and a header for each
+/// group after the ones in none:
+///
+/// ```text
+/// on 0075:09 Nine lives
+/// [Invincibility]
+/// off SXIOPO Infinite lives
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Cheats {
+    entries: Vec<(Cheat, bool)>,
+    // held values by address, RAM ones folded onto $0000-$07FF
+    frozen: BTreeMap<u16, u8>,
+}
+
+impl Cheats {
+    pub fn new() -> Self {
+        Cheats::default()
+    }
+
+    /// Adds `cheat` turned on, replacing one with the same code.
+    pub fn add(&mut self, cheat: Cheat) {
+        self.remove(&cheat.code);
+        self.entries.push((cheat, true));
+    }
+
+    /// Reads a cheat file, see above.
+    pub fn parse(text: &str) -> Result<Cheats, String> {
+        let mut cheats = Cheats::new();
+        let mut group = None;
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some(name) = line.strip_prefix('[').and_then(|line| line.strip_suffix(']')) {
+                group = Some(name.trim());
+                continue;
+            }
+            let mut fields = line.splitn(3, char::is_whitespace);
+            let enabled = match fields.next() {
+                Some("on") => true,
+                Some("off") => false,
+                _ => return Err(format!("line {}: expected on or off", number + 1)),
+            };
+            let code = fields.next().unwrap_or("");
+            let cheat = Cheat::parse(code).map_err(|err| format!("line {}: {}", number + 1, err))?;
+            cheats.remove(&cheat.code);
+            cheats.entries.push((cheat.with_label(fields.next().unwrap_or("").trim()).with_group(group), enabled));
+        }
+        Ok(cheats)
+    }
+
+    #[cfg(feature = "std")]
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Cheats, String> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).map_err(|err| format!("couldn't read {}: {}", path.display(), err))?;
+        Cheats::parse(&text)
+    }
+
+    #[cfg(feature = "std")]
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        let path = path.as_ref();
+        fs::write(path, self.to_string()).map_err(|err| format!("couldn't write {}: {}", path.display(), err))
+    }
+
+    /// Where the cheats for the game with `Nes::rom_crc` of `crc` go in `dir`.
+    #[cfg(feature = "std")]
+    pub fn path_for<P: AsRef<Path>>(dir: P, crc: u32) -> PathBuf {
+        dir.as_ref().join(format!("{:08x}.cht", crc))
+    }
+
+    pub fn remove(&mut self, code: &str) -> bool {
+        let len = self.entries.len();
+        self.entries.retain(|(cheat, _)| !cheat.code.eq_ignore_ascii_case(code));
+        self.entries.len() != len
+    }
+
+    /// False if there's no cheat with that code.
+    pub fn set_enabled(&mut self, code: &str, enabled: bool) -> bool {
+        match self.entries.iter_mut().find(|(cheat, _)| cheat.code.eq_ignore_ascii_case(code)) {
+            Some(entry) => {
+                entry.1 = enabled;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Turns every cheat in `group` off or on, returning how many there are.
+    pub fn set_group_enabled(&mut self, group: &str, enabled: bool) -> usize {
+        let mut count = 0;
+        for entry in self.entries.iter_mut().filter(|(cheat, _)| cheat.group.as_deref() == Some(group)) {
+            entry.1 = enabled;
+            count += 1;
+        }
+        count
+    }
+
+    /// Group names in the order they first appear.
+    pub fn groups(&self) -> Vec<&str> {
+        let mut groups = Vec::new();
+        for group in self.entries.iter().filter_map(|(cheat, _)| cheat.group.as_deref()) {
+            if !groups.contains(&group) {
+                groups.push(group);
+            }
+        }
+        groups
+    }
+
+    /// Each cheat and whether it's on.
+    pub fn iter(&self) -> impl Iterator<Item = (&Cheat, bool)> {
+        self.entries.iter().map(|(cheat, enabled)| (cheat, *enabled))
+    }
+
+    /// Holds RAM or PRG RAM at `addr` at `value`: writes there store
+    /// `value` whatever the game writes, however it writes it.
+    pub fn freeze(&mut self, addr: u16, value: u8) -> Result<(), String> {
+        let addr = match addr {
+            0x0000..=0x1fff => addr & 0x07ff,
+            0x6000..=0x7fff => addr,
+            _ => return Err(format!("${:04X} isn't in RAM or PRG RAM", addr)),
+        };
+        self.frozen.insert(addr, value);
+        Ok(())
+    }
+
+    pub fn unfreeze(&mut self, addr: u16) -> bool {
+        let addr = if addr < 0x2000 { addr & 0x07ff } else { addr };
+        self.frozen.remove(&addr).is_some()
+    }
+
+    /// Frozen addresses and the values they're held at.
+    pub fn frozen(&self) -> &BTreeMap<u16, u8> {
+        &self.frozen
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty() && self.frozen.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.frozen.clear();
+    }
+
+    // what the CPU reads at `addr` in place of `value`
+    pub(crate) fn patch(&self, addr: u16, value: u8) -> u8 {
+        self.entries
+            .iter()
+            .find(|(cheat, enabled)| *enabled && cheat.kind == CheatKind::GameGenie && cheat.addr == addr && cheat.compare.is_none_or(|compare| compare == value))
+            .map_or(value, |(cheat, _)| cheat.value)
+    }
+
+    // what a write of RAM or PRG RAM at `addr` stores, with RAM mirrors already folded
+    pub(crate) fn write(&self, addr: u16, value: u8) -> u8 {
+        self.frozen.get(&addr).copied().unwrap_or(value)
+    }
+
+    // the RAM cheats that are on, to write at the end of a frame
+    pub(crate) fn ram_writes(&self) -> impl Iterator<Item = &Cheat> {
+        self.entries.iter().filter(|(cheat, enabled)| *enabled && cheat.kind == CheatKind::Ram).map(|(cheat, _)| cheat)
+    }
+}
+
+impl fmt::Display for Cheats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut groups = vec![None];
+        groups.extend(self.groups().into_iter().map(Some));
+        for group in groups {
+            if let Some(name) = group {
+                writeln!(f, "[{}]", name)?;
+            }
+            for (cheat, enabled) in self.entries.iter().filter(|(cheat, _)| cheat.group.as_deref() == group) {
+                let state = if *enabled { "on" } else { "off" };
+                writeln!(f, "{} {} {}", state, cheat.code, cheat.label)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        // infinite lives in Super Mario Bros.
+        let cheat = Cheat::from_game_genie("sxiopo").unwrap();
+        assert_eq!((cheat.code.as_str(), cheat.addr, cheat.value, cheat.compare), ("SXIOPO", 0x91d9, 0xad, None));
+        let cheat = Cheat::from_game_genie("YEUZUGAA").unwrap();
+        assert_eq!((cheat.addr, cheat.value, cheat.compare), (0xacb3, 0x07, Some(0x00)));
+        assert!(Cheat::from_game_genie("SXIOP").is_err());
+        assert!(Cheat::from_game_genie("SXIOPB").is_err());
+    }
+
+    #[test]
+    fn test_raw() {
+        let cheat = Cheat::parse("0075:09").unwrap();
+        assert_eq!((cheat.kind, cheat.addr, cheat.value, cheat.compare), (CheatKind::Ram, 0x0075, 0x09, None));
+        let cheat = Cheat::parse("6010:ff:00").unwrap();
+        assert_eq!((cheat.addr, cheat.value, cheat.compare), (0x6010, 0xff, Some(0x00)));
+        assert_eq!(Cheat::parse("07ff63").unwrap().addr, 0x07ff);
+        assert_eq!(Cheat::parse("SXIOPO").unwrap().kind, CheatKind::GameGenie);
+        assert!(Cheat::parse("8000:01").is_err());
+        assert!(Cheat::parse("0075:123").is_err());
+    }
+
+    #[test]
+    fn test_freeze() {
+        let mut cheats = Cheats::new();
+        cheats.freeze(0x0875, 9).unwrap();
+        cheats.freeze(0x6000, 1).unwrap();
+        assert!(cheats.freeze(0x2000, 1).is_err());
+        assert_eq!(cheats.frozen().keys().copied().collect::<Vec<_>>(), vec![0x0075, 0x6000]);
+        assert_eq!(cheats.write(0x0075, 3), 9);
+        assert_eq!(cheats.write(0x0076, 3), 3);
+        assert!(cheats.unfreeze(0x1075));
+        assert!(!cheats.unfreeze(0x0075));
+        assert!(!cheats.is_empty());
+    }
+
+    #[test]
+    fn test_file() {
+        let text = "# Super Mario Bros.\non 0075:09 Nine lives\n[Invincibility]\noff SXIOPO Infinite lives\non 079F:20\n";
+        let mut cheats = Cheats::parse(text).unwrap();
+        assert_eq!(cheats.groups(), vec!["Invincibility"]);
+        assert_eq!(cheats.iter().map(|(cheat, enabled)| (cheat.label.as_str(), enabled)).collect::<Vec<_>>(), vec![("Nine lives", true), ("Infinite lives", false), ("", true)]);
+        assert_eq!(cheats.set_group_enabled("Invincibility", true), 2);
+        assert_eq!(Cheats::parse(&cheats.to_string()).unwrap().to_string(), cheats.to_string());
+        assert!(cheats.to_string().starts_with("on 0075:09 Nine lives\n[Invincibility]\non SXIOPO"));
+        assert!(Cheats::parse("maybe 0075:09").is_err());
+        assert!(Cheats::parse("on 0075:zz").is_err());
+        assert_eq!(Cheats::path_for("cheats", 0xabc), Path::new("cheats/00000abc.cht"));
+    }
+
+    #[test]
+    fn test_patch() {
+        let mut cheats = Cheats::new();
+        let patch = |code: &str, addr, value, compare| Cheat { code: code.to_string(), kind: CheatKind::GameGenie, addr, value, compare, label: String::new(), group: None };
+        cheats.add(patch("A", 0x8000, 1, None));
+        cheats.add(patch("B", 0x9000, 2, Some(5)));
+        assert_eq!(cheats.patch(0x8000, 9), 1);
+        assert_eq!(cheats.patch(0x8001, 9), 9);
+        assert_eq!(cheats.patch(0x9000, 5), 2);
+        assert_eq!(cheats.patch(0x9000, 6), 6);
+        assert!(cheats.set_enabled("a", false));
+        assert_eq!(cheats.patch(0x8000, 9), 9);
+        assert!(!cheats.set_enabled("C", false));
+        assert!(cheats.remove("B"));
+        assert_eq!(cheats.iter().count(), 1);
+    }
+}
@@ -0,0 +1,107 @@
+use alloc::vec::Vec;
+use crate::cpu::CPU;
+
+/// How a byte has to compare with the last look at it to stay a candidate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Filter {
+    Equals(u8),
+    Increased,
+    Decreased,
+    Changed,
+    Unchanged,
+    /// Went up by this much, or down for a negative one, wrapping as a byte does.
+    ChangedBy(i8),
+}
+
+impl Filter {
+    fn keeps(self, previous: u8, value: u8) -> bool {
+        match self {
+            Filter::Equals(wanted) => value == wanted,
+            Filter::Increased => value > previous,
+            Filter::Decreased => value < previous,
+            Filter::Changed => value != previous,
+            Filter::Unchanged => value == previous,
+            Filter::ChangedBy(delta) => value.wrapping_sub(previous) as i8 == delta,
+        }
+    }
+}
+
+/// Finding where a game keeps something, by narrowing down RAM and PRG RAM
+/// to the bytes that change the way it does: take a look, play until lives
+/// go down, keep what decreased, and so on.
+#[derive(Debug, Clone)]
+pub struct CheatSearch {
+    // internal RAM then PRG RAM, as of the last look
+    previous: Vec<u8>,
+    // indexes into `previous`
+    candidates: Vec<usize>,
+}
+
+impl CheatSearch {
+    /// Starts with every byte as a candidate.
+    pub fn new(cpu: &CPU) -> Self {
+        let previous = memory(cpu);
+        CheatSearch { candidates: (0..previous.len()).collect(), previous }
+    }
+
+    /// Keeps the candidates that pass `filter` compared with the last look,
+    /// returning how many are left.
+    pub fn filter(&mut self, cpu: &CPU, filter: Filter) -> usize {
+        let memory = memory(cpu);
+        self.candidates.retain(|&index| filter.keeps(self.previous[index], memory[index]));
+        self.previous = memory;
+        self.candidates.len()
+    }
+
+    /// Candidate addresses and their values at the last look.
+    pub fn candidates(&self) -> impl Iterator<Item = (u16, u8)> + '_ {
+        self.candidates.iter().map(|&index| (address(index), self.previous[index]))
+    }
+
+    pub fn len(&self) -> usize {
+        self.candidates.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.candidates.is_empty()
+    }
+}
+
+fn memory(cpu: &CPU) -> Vec<u8> {
+    let mut memory = cpu.bus.ram().to_vec();
+    memory.extend_from_slice(cpu.bus.prg_ram());
+    memory
+}
+
+fn address(index: usize) -> u16 {
+    if index < 0x800 {
+        index as u16
+    } else {
+        (0x6000 + index - 0x800) as u16
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_search() {
+        let mut cpu = CPU::new();
+        cpu.bus.mem_write(0x0075, 3);
+        cpu.bus.mem_write(0x6010, 3);
+        let mut search = CheatSearch::new(&cpu);
+        assert_eq!(search.len(), 0x800 + 0x2000);
+        assert_eq!(search.filter(&cpu, Filter::Equals(3)), 2);
+
+        cpu.bus.mem_write(0x0075, 2);
+        assert_eq!(search.filter(&cpu, Filter::Decreased), 1);
+        assert_eq!(search.candidates().collect::<Vec<_>>(), vec![(0x0075, 2)]);
+
+        cpu.bus.mem_write(0x0075, 0xff);
+        assert_eq!(search.filter(&cpu, Filter::ChangedBy(-3)), 1);
+        assert_eq!(search.filter(&cpu, Filter::Unchanged), 1);
+        assert_eq!(search.filter(&cpu, Filter::Changed), 0);
+        assert!(search.is_empty());
+    }
+}
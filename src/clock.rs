@@ -0,0 +1,86 @@
+use crate::region::Region;
+
+/// Master clock all the chips are derived from. The CPU and PPU each take
+/// a fixed number of master cycles per step, depending on the region, and
+/// the PPU is caught up with the CPU by running every dot that fits into
+/// the master cycles the CPU has used. This is the one place cycle counts
+/// are kept.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Clock {
+    region: Region,
+    master_cycles: u64,
+    cpu_cycles: u64,
+    ppu_dots: u64,
+}
+
+impl Clock {
+    pub fn new(region: Region) -> Self {
+        Clock { region, ..Clock::default() }
+    }
+
+    /// Switches the dividers from now on; counts carry over.
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+        // drop a partial dot so the PPU starts in step with the CPU
+        let (_, ppu_divider) = region.master_clock_dividers();
+        self.master_cycles = self.ppu_dots * ppu_divider;
+    }
+
+    /// Runs the CPU for some cycles and returns how many PPU dots are due.
+    pub fn advance_cpu(&mut self, cycles: u32) -> u32 {
+        let (cpu_divider, ppu_divider) = self.region.master_clock_dividers();
+        self.cpu_cycles += cycles as u64;
+        self.master_cycles += cycles as u64 * cpu_divider;
+        let dots = self.master_cycles / ppu_divider - self.ppu_dots;
+        self.ppu_dots += dots;
+        dots as u32
+    }
+
+    /// Lines the PPU up with the CPU `offset` master cycles into a dot, as
+    /// power on does at random.
+    pub fn set_phase(&mut self, offset: u64) {
+        let (_, ppu_divider) = self.region.master_clock_dividers();
+        self.master_cycles = self.ppu_dots * ppu_divider + offset % ppu_divider;
+    }
+
+    pub fn cpu_cycles(&self) -> u64 {
+        self.cpu_cycles
+    }
+
+    pub fn ppu_dots(&self) -> u64 {
+        self.ppu_dots
+    }
+
+    pub fn master_cycles(&self) -> u64 {
+        self.master_cycles
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_dot_ratios() {
+        let mut ntsc = Clock::new(Region::Ntsc);
+        assert_eq!(ntsc.advance_cpu(1), 3);
+        assert_eq!(ntsc.advance_cpu(100), 300);
+
+        let mut pal = Clock::new(Region::Pal);
+        let dots: Vec<u32> = (0..5).map(|_| pal.advance_cpu(1)).collect();
+        assert_eq!(dots, vec![3, 3, 3, 3, 4]);
+        assert_eq!(pal.ppu_dots(), 16);
+        assert_eq!(pal.master_cycles(), 80);
+
+        let mut dendy = Clock::new(Region::Dendy);
+        assert_eq!(dendy.advance_cpu(7), 21);
+        assert_eq!(dendy.cpu_cycles(), 7);
+
+        // 4 master cycles in, the first CPU cycle already finishes a fourth dot
+        let mut late = Clock::new(Region::Pal);
+        late.set_phase(4);
+        let dots: Vec<u32> = (0..5).map(|_| late.advance_cpu(1)).collect();
+        assert_eq!(dots, vec![4, 3, 3, 3, 3]);
+    }
+}
@@ -0,0 +1,212 @@
+//! Emulator settings the frontends share, kept in a TOML file:
+//!
+//! ```toml
+//! region = "Pal"
+//! palette = "palettes/smooth.pal"
+//!
+//! [audio]
+//! sample_rate = 44100
+//!
+//! [accuracy]
+//! sprite_limit = false
+//!
+//! [paths]
+//! saves = "saves"
+//! states = "states"
+//! ```
+//!
+//! Anything left out keeps its default, so an empty file is a valid one.
+//! An `[input]` table replaces the whole default `InputProfile`.
+
+use crate::input::mapping::InputProfile;
+use crate::nes::Nes;
+use crate::ppu::palette::Palette;
+use crate::ppu::{PpuConfig, DEFAULT_OPEN_BUS_DECAY_FRAMES};
+use crate::region::Region;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const DEFAULT_SAMPLE_RATE: u32 = 48000;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Forces a TV system whatever the game asks for; left out, the header
+    /// and the ROM database decide.
+    pub region: Option<Region>,
+    /// A .pal file to draw with instead of the built-in NTSC colors.
+    pub palette: Option<PathBuf>,
+    pub audio: AudioConfig,
+    pub accuracy: Accuracy,
+    pub paths: Paths,
+    pub input: InputProfile,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AudioConfig {
+    /// Rate the frontend asks the sound device for, in Hz.
+    pub sample_rate: u32,
+}
+
+impl Default for AudioConfig {
+    fn default() -> Self {
+        AudioConfig { sample_rate: DEFAULT_SAMPLE_RATE }
+    }
+}
+
+/// Where to side with the hardware over what's nicer to look at, the
+/// defaults being the hardware's. See `PpuConfig` for what each does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Accuracy {
+    /// 8 sprites a scanline; off draws them all and gets rid of flicker.
+    pub sprite_limit: bool,
+    pub oam_corruption: bool,
+    /// Off, the open bus latch keeps its value forever.
+    pub open_bus_decay: bool,
+    pub overclock_scanlines: u16,
+}
+
+impl Default for Accuracy {
+    fn default() -> Self {
+        let ppu = PpuConfig::default();
+        Accuracy {
+            sprite_limit: ppu.sprite_limit.is_some(),
+            oam_corruption: ppu.oam_corruption,
+            open_bus_decay: ppu.open_bus_decay.is_some(),
+            overclock_scanlines: ppu.overclock_scanlines,
+        }
+    }
+}
+
+/// Directories for what a game leaves behind. Left out, battery saves go
+/// next to the game and save states in a `states` directory beside it.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Paths {
+    pub saves: Option<PathBuf>,
+    pub states: Option<PathBuf>,
+}
+
+impl Config {
+    pub fn parse(text: &str) -> Result<Config, String> {
+        toml::from_str(text).map_err(|e| format!("can't read config: {}", e))
+    }
+
+    /// Reads a config file. Relative paths in it are taken from the
+    /// directory the file is in.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Config, String> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).map_err(|e| format!("can't read {}: {}", path.display(), e))?;
+        let mut config = Config::parse(&text).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let dir = path.parent().unwrap_or(Path::new(""));
+        for path in [&mut config.palette, &mut config.paths.saves, &mut config.paths.states].into_iter().flatten() {
+            *path = dir.join(&*path);
+        }
+        Ok(config)
+    }
+
+    pub fn to_toml(&self) -> Result<String, String> {
+        toml::to_string(self).map_err(|e| format!("can't write config: {}", e))
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        fs::write(path.as_ref(), self.to_toml()?).map_err(|e| format!("can't write {}: {}", path.as_ref().display(), e))
+    }
+
+    pub fn ppu_config(&self) -> PpuConfig {
+        PpuConfig {
+            open_bus_decay: self.accuracy.open_bus_decay.then_some(DEFAULT_OPEN_BUS_DECAY_FRAMES),
+            oam_corruption: self.accuracy.oam_corruption,
+            sprite_limit: self.accuracy.sprite_limit.then_some(8),
+            overclock_scanlines: self.accuracy.overclock_scanlines,
+        }
+    }
+
+    /// Sets the console up as the config says: region, palette, sample
+    /// rate, accuracy and the devices the input profile plugs in. Host
+    /// inputs stay with the frontend, through `input`.
+    pub fn apply(&self, nes: &mut Nes) -> Result<(), String> {
+        if let Some(path) = &self.palette {
+            nes.cpu_mut().bus.ppu.set_palette(Palette::load(path)?);
+        }
+        nes.set_region_override(self.region);
+        let bus = &mut nes.cpu_mut().bus;
+        bus.ppu.config = self.ppu_config();
+        bus.apu.set_sample_rate(self.audio.sample_rate);
+        self.input.apply(bus);
+        Ok(())
+    }
+
+    /// Where the battery save of the game at `rom` goes.
+    pub fn battery_path(&self, rom: &Path) -> PathBuf {
+        match (&self.paths.saves, rom.file_name()) {
+            (Some(dir), Some(name)) => dir.join(name).with_extension("sav"),
+            _ => rom.with_extension("sav"),
+        }
+    }
+
+    /// The directory for the save states of the game at `rom`, for a
+    /// `SlotManager`.
+    pub fn states_dir(&self, rom: &Path) -> PathBuf {
+        match &self.paths.states {
+            Some(dir) => dir.clone(),
+            None => rom.parent().unwrap_or(Path::new(".")).join("states"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::input::mapping::{HostInput, Target};
+    use crate::joypad::Button;
+
+    #[test]
+    fn test_defaults() {
+        assert_eq!(Config::parse("").unwrap(), Config::default());
+        assert_eq!(Config::default().ppu_config(), PpuConfig::default());
+
+        let config = Config::parse("region = \"Pal\"\n[accuracy]\nsprite_limit = false\n[audio]\nsample_rate = 44100\n").unwrap();
+        assert_eq!(config.region, Some(Region::Pal));
+        assert_eq!(config.ppu_config().sprite_limit, None);
+        assert!(config.accuracy.open_bus_decay);
+        assert_eq!(config.audio.sample_rate, 44100);
+        assert_eq!(config.input, InputProfile::default());
+
+        assert!(Config::parse("region = \"Mars\"").is_err());
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut config = Config { region: Some(Region::Dendy), palette: Some(PathBuf::from("smooth.pal")), ..Config::default() };
+        config.paths.saves = Some(PathBuf::from("saves"));
+        config.accuracy.overclock_scanlines = 20;
+        config.input.bind(HostInput::GamepadButton { gamepad: 1, button: 0 }, Target::Joypad { player: 1, button: Button::A });
+        assert_eq!(Config::parse(&config.to_toml().unwrap()).unwrap(), config);
+    }
+
+    #[test]
+    fn test_load() {
+        let dir = std::env::temp_dir().join(format!("nessie-config-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("nessie.toml");
+        fs::write(&path, "[paths]\nsaves = \"saves\"\n").unwrap();
+        let config = Config::load(&path).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(config.battery_path(Path::new("games/smb.nes")), dir.join("saves/smb.sav"));
+        assert_eq!(config.states_dir(Path::new("games/smb.nes")), Path::new("games/states"));
+        assert_eq!(Config::default().battery_path(Path::new("games/smb.nes")), Path::new("games/smb.sav"));
+    }
+
+    #[test]
+    fn test_apply() {
+        let config = Config::parse("region = \"Pal\"\n[accuracy]\noam_corruption = true\n").unwrap();
+        let mut nes = Nes::new();
+        config.apply(&mut nes).unwrap();
+        assert_eq!(nes.region(), Region::Pal);
+        assert!(nes.cpu().bus.ppu.config.oam_corruption);
+    }
+}
@@ -1,16 +1,89 @@
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use crate::bus::Bus;
+use crate::debugger::BreakReason;
+use crate::frame::Frame;
+use crate::history::{History, HistoryEntry};
 use crate::ops;
-use std::collections::HashMap;
-
+use crate::ppu::events::PpuEventKind;
+use crate::profiler::{Location, Profiler};
+use crate::symbols::Symbols;
+use crate::trace;
+
+const STACK: u16 = 0x0100;
+const STACK_RESET: u8 = 0xfd;
+// the bits XAA and LXA or into a first on most consoles
+const UNSTABLE_MAGIC: u8 = 0xee;
+
+// status flags
+const CARRY: u8 = 0b0000_0001;
+const ZERO: u8 = 0b0000_0010;
+const INTERRUPT_DISABLE: u8 = 0b0000_0100;
+const DECIMAL_MODE: u8 = 0b0000_1000;
+const BREAK: u8 = 0b0001_0000;
+const BREAK2: u8 = 0b0010_0000;
+const OVERFLOW: u8 = 0b0100_0000;
+const NEGATIVE: u8 = 0b1000_0000;
+
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct CPU {
     pub register_a: u8,
     pub register_x: u8,
     pub register_y: u8,
     pub status: u8,
     pub program_counter: u16,
-    pub memory: [u8; 0xffff],
+    pub stack_pointer: u8,
+    pub bus: Bus,
+    // the last operand address was indexed into the next page
+    page_crossed: bool,
+    // ran into one of the opcodes that halt the 6502 until a reset; the
+    // program counter stays on it, so save states jam again once loaded
+    #[cfg_attr(feature = "serde", serde(skip))]
+    jammed: bool,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub(crate) tracer: Tracer,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    history: History,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub(crate) profiler: Option<Box<Profiler>>,
 }
 
-#[derive(Debug)]
+pub type TraceCallback = Box<dyn FnMut(&str) + Send>;
+
+// callbacks can't be copied, so a cloned CPU doesn't trace
+#[derive(Default)]
+pub(crate) struct Tracer(Option<TraceCallback>, Option<Symbols>);
+
+impl Clone for Tracer {
+    fn clone(&self) -> Self {
+        Tracer(None, self.1.clone())
+    }
+}
+
+// what `execute` came to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Execution {
+    // the instruction ran
+    Ran,
+    // the debugger stopped the CPU before it
+    Stopped,
+    // a BRK ended `run`, or the CPU is jammed
+    Ended,
+}
+
+/// The CPU registers at one point in time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Registers {
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub status: u8,
+    pub program_counter: u16,
+    pub stack_pointer: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[allow(non_camel_case_types)]
 pub enum AddressingMode {
     Immediate,
@@ -28,6 +101,12 @@ pub enum AddressingMode {
     Implied,
 }
 
+impl Default for CPU {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl CPU {
     pub fn new() -> Self {
         CPU {
@@ -36,78 +115,93 @@ impl CPU {
             register_y: 0,
             status: 0,
             program_counter: 0,
-            memory: [0; 0xffff],
+            stack_pointer: STACK_RESET,
+            bus: Bus::new(),
+            page_crossed: false,
+            jammed: false,
+            tracer: Tracer::default(),
+            history: History::default(),
+            profiler: None,
         }
     }
 
-    fn mem_read(&self, addr: u16) -> u8 {
-        self.memory[addr as usize]
+    fn mem_read(&mut self, addr: u16) -> u8 {
+        self.bus.mem_read(addr)
     }
 
     fn mem_write(&mut self, addr: u16, data: u8) {
-        self.memory[addr as usize] = data;
+        self.bus.mem_write(addr, data);
     }
 
-    fn mem_read_u16(&self, pos: u16) -> u16 {
-        let lo = self.mem_read(pos) as u16;
-        let hi = self.mem_read(pos + 1) as u16;
-        (hi << 8) | (lo as u16)
+    fn mem_read_u16(&mut self, pos: u16) -> u16 {
+        self.bus.mem_read_u16(pos)
     }
 
     fn mem_write_u16(&mut self, pos: u16, data: u16) {
-        let hi = (data >> 8) as u8;
-        let lo = (data & 0xff) as u8;
-        self.mem_write(pos, lo);
-        self.mem_write(pos + 1, hi);
+        self.bus.mem_write_u16(pos, data);
     }
 
     pub fn reset(&mut self) {
         self.register_a = 0;
         self.register_x = 0;
         self.register_y = 0;
+        self.status = 0b0010_0100;
+        self.stack_pointer = STACK_RESET;
+        self.program_counter = self.mem_read_u16(0xfffc);
+        self.jammed = false;
+    }
+
+    /// The reset line: registers are kept, the stack pointer moves down as
+    /// if three bytes were pushed and interrupts get disabled.
+    pub fn soft_reset(&mut self) {
+        self.stack_pointer = self.stack_pointer.wrapping_sub(3);
+        self.status |= INTERRUPT_DISABLE;
         self.program_counter = self.mem_read_u16(0xfffc);
+        self.jammed = false;
     }
 
     pub fn load(&mut self, program: Vec<u8>) {
-        self.memory[0x8000 .. (0x8000 + program.len())].copy_from_slice(&program[..]);
+        for (i, byte) in program.iter().enumerate() {
+            self.mem_write(0x8000 + i as u16, *byte);
+        }
         self.program_counter = 0x8000;
         self.mem_write_u16(0xfffc, 0x8000);
     }
 
-    pub fn load_and_run(&mut self, program: Vec<u8>) {
+    pub fn load_and_run(&mut self, program: Vec<u8>) -> Option<BreakReason> {
         self.load(program);
         self.reset();
-        self.run();
+        self.run()
     }
 
-    fn get_operand_address(&self, mode: &AddressingMode) -> u16 {
+    fn get_operand_address(&mut self, mode: &AddressingMode) -> u16 {
         match mode {
             AddressingMode::Immediate => self.program_counter,
             AddressingMode::ZeroPage => self.mem_read(self.program_counter) as u16,
             AddressingMode::Absolute => self.mem_read_u16(self.program_counter), 
             AddressingMode::ZeroPage_X => {
                 let pos = self.mem_read(self.program_counter);
-                let addr = pos.wrapping_add(self.register_x) as u16;
-                addr
+                pos.wrapping_add(self.register_x) as u16
             },
             AddressingMode::ZeroPage_Y => {
                 let pos = self.mem_read(self.program_counter);
-                let addr = pos.wrapping_add(self.register_y) as u16;
-                addr
+                pos.wrapping_add(self.register_y) as u16
             },
             AddressingMode::Absolute_X => {
                 let base = self.mem_read_u16(self.program_counter);
                 let addr = base.wrapping_add(self.register_x as u16);
+                self.page_crossed = base & 0xff00 != addr & 0xff00;
                 addr
             },
             AddressingMode::Absolute_Y => {
                 let base = self.mem_read_u16(self.program_counter);
                 let addr = base.wrapping_add(self.register_y as u16);
+                self.page_crossed = base & 0xff00 != addr & 0xff00;
                 addr
             },
             AddressingMode::Indirect_X => {
                 let base = self.mem_read(self.program_counter);
-                let ptr: u8 = (base as u8).wrapping_add(self.register_x);
+                let ptr: u8 = base.wrapping_add(self.register_x);
                 let lo = self.mem_read(ptr as u16);
                 let hi = self.mem_read(ptr.wrapping_add(1) as u16);
                 (hi as u16) << 8 | (lo as u16)
@@ -116,9 +210,10 @@ impl CPU {
                 let base = self.mem_read(self.program_counter);
 
                 let lo = self.mem_read(base as u16);
-                let hi = self.mem_read((base as u8).wrapping_add(1) as u16);
+                let hi = self.mem_read(base.wrapping_add(1) as u16);
                 let deref_base = (hi as u16) << 8 | (lo as u16);
                 let deref = deref_base.wrapping_add(self.register_y as u16);
+                self.page_crossed = deref_base & 0xff00 != deref & 0xff00;
                 deref
             }
             _ => {
@@ -140,109 +235,267 @@ impl CPU {
         self.update_processor_status(value & 0b1000_0000 != 0,  0b1000_0000);
     }
 
+    fn stack_push(&mut self, data: u8) {
+        self.mem_write(STACK + self.stack_pointer as u16, data);
+        self.stack_pointer = self.stack_pointer.wrapping_sub(1);
+    }
+
+    fn stack_push_u16(&mut self, data: u16) {
+        self.stack_push((data >> 8) as u8);
+        self.stack_push((data & 0xff) as u8);
+    }
+
+    fn stack_pop(&mut self) -> u8 {
+        self.stack_pointer = self.stack_pointer.wrapping_add(1);
+        self.mem_read(STACK + self.stack_pointer as u16)
+    }
+
+    fn stack_pop_u16(&mut self) -> u16 {
+        let lo = self.stack_pop() as u16;
+        let hi = self.stack_pop() as u16;
+        hi << 8 | lo
+    }
+
+    fn interrupt(&mut self, vector: u16) {
+        self.stack_push_u16(self.program_counter);
+        // break flag clear, bit 5 always set
+        self.stack_push((self.status & !BREAK) | BREAK2);
+        self.status |= INTERRUPT_DISABLE;
+
+        self.bus.tick(7);
+        let return_addr = self.program_counter;
+        self.program_counter = self.mem_read_u16(vector);
+        diag!(debug, kind = if vector == 0xfffa { "NMI" } else { "IRQ" }, from = format_args!("${:04X}", return_addr), handler = format_args!("${:04X}", self.program_counter), "interrupt");
+        if let Some(profiler) = &mut self.profiler {
+            profiler.on_interrupt();
+        }
+        if let Some(mut debugger) = self.bus.debugger.0.take() {
+            debugger.on_interrupt(vector == 0xfffa, self, return_addr);
+            self.bus.debugger.0 = Some(debugger);
+        }
+        if vector == 0xfffa {
+            self.run_nmi_hooks();
+        } else {
+            self.bus.ppu.record_event(PpuEventKind::Irq);
+        }
+    }
+
+    // reads the operand, taking the extra cycle indexing across a page costs
+    fn read_operand(&mut self, mode: &AddressingMode) -> u8 {
+        let addr = self.get_operand_address(mode);
+        if self.page_crossed {
+            self.bus.tick(1);
+        }
+        self.mem_read(addr)
+    }
+
+    fn add_to_register_a(&mut self, value: u8) {
+        let sum = self.register_a as u16 + value as u16 + (self.status & CARRY) as u16;
+        let result = sum as u8;
+        self.update_processor_status(sum > 0xff, CARRY);
+        // signed overflow: both inputs have the same sign and the result doesn't
+        self.update_processor_status((value ^ result) & (self.register_a ^ result) & 0x80 != 0, OVERFLOW);
+        self.register_a = result;
+        self.update_status_z_n(result);
+    }
+
+    // read-modify-write on the accumulator or memory
+    fn modify(&mut self, mode: &AddressingMode, f: fn(&mut CPU, u8) -> u8) -> u8 {
+        if let AddressingMode::Accumulator = mode {
+            self.register_a = f(self, self.register_a);
+            self.update_status_z_n(self.register_a);
+            return self.register_a;
+        }
+        let addr = self.get_operand_address(mode);
+        let value = self.mem_read(addr);
+        let result = f(self, value);
+        self.mem_write(addr, result);
+        self.update_status_z_n(result);
+        result
+    }
+
+    fn compare(&mut self, mode: &AddressingMode, register: u8) {
+        let value = self.read_operand(mode);
+        self.update_processor_status(register >= value, CARRY);
+        self.update_status_z_n(register.wrapping_sub(value));
+    }
+
+    fn branch(&mut self, condition: bool) {
+        if !condition {
+            return;
+        }
+        let offset = self.mem_read(self.program_counter) as i8;
+        let next = self.program_counter.wrapping_add(1);
+        let target = next.wrapping_add(offset as u16);
+        // taking the branch costs a cycle, and another one when it crosses a page
+        self.bus.tick(if next & 0xff00 != target & 0xff00 { 2 } else { 1 });
+        self.program_counter = target;
+    }
+
     // add with carry
-    fn _adc() {}
+    fn adc(&mut self, mode: &AddressingMode) {
+        let value = self.read_operand(mode);
+        self.add_to_register_a(value);
+    }
 
     // logical and
-    fn _and() {}
+    fn and(&mut self, mode: &AddressingMode) {
+        self.register_a &= self.read_operand(mode);
+        self.update_status_z_n(self.register_a);
+    }
 
     // arithmetic shift left
-    fn _asl() {}
-
-    // branches
-    fn _bcc() {}
-    fn _bcs() {}
-    fn _beq() {}
-    fn _bit() {}
-    fn _bmi() {}
-    fn _bne() {}
-    fn _bpl() {}
-
-    // break
-    fn _brk() {}
-
-    // clear carry/decimal/int_dsbl/overflow flags
-    fn _clc() {}
-    fn _cld() {}
-    fn _cli() {}
-    fn _clv() {}
-
-    // compare mem, x/y
-    fn _cmp() {}
-    fn _cpx() {}
-    fn _cpy() {}
+    fn asl(&mut self, mode: &AddressingMode) -> u8 {
+        self.modify(mode, |cpu, value| {
+            cpu.update_processor_status(value & 0x80 != 0, CARRY);
+            value << 1
+        })
+    }
+
+    fn bit(&mut self, mode: &AddressingMode) {
+        let addr = self.get_operand_address(mode);
+        let value = self.mem_read(addr);
+        self.update_processor_status(self.register_a & value == 0, ZERO);
+        self.update_processor_status(value & OVERFLOW != 0, OVERFLOW);
+        self.update_processor_status(value & NEGATIVE != 0, NEGATIVE);
+    }
+
+    // break, a software interrupt that skips the byte after the opcode
+    fn brk(&mut self) {
+        self.stack_push_u16(self.program_counter.wrapping_add(1));
+        self.stack_push(self.status | BREAK | BREAK2);
+        self.status |= INTERRUPT_DISABLE;
+        self.program_counter = self.mem_read_u16(0xfffe);
+    }
 
     // dec mem, reg x/y
-    fn _dec() {}
-    fn _dex() {}
-    fn _dey() {}
+    fn dec(&mut self, mode: &AddressingMode) -> u8 {
+        self.modify(mode, |_, value| value.wrapping_sub(1))
+    }
+    fn dex(&mut self, _mode: &AddressingMode) {
+        self.register_x = self.register_x.wrapping_sub(1);
+        self.update_status_z_n(self.register_x);
+    }
+    fn dey(&mut self, _mode: &AddressingMode) {
+        self.register_y = self.register_y.wrapping_sub(1);
+        self.update_status_z_n(self.register_y);
+    }
 
     // exclusive or
-    fn _eor() {}
+    fn eor(&mut self, mode: &AddressingMode) {
+        self.register_a ^= self.read_operand(mode);
+        self.update_status_z_n(self.register_a);
+    }
 
     // inc mem, reg x/y
-    fn _inc() {}
+    fn inc(&mut self, mode: &AddressingMode) -> u8 {
+        self.modify(mode, |_, value| value.wrapping_add(1))
+    }
     fn inx(&mut self, _mode: &AddressingMode) {
         self.register_x = self.register_x.wrapping_add(1);
         self.update_status_z_n(self.register_x);
     }
-    fn _iny() {}
+    fn iny(&mut self, _mode: &AddressingMode) {
+        self.register_y = self.register_y.wrapping_add(1);
+        self.update_status_z_n(self.register_y);
+    }
 
     // jmp
-    fn _jmp() {}
+    fn jmp(&mut self, mode: &AddressingMode) {
+        let addr = self.mem_read_u16(self.program_counter);
+        self.program_counter = match mode {
+            AddressingMode::Indirect => {
+                // the pointer's high byte is fetched without carrying into the page
+                let lo = self.mem_read(addr) as u16;
+                let hi = self.mem_read((addr & 0xff00) | (addr.wrapping_add(1) & 0x00ff)) as u16;
+                hi << 8 | lo
+            }
+            _ => addr,
+        };
+    }
     // jmp subroutine
-    fn _jsr() {}
+    fn jsr(&mut self, _mode: &AddressingMode) {
+        // the return address pushed is the last byte of the instruction
+        self.stack_push_u16(self.program_counter.wrapping_add(1));
+        self.program_counter = self.mem_read_u16(self.program_counter);
+    }
 
     // load reg a/x/y
     fn lda(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
-        let value = self.mem_read(addr);
+        let value = self.read_operand(mode);
         self.register_a = value;
         self.update_status_z_n(value);
     }
     fn ldx(&mut self, mode: &AddressingMode){
-        let addr = self.get_operand_address(mode);
-        let value = self.mem_read(addr);
+        let value = self.read_operand(mode);
         self.register_x = value;
         self.update_status_z_n(value);
     }
     fn ldy(&mut self, mode: &AddressingMode){
-        let addr = self.get_operand_address(mode);
-        let value = self.mem_read(addr);
+        let value = self.read_operand(mode);
         self.register_y = value;
         self.update_status_z_n(value);
     }
 
     // logical shift right
-    fn _lsr() {}
-
-    // no op
-    fn _nop() {}
+    fn lsr(&mut self, mode: &AddressingMode) -> u8 {
+        self.modify(mode, |cpu, value| {
+            cpu.update_processor_status(value & 1 != 0, CARRY);
+            value >> 1
+        })
+    }
 
     // logical inclusive or
-    fn _ora() {}
+    fn ora(&mut self, mode: &AddressingMode) {
+        self.register_a |= self.read_operand(mode);
+        self.update_status_z_n(self.register_a);
+    }
 
     // push/pop reg a/p
-    fn _pha() {}
-    fn _php() {}
-    fn _pla() {}
-    fn _plp() {}
+    fn pha(&mut self, _mode: &AddressingMode) {
+        self.stack_push(self.register_a);
+    }
+    fn php(&mut self, _mode: &AddressingMode) {
+        self.stack_push(self.status | BREAK | BREAK2);
+    }
+    fn pla(&mut self, _mode: &AddressingMode) {
+        self.register_a = self.stack_pop();
+        self.update_status_z_n(self.register_a);
+    }
+    fn plp(&mut self, _mode: &AddressingMode) {
+        self.status = (self.stack_pop() & !BREAK) | BREAK2;
+    }
 
     // rotate left/right
-    fn _rol() {}
-    fn _ror() {}
+    fn rol(&mut self, mode: &AddressingMode) -> u8 {
+        self.modify(mode, |cpu, value| {
+            let carry = cpu.status & CARRY;
+            cpu.update_processor_status(value & 0x80 != 0, CARRY);
+            value << 1 | carry
+        })
+    }
+    fn ror(&mut self, mode: &AddressingMode) -> u8 {
+        self.modify(mode, |cpu, value| {
+            let carry = cpu.status & CARRY;
+            cpu.update_processor_status(value & 1 != 0, CARRY);
+            value >> 1 | carry << 7
+        })
+    }
 
     // return from interrrupt/subroutine
-    fn _rti() {}
-    fn _rts() {}
+    fn rti(&mut self, _mode: &AddressingMode) {
+        self.status = (self.stack_pop() & !BREAK) | BREAK2;
+        self.program_counter = self.stack_pop_u16();
+    }
+    fn rts(&mut self, _mode: &AddressingMode) {
+        self.program_counter = self.stack_pop_u16().wrapping_add(1);
+    }
 
     // subtract with carry
-    fn _sbc() {}
-
-    // set carry/decimal/int_dsbl flags
-    fn _sec() {}
-    fn _sed() {}
-    fn _sei() {}
+    fn sbc(&mut self, mode: &AddressingMode) {
+        let value = self.read_operand(mode);
+        self.add_to_register_a(!value);
+    }
 
     // store reg a/x/y
     fn sta(&mut self, mode: &AddressingMode) {
@@ -262,78 +515,648 @@ impl CPU {
         self.register_x = self.register_a;
         self.update_status_z_n(self.register_x);
     }
-    fn _tay() {}
-    fn _tsx() {}
-    fn _txa() {}
-    fn _txs() {}
-    fn _tya() {}
+    fn tay(&mut self, _mode: &AddressingMode) {
+        self.register_y = self.register_a;
+        self.update_status_z_n(self.register_y);
+    }
+    fn tsx(&mut self, _mode: &AddressingMode) {
+        self.register_x = self.stack_pointer;
+        self.update_status_z_n(self.register_x);
+    }
+    fn txa(&mut self, _mode: &AddressingMode) {
+        self.register_a = self.register_x;
+        self.update_status_z_n(self.register_a);
+    }
+    fn txs(&mut self, _mode: &AddressingMode) {
+        self.stack_pointer = self.register_x;
+    }
+    fn tya(&mut self, _mode: &AddressingMode) {
+        self.register_a = self.register_y;
+        self.update_status_z_n(self.register_a);
+    }
 
-    pub fn run(&mut self) {
-        let ref opcodes: HashMap<u8, &'static ops::OpCode> = *ops::OPCODES_MAP;
+    // unofficial: load a and x, store a & x
+    fn lax(&mut self, mode: &AddressingMode) {
+        let value = self.read_operand(mode);
+        self.register_a = value;
+        self.register_x = value;
+        self.update_status_z_n(value);
+    }
+    fn sax(&mut self, mode: &AddressingMode) {
+        let addr = self.get_operand_address(mode);
+        self.mem_write(addr, self.register_a & self.register_x);
+    }
+
+    // unofficial: a read-modify-write, then an operation on a with the result
+    fn dcp(&mut self, mode: &AddressingMode) {
+        let value = self.dec(mode);
+        self.update_processor_status(self.register_a >= value, CARRY);
+        self.update_status_z_n(self.register_a.wrapping_sub(value));
+    }
+    fn isb(&mut self, mode: &AddressingMode) {
+        let value = self.inc(mode);
+        self.add_to_register_a(!value);
+    }
+    fn slo(&mut self, mode: &AddressingMode) {
+        self.register_a |= self.asl(mode);
+        self.update_status_z_n(self.register_a);
+    }
+    fn rla(&mut self, mode: &AddressingMode) {
+        self.register_a &= self.rol(mode);
+        self.update_status_z_n(self.register_a);
+    }
+    fn sre(&mut self, mode: &AddressingMode) {
+        self.register_a ^= self.lsr(mode);
+        self.update_status_z_n(self.register_a);
+    }
+    fn rra(&mut self, mode: &AddressingMode) {
+        let value = self.ror(mode);
+        self.add_to_register_a(value);
+    }
+
+    // unofficial: and with an immediate, then something on a
+    fn anc(&mut self, mode: &AddressingMode) {
+        self.and(mode);
+        self.update_processor_status(self.register_a & NEGATIVE != 0, CARRY);
+    }
+    fn alr(&mut self, mode: &AddressingMode) {
+        self.and(mode);
+        self.lsr(&AddressingMode::Accumulator);
+    }
+    fn arr(&mut self, mode: &AddressingMode) {
+        self.and(mode);
+        let value = self.ror(&AddressingMode::Accumulator);
+        // carry and overflow come from bits 6 and 5 of the result
+        self.update_processor_status(value & 0x40 != 0, CARRY);
+        self.update_processor_status((value >> 6 ^ value >> 5) & 1 != 0, OVERFLOW);
+    }
+    fn axs(&mut self, mode: &AddressingMode) {
+        let value = self.read_operand(mode);
+        let masked = self.register_a & self.register_x;
+        self.update_processor_status(masked >= value, CARRY);
+        self.register_x = masked.wrapping_sub(value);
+        self.update_status_z_n(self.register_x);
+    }
+    fn las(&mut self, mode: &AddressingMode) {
+        let value = self.read_operand(mode) & self.stack_pointer;
+        self.register_a = value;
+        self.register_x = value;
+        self.stack_pointer = value;
+        self.update_status_z_n(value);
+    }
+
+    // unofficial and unstable: a mixes in bits that vary between chips
+    fn xaa(&mut self, mode: &AddressingMode) {
+        self.register_a = (self.register_a | UNSTABLE_MAGIC) & self.register_x & self.read_operand(mode);
+        self.update_status_z_n(self.register_a);
+    }
+    fn lxa(&mut self, mode: &AddressingMode) {
+        let value = (self.register_a | UNSTABLE_MAGIC) & self.read_operand(mode);
+        self.register_a = value;
+        self.register_x = value;
+        self.update_status_z_n(value);
+    }
 
+    // unofficial and unstable: stores `value` anded with the high byte of
+    // the address before indexing plus one; when indexing crossed a page
+    // the address's high byte gets replaced by what's stored
+    fn store_and_high(&mut self, mode: &AddressingMode, value: u8) {
+        let addr = self.get_operand_address(mode);
+        let high = ((addr >> 8) as u8).wrapping_sub(self.page_crossed as u8);
+        let value = value & high.wrapping_add(1);
+        let addr = if self.page_crossed { (value as u16) << 8 | (addr & 0xff) } else { addr };
+        self.mem_write(addr, value);
+    }
+    fn tas(&mut self, mode: &AddressingMode) {
+        self.stack_pointer = self.register_a & self.register_x;
+        self.store_and_high(mode, self.stack_pointer);
+    }
+
+    /// Whether the CPU ran into one of the opcodes that halt it. The rest
+    /// of the console keeps running; only a reset gets the CPU going again.
+    pub fn is_jammed(&self) -> bool {
+        self.jammed
+    }
+
+    /// Runs until a BRK or a jam, or until the attached debugger stops the
+    /// CPU, and tells why in that case. Running again carries on from there.
+    pub fn run(&mut self) -> Option<BreakReason> {
         loop {
-            let opcode = self.mem_read(self.program_counter);
-            let op = opcodes.get(&opcode).unwrap();
-            self.program_counter += 1;
-
-            match opcode {
-
-                /* BRK */
-                0x00 => {
-                    return;
-                },
-
-                /* INX */
-                0xe8 => {
-                    self.inx(&op.mode);
-                },
-
-                /* LDA */
-                0xa9 | 0xa5 | 0xb5 | 0xad | 0xbd | 0xb9 | 0xa1 | 0xb1 => {
-                    self.lda(&op.mode);
-                },
-
-                /* LDX */
-                0xa2 | 0xa6 | 0xb6 | 0xae | 0xbe => {
-                    self.ldx(&op.mode);
-                },
-
-                /* LDY */
-                0xa0 | 0xa4 | 0xb4 | 0xac | 0xbc => {
-                    self.ldy(&op.mode);
-                },
-
-                /* STA */
-                0x85 | 0x95 | 0x8d | 0x9d | 0x99 | 0x81 | 0x91 => {
-                    self.sta(&op.mode);
-                },
-
-                /* STX */
-                0x86 | 0x96 | 0x8e => {
-                    self.stx(&op.mode);
-                },
-
-                /* STY */
-                0x84 | 0x94 | 0x8c => {
-                    self.sty(&op.mode);
-                },
-
-                /* TAX */
-                0xaa => {
-                    self.tax(&op.mode);
-                },
-
-                _ => todo!("opcode {:#02x}", opcode)
+            match self.execute(true) {
+                Execution::Ran => {
+                    self.after_instruction();
+                    if let Some(reason) = self.take_debugger_stop() {
+                        return Some(reason);
+                    }
+                }
+                Execution::Stopped => return self.take_debugger_stop(),
+                Execution::Ended => return None,
+            }
+        }
+    }
+
+    /// Calls `callback` with a nestest.log line for every instruction
+    /// before it runs, see `trace::trace`. `None` stops tracing.
+    pub fn set_tracer(&mut self, callback: Option<TraceCallback>) {
+        self.tracer.0 = callback;
+    }
+
+    /// Labels operands in trace lines, see `trace::trace_with_symbols`.
+    pub fn set_trace_symbols(&mut self, symbols: Option<Symbols>) {
+        self.tracer.1 = symbols;
+    }
+
+    pub fn is_tracing(&self) -> bool {
+        self.tracer.0.is_some()
+    }
+
+    /// Keeps the last `len` instructions run, for working out how the CPU
+    /// got to where it jammed. 0 turns it off, as it is at first.
+    pub fn set_history_len(&mut self, len: usize) {
+        self.history.set_len(len);
+    }
+
+    pub fn history(&self) -> &History {
+        &self.history
+    }
+
+    pub fn registers(&self) -> Registers {
+        Registers {
+            a: self.register_a,
+            x: self.register_x,
+            y: self.register_y,
+            status: self.status,
+            program_counter: self.program_counter,
+            stack_pointer: self.stack_pointer,
+        }
+    }
+
+    /// Runs until the PPU finishes the frame it is drawing and returns it.
+    /// The instruction the frame ends in runs to completion, so the few
+    /// cycles past the end count towards the next frame. Stops early when
+    /// the debugger breaks, see `run_to_frame_end`.
+    pub fn run_frame(&mut self) -> &Frame {
+        self.run_to_frame_end();
+        self.bus.ppu.frame()
+    }
+
+    /// Runs until the PPU finishes the frame it is drawing, or until the
+    /// attached debugger stops the CPU, and tells why.
+    pub fn run_to_frame_end(&mut self) -> Option<BreakReason> {
+        let frame = self.bus.ppu.frame().number();
+        while self.bus.ppu.frame().number() == frame {
+            self.step();
+            if let Some(reason) = self.take_debugger_stop() {
+                return Some(reason);
+            }
+        }
+        self.run_frame_hooks();
+        None
+    }
+
+    /// Executes one instruction, taking a pending interrupt first. Doesn't
+    /// run it if the attached debugger breaks there.
+    pub fn step(&mut self) {
+        self.execute(false);
+        self.after_instruction();
+    }
+
+    fn after_instruction(&mut self) {
+        if let Some(mut debugger) = self.bus.debugger.0.take() {
+            debugger.after_instruction(self);
+            self.bus.debugger.0 = Some(debugger);
+        }
+    }
+
+    fn take_debugger_stop(&mut self) -> Option<BreakReason> {
+        self.bus.debugger.0.as_mut().and_then(|debugger| debugger.take_stop())
+    }
+
+    // runs one instruction; `run` treats BRK as the end of the program
+    // instead of taking the interrupt. A jammed CPU lets the rest of the
+    // console run a cycle.
+    fn execute(&mut self, stop_at_brk: bool) -> Execution {
+        if self.jammed {
+            self.bus.tick(1);
+            return Execution::Ended;
+        }
+        if self.bus.poll_nmi_status() {
+            self.interrupt(0xfffa);
+        } else if self.bus.irq_pending() && self.status & INTERRUPT_DISABLE == 0 {
+            self.interrupt(0xfffe);
+        }
+
+        // the debugger looks at the whole CPU, so it's taken out meanwhile
+        if let Some(mut debugger) = self.bus.debugger.0.take() {
+            let stop = debugger.before_instruction(self);
+            self.bus.debugger.0 = Some(debugger);
+            if stop {
+                return Execution::Stopped;
+            }
+        }
+        self.run_instruction_hooks();
+
+        if self.tracer.0.is_some() {
+            let line = match &self.tracer.1 {
+                Some(symbols) => trace::trace_with_symbols(self, symbols),
+                None => trace::trace(self),
             };
+            if let Some(callback) = &mut self.tracer.0 {
+                callback(&line);
+            }
+        }
 
-            self.program_counter += op.cycles as u16 - 1;
+        let opcode = self.mem_read(self.program_counter);
+        let Some(op) = ops::opcode(opcode) else {
+            diag!(error, pc = format_args!("${:04X}", self.program_counter), opcode = format_args!("${:02X}", opcode), "CPU jammed");
+            self.jammed = true;
+            self.bus.tick(1);
+            return Execution::Ended;
+        };
+        diag!(
+            trace,
+            pc = format_args!("${:04X}", self.program_counter),
+            op = op.name,
+            a = format_args!("${:02X}", self.register_a),
+            x = format_args!("${:02X}", self.register_x),
+            y = format_args!("${:02X}", self.register_y),
+            p = format_args!("${:02X}", self.status),
+            sp = format_args!("${:02X}", self.stack_pointer),
+            "instruction"
+        );
+        if self.history.is_enabled() {
+            self.history.push(HistoryEntry { opcode, registers: self.registers() });
+        }
+        if let Some(profiler) = &mut self.profiler {
+            let location = Location { bank: self.bus.prg_bank(self.program_counter), addr: self.program_counter };
+            profiler.record(opcode, location, self.bus.clock().cpu_cycles(), self.stack_pointer);
+        }
+        if opcode == 0x00 && stop_at_brk {
+            return Execution::Ended;
         }
+        self.program_counter += 1;
+        let program_counter_state = self.program_counter;
+        self.page_crossed = false;
+
+        // operands are accessed on the last cycle of an instruction, so let the
+        // rest of the system catch up to that point before executing it
+        self.bus.tick(op.cycles as u16 - 1);
+
+        match opcode {
+
+            /* ADC */
+            0x69 | 0x65 | 0x75 | 0x6d | 0x7d | 0x79 | 0x61 | 0x71 => {
+                self.adc(&op.mode);
+            },
+
+            /* AND */
+            0x29 | 0x25 | 0x35 | 0x2d | 0x3d | 0x39 | 0x21 | 0x31 => {
+                self.and(&op.mode);
+            },
+
+            /* ASL */
+            0x0a | 0x06 | 0x16 | 0x0e | 0x1e => {
+                self.asl(&op.mode);
+            },
+
+            /* BCC, BCS, BEQ, BMI, BNE, BPL, BVC, BVS */
+            0x90 => self.branch(self.status & CARRY == 0),
+            0xb0 => self.branch(self.status & CARRY != 0),
+            0xf0 => self.branch(self.status & ZERO != 0),
+            0x30 => self.branch(self.status & NEGATIVE != 0),
+            0xd0 => self.branch(self.status & ZERO == 0),
+            0x10 => self.branch(self.status & NEGATIVE == 0),
+            0x50 => self.branch(self.status & OVERFLOW == 0),
+            0x70 => self.branch(self.status & OVERFLOW != 0),
+
+            /* BIT */
+            0x24 | 0x2c => {
+                self.bit(&op.mode);
+            },
+
+            /* BRK */
+            0x00 => {
+                self.brk();
+            },
+
+            /* CLC, CLD, CLI, CLV */
+            0x18 => self.status &= !CARRY,
+            0xd8 => self.status &= !DECIMAL_MODE,
+            0x58 => self.status &= !INTERRUPT_DISABLE,
+            0xb8 => self.status &= !OVERFLOW,
+
+            /* CMP */
+            0xc9 | 0xc5 | 0xd5 | 0xcd | 0xdd | 0xd9 | 0xc1 | 0xd1 => {
+                self.compare(&op.mode, self.register_a);
+            },
+
+            /* CPX */
+            0xe0 | 0xe4 | 0xec => {
+                self.compare(&op.mode, self.register_x);
+            },
+
+            /* CPY */
+            0xc0 | 0xc4 | 0xcc => {
+                self.compare(&op.mode, self.register_y);
+            },
+
+            /* DEC */
+            0xc6 | 0xd6 | 0xce | 0xde => {
+                self.dec(&op.mode);
+            },
+
+            /* DEX */
+            0xca => {
+                self.dex(&op.mode);
+            },
+
+            /* DEY */
+            0x88 => {
+                self.dey(&op.mode);
+            },
+
+            /* EOR */
+            0x49 | 0x45 | 0x55 | 0x4d | 0x5d | 0x59 | 0x41 | 0x51 => {
+                self.eor(&op.mode);
+            },
+
+            /* INC */
+            0xe6 | 0xf6 | 0xee | 0xfe => {
+                self.inc(&op.mode);
+            },
+
+            /* INX */
+            0xe8 => {
+                self.inx(&op.mode);
+            },
+
+            /* INY */
+            0xc8 => {
+                self.iny(&op.mode);
+            },
+
+            /* JMP */
+            0x4c | 0x6c => {
+                self.jmp(&op.mode);
+            },
+
+            /* JSR */
+            0x20 => {
+                self.jsr(&op.mode);
+            },
+
+            /* LDA */
+            0xa9 | 0xa5 | 0xb5 | 0xad | 0xbd | 0xb9 | 0xa1 | 0xb1 => {
+                self.lda(&op.mode);
+            },
+
+            /* LDX */
+            0xa2 | 0xa6 | 0xb6 | 0xae | 0xbe => {
+                self.ldx(&op.mode);
+            },
+
+            /* LDY */
+            0xa0 | 0xa4 | 0xb4 | 0xac | 0xbc => {
+                self.ldy(&op.mode);
+            },
+
+            /* LSR */
+            0x4a | 0x46 | 0x56 | 0x4e | 0x5e => {
+                self.lsr(&op.mode);
+            },
+
+            /* NOP */
+            0xea => {},
+
+            /* ORA */
+            0x09 | 0x05 | 0x15 | 0x0d | 0x1d | 0x19 | 0x01 | 0x11 => {
+                self.ora(&op.mode);
+            },
+
+            /* PHA */
+            0x48 => {
+                self.pha(&op.mode);
+            },
+
+            /* PHP */
+            0x08 => {
+                self.php(&op.mode);
+            },
+
+            /* PLA */
+            0x68 => {
+                self.pla(&op.mode);
+            },
+
+            /* PLP */
+            0x28 => {
+                self.plp(&op.mode);
+            },
+
+            /* ROL */
+            0x2a | 0x26 | 0x36 | 0x2e | 0x3e => {
+                self.rol(&op.mode);
+            },
+
+            /* ROR */
+            0x6a | 0x66 | 0x76 | 0x6e | 0x7e => {
+                self.ror(&op.mode);
+            },
+
+            /* RTI */
+            0x40 => {
+                self.rti(&op.mode);
+            },
+
+            /* RTS */
+            0x60 => {
+                self.rts(&op.mode);
+            },
+
+            /* SBC */
+            0xe9 | 0xe5 | 0xf5 | 0xed | 0xfd | 0xf9 | 0xe1 | 0xf1 => {
+                self.sbc(&op.mode);
+            },
+
+            /* SEC, SED, SEI */
+            0x38 => self.status |= CARRY,
+            0xf8 => self.status |= DECIMAL_MODE,
+            0x78 => self.status |= INTERRUPT_DISABLE,
+
+            /* STA */
+            0x85 | 0x95 | 0x8d | 0x9d | 0x99 | 0x81 | 0x91 => {
+                self.sta(&op.mode);
+            },
+
+            /* STX */
+            0x86 | 0x96 | 0x8e => {
+                self.stx(&op.mode);
+            },
+
+            /* STY */
+            0x84 | 0x94 | 0x8c => {
+                self.sty(&op.mode);
+            },
+
+            /* TAX */
+            0xaa => {
+                self.tax(&op.mode);
+            },
+
+            /* TAY */
+            0xa8 => {
+                self.tay(&op.mode);
+            },
+
+            /* TSX */
+            0xba => {
+                self.tsx(&op.mode);
+            },
+
+            /* TXA */
+            0x8a => {
+                self.txa(&op.mode);
+            },
+
+            /* TXS */
+            0x9a => {
+                self.txs(&op.mode);
+            },
+
+            /* TYA */
+            0x98 => {
+                self.tya(&op.mode);
+            },
+
+            /* unofficial NOPs, those with an operand read it */
+            0x1a | 0x3a | 0x5a | 0x7a | 0xda | 0xfa => {},
+            0x80 | 0x82 | 0x89 | 0xc2 | 0xe2 | 0x04 | 0x44 | 0x64 | 0x14 | 0x34 | 0x54 | 0x74 | 0xd4 | 0xf4 | 0x0c | 0x1c | 0x3c | 0x5c
+            | 0x7c | 0xdc | 0xfc => {
+                self.read_operand(&op.mode);
+            },
+
+            /* LAX */
+            0xa7 | 0xb7 | 0xaf | 0xbf | 0xa3 | 0xb3 => {
+                self.lax(&op.mode);
+            },
+
+            /* SAX */
+            0x87 | 0x97 | 0x8f | 0x83 => {
+                self.sax(&op.mode);
+            },
+
+            /* SBC, unofficial copy */
+            0xeb => {
+                self.sbc(&op.mode);
+            },
+
+            /* DCP */
+            0xc7 | 0xd7 | 0xcf | 0xdf | 0xdb | 0xc3 | 0xd3 => {
+                self.dcp(&op.mode);
+            },
+
+            /* ISB */
+            0xe7 | 0xf7 | 0xef | 0xff | 0xfb | 0xe3 | 0xf3 => {
+                self.isb(&op.mode);
+            },
+
+            /* SLO */
+            0x07 | 0x17 | 0x0f | 0x1f | 0x1b | 0x03 | 0x13 => {
+                self.slo(&op.mode);
+            },
+
+            /* RLA */
+            0x27 | 0x37 | 0x2f | 0x3f | 0x3b | 0x23 | 0x33 => {
+                self.rla(&op.mode);
+            },
+
+            /* SRE */
+            0x47 | 0x57 | 0x4f | 0x5f | 0x5b | 0x43 | 0x53 => {
+                self.sre(&op.mode);
+            },
+
+            /* RRA */
+            0x67 | 0x77 | 0x6f | 0x7f | 0x7b | 0x63 | 0x73 => {
+                self.rra(&op.mode);
+            },
+
+            /* ANC, ALR, ARR, AXS, LAS */
+            0x0b | 0x2b => self.anc(&op.mode),
+            0x4b => self.alr(&op.mode),
+            0x6b => self.arr(&op.mode),
+            0xcb => self.axs(&op.mode),
+            0xbb => self.las(&op.mode),
+
+            /* XAA, LXA, AHX, TAS, SHY, SHX */
+            0x8b => self.xaa(&op.mode),
+            0xab => self.lxa(&op.mode),
+            0x9f | 0x93 => self.store_and_high(&op.mode, self.register_a & self.register_x),
+            0x9b => self.tas(&op.mode),
+            0x9c => self.store_and_high(&op.mode, self.register_y),
+            0x9e => self.store_and_high(&op.mode, self.register_x),
+
+            _ => unreachable!("opcode {:#02x} is in the table but not handled", opcode)
+        };
+
+        self.bus.tick(1);
+
+        if program_counter_state == self.program_counter {
+            self.program_counter += op.len as u16 - 1;
+        }
+        Execution::Ran
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::debugger::Debugger;
+
+    #[test]
+    fn test_jam_keeps_history() {
+        let mut cpu = CPU::new();
+        cpu.set_history_len(2);
+        cpu.load_and_run(vec![0xa2, 0x05, 0xe8, 0x02]);
+        assert!(cpu.is_jammed());
+        assert_eq!(cpu.program_counter, 0x8003);
+        assert_eq!(cpu.history().to_string(), "8000  A2  A:00 X:00 Y:00 P:24 SP:FD\n8002  E8  A:00 X:05 Y:00 P:24 SP:FD\n");
+    }
+
+    #[test]
+    fn test_run_stops_for_the_debugger() {
+        let mut cpu = CPU::new();
+        cpu.load(vec![0xe8, 0xe8, 0xe8, 0x00]);
+        cpu.reset();
+        let mut debugger = Debugger::new();
+        debugger.add_breakpoint(0x8002);
+        cpu.bus.debugger.0 = Some(Box::new(debugger));
+        assert_eq!(cpu.run(), Some(BreakReason::Breakpoint(0x8002)));
+        assert_eq!(cpu.register_x, 2);
+        assert_eq!(cpu.run(), None);
+        assert_eq!(cpu.register_x, 3);
+    }
+
+    #[test]
+    fn test_jammed_console_keeps_running_until_reset() {
+        let mut cpu = CPU::new();
+        cpu.load(vec![0x02]);
+        cpu.reset();
+        assert_eq!(cpu.run_frame().number(), 1);
+        assert_eq!(cpu.run_frame().number(), 2);
+        assert!(cpu.is_jammed());
+        assert_eq!(cpu.program_counter, 0x8000);
+        cpu.mem_write(0x8000, 0xe8);
+        cpu.soft_reset();
+        assert!(!cpu.is_jammed());
+        cpu.step();
+        assert_eq!(cpu.register_x, 1);
+    }
+
+    #[test]
+    fn test_only_jams_are_missing() {
+        let missing: Vec<u8> = (0..=0xffu8).filter(|&code| ops::opcode(code).is_none()).collect();
+        assert_eq!(missing, [0x02, 0x12, 0x22, 0x32, 0x42, 0x52, 0x62, 0x72, 0x92, 0xb2, 0xd2, 0xf2]);
+    }
 
     #[test]
     fn test_0xa0_ldy_immediate_load_data() {
@@ -809,4 +1632,270 @@ mod test {
         assert_eq!(cpu.register_x, 0x10);
         assert_eq!(value, 0x05);
     }
+
+    #[test]
+    fn test_adc_carry_and_overflow() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![
+            0xa9, 0x50, // LDA #$50
+            0x69, 0x50, // ADC #$50
+            0x00,
+        ]);
+        assert_eq!(cpu.register_a, 0xa0);
+        assert_eq!(cpu.status & (CARRY | OVERFLOW | NEGATIVE), OVERFLOW | NEGATIVE);
+
+        cpu.load_and_run(vec![
+            0xa9, 0xff, // LDA #$ff
+            0x69, 0x02, // ADC #$02
+            0x00,
+        ]);
+        assert_eq!(cpu.register_a, 0x01);
+        assert_eq!(cpu.status & (CARRY | OVERFLOW), CARRY);
+    }
+
+    #[test]
+    fn test_sbc_borrows_through_carry() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![
+            0x38,       // SEC
+            0xa9, 0x05, // LDA #$05
+            0xe9, 0x07, // SBC #$07
+            0x00,
+        ]);
+        assert_eq!(cpu.register_a, 0xfe);
+        assert_eq!(cpu.status & CARRY, 0);
+        assert_eq!(cpu.status & NEGATIVE, NEGATIVE);
+    }
+
+    #[test]
+    fn test_countdown_loop() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![
+            0xa2, 0x05, // LDX #$05
+            0xa9, 0x00, // LDA #$00
+            0x18,       // CLC
+            0x69, 0x03, // ADC #$03
+            0xca,       // DEX
+            0xd0, 0xfa, // BNE -6
+            0x00,
+        ]);
+        assert_eq!(cpu.register_a, 15);
+        assert_eq!(cpu.register_x, 0);
+    }
+
+    #[test]
+    fn test_jsr_rts_and_stack() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![
+            0x20, 0x05, 0x80, // JSR $8005
+            0xaa,             // TAX
+            0x00,
+            0xa9, 0x42,       // LDA #$42
+            0x48,             // PHA
+            0xa9, 0x00,       // LDA #$00
+            0x68,             // PLA
+            0x60,             // RTS
+        ]);
+        assert_eq!(cpu.register_x, 0x42);
+        assert_eq!(cpu.stack_pointer, STACK_RESET);
+    }
+
+    #[test]
+    fn test_jmp_indirect_wraps_within_page() {
+        let mut cpu = CPU::new();
+        cpu.mem_write(0x02ff, 0x10);
+        cpu.mem_write(0x0200, 0x80);
+        cpu.mem_write(0x0300, 0x90);
+        cpu.mem_write(0x8010, 0xe8); // INX
+        cpu.mem_write(0x8011, 0x00);
+        cpu.load(vec![0x6c, 0xff, 0x02]);
+        cpu.reset();
+        cpu.run();
+        assert_eq!(cpu.register_x, 1);
+        assert_eq!(cpu.program_counter, 0x8011);
+    }
+
+    #[test]
+    fn test_shifts_and_rotates_memory() {
+        let mut cpu = CPU::new();
+        cpu.mem_write(0x10, 0b1000_0001);
+        cpu.load_and_run(vec![
+            0x06, 0x10, // ASL $10
+            0x66, 0x10, // ROR $10
+            0x00,
+        ]);
+        assert_eq!(cpu.mem_read(0x10), 0b1000_0001);
+        assert_eq!(cpu.status & CARRY, 0);
+    }
+
+    #[test]
+    fn test_php_plp_flags() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![
+            0x38,       // SEC
+            0xf8,       // SED
+            0x08,       // PHP
+            0x18,       // CLC
+            0xd8,       // CLD
+            0x68,       // PLA
+            0x48,       // PHA
+            0x28,       // PLP
+            0x00,
+        ]);
+        // PHP pushes the break bits, PLP drops bit 4 again
+        assert_eq!(cpu.register_a & (BREAK | BREAK2), BREAK | BREAK2);
+        assert_eq!(cpu.status & (CARRY | DECIMAL_MODE | BREAK), CARRY | DECIMAL_MODE);
+    }
+
+    #[test]
+    fn test_cmp_sets_carry_and_zero() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![0xa9, 0x40, 0xc9, 0x40, 0x00]);
+        assert_eq!(cpu.status & (CARRY | ZERO), CARRY | ZERO);
+        cpu.load_and_run(vec![0xa0, 0x10, 0xc0, 0x20, 0x00]);
+        assert_eq!(cpu.status & (CARRY | ZERO | NEGATIVE), NEGATIVE);
+    }
+
+    #[test]
+    fn test_page_cross_and_branch_cycles() {
+        let mut cpu = CPU::new();
+        cpu.load(vec![
+            0xa2, 0x01,       // LDX #$01      2
+            0xbd, 0xff, 0x02, // LDA $02ff,X   4 + 1
+            0xbd, 0x00, 0x02, // LDA $0200,X   4
+            0xf0, 0x00,       // BEQ +0        2 + 1
+            0x00,
+        ]);
+        cpu.reset();
+        let start = cpu.bus.cycles();
+        cpu.run();
+        assert_eq!(cpu.bus.cycles() - start, 14);
+    }
+
+    #[test]
+    fn test_brk_is_an_interrupt_when_stepping() {
+        let mut cpu = CPU::new();
+        cpu.load(vec![0x00, 0xff, 0xe8]);
+        cpu.mem_write_u16(0xfffe, 0x8002);
+        cpu.reset();
+        cpu.step();
+        assert_eq!(cpu.program_counter, 0x8002);
+        assert_eq!(cpu.status & INTERRUPT_DISABLE, INTERRUPT_DISABLE);
+        assert_eq!(cpu.stack_pop() & BREAK, BREAK);
+        assert_eq!(cpu.stack_pop_u16(), 0x8002);
+    }
+
+    #[test]
+    fn test_unofficial_loads_and_stores() {
+        let mut cpu = CPU::new();
+        cpu.mem_write(0x10, 0x8f);
+        cpu.load_and_run(vec![
+            0xa7, 0x10,       // LAX $10
+            0xa9, 0xf0,       // LDA #$f0
+            0x87, 0x11,       // SAX $11
+            0x04, 0x10,       // NOP $10
+            0x00,
+        ]);
+        assert_eq!(cpu.register_x, 0x8f);
+        assert_eq!(cpu.mem_read(0x11), 0x80);
+    }
+
+    #[test]
+    fn test_unofficial_read_modify_writes() {
+        let mut cpu = CPU::new();
+        cpu.mem_write(0x10, 0x41);
+        cpu.mem_write(0x11, 0x80);
+        cpu.mem_write(0x12, 0x01);
+        cpu.load_and_run(vec![
+            0xa9, 0x40,       // LDA #$40
+            0xc7, 0x10,       // DCP $10: $10 = $40, A - $40 = 0
+            0x07, 0x11,       // SLO $11: $11 = $00, carry out, A |= 0
+            0x67, 0x12,       // RRA $12: $12 = $80 with the carry, A = $40 + $80 + 1
+            0x00,
+        ]);
+        assert_eq!(cpu.mem_read(0x10), 0x40);
+        assert_eq!(cpu.mem_read(0x11), 0x00);
+        assert_eq!(cpu.mem_read(0x12), 0x80);
+        assert_eq!(cpu.register_a, 0xc1);
+        assert_eq!(cpu.status & (CARRY | NEGATIVE), NEGATIVE);
+
+        let mut cpu = CPU::new();
+        cpu.mem_write(0x10, 0xff);
+        cpu.load_and_run(vec![
+            0x38,             // SEC
+            0xa9, 0x05,       // LDA #$05
+            0xe7, 0x10,       // ISB $10: $10 = $00, A - 0
+            0x00,
+        ]);
+        assert_eq!(cpu.mem_read(0x10), 0x00);
+        assert_eq!(cpu.register_a, 0x05);
+        assert_eq!(cpu.status & CARRY, CARRY);
+    }
+
+    #[test]
+    fn test_unofficial_immediates() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![
+            0xa9, 0xff,       // LDA #$ff
+            0x0b, 0x81,       // ANC #$81: A = $81, carry from bit 7
+            0x6b, 0xc0,       // ARR #$c0: A = $80, rotated with the carry to $c0
+            0xa2, 0x0f,       // LDX #$0f
+            0xcb, 0x01,       // AXS #$01: X = ($c0 & $0f) - 1
+            0x00,
+        ]);
+        assert_eq!(cpu.register_a, 0xc0);
+        assert_eq!(cpu.register_x, 0xff);
+        // AXS borrowed, ARR set overflow from bits 6 and 5
+        assert_eq!(cpu.status & (CARRY | OVERFLOW | NEGATIVE), OVERFLOW | NEGATIVE);
+    }
+
+    #[test]
+    fn test_unofficial_high_byte_stores() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![
+            0xa2, 0xff,       // LDX #$ff
+            0xa0, 0x01,       // LDY #$01
+            0x9e, 0x00, 0x04, // SHX $0400,Y: $ff & $05 to $0401
+            0xa2, 0x01,       // LDX #$01
+            0x9e, 0xff, 0x02, // SHX $02ff,Y: $01 & $03, and to $0100 instead of $0300
+            0x00,
+        ]);
+        assert_eq!(cpu.mem_read(0x0401), 0x05);
+        assert_eq!(cpu.mem_read(0x0100), 0x01);
+        assert_eq!(cpu.mem_read(0x0300), 0x00);
+    }
+
+    fn cpu_with_dmc_irq() -> CPU {
+        let mut cpu = CPU::new();
+        cpu.load(vec![0xa9, 0x01, 0x00]);
+        // handler: LDX #$42, BRK
+        cpu.mem_write(0x8100, 0xa2);
+        cpu.mem_write(0x8101, 0x42);
+        cpu.mem_write(0x8102, 0x00);
+        cpu.mem_write_u16(0xfffe, 0x8100);
+        cpu.reset();
+        // a one byte sample that raises its IRQ as soon as it's fetched
+        cpu.mem_write(0x4010, 0b1000_0000);
+        cpu.mem_write(0x4013, 0x00);
+        cpu.mem_write(0x4015, 0b1_0000);
+        cpu
+    }
+
+    #[test]
+    fn test_irq_is_taken_when_enabled() {
+        let mut cpu = cpu_with_dmc_irq();
+        cpu.status &= !0b0000_0100;
+        cpu.run();
+        assert_eq!(cpu.register_a, 0x01);
+        assert_eq!(cpu.register_x, 0x42);
+        assert!(cpu.status & 0b0000_0100 != 0);
+    }
+
+    #[test]
+    fn test_irq_is_ignored_with_interrupts_disabled() {
+        let mut cpu = cpu_with_dmc_irq();
+        cpu.run();
+        assert_eq!(cpu.register_a, 0x01);
+        assert_eq!(cpu.register_x, 0x00);
+    }
 }
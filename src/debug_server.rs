@@ -0,0 +1,388 @@
+//! A debug server for web debugger UIs and editor plugins, speaking JSON
+//! over a WebSocket. Each text message from the client is a request:
+//!
+//! ```json
+//! {"id": 1, "method": "readMemory", "params": {"addr": 49152, "len": 16}}
+//! ```
+//!
+//! and gets a reply with the same `id` and either a `result` or an `error`
+//! string. The server also sends messages of its own, with an `event` name:
+//! `stopped` with the `reason` whenever the console stops after a
+//! `continue`, and the console events the client subscribed to.
+//!
+//! | method | params | result |
+//! |---|---|---|
+//! | `registers` | | `a` `x` `y` `p` `sp` `pc` `cycles` `scanline` `dot` `frame` |
+//! | `setRegisters` | any of `a` `x` `y` `p` `sp` `pc` | |
+//! | `readMemory` | `addr`, `len`, `space` `"cpu"` or `"ppu"` | `bytes` |
+//! | `writeMemory` | `addr`, `bytes`, `space` | |
+//! | `setBreakpoint` | `addr` or `label`, `condition` | `addr` |
+//! | `removeBreakpoint` | `addr` | `removed` |
+//! | `setWatchpoint` | `start`, `end`, `kind` `"read"` `"write"` or `"readwrite"`, `condition` | `id` |
+//! | `removeWatchpoint` | `id` | `removed` |
+//! | `breakpoints` | | `breakpoints`, `watchpoints` |
+//! | `callStack` | | `frames` with `kind` `entry` `returnAddr` |
+//! | `step` | `kind` `"into"` `"over"` or `"out"` | `reason` |
+//! | `runTo` | `addr` | `reason` |
+//! | `continue` | | |
+//! | `pause` | | |
+//! | `subscribe` | `events`: any of `frame` `vblank` `mapperIrq` `apuIrq` `stateLoaded` `lagFrame` | |
+//!
+//! Addresses and values are plain numbers. A `reason` is `{"kind":
+//! "breakpoint", "addr"}`, `{"kind": "watchpoint", "id", "addr", "value",
+//! "access", "pc"}`, `{"kind": "step"}`, `{"kind": "pause"}` or, for a
+//! `runTo` or `step` that got to the end of the frame first, `{"kind":
+//! "frame"}`.
+
+use crate::debugger::condition::Condition;
+use crate::debugger::memory::{self, Space};
+use crate::debugger::{Access, BreakReason, Debugger, RunResult, WatchKind, WatchpointId};
+use crate::events::ConsoleEvent;
+use crate::nes::Nes;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashSet};
+use std::io::ErrorKind;
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{channel, Receiver};
+use tungstenite::{Message, WebSocket};
+
+/// The state of one client: its watchpoints by the ids it was given, what
+/// it subscribed to and whether the console is running for it.
+#[derive(Debug, Default)]
+pub struct DebugServer {
+    watchpoints: BTreeMap<u64, WatchpointId>,
+    next_watchpoint: u64,
+    subscribed: HashSet<&'static str>,
+    running: bool,
+}
+
+/// Waits for a client to connect on `listener` and serves it until it
+/// disconnects. Attaches a debugger to `nes` if there is none.
+pub fn serve(nes: &mut Nes, listener: &TcpListener) -> Result<(), String> {
+    let (stream, _) = listener.accept().map_err(|err| format!("couldn't accept debug client: {}", err))?;
+    let socket = tungstenite::accept(stream).map_err(|err| format!("WebSocket handshake failed: {}", err))?;
+    let (sender, events) = channel();
+    let subscription = nes.subscribe(move |event| {
+        let _ = sender.send(event);
+    });
+    let served = DebugServer::default().run(nes, socket, &events);
+    nes.unsubscribe(subscription);
+    served
+}
+
+impl DebugServer {
+    fn run(&mut self, nes: &mut Nes, mut socket: WebSocket<TcpStream>, events: &Receiver<ConsoleEvent>) -> Result<(), String> {
+        if nes.debugger().is_none() {
+            nes.attach_debugger(Debugger::new());
+        }
+        let failed = |err: tungstenite::Error| format!("debug connection failed: {}", err);
+        loop {
+            if self.running {
+                if let RunResult::Stopped(reason) = nes.run() {
+                    self.running = false;
+                    socket.send(Message::text(json!({ "event": "stopped", "reason": self.reason(Some(reason)) }).to_string())).map_err(failed)?;
+                }
+            }
+            for event in events.try_iter() {
+                if let Some(message) = self.event(event) {
+                    socket.send(Message::text(message.to_string())).map_err(failed)?;
+                }
+            }
+
+            // only wait for the client while the console is stopped
+            socket.get_mut().set_nonblocking(self.running).map_err(|err| err.to_string())?;
+            let message = socket.read();
+            socket.get_mut().set_nonblocking(false).map_err(|err| err.to_string())?;
+            let text = match message {
+                Ok(Message::Text(text)) => text,
+                Ok(Message::Close(_)) | Err(tungstenite::Error::ConnectionClosed) => return Ok(()),
+                Ok(_) => continue,
+                Err(tungstenite::Error::Io(err)) if err.kind() == ErrorKind::WouldBlock => continue,
+                Err(err) => return Err(failed(err)),
+            };
+            let reply = self.request(nes, &text);
+            socket.send(Message::text(reply.to_string())).map_err(failed)?;
+        }
+    }
+
+    fn run_reason(&self, result: RunResult) -> Value {
+        match result {
+            RunResult::Stopped(reason) => self.reason(Some(reason)),
+            RunResult::Completed => json!({ "kind": "frame" }),
+        }
+    }
+
+    // `None` for a pause
+    fn reason(&self, reason: Option<BreakReason>) -> Value {
+        match reason {
+            Some(BreakReason::Breakpoint(addr)) => json!({ "kind": "breakpoint", "addr": addr }),
+            Some(BreakReason::Watchpoint { id, addr, value, access, program_counter }) => {
+                let id = self.watchpoints.iter().find(|(_, other)| **other == id).map(|(number, _)| *number);
+                let access = if access == Access::Read { "read" } else { "write" };
+                json!({ "kind": "watchpoint", "id": id, "addr": addr, "value": value, "access": access, "pc": program_counter })
+            }
+            Some(BreakReason::Step) => json!({ "kind": "step" }),
+            None => json!({ "kind": "pause" }),
+        }
+    }
+
+    fn event(&self, event: ConsoleEvent) -> Option<Value> {
+        let (name, fields) = match event {
+            ConsoleEvent::FrameComplete(number) => ("frame", json!({ "number": number })),
+            ConsoleEvent::VblankStart => ("vblank", json!({})),
+            ConsoleEvent::MapperIrq => ("mapperIrq", json!({})),
+            ConsoleEvent::ApuFrameIrq => ("apuIrq", json!({})),
+            ConsoleEvent::StateLoaded => ("stateLoaded", json!({})),
+            ConsoleEvent::LagFrame(number) => ("lagFrame", json!({ "number": number })),
+        };
+        if !self.subscribed.contains(name) {
+            return None;
+        }
+        let mut message = fields;
+        message["event"] = json!(name);
+        Some(message)
+    }
+
+    /// Answers one request, see the module docs.
+    pub fn request(&mut self, nes: &mut Nes, text: &str) -> Value {
+        let request: Value = match serde_json::from_str(text) {
+            Ok(request) => request,
+            Err(err) => return json!({ "id": null, "error": format!("bad JSON: {}", err) }),
+        };
+        let id = request.get("id").cloned().unwrap_or(Value::Null);
+        let method = request.get("method").and_then(Value::as_str).unwrap_or("");
+        let params = request.get("params").cloned().unwrap_or_else(|| json!({}));
+        match self.call(nes, method, &params) {
+            Ok(result) => json!({ "id": id, "result": result }),
+            Err(err) => json!({ "id": id, "error": err }),
+        }
+    }
+
+    fn call(&mut self, nes: &mut Nes, method: &str, params: &Value) -> Result<Value, String> {
+        if nes.debugger().is_none() {
+            nes.attach_debugger(Debugger::new());
+        }
+        let result = match method {
+            "registers" => {
+                let cpu = nes.cpu();
+                let ppu = &cpu.bus.ppu;
+                json!({
+                    "a": cpu.register_a, "x": cpu.register_x, "y": cpu.register_y, "p": cpu.status, "sp": cpu.stack_pointer, "pc": cpu.program_counter,
+                    "cycles": cpu.bus.clock().cpu_cycles(), "scanline": ppu.scanline(), "dot": ppu.dot(), "frame": ppu.frame().number(),
+                })
+            }
+            "setRegisters" => {
+                let cpu = nes.cpu_mut();
+                for (name, register) in [("a", &mut cpu.register_a), ("x", &mut cpu.register_x), ("y", &mut cpu.register_y), ("p", &mut cpu.status), ("sp", &mut cpu.stack_pointer)] {
+                    if params.get(name).is_some() {
+                        *register = number(params, name)? as u8;
+                    }
+                }
+                if params.get("pc").is_some() {
+                    cpu.program_counter = number(params, "pc")? as u16;
+                }
+                json!({})
+            }
+            "readMemory" => {
+                let addr = number(params, "addr")? as u16;
+                let len = number(params, "len")?.clamp(1, 0x10000);
+                let end = (addr as u64 + len - 1).min(0xffff) as u16;
+                json!({ "bytes": memory::read(nes.cpu(), space(params)?, addr..=end) })
+            }
+            "writeMemory" => {
+                let addr = number(params, "addr")? as u16;
+                let bytes = params.get("bytes").and_then(Value::as_array).ok_or("missing bytes")?;
+                let space = space(params)?;
+                for (offset, byte) in bytes.iter().enumerate() {
+                    let byte = byte.as_u64().ok_or("bytes have to be numbers")? as u8;
+                    let addr = addr.wrapping_add(offset as u16);
+                    match space {
+                        Space::Cpu => nes.cpu_mut().bus.mem_write(addr, byte),
+                        Space::Ppu => nes.cpu_mut().bus.ppu.write_vram(addr, byte),
+                    }
+                }
+                json!({})
+            }
+            "setBreakpoint" => {
+                let condition = condition(params)?;
+                let debugger = nes.debugger_mut().expect("attached above");
+                let addr = match params.get("label").and_then(Value::as_str) {
+                    Some(label) => debugger.add_breakpoint_at(label)?,
+                    None => {
+                        let addr = number(params, "addr")? as u16;
+                        debugger.add_breakpoint(addr);
+                        addr
+                    }
+                };
+                debugger.set_breakpoint_condition(addr, condition);
+                json!({ "addr": addr })
+            }
+            "removeBreakpoint" => {
+                let addr = number(params, "addr")? as u16;
+                json!({ "removed": nes.debugger_mut().expect("attached above").remove_breakpoint(addr) })
+            }
+            "setWatchpoint" => {
+                let start = number(params, "start")? as u16;
+                let end = params.get("end").map_or(Ok(start as u64), |_| number(params, "end"))? as u16;
+                let kind = match params.get("kind").and_then(Value::as_str).unwrap_or("readwrite") {
+                    "read" => WatchKind::Read,
+                    "write" => WatchKind::Write,
+                    "readwrite" => WatchKind::ReadWrite,
+                    other => return Err(format!("unknown watchpoint kind {:?}", other)),
+                };
+                let condition = condition(params)?;
+                let debugger = nes.debugger_mut().expect("attached above");
+                let id = debugger.add_watchpoint(start..=end, kind);
+                debugger.set_watchpoint_condition(id, condition);
+                self.next_watchpoint += 1;
+                self.watchpoints.insert(self.next_watchpoint, id);
+                json!({ "id": self.next_watchpoint })
+            }
+            "removeWatchpoint" => {
+                let removed = match self.watchpoints.remove(&number(params, "id")?) {
+                    Some(id) => nes.debugger_mut().expect("attached above").remove_watchpoint(id),
+                    None => false,
+                };
+                json!({ "removed": removed })
+            }
+            "breakpoints" => {
+                let debugger = nes.debugger().expect("attached above");
+                let breakpoints: Vec<Value> = debugger.breakpoints().map(|addr| json!({ "addr": addr, "condition": debugger.breakpoint_condition(addr).map(Condition::source) })).collect();
+                let watchpoints: Vec<Value> = self
+                    .watchpoints
+                    .iter()
+                    .filter_map(|(number, id)| debugger.watchpoints().iter().find(|watch| watch.id == *id).map(|watch| (number, watch)))
+                    .map(|(number, watch)| json!({ "id": number, "start": watch.range.start(), "end": watch.range.end(), "kind": format!("{:?}", watch.kind).to_lowercase(), "condition": watch.condition.as_ref().map(Condition::source) }))
+                    .collect();
+                json!({ "breakpoints": breakpoints, "watchpoints": watchpoints })
+            }
+            "callStack" => {
+                let frames: Vec<Value> = nes
+                    .debugger()
+                    .expect("attached above")
+                    .call_stack()
+                    .frames()
+                    .iter()
+                    .map(|frame| json!({ "kind": format!("{:?}", frame.kind).to_lowercase(), "entry": frame.entry, "returnAddr": frame.return_addr }))
+                    .collect();
+                json!({ "frames": frames })
+            }
+            "step" => {
+                let result = match params.get("kind").and_then(Value::as_str).unwrap_or("into") {
+                    "into" => nes.step_into(),
+                    "over" => nes.step_over(),
+                    "out" => nes.step_out(),
+                    other => return Err(format!("unknown step {:?}", other)),
+                };
+                json!({ "reason": self.run_reason(result) })
+            }
+            "runTo" => {
+                let addr = number(params, "addr")? as u16;
+                json!({ "reason": self.run_reason(nes.run_to(addr)) })
+            }
+            "continue" => {
+                self.running = true;
+                json!({})
+            }
+            "pause" => {
+                self.running = false;
+                json!({ "reason": self.reason(None) })
+            }
+            "subscribe" => {
+                let names = params.get("events").and_then(Value::as_array).ok_or("missing events")?;
+                for name in names {
+                    let name = name.as_str().unwrap_or("");
+                    let known = ["frame", "vblank", "mapperIrq", "apuIrq", "stateLoaded", "lagFrame"].into_iter().find(|known| *known == name);
+                    self.subscribed.insert(known.ok_or_else(|| format!("unknown event {:?}", name))?);
+                }
+                json!({})
+            }
+            _ => return Err(format!("unknown method {:?}", method)),
+        };
+        Ok(result)
+    }
+}
+
+fn number(params: &Value, name: &str) -> Result<u64, String> {
+    params.get(name).and_then(Value::as_u64).ok_or_else(|| format!("{} has to be a number", name))
+}
+
+fn space(params: &Value) -> Result<Space, String> {
+    match params.get("space").and_then(Value::as_str).unwrap_or("cpu") {
+        "cpu" => Ok(Space::Cpu),
+        "ppu" => Ok(Space::Ppu),
+        other => Err(format!("unknown space {:?}", other)),
+    }
+}
+
+fn condition(params: &Value) -> Result<Option<Condition>, String> {
+    params.get("condition").and_then(Value::as_str).map(Condition::parse).transpose()
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::test_nes;
+
+    fn console() -> Nes {
+        test_nes(
+            "
+                    .org $C000
+            reset:  lda #$05
+                    sta $10
+                    jmp reset
+                    .org $FFFA
+                    .word reset, reset, reset
+            ",
+        )
+    }
+
+    fn call(server: &mut DebugServer, nes: &mut Nes, method: &str, params: Value) -> Value {
+        let reply = server.request(nes, &json!({ "id": 7, "method": method, "params": params }).to_string());
+        assert_eq!(reply["id"], 7);
+        reply
+    }
+
+    #[test]
+    fn test_requests() {
+        let mut nes = console();
+        let mut server = DebugServer::default();
+        assert_eq!(call(&mut server, &mut nes, "registers", json!({}))["result"]["pc"], 0xc000);
+
+        let reply = call(&mut server, &mut nes, "step", json!({ "kind": "into" }));
+        assert_eq!(reply["result"]["reason"]["kind"], "step");
+        assert_eq!(call(&mut server, &mut nes, "registers", json!({}))["result"]["a"], 5);
+
+        let reply = call(&mut server, &mut nes, "setWatchpoint", json!({ "start": 0x10, "kind": "write", "condition": "value == 5" }));
+        assert_eq!(reply["result"]["id"], 1);
+        let reply = call(&mut server, &mut nes, "runTo", json!({ "addr": 0xc006 }));
+        assert_eq!(reply["result"]["reason"], json!({ "kind": "watchpoint", "id": 1, "addr": 0x10, "value": 5, "access": "write", "pc": 0xc002 }));
+        assert_eq!(call(&mut server, &mut nes, "readMemory", json!({ "addr": 0x10, "len": 2 }))["result"]["bytes"], json!([5, 0]));
+
+        call(&mut server, &mut nes, "writeMemory", json!({ "addr": 0x11, "bytes": [1, 2] }));
+        assert_eq!(nes.cpu().bus.peek(0x12), 2);
+        assert_eq!(call(&mut server, &mut nes, "setBreakpoint", json!({ "addr": 0xc004 }))["result"]["addr"], 0xc004);
+        let listed = call(&mut server, &mut nes, "breakpoints", json!({}))["result"].clone();
+        assert_eq!(listed["breakpoints"], json!([{ "addr": 0xc004, "condition": null }]));
+        assert_eq!(listed["watchpoints"][0]["condition"], "value == 5");
+        assert_eq!(call(&mut server, &mut nes, "removeWatchpoint", json!({ "id": 1 }))["result"]["removed"], true);
+
+        call(&mut server, &mut nes, "setRegisters", json!({ "x": 9 }));
+        assert_eq!(nes.cpu().register_x, 9);
+    }
+
+    #[test]
+    fn test_errors_and_events() {
+        let mut nes = console();
+        let mut server = DebugServer::default();
+        assert!(server.request(&mut nes, "{").get("error").is_some());
+        assert!(call(&mut server, &mut nes, "fly", json!({}))["error"].as_str().unwrap().contains("unknown method"));
+        assert!(call(&mut server, &mut nes, "readMemory", json!({ "addr": "here" }))["error"].is_string());
+        assert!(call(&mut server, &mut nes, "subscribe", json!({ "events": ["meteor"] }))["error"].is_string());
+
+        call(&mut server, &mut nes, "subscribe", json!({ "events": ["frame"] }));
+        assert_eq!(server.event(ConsoleEvent::FrameComplete(3)), Some(json!({ "event": "frame", "number": 3 })));
+        assert_eq!(server.event(ConsoleEvent::VblankStart), None);
+    }
+}
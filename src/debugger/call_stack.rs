@@ -0,0 +1,65 @@
+use alloc::vec::Vec;
+
+// deeper than this, the oldest frames are dropped: code that calls without
+// ever returning or popping would otherwise grow the stack forever
+const MAX_DEPTH: usize = 256;
+
+/// How a call stack frame was entered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CallKind {
+    Jsr,
+    Nmi,
+    Irq,
+    Brk,
+}
+
+/// One level of the shadow call stack.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CallFrame {
+    pub kind: CallKind,
+    /// First instruction of the subroutine or handler.
+    pub entry: u16,
+    /// Where execution carries on once it returns.
+    pub return_addr: u16,
+    /// The stack pointer right after the return address was pushed.
+    pub stack_pointer: u8,
+}
+
+/// Follows calls and returns as the CPU runs. Rather than pairing each
+/// return with a call, a frame is gone once the stack pointer moves above
+/// it, which copes with RTS used as a jump and with return addresses
+/// pulled off by hand or dropped by resetting the stack.
+#[derive(Debug, Clone, Default)]
+pub struct CallStack {
+    frames: Vec<CallFrame>,
+}
+
+impl CallStack {
+    /// Outermost call first.
+    pub fn frames(&self) -> &[CallFrame] {
+        &self.frames
+    }
+
+    pub fn depth(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.frames.clear();
+    }
+
+    pub(crate) fn push(&mut self, frame: CallFrame) {
+        self.unwind(frame.stack_pointer);
+        if self.frames.len() == MAX_DEPTH {
+            self.frames.remove(0);
+        }
+        self.frames.push(frame);
+    }
+
+    // drops the frames whose return address is no longer on the stack
+    pub(crate) fn unwind(&mut self, stack_pointer: u8) {
+        while self.frames.last().is_some_and(|frame| frame.stack_pointer < stack_pointer) {
+            self.frames.pop();
+        }
+    }
+}
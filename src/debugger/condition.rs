@@ -0,0 +1,315 @@
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+use core::str::FromStr;
+use crate::cpu::CPU;
+
+// binary operators from the loosest binding to the tightest, as in Rust
+const PRECEDENCE: [&[&str]; 9] = [
+    &["||"],
+    &["&&"],
+    &["==", "!=", "<", "<=", ">", ">="],
+    &["|"],
+    &["^"],
+    &["&"],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+// longest first, so `<=` isn't read as `<`
+const OPERATORS: [&str; 20] = ["||", "&&", "==", "!=", "<=", ">=", "<<", ">>", "<", ">", "|", "^", "&", "+", "-", "*", "/", "%", "!", "~"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Var {
+    A,
+    X,
+    Y,
+    Status,
+    StackPointer,
+    ProgramCounter,
+    Scanline,
+    Dot,
+    Frame,
+    Cycles,
+    Addr,
+    Value,
+}
+
+impl Var {
+    fn from_name(name: &str) -> Option<Var> {
+        let var = match name.to_ascii_lowercase().as_str() {
+            "a" => Var::A,
+            "x" => Var::X,
+            "y" => Var::Y,
+            "p" => Var::Status,
+            "sp" => Var::StackPointer,
+            "pc" => Var::ProgramCounter,
+            "scanline" => Var::Scanline,
+            "dot" => Var::Dot,
+            "frame" => Var::Frame,
+            "cycles" => Var::Cycles,
+            "addr" => Var::Addr,
+            "value" => Var::Value,
+            _ => return None,
+        };
+        Some(var)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Expr {
+    Number(i64),
+    Var(Var),
+    // a byte of CPU memory, read without side effects
+    Peek(Box<Expr>),
+    Unary(&'static str, Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Number(i64),
+    Ident(String),
+    Op(&'static str),
+    Open(char),
+    Close(char),
+}
+
+/// A test on the console's state that breakpoints and watchpoints only
+/// stop on when it holds, like `A == 0x20 && scanline > 200`.
+///
+/// Values are `A`, `X`, `Y`, `P`, `SP`, `PC`, `scanline`, `dot`, `frame`
+/// and `cycles`, plus `addr` and `value` for the access a watchpoint
+/// caught. `[expr]` reads a byte of memory. Numbers are decimal, `0x20`,
+/// `$20` or `%100000`. The operators are Rust's, and anything but 0 is true.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Condition {
+    source: String,
+    expr: Expr,
+}
+
+impl Condition {
+    pub fn parse(source: &str) -> Result<Condition, String> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser { tokens: &tokens, pos: 0 };
+        let expr = parser.expr(0)?;
+        if let Some(token) = parser.peek() {
+            return Err(format!("unexpected {:?} in condition", token));
+        }
+        Ok(Condition { source: source.to_string(), expr })
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    // `access` is the address and value a watchpoint caught
+    pub(crate) fn holds(&self, cpu: &CPU, access: Option<(u16, u8)>) -> bool {
+        eval(&self.expr, cpu, access) != 0
+    }
+}
+
+impl FromStr for Condition {
+    type Err = String;
+
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        Condition::parse(source)
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = source.trim_start();
+    while let Some(c) = rest.chars().next() {
+        let len = if c.is_ascii_digit() || c == '$' || c == '%' && !tokens.last().is_some_and(ends_operand) {
+            let (radix, prefix) = match c {
+                '$' => (16, 1),
+                '%' => (2, 1),
+                _ if rest.starts_with("0x") || rest.starts_with("0X") => (16, 2),
+                _ => (10, 0),
+            };
+            let digits = rest[prefix..].find(|c: char| !c.is_ascii_alphanumeric()).unwrap_or(rest.len() - prefix);
+            let number = i64::from_str_radix(&rest[prefix..prefix + digits], radix).map_err(|_| format!("bad number {:?}", &rest[..prefix + digits]))?;
+            tokens.push(Token::Number(number));
+            prefix + digits
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let len = rest.find(|c: char| !c.is_ascii_alphanumeric() && c != '_').unwrap_or(rest.len());
+            tokens.push(Token::Ident(rest[..len].to_string()));
+            len
+        } else if c == '(' || c == '[' {
+            tokens.push(Token::Open(c));
+            1
+        } else if c == ')' || c == ']' {
+            tokens.push(Token::Close(c));
+            1
+        } else if let Some(op) = OPERATORS.iter().find(|op| rest.starts_with(**op)) {
+            tokens.push(Token::Op(op));
+            op.len()
+        } else {
+            return Err(format!("unexpected {:?} in condition", c));
+        };
+        rest = rest[len..].trim_start();
+    }
+    Ok(tokens)
+}
+
+// a `%` after one of these is the remainder, not a binary number
+fn ends_operand(token: &Token) -> bool {
+    matches!(token, Token::Number(_) | Token::Ident(_) | Token::Close(_))
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Result<Token, String> {
+        let token = self.peek().cloned().ok_or("condition ends too early")?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    // binary operators of `level` and tighter
+    fn expr(&mut self, level: usize) -> Result<Expr, String> {
+        if level == PRECEDENCE.len() {
+            return self.unary();
+        }
+        let mut left = self.expr(level + 1)?;
+        while let Some(&Token::Op(op)) = self.peek() {
+            if !PRECEDENCE[level].contains(&op) {
+                break;
+            }
+            self.pos += 1;
+            let right = self.expr(level + 1)?;
+            left = Expr::Binary(op, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        match self.next()? {
+            Token::Op(op @ ("!" | "-" | "~")) => Ok(Expr::Unary(op, Box::new(self.unary()?))),
+            Token::Number(number) => Ok(Expr::Number(number)),
+            Token::Ident(name) => Var::from_name(&name).map(Expr::Var).ok_or_else(|| format!("unknown value {:?} in condition", name)),
+            Token::Open(open) => {
+                let inner = self.expr(0)?;
+                let close = if open == '(' { ')' } else { ']' };
+                if self.next()? != Token::Close(close) {
+                    return Err(format!("missing {:?} in condition", close));
+                }
+                Ok(if open == '[' { Expr::Peek(Box::new(inner)) } else { inner })
+            }
+            token => Err(format!("unexpected {:?} in condition", token)),
+        }
+    }
+}
+
+// wrapping arithmetic; dividing by 0 gives 0 rather than failing mid-run
+fn eval(expr: &Expr, cpu: &CPU, access: Option<(u16, u8)>) -> i64 {
+    match expr {
+        Expr::Number(number) => *number,
+        Expr::Var(var) => match var {
+            Var::A => cpu.register_a as i64,
+            Var::X => cpu.register_x as i64,
+            Var::Y => cpu.register_y as i64,
+            Var::Status => cpu.status as i64,
+            Var::StackPointer => cpu.stack_pointer as i64,
+            Var::ProgramCounter => cpu.program_counter as i64,
+            Var::Scanline => cpu.bus.ppu.scanline() as i64,
+            Var::Dot => cpu.bus.ppu.dot() as i64,
+            Var::Frame => cpu.bus.ppu.frame().number() as i64,
+            Var::Cycles => cpu.bus.clock().cpu_cycles() as i64,
+            Var::Addr => access.map_or(0, |(addr, _)| addr as i64),
+            Var::Value => access.map_or(0, |(_, value)| value as i64),
+        },
+        Expr::Peek(addr) => cpu.bus.peek(eval(addr, cpu, access) as u16) as i64,
+        Expr::Unary(op, operand) => {
+            let value = eval(operand, cpu, access);
+            match *op {
+                "!" => (value == 0) as i64,
+                "-" => value.wrapping_neg(),
+                _ => !value,
+            }
+        }
+        Expr::Binary(op, left, right) => {
+            let left = eval(left, cpu, access);
+            // short circuit, so `[addr]` behind a false test isn't read
+            match *op {
+                "&&" => return (left != 0 && eval(right, cpu, access) != 0) as i64,
+                "||" => return (left != 0 || eval(right, cpu, access) != 0) as i64,
+                _ => {}
+            }
+            let right = eval(right, cpu, access);
+            match *op {
+                "==" => (left == right) as i64,
+                "!=" => (left != right) as i64,
+                "<" => (left < right) as i64,
+                "<=" => (left <= right) as i64,
+                ">" => (left > right) as i64,
+                ">=" => (left >= right) as i64,
+                "|" => left | right,
+                "^" => left ^ right,
+                "&" => left & right,
+                "<<" => left.wrapping_shl(right as u32),
+                ">>" => left.wrapping_shr(right as u32),
+                "+" => left.wrapping_add(right),
+                "-" => left.wrapping_sub(right),
+                "*" => left.wrapping_mul(right),
+                "/" => left.checked_div(right).unwrap_or(0),
+                _ => left.checked_rem(right).unwrap_or(0),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn check(source: &str, cpu: &CPU) -> bool {
+        Condition::parse(source).unwrap().holds(cpu, Some((0x0300, 0x42)))
+    }
+
+    #[test]
+    fn test_eval() {
+        let mut cpu = CPU::new();
+        cpu.register_a = 0x20;
+        cpu.register_x = 3;
+        cpu.bus.mem_write(0x10, 7);
+        assert!(check("A == 0x20 && x < 4", &cpu));
+        assert!(check("a == $20 && !(X == 3 || x == 4) || 1", &cpu));
+        assert!(!check("a == $20 && !(X == 3 || x == 4)", &cpu));
+        assert!(check("[$10] == 7 && [0x08 + 8] - 7 == 0", &cpu));
+        assert!(check("1 + 2 * 3 == 7 && (1 + 2) * 3 == 9", &cpu));
+        assert!(check("A & %100000 && a % 3 == 2 && ~0 == -1", &cpu));
+        assert!(check("addr == 0x300 && value >= 0x42", &cpu));
+        assert!(check("1 << 4 == 16 && 5 / 0 == 0", &cpu));
+        assert!(check("scanline == 0 && dot == 0 && frame == 0", &cpu));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(Condition::parse("A ==").is_err());
+        assert!(Condition::parse("B == 1").is_err());
+        assert!(Condition::parse("(A == 1").is_err());
+        assert!(Condition::parse("[A == 1)").is_err());
+        assert!(Condition::parse("A == 1 2").is_err());
+        assert!(Condition::parse("A # 1").is_err());
+        assert!(Condition::parse("$zz").is_err());
+        assert_eq!("A==1".parse::<Condition>().unwrap().to_string(), "A==1");
+    }
+}
@@ -0,0 +1,134 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use core::ops::RangeInclusive;
+use crate::cpu::CPU;
+
+const BYTES_PER_LINE: usize = 16;
+
+/// Which address space to look at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Space {
+    /// What the CPU sees. Registers read as $FF, see `Bus::peek`.
+    Cpu,
+    /// Pattern tables, nametables and palettes.
+    Ppu,
+}
+
+/// One line of a hex dump.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HexLine {
+    pub addr: u16,
+    pub bytes: Vec<u8>,
+}
+
+impl fmt::Display for HexLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04X}:", self.addr)?;
+        for byte in &self.bytes {
+            write!(f, " {:02X}", byte)?;
+        }
+        let text: String = self.bytes.iter().map(|&byte| if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '.' }).collect();
+        write!(f, "{:width$}  |{}|", "", text, width = (BYTES_PER_LINE - self.bytes.len()) * 3)
+    }
+}
+
+/// A byte that differs between two copies of memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Difference {
+    pub addr: u16,
+    pub before: u8,
+    pub after: u8,
+}
+
+/// Reads `range` of `space` without side effects.
+pub fn read(cpu: &CPU, space: Space, range: RangeInclusive<u16>) -> Vec<u8> {
+    range
+        .map(|addr| match space {
+            Space::Cpu => cpu.bus.peek(addr),
+            Space::Ppu => cpu.bus.ppu.read_vram(addr),
+        })
+        .collect()
+}
+
+/// `range` of `space` as lines of 16 bytes.
+pub fn dump(cpu: &CPU, space: Space, range: RangeInclusive<u16>) -> Vec<HexLine> {
+    let start = *range.start();
+    read(cpu, space, range)
+        .chunks(BYTES_PER_LINE)
+        .enumerate()
+        .map(|(i, bytes)| HexLine { addr: start.wrapping_add((i * BYTES_PER_LINE) as u16), bytes: bytes.to_vec() })
+        .collect()
+}
+
+/// Where in `range` the bytes match `pattern`, `None` matching any byte.
+pub fn search(cpu: &CPU, space: Space, range: RangeInclusive<u16>, pattern: &[Option<u8>]) -> Vec<u16> {
+    let start = *range.start();
+    let memory = read(cpu, space, range);
+    if pattern.is_empty() {
+        return Vec::new();
+    }
+    memory
+        .windows(pattern.len())
+        .enumerate()
+        .filter(|(_, window)| window.iter().zip(pattern).all(|(byte, want)| want.is_none_or(|want| *byte == want)))
+        .map(|(offset, _)| start.wrapping_add(offset as u16))
+        .collect()
+}
+
+/// Where in `range` `value` is stored, little endian as the 6502 keeps it.
+pub fn search_u16(cpu: &CPU, space: Space, range: RangeInclusive<u16>, value: u16) -> Vec<u16> {
+    let [lo, hi] = value.to_le_bytes();
+    search(cpu, space, range, &[Some(lo), Some(hi)])
+}
+
+/// The bytes that differ between two copies of memory that start at `base`.
+pub fn compare(before: &[u8], after: &[u8], base: u16) -> Vec<Difference> {
+    before
+        .iter()
+        .zip(after)
+        .enumerate()
+        .filter(|(_, (before, after))| before != after)
+        .map(|(offset, (&before, &after))| Difference { addr: base.wrapping_add(offset as u16), before, after })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::nes::Nes;
+
+    #[test]
+    fn test_dump_and_search() {
+        let mut cpu = CPU::new();
+        for (i, &byte) in b"Hello, NES!".iter().enumerate() {
+            cpu.bus.mem_write(0x0200 + i as u16, byte);
+        }
+        cpu.bus.mem_write(0x0300, 0x34);
+        cpu.bus.mem_write(0x0301, 0x12);
+        cpu.bus.ppu.write_vram(0x2005, 0xab);
+
+        let lines = dump(&cpu, Space::Cpu, 0x01fc..=0x020f);
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].to_string(), "01FC: 00 00 00 00 48 65 6C 6C 6F 2C 20 4E 45 53 21 00  |....Hello, NES!.|");
+        assert_eq!(lines[1].to_string(), format!("020C: 00 00 00 00{}  |....|", " ".repeat(36)));
+
+        assert_eq!(search(&cpu, Space::Cpu, 0x0000..=0x07ff, &[Some(b'l'), None, Some(b'o')]), vec![0x0202]);
+        assert_eq!(search_u16(&cpu, Space::Cpu, 0x0000..=0x07ff, 0x1234), vec![0x0300]);
+        assert_eq!(search(&cpu, Space::Ppu, 0x2000..=0x23ff, &[Some(0xab)]), vec![0x2005]);
+        assert_eq!(read(&cpu, Space::Ppu, 0x2004..=0x2006), vec![0, 0xab, 0]);
+    }
+
+    #[test]
+    fn test_compare_snapshots() {
+        let mut nes = Nes::new();
+        let before = nes.snapshot();
+        nes.cpu_mut().bus.mem_write(0x10, 5);
+        nes.cpu_mut().bus.mem_write(0x7ff, 1);
+        let after = nes.snapshot();
+        assert_eq!(
+            compare(before.ram(), after.ram(), 0),
+            vec![Difference { addr: 0x10, before: 0, after: 5 }, Difference { addr: 0x7ff, before: 0, after: 1 }]
+        );
+    }
+}
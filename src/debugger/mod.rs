@@ -0,0 +1,610 @@
+pub mod call_stack;
+pub mod condition;
+pub mod memory;
+pub mod source;
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use call_stack::{CallFrame, CallKind, CallStack};
+use condition::Condition;
+use core::ops::RangeInclusive;
+use crate::cpu::CPU;
+use crate::symbols::Symbols;
+use source::{SourceLine, SourceMap};
+
+const BRK: u8 = 0x00;
+const JSR: u8 = 0x20;
+const RTI: u8 = 0x40;
+const RTS: u8 = 0x60;
+
+/// Why the console stopped before finishing the frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakReason {
+    /// The CPU was about to run the instruction at this address.
+    Breakpoint(u16),
+    /// The instruction at `program_counter` accessed memory a watchpoint
+    /// covers. It ran to completion before the console stopped.
+    Watchpoint { id: WatchpointId, addr: u16, value: u8, access: Access, program_counter: u16 },
+    /// A step or run to an address finished.
+    Step,
+}
+
+// what a step in progress waits for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Step {
+    // the end of the next instruction
+    Into,
+    // reaching the address with the stack no deeper than it was
+    Over { return_to: u16, stack_pointer: u8 },
+    // a return that pops the stack above where it was
+    Out { stack_pointer: u8 },
+    RunTo(u16),
+    // an instruction from a source line other than this one
+    Line(Option<SourceLine>),
+}
+
+/// A single memory access by the CPU.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+/// Which accesses a watchpoint stops on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl WatchKind {
+    fn matches(self, access: Access) -> bool {
+        matches!((self, access), (WatchKind::ReadWrite, _) | (WatchKind::Read, Access::Read) | (WatchKind::Write, Access::Write))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct WatchpointId(usize);
+
+/// Stops on accesses to a range of CPU addresses.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub id: WatchpointId,
+    pub range: RangeInclusive<u16>,
+    pub kind: WatchKind,
+    pub condition: Option<Condition>,
+}
+
+/// How far `Nes::run` got.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunResult {
+    /// The frame was finished.
+    Completed,
+    /// The debugger stopped the console mid-frame; running again carries on
+    /// from there.
+    Stopped(BreakReason),
+}
+
+/// Stops the console at chosen points for frontends to inspect it. Attach
+/// one with `Nes::attach_debugger`.
+#[derive(Debug, Clone, Default)]
+pub struct Debugger {
+    breakpoints: BTreeMap<u16, Option<Condition>>,
+    watchpoints: Vec<Watchpoint>,
+    next_watchpoint: usize,
+    // address of the instruction running, for telling who hit a watchpoint
+    program_counter: u16,
+    // accesses watched during the instruction running, checked once it's done
+    hits: Vec<(WatchpointId, u16, u8, Access)>,
+    step: Option<Step>,
+    // opcode and stack pointer of the instruction running, for step out
+    // and the call stack, and whether it wasn't stopped before it ran
+    opcode: u8,
+    stack_pointer: u8,
+    ran: bool,
+    call_stack: CallStack,
+    symbols: Symbols,
+    source: SourceMap,
+    // where the console last stopped, passed over once when it carries on
+    stopped_at: Option<u16>,
+    stop: Option<BreakReason>,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Debugger::default()
+    }
+
+    /// Stops before the instruction at `addr` runs. False if there already was one.
+    pub fn add_breakpoint(&mut self, addr: u16) -> bool {
+        if self.breakpoints.contains_key(&addr) {
+            return false;
+        }
+        self.breakpoints.insert(addr, None);
+        true
+    }
+
+    pub fn remove_breakpoint(&mut self, addr: u16) -> bool {
+        self.breakpoints.remove(&addr).is_some()
+    }
+
+    pub fn has_breakpoint(&self, addr: u16) -> bool {
+        self.breakpoints.contains_key(&addr)
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = u16> + '_ {
+        self.breakpoints.keys().copied()
+    }
+
+    /// Only stops at the breakpoint at `addr` when `condition` holds, or
+    /// always with `None`. False if there's no breakpoint there.
+    pub fn set_breakpoint_condition(&mut self, addr: u16, condition: Option<Condition>) -> bool {
+        match self.breakpoints.get_mut(&addr) {
+            Some(slot) => {
+                *slot = condition;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn breakpoint_condition(&self, addr: u16) -> Option<&Condition> {
+        self.breakpoints.get(&addr)?.as_ref()
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    /// Stops after the instruction that makes an access of `kind` to
+    /// anywhere in `range`, mapped registers and mirrors by the address used.
+    pub fn add_watchpoint(&mut self, range: RangeInclusive<u16>, kind: WatchKind) -> WatchpointId {
+        let id = WatchpointId(self.next_watchpoint);
+        self.next_watchpoint += 1;
+        self.watchpoints.push(Watchpoint { id, range, kind, condition: None });
+        id
+    }
+
+    /// Only stops on the watchpoint when `condition` holds for the access,
+    /// or always with `None`. False if there's no such watchpoint.
+    pub fn set_watchpoint_condition(&mut self, id: WatchpointId, condition: Option<Condition>) -> bool {
+        match self.watchpoints.iter_mut().find(|watchpoint| watchpoint.id == id) {
+            Some(watchpoint) => {
+                watchpoint.condition = condition;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn remove_watchpoint(&mut self, id: WatchpointId) -> bool {
+        let len = self.watchpoints.len();
+        self.watchpoints.retain(|watchpoint| watchpoint.id != id);
+        self.watchpoints.len() != len
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    pub fn clear_watchpoints(&mut self) {
+        self.watchpoints.clear();
+    }
+
+    /// Stops after the next instruction. The step, like the ones below,
+    /// stays armed across frames until it finishes, the console stops for
+    /// another reason or `cancel_step` is called.
+    pub fn step_into(&mut self) {
+        self.step = Some(Step::Into);
+    }
+
+    /// Like `step_into`, but runs a JSR about to run until it returns.
+    pub fn step_over(&mut self, cpu: &CPU) {
+        self.step = Some(if cpu.bus.peek(cpu.program_counter) == JSR {
+            Step::Over { return_to: cpu.program_counter.wrapping_add(3), stack_pointer: cpu.stack_pointer }
+        } else {
+            Step::Into
+        });
+    }
+
+    /// Runs until the subroutine or interrupt handler the CPU is in returns.
+    pub fn step_out(&mut self, cpu: &CPU) {
+        self.step = Some(Step::Out { stack_pointer: cpu.stack_pointer });
+    }
+
+    /// Stops before the instruction at `addr` runs, once.
+    pub fn run_to(&mut self, addr: u16) {
+        self.step = Some(Step::RunTo(addr));
+    }
+
+    pub fn cancel_step(&mut self) {
+        self.step = None;
+    }
+
+    pub fn is_stepping(&self) -> bool {
+        self.step.is_some()
+    }
+
+    /// Names for addresses, for frontends showing breakpoints, the call
+    /// stack and disassembly, and for `add_breakpoint_at`.
+    pub fn set_symbols(&mut self, symbols: Symbols) {
+        self.symbols = symbols;
+    }
+
+    pub fn symbols(&self) -> &Symbols {
+        &self.symbols
+    }
+
+    /// Source lines for the code, for `step_line` and `add_breakpoint_at_line`.
+    pub fn set_source_map(&mut self, source: SourceMap) {
+        self.source = source;
+    }
+
+    pub fn source_map(&self) -> &SourceMap {
+        &self.source
+    }
+
+    /// Runs until the CPU gets to code from another source line than the
+    /// one it's on, following calls and jumps.
+    pub fn step_line(&mut self, cpu: &CPU) {
+        self.step = Some(Step::Line(self.source.line(cpu.program_counter)));
+    }
+
+    /// Adds a breakpoint at the first instruction of `line` in `file`, see
+    /// `SourceMap::address`.
+    pub fn add_breakpoint_at_line(&mut self, file: &str, line: u32) -> Result<u16, String> {
+        let addr = self.source.address(file, line).ok_or_else(|| format!("no code for {}:{}", file, line))?;
+        self.add_breakpoint(addr);
+        Ok(addr)
+    }
+
+    /// Adds a breakpoint at the address with the label `name`.
+    pub fn add_breakpoint_at(&mut self, name: &str) -> Result<u16, String> {
+        let addr = self.symbols.address(name).ok_or_else(|| format!("no label {:?}", name))?;
+        self.add_breakpoint(addr);
+        Ok(addr)
+    }
+
+    /// How the CPU got to where it is, as far as JSRs and interrupts tell.
+    pub fn call_stack(&self) -> &CallStack {
+        &self.call_stack
+    }
+
+    /// Forgets the calls seen so far, for when the console jumped elsewhere
+    /// as on resets and loading states.
+    pub fn clear_call_stack(&mut self) {
+        self.call_stack.clear();
+    }
+
+    // called before every instruction; true stops the CPU before it
+    pub(crate) fn before_instruction(&mut self, cpu: &CPU) -> bool {
+        let program_counter = cpu.program_counter;
+        self.program_counter = program_counter;
+        self.opcode = cpu.bus.peek(program_counter);
+        self.stack_pointer = cpu.stack_pointer;
+        if self.stopped_at.take() == Some(program_counter) {
+            self.ran = true;
+            return false;
+        }
+        let reason = match self.step {
+            Some(Step::RunTo(addr)) if addr == program_counter => Some(BreakReason::Step),
+            Some(Step::Over { return_to, stack_pointer }) if return_to == program_counter && cpu.stack_pointer >= stack_pointer => Some(BreakReason::Step),
+            Some(Step::Line(from)) if self.source.line(program_counter).is_some_and(|line| Some(line) != from) => Some(BreakReason::Step),
+            _ => match self.breakpoints.get(&program_counter) {
+                Some(Some(condition)) if condition.holds(cpu, None) => Some(BreakReason::Breakpoint(program_counter)),
+                Some(None) => Some(BreakReason::Breakpoint(program_counter)),
+                _ => None,
+            },
+        };
+        if reason.is_some() {
+            self.stopped_at = Some(program_counter);
+            self.stop_with(reason);
+        }
+        self.ran = reason.is_none();
+        reason.is_some()
+    }
+
+    // called by the CPU once it took an NMI or IRQ, on the handler's address
+    pub(crate) fn on_interrupt(&mut self, nmi: bool, cpu: &CPU, return_addr: u16) {
+        let kind = if nmi { CallKind::Nmi } else { CallKind::Irq };
+        self.call_stack.push(CallFrame { kind, entry: cpu.program_counter, return_addr, stack_pointer: cpu.stack_pointer });
+    }
+
+    fn stop_with(&mut self, reason: Option<BreakReason>) {
+        self.stop = reason;
+        self.step = None;
+    }
+
+    // called by the bus for every CPU read and write
+    pub(crate) fn on_access(&mut self, addr: u16, value: u8, access: Access) {
+        for watchpoint in &self.watchpoints {
+            if watchpoint.kind.matches(access) && watchpoint.range.contains(&addr) {
+                self.hits.push((watchpoint.id, addr, value, access));
+            }
+        }
+    }
+
+    // called after every instruction, to stop on the first watched access
+    // whose condition holds now that it's done
+    pub(crate) fn after_instruction(&mut self, cpu: &CPU) {
+        if core::mem::take(&mut self.ran) {
+            let kind = match self.opcode {
+                JSR => Some((CallKind::Jsr, 3)),
+                BRK => Some((CallKind::Brk, 2)),
+                _ => None,
+            };
+            match kind {
+                Some((kind, len)) => {
+                    let return_addr = self.program_counter.wrapping_add(len);
+                    self.call_stack.push(CallFrame { kind, entry: cpu.program_counter, return_addr, stack_pointer: cpu.stack_pointer });
+                }
+                None => self.call_stack.unwind(cpu.stack_pointer),
+            }
+        }
+        for (id, addr, value, access) in core::mem::take(&mut self.hits) {
+            if self.stop.is_some() {
+                continue;
+            }
+            let Some(watchpoint) = self.watchpoints.iter().find(|watchpoint| watchpoint.id == id) else {
+                continue;
+            };
+            if watchpoint.condition.as_ref().is_none_or(|condition| condition.holds(cpu, Some((addr, value)))) {
+                self.stop_with(Some(BreakReason::Watchpoint { id, addr, value, access, program_counter: self.program_counter }));
+            }
+        }
+        if self.stop.is_some() {
+            return;
+        }
+        let done = match self.step {
+            Some(Step::Into) => true,
+            Some(Step::Out { stack_pointer }) => matches!(self.opcode, RTS | RTI) && self.stack_pointer >= stack_pointer,
+            _ => false,
+        };
+        if done {
+            self.stop_with(Some(BreakReason::Step));
+        }
+    }
+
+    pub(crate) fn take_stop(&mut self) -> Option<BreakReason> {
+        self.stop.take()
+    }
+}
+
+// the debugger attached to a console; copies of it, like frames run
+// ahead, run without one
+#[derive(Default)]
+pub(crate) struct Attached(pub(crate) Option<Box<Debugger>>);
+
+impl Clone for Attached {
+    fn clone(&self) -> Self {
+        Attached(None)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::test_nes;
+    use crate::nes::Nes;
+
+    // counts in X forever
+    fn counter() -> Nes {
+        test_nes(
+            "
+                    .org $C000
+            reset:  inx
+                    jmp reset
+                    .org $FFFA
+                    .word reset, reset, reset
+            ",
+        )
+    }
+
+    #[test]
+    fn test_breakpoint() {
+        let mut nes = counter();
+        let mut debugger = Debugger::new();
+        assert!(debugger.add_breakpoint(0xc001));
+        assert!(!debugger.add_breakpoint(0xc001));
+        nes.attach_debugger(debugger);
+
+        assert_eq!(nes.run(), RunResult::Stopped(BreakReason::Breakpoint(0xc001)));
+        assert_eq!(nes.cpu().program_counter, 0xc001);
+        assert_eq!(nes.cpu().register_x, 1);
+        assert_eq!(nes.run(), RunResult::Stopped(BreakReason::Breakpoint(0xc001)));
+        assert_eq!(nes.cpu().register_x, 2);
+
+        // the debugger survives going back to a snapshot
+        let snapshot = nes.snapshot();
+        nes.restore(&snapshot);
+        assert!(nes.debugger().unwrap().has_breakpoint(0xc001));
+
+        assert!(nes.debugger_mut().unwrap().remove_breakpoint(0xc001));
+        assert_eq!(nes.run(), RunResult::Completed);
+        assert_eq!(nes.run_frame().number(), 2);
+        assert!(nes.detach_debugger().is_some());
+        assert!(nes.debugger().is_none());
+    }
+
+    #[test]
+    fn test_breakpoint_at_label() {
+        let mut nes = counter();
+        let mut symbols = Symbols::new();
+        symbols.insert(0xc001, "loop_end");
+        let mut debugger = Debugger::new();
+        debugger.set_symbols(symbols);
+        assert_eq!(debugger.add_breakpoint_at("loop_end"), Ok(0xc001));
+        assert!(debugger.add_breakpoint_at("nowhere").is_err());
+        nes.attach_debugger(debugger);
+        assert_eq!(nes.run(), RunResult::Stopped(BreakReason::Breakpoint(0xc001)));
+        assert_eq!(nes.debugger().unwrap().symbols().label(nes.cpu().program_counter), Some("loop_end"));
+    }
+
+    #[test]
+    fn test_source_lines() {
+        // the program source::test::DBG describes
+        let mut nes = test_nes(
+            "
+                    .org $C000
+            reset:  sei
+            loop:   inx
+                    jmp loop
+                    .org $FFFA
+                    .word reset, reset, reset
+            ",
+        );
+        let mut debugger = Debugger::new();
+        debugger.set_source_map(SourceMap::parse(source::test::DBG).unwrap());
+        assert_eq!(debugger.add_breakpoint_at_line("main.s", 5), Ok(0xc002));
+        assert!(debugger.add_breakpoint_at_line("main.s", 9).is_err());
+        nes.attach_debugger(debugger);
+
+        assert_eq!(nes.run(), RunResult::Stopped(BreakReason::Breakpoint(0xc002)));
+        assert!(nes.debugger_mut().unwrap().remove_breakpoint(0xc002));
+        assert_eq!(nes.step_line(), RunResult::Stopped(BreakReason::Step));
+        let source = nes.debugger().unwrap().source_map();
+        assert_eq!(source.lookup(nes.cpu().program_counter), Some(("src/main.s", 4)));
+        nes.step_line();
+        assert_eq!(nes.cpu().program_counter, 0xc002);
+    }
+
+    #[test]
+    fn test_watchpoints() {
+        let mut nes = test_nes(
+            "
+                    .org $C000
+            reset:  lda #$42
+                    sta $0300
+                    lda $0300
+                    jmp reset
+                    .org $FFFA
+                    .word reset, reset, reset
+            ",
+        );
+
+        let mut debugger = Debugger::new();
+        let write = debugger.add_watchpoint(0x0300..=0x03ff, WatchKind::Write);
+        let read = debugger.add_watchpoint(0x02ff..=0x0300, WatchKind::Read);
+        nes.attach_debugger(debugger);
+
+        let expected = BreakReason::Watchpoint { id: write, addr: 0x0300, value: 0x42, access: Access::Write, program_counter: 0xc002 };
+        assert_eq!(nes.run(), RunResult::Stopped(expected));
+        assert_eq!(nes.cpu().program_counter, 0xc005);
+        let expected = BreakReason::Watchpoint { id: read, addr: 0x0300, value: 0x42, access: Access::Read, program_counter: 0xc005 };
+        assert_eq!(nes.run(), RunResult::Stopped(expected));
+
+        let debugger = nes.debugger_mut().unwrap();
+        assert!(debugger.remove_watchpoint(write));
+        assert!(!debugger.remove_watchpoint(write));
+        debugger.add_watchpoint(0x0300..=0x0300, WatchKind::ReadWrite);
+        assert!(matches!(nes.run(), RunResult::Stopped(BreakReason::Watchpoint { access: Access::Write, .. })));
+        assert_eq!(nes.debugger().unwrap().watchpoints().len(), 2);
+    }
+
+    #[test]
+    fn test_conditions() {
+        let mut nes = counter();
+        let mut debugger = Debugger::new();
+        debugger.add_breakpoint(0xc000);
+        assert!(debugger.set_breakpoint_condition(0xc000, Some(Condition::parse("X == 5").unwrap())));
+        assert!(!debugger.set_breakpoint_condition(0xc001, None));
+        let id = debugger.add_watchpoint(0x0000..=0xffff, WatchKind::Read);
+        assert!(debugger.set_watchpoint_condition(id, Some("addr == $c001 && x == 9".parse().unwrap())));
+        nes.attach_debugger(debugger);
+
+        assert_eq!(nes.run(), RunResult::Stopped(BreakReason::Breakpoint(0xc000)));
+        assert_eq!(nes.cpu().register_x, 5);
+        assert_eq!(nes.debugger().unwrap().breakpoint_condition(0xc000).unwrap().source(), "X == 5");
+        let expected = BreakReason::Watchpoint { id, addr: 0xc001, value: 0x4c, access: Access::Read, program_counter: 0xc001 };
+        assert_eq!(nes.run(), RunResult::Stopped(expected));
+        assert_eq!(nes.cpu().register_x, 9);
+    }
+
+    fn calls() -> Nes {
+        test_nes(
+            "
+                    .org $C000
+            main:   jsr sub
+                    inx
+                    jmp main
+                    .org $C100
+            sub:    jsr leaf
+                    iny
+                    rts
+                    .org $C200
+            leaf:   rts
+                    .org $C300
+            nmi:    rti
+                    .org $FFFA
+                    .word nmi, main, main
+            ",
+        )
+    }
+
+    #[test]
+    fn test_stepping() {
+        let mut nes = calls();
+        assert_eq!(nes.step_into(), RunResult::Stopped(BreakReason::Step));
+        assert_eq!(nes.cpu().program_counter, 0xc100);
+        assert_eq!(nes.step_over(), RunResult::Stopped(BreakReason::Step));
+        assert_eq!(nes.cpu().program_counter, 0xc103);
+        assert_eq!(nes.cpu().register_y, 0);
+        assert_eq!(nes.step_over(), RunResult::Stopped(BreakReason::Step));
+        assert_eq!(nes.cpu().register_y, 1);
+        assert_eq!(nes.step_out(), RunResult::Stopped(BreakReason::Step));
+        assert_eq!(nes.cpu().program_counter, 0xc003);
+
+        // stepping out of sub doesn't stop at leaf returning first
+        assert_eq!(nes.run_to(0xc100), RunResult::Stopped(BreakReason::Step));
+        assert_eq!(nes.cpu().register_x, 1);
+        assert_eq!(nes.step_out(), RunResult::Stopped(BreakReason::Step));
+        assert_eq!(nes.cpu().program_counter, 0xc003);
+        assert_eq!(nes.cpu().register_y, 2);
+
+        // breakpoints still stop steps, and end them
+        nes.debugger_mut().unwrap().add_breakpoint(0xc200);
+        assert_eq!(nes.step_over(), RunResult::Stopped(BreakReason::Step));
+        assert_eq!(nes.step_over(), RunResult::Stopped(BreakReason::Step));
+        assert_eq!(nes.step_over(), RunResult::Stopped(BreakReason::Breakpoint(0xc200)));
+        assert!(!nes.debugger().unwrap().is_stepping());
+    }
+
+    #[test]
+    fn test_call_stack() {
+        let mut nes = calls();
+        nes.attach_debugger(Debugger::new());
+        nes.run_to(0xc200);
+        let frames = nes.debugger().unwrap().call_stack().frames().to_vec();
+        assert_eq!(
+            frames,
+            vec![
+                CallFrame { kind: CallKind::Jsr, entry: 0xc100, return_addr: 0xc003, stack_pointer: 0xfb },
+                CallFrame { kind: CallKind::Jsr, entry: 0xc200, return_addr: 0xc103, stack_pointer: 0xf9 },
+            ]
+        );
+        nes.step_into();
+        assert_eq!(nes.debugger().unwrap().call_stack().depth(), 1);
+        nes.step_out();
+        assert_eq!(nes.debugger().unwrap().call_stack().depth(), 0);
+
+        // an NMI handler shows up on top of whatever it interrupted
+        nes.cpu_mut().bus.mem_write(0x2000, 0x80);
+        while nes.run_to(0xc300) == RunResult::Completed {}
+        let depth = nes.debugger().unwrap().call_stack().depth();
+        let top = *nes.debugger().unwrap().call_stack().frames().last().unwrap();
+        assert_eq!((top.kind, top.entry), (CallKind::Nmi, 0xc300));
+        nes.step_into();
+        assert_eq!(nes.debugger().unwrap().call_stack().depth(), depth - 1);
+        nes.cpu_mut().bus.mem_write(0x2000, 0);
+
+        // resetting the stack drops everything above it
+        nes.run_to(0xc200);
+        nes.cpu_mut().stack_pointer = 0xfd;
+        nes.step_into();
+        assert_eq!(nes.debugger().unwrap().call_stack().depth(), 0);
+    }
+}
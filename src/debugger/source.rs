@@ -0,0 +1,211 @@
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use std::fs;
+#[cfg(feature = "std")]
+use std::path::Path;
+
+// ld65 line type for lines expanded from a macro, which point into the
+// macro's definition rather than where it was used
+const MACRO_LINE: u32 = 2;
+
+/// A line of one of the source files in a `SourceMap`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SourceLine {
+    /// Index into `SourceMap::files`.
+    pub file: usize,
+    pub line: u32,
+}
+
+/// Which source line each CPU address was assembled from, read from the
+/// debug file `ld65 --dbgfile` writes, for stepping through homebrew by
+/// source line rather than by instruction.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SourceMap {
+    files: Vec<String>,
+    lines: BTreeMap<u16, (SourceLine, bool)>,
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        SourceMap::default()
+    }
+
+    #[cfg(feature = "std")]
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<SourceMap, String> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).map_err(|err| format!("couldn't read {}: {}", path.display(), err))?;
+        SourceMap::parse(&text)
+    }
+
+    /// Reads the `file`, `line`, `seg` and `span` records of a debug file.
+    /// Lines in macros only count where no line outside one covers the
+    /// same address.
+    pub fn parse(text: &str) -> Result<SourceMap, String> {
+        let mut files = BTreeMap::new();
+        let mut segments = BTreeMap::new();
+        let mut spans = BTreeMap::new();
+        let mut lines = Vec::new();
+        for (number, record) in text.lines().enumerate() {
+            let Some((kind, fields)) = record.split_once(char::is_whitespace) else {
+                continue;
+            };
+            let fields = Fields::parse(fields.trim(), number)?;
+            match kind {
+                "file" => {
+                    files.insert(fields.number("id")?, fields.get("name").unwrap_or("").trim_matches('"').to_string());
+                }
+                "seg" => {
+                    segments.insert(fields.number("id")?, fields.number("start")?);
+                }
+                "span" => {
+                    spans.insert(fields.number("id")?, (fields.number("seg")?, fields.number("start")?, fields.number("size")?));
+                }
+                "line" => {
+                    // lines that made no code have no spans
+                    if let Some(span) = fields.get("span") {
+                        let kind = fields.get("type").map_or(Ok(0), |_| fields.number("type"))?;
+                        lines.push((fields.number("file")?, fields.number("line")?, span.to_string(), kind == MACRO_LINE, number));
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let mut map = SourceMap::new();
+        let indices: BTreeMap<u32, usize> = files.keys().enumerate().map(|(index, &id)| (id, index)).collect();
+        map.files = files.into_values().collect();
+        for (file, line, span_ids, in_macro, number) in lines {
+            let at = |err: String| format!("line {}: {}", number + 1, err);
+            let file = *indices.get(&file).ok_or_else(|| at(format!("no file {}", file)))?;
+            let source = SourceLine { file, line };
+            for id in span_ids.split('+') {
+                let id: u32 = id.parse().map_err(|_| at(format!("bad span {:?}", id)))?;
+                let &(segment, start, size) = spans.get(&id).ok_or_else(|| at(format!("no span {}", id)))?;
+                let base = *segments.get(&segment).ok_or_else(|| at(format!("no segment {}", segment)))?;
+                for addr in (base + start..base + start + size).filter(|&addr| addr <= 0xffff) {
+                    let entry = map.lines.entry(addr as u16).or_insert((source, in_macro));
+                    if entry.1 && !in_macro {
+                        *entry = (source, in_macro);
+                    }
+                }
+            }
+        }
+        Ok(map)
+    }
+
+    /// The source files, as named when they were assembled.
+    pub fn files(&self) -> &[String] {
+        &self.files
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+
+    /// The line the code at `addr` came from.
+    pub fn line(&self, addr: u16) -> Option<SourceLine> {
+        self.lines.get(&addr).map(|(line, _)| *line)
+    }
+
+    /// The file and line number the code at `addr` came from.
+    pub fn lookup(&self, addr: u16) -> Option<(&str, u32)> {
+        self.line(addr).map(|line| (self.files[line.file].as_str(), line.line))
+    }
+
+    /// The first address assembled from `line` of `file`, which can be the
+    /// name the debug file has or just the end of it, like `main.s` for
+    /// `src/main.s`.
+    pub fn address(&self, file: &str, line: u32) -> Option<u16> {
+        self.lines
+            .iter()
+            .find(|(_, (source, _))| source.line == line && ends_with(&self.files[source.file], file))
+            .map(|(&addr, _)| addr)
+    }
+}
+
+// whether the last components of `path` are those of `end`, like
+// `Path::ends_with` but taking either kind of slash
+fn ends_with(path: &str, end: &str) -> bool {
+    fn components(path: &str) -> Vec<&str> {
+        path.split(['/', '\\']).filter(|part| !part.is_empty() && *part != ".").collect()
+    }
+    components(path).ends_with(&components(end))
+}
+
+// the `key=value,...` part of a record
+struct Fields<'a> {
+    fields: Vec<(&'a str, &'a str)>,
+    number: usize,
+}
+
+impl<'a> Fields<'a> {
+    fn parse(text: &'a str, number: usize) -> Result<Fields<'a>, String> {
+        let fields = text
+            .split(',')
+            .map(|field| field.split_once('=').ok_or_else(|| format!("line {}: expected key=value, got {:?}", number + 1, field)))
+            .collect::<Result<_, _>>()?;
+        Ok(Fields { fields, number })
+    }
+
+    fn get(&self, key: &str) -> Option<&'a str> {
+        self.fields.iter().find(|(name, _)| *name == key).map(|(_, value)| *value)
+    }
+
+    fn number(&self, key: &str) -> Result<u32, String> {
+        let value = self.get(key).ok_or_else(|| format!("line {}: no {}", self.number + 1, key))?;
+        let parsed = match value.strip_prefix("0x") {
+            Some(hex) => u32::from_str_radix(hex, 16),
+            None => value.parse(),
+        };
+        parsed.map_err(|_| format!("line {}: bad {} {:?}", self.number + 1, key, value))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+
+    // main.s assembled to $C000:
+    //   3  reset: sei
+    //   4  loop:  inx
+    //   5         jmp loop
+    // with line 7 a macro expanded into line 4
+    pub(crate) const DBG: &str = "version\tmajor=2,minor=0\n\
+        file\tid=0,name=\"src/main.s\",size=100,mtime=0x5F000000,mod=0\n\
+        file\tid=1,name=\"macros.inc\",size=10,mtime=0x5F000000,mod=0\n\
+        line\tid=0,file=0,line=3,span=0\n\
+        line\tid=1,file=0,line=4,span=1\n\
+        line\tid=2,file=0,line=5,span=2\n\
+        line\tid=3,file=1,line=7,type=2,span=1+3\n\
+        line\tid=4,file=0,line=1\n\
+        seg\tid=0,name=\"CODE\",start=0x00C000,size=0x0005,addrsize=absolute,type=ro,oname=\"game.nes\",ooffs=16\n\
+        span\tid=0,seg=0,start=0,size=1\n\
+        span\tid=1,seg=0,start=1,size=1\n\
+        span\tid=2,seg=0,start=2,size=3\n\
+        span\tid=3,seg=0,start=5,size=1\n";
+
+    #[test]
+    fn test_parse() {
+        let map = SourceMap::parse(DBG).unwrap();
+        assert_eq!(map.files(), ["src/main.s", "macros.inc"]);
+        assert_eq!(map.lookup(0xc000), Some(("src/main.s", 3)));
+        assert_eq!(map.lookup(0xc001), Some(("src/main.s", 4)));
+        assert_eq!(map.lookup(0xc004), Some(("src/main.s", 5)));
+        assert_eq!(map.lookup(0xc005), Some(("macros.inc", 7)));
+        assert_eq!(map.lookup(0xc006), None);
+        assert_eq!(map.address("main.s", 5), Some(0xc002));
+        assert_eq!(map.address("src/main.s", 4), Some(0xc001));
+        assert_eq!(map.address("main.s", 6), None);
+        assert_eq!(map.address("ain.s", 5), None);
+    }
+
+    #[test]
+    fn test_bad_records() {
+        assert!(SourceMap::parse("line\tid=0,file=0,line=3,span=0\n").unwrap_err().contains("no file"));
+        assert!(SourceMap::parse("seg\tid=zero,start=0xC000\n").is_err());
+        assert!(SourceMap::parse("span\tid=0;seg=0\n").is_err());
+    }
+}
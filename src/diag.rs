@@ -0,0 +1,51 @@
+// Internal diagnostics through `tracing`, which compile to nothing without
+// the tracing feature: `diag!(debug, pc = addr, "interrupt")` is
+// `tracing::debug!(pc = addr, "interrupt")` with it. Targets are the
+// module paths, so `RUST_LOG=nessie::cpu=trace` style filters pick chips.
+macro_rules! diag {
+    ($level:ident, $($arg:tt)+) => {
+        #[cfg(feature = "tracing")]
+        tracing::$level!($($arg)+);
+    };
+}
+
+#[cfg(all(test, feature = "tracing"))]
+mod test {
+    use crate::cpu::CPU;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Metadata, Subscriber};
+
+    // counts instruction events
+    struct Counter(Arc<AtomicUsize>);
+
+    impl Subscriber for Counter {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+        fn new_span(&self, _: &Attributes<'_>) -> Id {
+            Id::from_u64(1)
+        }
+        fn record(&self, _: &Id, _: &Record<'_>) {}
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+        fn event(&self, event: &Event<'_>) {
+            if event.metadata().target() == "nessie::cpu" {
+                self.0.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        fn enter(&self, _: &Id) {}
+        fn exit(&self, _: &Id) {}
+    }
+
+    #[test]
+    fn test_instruction_events() {
+        let seen = Arc::new(AtomicUsize::new(0));
+        tracing::subscriber::with_default(Counter(seen.clone()), || {
+            // LDA #$01; TAX; BRK
+            CPU::new().load_and_run(vec![0xa9, 0x01, 0xaa, 0x00]);
+        });
+        // the BRK that ends `run` is fetched too
+        assert_eq!(seen.load(Ordering::Relaxed), 3);
+    }
+}
@@ -0,0 +1,173 @@
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+use crate::cpu::AddressingMode;
+use crate::ops;
+use crate::symbols::Symbols;
+
+/// One decoded instruction, or a byte that isn't one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Instruction {
+    pub addr: u16,
+    pub bytes: Vec<u8>,
+    /// "LDA", or ".byte" for a byte that doesn't start a known instruction.
+    pub mnemonic: &'static str,
+    /// "#$10", "($20),Y", "$C004" for branches, empty for implied ones.
+    pub operand: String,
+}
+
+impl Instruction {
+    pub fn len(&self) -> u16 {
+        self.bytes.len() as u16
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    /// Puts the label of the address the operand names in place of it, so
+    /// `JSR $C123` reads `JSR update_sprites`.
+    pub fn symbolize(&mut self, symbols: &Symbols) {
+        if self.mnemonic == ".byte" || self.operand.starts_with('#') {
+            return;
+        }
+        let Some(start) = self.operand.find('$') else {
+            return;
+        };
+        let digits = self.operand[start + 1..].chars().take_while(char::is_ascii_hexdigit).count();
+        let Ok(addr) = u16::from_str_radix(&self.operand[start + 1..start + 1 + digits], 16) else {
+            return;
+        };
+        if let Some(label) = symbols.label(addr) {
+            self.operand.replace_range(start..start + 1 + digits, label);
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.operand.is_empty() {
+            write!(f, "{}", self.mnemonic)
+        } else {
+            write!(f, "{} {}", self.mnemonic, self.operand)
+        }
+    }
+}
+
+/// Decodes the instruction at `addr`, fetching its bytes with `read`.
+pub fn decode_with<F: FnMut(u16) -> u8>(mut read: F, addr: u16) -> Instruction {
+    let opcode = read(addr);
+    let Some(op) = ops::opcode(opcode) else {
+        return Instruction { addr, bytes: vec![opcode], mnemonic: ".byte", operand: format!("${:02X}", opcode) };
+    };
+    let bytes: Vec<u8> = (0..op.len as u16).map(|i| read(addr.wrapping_add(i))).collect();
+    let lo = bytes.get(1).copied().unwrap_or(0);
+    let word = u16::from_le_bytes([lo, bytes.get(2).copied().unwrap_or(0)]);
+    let operand = match op.mode {
+        AddressingMode::Immediate => format!("#${:02X}", lo),
+        AddressingMode::ZeroPage => format!("${:02X}", lo),
+        AddressingMode::ZeroPage_X => format!("${:02X},X", lo),
+        AddressingMode::ZeroPage_Y => format!("${:02X},Y", lo),
+        AddressingMode::Absolute => format!("${:04X}", word),
+        AddressingMode::Absolute_X => format!("${:04X},X", word),
+        AddressingMode::Absolute_Y => format!("${:04X},Y", word),
+        AddressingMode::Indirect_X => format!("(${:02X},X)", lo),
+        AddressingMode::Indirect_Y => format!("(${:02X}),Y", lo),
+        AddressingMode::Indirect => format!("(${:04X})", word),
+        AddressingMode::Accumulator => "A".to_string(),
+        AddressingMode::Relative => format!("${:04X}", addr.wrapping_add(2).wrapping_add(lo as i8 as u16)),
+        AddressingMode::Implied => String::new(),
+    };
+    Instruction { addr, bytes, mnemonic: op.name, operand }
+}
+
+/// Decodes the instruction at the start of `bytes`, which sit at `addr`.
+/// An instruction cut off by the end of `bytes` comes out as `.byte`, and
+/// no bytes at all as an empty one.
+pub fn decode(bytes: &[u8], addr: u16) -> Instruction {
+    if bytes.is_empty() {
+        return Instruction { addr, bytes: Vec::new(), mnemonic: ".byte", operand: String::new() };
+    }
+    let len = ops::opcode(bytes.first().copied().unwrap_or(0)).map_or(1, |op| op.len as usize);
+    if bytes.len() < len {
+        return Instruction { addr, bytes: bytes[..1].to_vec(), mnemonic: ".byte", operand: format!("${:02X}", bytes[0]) };
+    }
+    decode_with(|at| bytes[at.wrapping_sub(addr) as usize], addr)
+}
+
+/// Decodes all of `bytes`, which sit at `addr`, one instruction after another.
+pub fn disassemble(bytes: &[u8], addr: u16) -> Vec<Instruction> {
+    let mut instructions = Vec::new();
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let instruction = decode(&bytes[offset..], addr.wrapping_add(offset as u16));
+        offset += instruction.bytes.len();
+        instructions.push(instruction);
+    }
+    instructions
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_addressing_modes() {
+        let program = [
+            0xa9, 0x10, // LDA #$10
+            0xb5, 0x20, // LDA $20,X
+            0xbe, 0x00, 0x02, // LDX $0200,Y
+            0xb1, 0x30, // LDA ($30),Y
+            0x6c, 0xfc, 0xff, // JMP ($FFFC)
+            0x0a, // ASL A
+            0xd0, 0xf1, // BNE $C000
+            0xe8, // INX
+            0x02, // not an instruction
+            0x8d, // STA cut short
+            0x00, // BRK
+        ];
+        let lines: Vec<String> = disassemble(&program, 0xc000).iter().map(|i| i.to_string()).collect();
+        assert_eq!(
+            lines,
+            vec![
+                "LDA #$10", "LDA $20,X", "LDX $0200,Y", "LDA ($30),Y", "JMP ($FFFC)", "ASL A", "BNE $C000", "INX",
+                ".byte $02", ".byte $8D", "BRK",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_symbolize() {
+        let mut symbols = Symbols::new();
+        symbols.insert(0xc123, "update_sprites");
+        symbols.insert(0x0020, "pointer");
+        symbols.insert(0x0010, "ten");
+        let program = [0x20, 0x23, 0xc1, 0xb1, 0x20, 0xa9, 0x10, 0xad, 0x24, 0xc1];
+        let lines: Vec<String> = disassemble(&program, 0xc000)
+            .into_iter()
+            .map(|mut instruction| {
+                instruction.symbolize(&symbols);
+                instruction.to_string()
+            })
+            .collect();
+        assert_eq!(lines, vec!["JSR update_sprites", "LDA (pointer),Y", "LDA #$10", "LDA $C124"]);
+    }
+
+    #[test]
+    fn test_decode_with_reader() {
+        let memory = [0x20, 0x34, 0x12];
+        let jsr = decode_with(|addr| memory[addr as usize], 0);
+        assert_eq!(jsr.bytes, memory);
+        assert_eq!(jsr.to_string(), "JSR $1234");
+    }
+
+    #[test]
+    fn test_decode_nothing() {
+        let instruction = decode(&[], 0xc000);
+        assert!(instruction.is_empty());
+        assert_eq!(instruction.addr, 0xc000);
+        assert_eq!(instruction.to_string(), ".byte");
+    }
+}
@@ -0,0 +1,65 @@
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+/// Something that happened in the console, for `Nes::subscribe`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConsoleEvent {
+    /// The PPU finished the frame with this number.
+    FrameComplete(u64),
+    /// The vblank flag was just set.
+    VblankStart,
+    /// The cartridge raised its IRQ line. None of the boards supported so
+    /// far have an IRQ counter, so this doesn't fire yet.
+    MapperIrq,
+    /// The APU frame counter raised its IRQ.
+    ApuFrameIrq,
+    /// The console jumped to a saved state: a snapshot, save state or rewind.
+    StateLoaded,
+    /// The frame with this number finished without the game reading the
+    /// controllers.
+    LagFrame(u64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SubscriptionId(usize);
+
+pub type EventCallback = Box<dyn FnMut(ConsoleEvent) + Send>;
+
+/// Callbacks subscribed to console events.
+#[derive(Default)]
+pub struct Subscribers {
+    callbacks: Vec<(SubscriptionId, EventCallback)>,
+    next_id: usize,
+}
+
+// callbacks can't be copied, so a cloned console starts out without them
+impl Clone for Subscribers {
+    fn clone(&self) -> Self {
+        Subscribers { callbacks: Vec::new(), next_id: self.next_id }
+    }
+}
+
+impl Subscribers {
+    pub fn subscribe(&mut self, callback: EventCallback) -> SubscriptionId {
+        let id = SubscriptionId(self.next_id);
+        self.next_id += 1;
+        self.callbacks.push((id, callback));
+        id
+    }
+
+    pub fn unsubscribe(&mut self, id: SubscriptionId) -> bool {
+        let before = self.callbacks.len();
+        self.callbacks.retain(|(other, _)| *other != id);
+        before != self.callbacks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.callbacks.is_empty()
+    }
+
+    pub fn emit(&mut self, event: ConsoleEvent) {
+        for (_, callback) in &mut self.callbacks {
+            callback(event);
+        }
+    }
+}
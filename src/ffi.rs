@@ -0,0 +1,280 @@
+//! A C API for embedding the emulator in programs not written in Rust.
+//! Build it as a shared library with
+//!
+//! ```text
+//! cargo rustc --release --lib --features ffi --crate-type cdylib
+//! ```
+//!
+//! `include/nessie.h` declares it. The build generates the header from the
+//! declarations here into `OUT_DIR`, and `test_header_is_current` fails
+//! until the checked-in copy matches it.
+//! Every function takes the console `nessie_new` made and accepts a null
+//! one, doing nothing. Calls that can fail return 0 on success and -1
+//! otherwise, with the reason in `nessie_last_error`.
+
+// the doc comments, which end up in the header, say what each of these
+// expects from its caller
+#![allow(clippy::missing_safety_doc)]
+
+use crate::achievements;
+use crate::cartridge::Cartridge;
+use crate::nes::{Nes, ResetKind};
+use std::ffi::{c_char, c_int, c_uint, CString};
+use std::ptr;
+
+pub const NESSIE_WIDTH: c_uint = 256;
+pub const NESSIE_HEIGHT: c_uint = 240;
+
+/// Bits of nessie_set_buttons(), the order the controller reports them in.
+pub const NESSIE_BUTTON_A: u8 = 0x01;
+pub const NESSIE_BUTTON_B: u8 = 0x02;
+pub const NESSIE_BUTTON_SELECT: u8 = 0x04;
+pub const NESSIE_BUTTON_START: u8 = 0x08;
+pub const NESSIE_BUTTON_UP: u8 = 0x10;
+pub const NESSIE_BUTTON_DOWN: u8 = 0x20;
+pub const NESSIE_BUTTON_LEFT: u8 = 0x40;
+pub const NESSIE_BUTTON_RIGHT: u8 = 0x80;
+
+/// The console behind a `NessieConsole *`.
+pub struct Console {
+    nes: Nes,
+    error: CString,
+    // audio made but not collected yet
+    samples: Vec<f32>,
+}
+
+impl Console {
+    fn fail(&mut self, err: String) -> c_int {
+        self.error = CString::new(err.replace('\0', " ")).unwrap_or_default();
+        -1
+    }
+
+    fn check(&mut self, result: Result<(), String>) -> c_int {
+        match result {
+            Ok(()) => 0,
+            Err(err) => self.fail(err),
+        }
+    }
+}
+
+/// A console with nothing plugged in. Free it with nessie_free().
+#[no_mangle]
+pub extern "C" fn nessie_new() -> *mut Console {
+    Box::into_raw(Box::new(Console { nes: Nes::new(), error: CString::default(), samples: Vec::new() }))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn nessie_free(console: *mut Console) {
+    if !console.is_null() {
+        drop(Box::from_raw(console));
+    }
+}
+
+/// Why the last call that returned -1 failed. Owned by the console and
+/// valid until the next failure.
+#[no_mangle]
+pub unsafe extern "C" fn nessie_last_error(console: *const Console) -> *const c_char {
+    console.as_ref().map_or(ptr::null(), |console| console.error.as_ptr())
+}
+
+/// Plugs in an iNES or NES 2.0 image and powers on. The data is copied.
+#[no_mangle]
+pub unsafe extern "C" fn nessie_load_rom(console: *mut Console, data: *const u8, len: usize) -> c_int {
+    let Some(console) = console.as_mut() else { return -1 };
+    if data.is_null() {
+        return console.fail("no ROM given".to_string());
+    }
+    match Cartridge::new(std::slice::from_raw_parts(data, len)) {
+        Ok(cartridge) => {
+            console.nes.insert_cartridge(cartridge);
+            console.samples.clear();
+            0
+        }
+        Err(err) => console.fail(err),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn nessie_reset(console: *mut Console, hard: bool) {
+    if let Some(console) = console.as_mut() {
+        console.nes.reset(if hard { ResetKind::Hard } else { ResetKind::Soft });
+    }
+}
+
+/// Runs until the next frame is done.
+#[no_mangle]
+pub unsafe extern "C" fn nessie_run_frame(console: *mut Console) {
+    if let Some(console) = console.as_mut() {
+        console.nes.run_frame();
+        console.nes.audio(&mut console.samples);
+        // nobody is collecting them, keep about a second around like the APU
+        let limit = console.nes.cpu().bus.apu.sample_rate() as usize;
+        if console.samples.len() > limit {
+            let excess = console.samples.len() - limit;
+            console.samples.drain(..excess);
+        }
+    }
+}
+
+/// Frames completed since power on.
+#[no_mangle]
+pub unsafe extern "C" fn nessie_frame_number(console: *const Console) -> u64 {
+    console.as_ref().map_or(0, |console| console.nes.frame().number())
+}
+
+/// The last frame as NESSIE_WIDTH x NESSIE_HEIGHT RGBA pixels, 4 bytes
+/// each. Valid until the next nessie_run_frame().
+#[no_mangle]
+pub unsafe extern "C" fn nessie_framebuffer(console: *const Console) -> *const u8 {
+    console.as_ref().map_or(ptr::null(), |console| console.nes.frame().pixels().as_ptr())
+}
+
+/// Buttons held on controller 0 to 3, NESSIE_BUTTON_* or'ed together.
+#[no_mangle]
+pub unsafe extern "C" fn nessie_set_buttons(console: *mut Console, player: c_uint, buttons: u8) {
+    if let Some(joypad) = console.as_mut().and_then(|console| console.nes.joypad_mut(player as usize)) {
+        joypad.set_buttons(buttons);
+    }
+}
+
+/// Rate nessie_audio() delivers samples at, in Hz.
+#[no_mangle]
+pub unsafe extern "C" fn nessie_set_sample_rate(console: *mut Console, rate: c_uint) {
+    if let Some(console) = console.as_mut() {
+        console.nes.cpu_mut().bus.apu.set_sample_rate(rate);
+    }
+}
+
+/// Moves up to `capacity` mono samples from 0.0 to 1.0 into `out` and
+/// returns how many. Audio not collected stays queued, up to about a
+/// second of it.
+#[no_mangle]
+pub unsafe extern "C" fn nessie_audio(console: *mut Console, out: *mut f32, capacity: usize) -> usize {
+    let Some(console) = console.as_mut() else { return 0 };
+    if out.is_null() {
+        return 0;
+    }
+    let count = capacity.min(console.samples.len());
+    ptr::copy_nonoverlapping(console.samples.as_ptr(), out, count);
+    console.samples.drain(..count);
+    count
+}
+
+/// Returns the size of the state, writing it to `out` only if it fits, so
+/// a call with a null `out` finds out how much room to make.
+#[no_mangle]
+pub unsafe extern "C" fn nessie_save_state(console: *const Console, out: *mut u8, capacity: usize) -> usize {
+    let Some(console) = console.as_ref() else { return 0 };
+    let state = console.nes.save_state();
+    if !out.is_null() && state.len() <= capacity {
+        ptr::copy_nonoverlapping(state.as_ptr(), out, state.len());
+    }
+    state.len()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn nessie_load_state(console: *mut Console, data: *const u8, len: usize) -> c_int {
+    let Some(console) = console.as_mut() else { return -1 };
+    if data.is_null() {
+        return console.fail("no state given".to_string());
+    }
+    let result = console.nes.load_state(std::slice::from_raw_parts(data, len));
+    console.check(result)
+}
+
+/// For RetroAchievements runtimes like rcheevos. Copies up to `len` bytes
+/// of the CPU address space from `address` on into `out` without side
+/// effects and returns how many; registers read as 0xFF.
+#[no_mangle]
+pub unsafe extern "C" fn nessie_read_memory(console: *const Console, address: u32, out: *mut u8, len: usize) -> usize {
+    match console.as_ref() {
+        Some(console) if !out.is_null() => achievements::read_memory(&console.nes, address, std::slice::from_raw_parts_mut(out, len)),
+        _ => 0,
+    }
+}
+
+/// Writes the RetroAchievements hash of an NES file, 32 hex digits and a
+/// NUL, to `out`, which needs room for 33 bytes.
+#[no_mangle]
+pub unsafe extern "C" fn nessie_rom_hash(data: *const u8, len: usize, out: *mut c_char) -> c_int {
+    if data.is_null() || out.is_null() {
+        return -1;
+    }
+    let hash = achievements::rom_hash(std::slice::from_raw_parts(data, len));
+    ptr::copy_nonoverlapping(hash.as_ptr() as *const c_char, out, hash.len());
+    *out.add(hash.len()) = 0;
+    0
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::asm::assemble;
+    use crate::cartridge::test::test_rom;
+    use crate::frame::{HEIGHT, WIDTH};
+    use std::ffi::CStr;
+
+    const HEADER: &str = include_str!("../include/nessie.h");
+
+    #[test]
+    fn test_header_declares_everything() {
+        let exported: Vec<&str> = include_str!("ffi.rs").lines().filter_map(|line| line.split("extern \"C\" fn ").nth(1)?.split('(').next()).collect();
+        assert!(exported.len() > 10);
+        for name in exported {
+            assert!(HEADER.contains(&format!("{}(", name)), "{} isn't in nessie.h", name);
+        }
+    }
+
+    #[test]
+    fn test_console() {
+        let rom = test_rom(&assemble(".org $C000\nreset: jmp reset\n.org $FFFA\n.word reset, reset, reset").unwrap().bytes);
+        unsafe {
+            let console = nessie_new();
+            assert_eq!(nessie_load_rom(console, b"junk".as_ptr(), 4), -1);
+            assert!(!CStr::from_ptr(nessie_last_error(console)).to_bytes().is_empty());
+            assert_eq!(nessie_load_rom(console, rom.as_ptr(), rom.len()), 0);
+
+            nessie_set_sample_rate(console, 44100);
+            nessie_set_buttons(console, 0, 0b1001);
+            nessie_run_frame(console);
+            assert_eq!(nessie_frame_number(console), 1);
+            assert_eq!(*nessie_framebuffer(console).add(WIDTH * HEIGHT * 4 - 1), 0xff);
+            let mut audio = [0.0; 2000];
+            assert!(nessie_audio(console, audio.as_mut_ptr(), audio.len()) > 500);
+            assert_eq!(nessie_audio(console, audio.as_mut_ptr(), audio.len()), 0);
+            // audio nobody collects doesn't pile up
+            for _ in 0..120 {
+                nessie_run_frame(console);
+            }
+            let mut queued = vec![0.0; 100_000];
+            assert_eq!(nessie_audio(console, queued.as_mut_ptr(), queued.len()), 44100);
+
+            let size = nessie_save_state(console, ptr::null_mut(), 0);
+            let mut state = vec![0; size];
+            assert_eq!(nessie_save_state(console, state.as_mut_ptr(), size), size);
+            nessie_run_frame(console);
+            assert_eq!(nessie_load_state(console, state.as_ptr(), size), 0);
+            assert_eq!(nessie_frame_number(console), 121);
+            assert_eq!(nessie_load_state(console, state.as_ptr(), 3), -1);
+
+            let mut memory = [0; 4];
+            assert_eq!(nessie_read_memory(console, 0x10000, memory.as_mut_ptr(), memory.len()), 0);
+            assert_eq!(nessie_read_memory(console, 0xfffc, memory.as_mut_ptr(), memory.len()), 4);
+            assert_eq!(memory[..2], [0x00, 0xc0]);
+            let mut hash = [0; 33];
+            assert_eq!(nessie_rom_hash(rom.as_ptr(), rom.len(), hash.as_mut_ptr()), 0);
+            assert_eq!(CStr::from_ptr(hash.as_ptr()).to_str().unwrap(), achievements::rom_hash(&rom));
+            nessie_free(console);
+
+            nessie_run_frame(ptr::null_mut());
+            assert!(nessie_framebuffer(ptr::null()).is_null());
+        }
+    }
+
+    #[test]
+    fn test_header_is_current() {
+        let generated = concat!(env!("OUT_DIR"), "/nessie.h");
+        let header = std::fs::read_to_string(generated).expect("the build didn't generate nessie.h");
+        assert!(header == include_str!("../include/nessie.h"), "include/nessie.h is out of date, copy {} over it", generated);
+    }
+}
@@ -0,0 +1,280 @@
+#[cfg(feature = "image")]
+use alloc::format;
+#[cfg(feature = "image")]
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use crate::bytes;
+use crate::cartridge::crc32;
+
+pub const WIDTH: usize = 256;
+pub const HEIGHT: usize = 240;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PixelFormat {
+    /// 4 bytes per pixel: red, green, blue, alpha.
+    Rgba8888,
+    /// 2 bytes per pixel, little endian `rrrrrggg_gggbbbbb`.
+    Rgb565,
+    /// 1 byte per pixel: the 6-bit PPU color, without emphasis.
+    Indexed8,
+}
+
+impl PixelFormat {
+    pub fn bytes_per_pixel(&self) -> usize {
+        match self {
+            PixelFormat::Rgba8888 => 4,
+            PixelFormat::Rgb565 => 2,
+            PixelFormat::Indexed8 => 1,
+        }
+    }
+}
+
+/// A 256x240 RGBA picture along with the number of the frame it shows.
+///
+/// The pixel buffer is allocated once and rewritten in place for every
+/// frame, so holding on to a `Frame` across frames costs no allocations.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Frame {
+    pixels: Vec<u8>,
+    indices: Vec<u16>,
+    number: u64,
+    format: PixelFormat,
+    // the picture in `format`, unless that's RGBA which `pixels` already holds
+    converted: Vec<u8>,
+}
+
+impl Default for Frame {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Frame {
+    pub fn new() -> Self {
+        Frame {
+            pixels: vec![0; WIDTH * HEIGHT * 4],
+            indices: vec![0; WIDTH * HEIGHT],
+            number: 0,
+            format: PixelFormat::Rgba8888,
+            converted: Vec::new(),
+        }
+    }
+
+    pub fn format(&self) -> PixelFormat {
+        self.format
+    }
+
+    /// Chooses the format `data` comes in from the next finished frame on.
+    pub fn set_format(&mut self, format: PixelFormat) {
+        self.format = format;
+        self.converted = match format {
+            PixelFormat::Rgba8888 => Vec::new(),
+            _ => vec![0; WIDTH * HEIGHT * format.bytes_per_pixel()],
+        };
+        self.convert();
+    }
+
+    /// The picture in the configured pixel format, row by row.
+    pub fn data(&self) -> &[u8] {
+        match self.format {
+            PixelFormat::Rgba8888 => &self.pixels,
+            _ => &self.converted,
+        }
+    }
+
+    fn convert(&mut self) {
+        match self.format {
+            PixelFormat::Rgba8888 => {}
+            PixelFormat::Rgb565 => {
+                for (out, rgba) in self.converted.chunks_mut(2).zip(self.pixels.chunks(4)) {
+                    let value = ((rgba[0] as u16 >> 3) << 11) | ((rgba[1] as u16 >> 2) << 5) | (rgba[2] as u16 >> 3);
+                    out.copy_from_slice(&value.to_le_bytes());
+                }
+            }
+            PixelFormat::Indexed8 => {
+                for (out, index) in self.converted.iter_mut().zip(self.indices.iter()) {
+                    *out = (*index & 0x3f) as u8;
+                }
+            }
+        }
+    }
+
+    /// RGBA pixels, row by row, 4 bytes per pixel.
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    /// The PPU color of every pixel before palette lookup: the 6-bit color
+    /// with the three emphasis bits above it.
+    pub fn indices(&self) -> &[u16] {
+        &self.indices
+    }
+
+    /// CRC-32 of `indices`, which doesn't depend on the palette or pixel
+    /// format, for telling frames apart in tests.
+    pub fn hash(&self) -> u32 {
+        crc32(self.indices.iter().flat_map(|index| index.to_le_bytes()))
+    }
+
+    /// How many frames have been completed, this one included.
+    pub fn number(&self) -> u64 {
+        self.number
+    }
+
+    pub fn width(&self) -> usize {
+        WIDTH
+    }
+
+    pub fn height(&self) -> usize {
+        HEIGHT
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> (u8, u8, u8) {
+        let base = (y * WIDTH + x) * 4;
+        (self.pixels[base], self.pixels[base + 1], self.pixels[base + 2])
+    }
+
+    pub fn set_pixel(&mut self, x: usize, y: usize, rgb: (u8, u8, u8)) {
+        let base = (y * WIDTH + x) * 4;
+        if base + 3 < self.pixels.len() {
+            self.pixels[base] = rgb.0;
+            self.pixels[base + 1] = rgb.1;
+            self.pixels[base + 2] = rgb.2;
+            self.pixels[base + 3] = 0xff;
+        }
+    }
+
+    pub fn set_pixel_index(&mut self, x: usize, y: usize, index: u16) {
+        self.indices[y * WIDTH + x] = index;
+    }
+
+    /// Drops the RGBA and converted pictures and goes back to RGBA output,
+    /// leaving what the PPU drew.
+    #[cfg(feature = "serde")]
+    pub(crate) fn clear_output(&mut self) {
+        self.pixels = Vec::new();
+        self.format = PixelFormat::Rgba8888;
+        self.converted = Vec::new();
+    }
+
+    /// Moves the picture out into `out`, leaving the frame empty.
+    pub(crate) fn take_memory(&mut self, out: &mut Vec<u8>) {
+        for index in core::mem::take(&mut self.indices) {
+            out.extend_from_slice(&index.to_le_bytes());
+        }
+        self.pixels = Vec::new();
+        self.converted = Vec::new();
+    }
+
+    /// Puts back a picture taken by `take_memory`, redrawing the pixels with `color`.
+    pub(crate) fn put_memory(&mut self, data: &mut &[u8], color: impl Fn(u16) -> (u8, u8, u8)) {
+        let bytes = bytes::read_bytes(data, WIDTH * HEIGHT * 2);
+        let indices: Vec<u16> = bytes.chunks(2).map(|pair| u16::from_le_bytes([pair[0], pair[1]])).collect();
+        self.pixels = vec![0; WIDTH * HEIGHT * 4];
+        for (i, index) in indices.iter().enumerate() {
+            self.set_pixel(i % WIDTH, i / WIDTH, color(*index));
+        }
+        self.indices = indices;
+        self.set_format(self.format);
+    }
+
+    /// The picture as a PNG file, in the colors `pixels` has them: through
+    /// the palette and with emphasis applied.
+    #[cfg(feature = "image")]
+    pub fn to_png(&self) -> Result<Vec<u8>, String> {
+        let error = |err: png::EncodingError| format!("couldn't encode PNG: {}", err);
+        let mut out = Vec::new();
+        let mut encoder = png::Encoder::new(&mut out, WIDTH as u32, HEIGHT as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header().map_err(error)?;
+        let rgb: Vec<u8> = self.pixels.chunks(4).flat_map(|rgba| [rgba[0], rgba[1], rgba[2]]).collect();
+        writer.write_image_data(&rgb).map_err(error)?;
+        writer.finish().map_err(error)?;
+        Ok(out)
+    }
+
+    /// Marks the picture as complete and advances the frame counter.
+    pub fn finish(&mut self) {
+        self.convert();
+        self.number += 1;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_frame_dimensions() {
+        let frame = Frame::new();
+        assert_eq!(frame.pixels().len(), 256 * 240 * 4);
+        assert_eq!(frame.number(), 0);
+    }
+
+    #[test]
+    fn test_set_pixel_is_opaque_rgba() {
+        let mut frame = Frame::new();
+        frame.set_pixel(1, 1, (0x10, 0x20, 0x30));
+        let base = (WIDTH + 1) * 4;
+        assert_eq!(&frame.pixels()[base..base + 4], &[0x10, 0x20, 0x30, 0xff]);
+        assert_eq!(frame.pixel(1, 1), (0x10, 0x20, 0x30));
+    }
+
+    #[test]
+    fn test_rgb565_output() {
+        let mut frame = Frame::new();
+        frame.set_format(PixelFormat::Rgb565);
+        frame.set_pixel(0, 0, (0xff, 0x00, 0xff));
+        frame.set_pixel(1, 0, (0x00, 0xff, 0x00));
+        frame.finish();
+        assert_eq!(frame.data().len(), 256 * 240 * 2);
+        assert_eq!(&frame.data()[0..4], &[0x1f, 0xf8, 0xe0, 0x07]);
+    }
+
+    #[test]
+    fn test_indexed_output() {
+        let mut frame = Frame::new();
+        frame.set_format(PixelFormat::Indexed8);
+        frame.set_pixel_index(2, 0, 0x16 | 0b101 << 6);
+        frame.finish();
+        assert_eq!(frame.data().len(), 256 * 240);
+        assert_eq!(frame.data()[2], 0x16);
+    }
+
+    #[test]
+    fn test_rgba_data_is_pixels() {
+        let mut frame = Frame::new();
+        frame.set_pixel(0, 0, (1, 2, 3));
+        frame.finish();
+        assert_eq!(frame.format(), PixelFormat::Rgba8888);
+        assert_eq!(frame.data(), frame.pixels());
+    }
+
+    #[cfg(feature = "image")]
+    #[test]
+    fn test_png() {
+        let mut frame = Frame::new();
+        frame.set_pixel(3, 2, (0x10, 0x20, 0x30));
+        let png = frame.to_png().unwrap();
+        let mut reader = png::Decoder::new(png.as_slice()).read_info().unwrap();
+        let mut rgb = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut rgb).unwrap();
+        assert_eq!((info.width, info.height, info.color_type), (256, 240, png::ColorType::Rgb));
+        let base = (2 * WIDTH + 3) * 3;
+        assert_eq!(&rgb[base..base + 3], &[0x10, 0x20, 0x30]);
+    }
+
+    #[test]
+    fn test_finish_reuses_buffer() {
+        let mut frame = Frame::new();
+        let ptr = frame.pixels().as_ptr();
+        frame.finish();
+        frame.finish();
+        assert_eq!(frame.number(), 2);
+        assert_eq!(frame.pixels().as_ptr(), ptr);
+    }
+}
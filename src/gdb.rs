@@ -0,0 +1,378 @@
+use crate::debugger::{Access, BreakReason, Debugger, RunResult, WatchKind, WatchpointId};
+use crate::nes::Nes;
+use std::collections::HashMap;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+// signals in stop replies
+const SIGINT: u8 = 2;
+const SIGTRAP: u8 = 5;
+
+/// What a packet asks the stub to do.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Action {
+    Reply(String),
+    Continue,
+    Step,
+    // reply OK and hang up
+    Detach,
+    Kill,
+}
+
+/// The state of one gdb connection: the watchpoints it set, by the
+/// `Z` packet that set them, and whether packets still get acknowledged.
+///
+/// There's no 6502 target description in gdb, so registers come in this
+/// order, one byte each but the PC: A, X, Y, P, SP, PC (little endian).
+#[derive(Debug, Default)]
+pub struct GdbStub {
+    watchpoints: HashMap<(char, u16, u16), WatchpointId>,
+    no_ack: bool,
+}
+
+/// Waits for gdb to connect on `listener` and serves it until it detaches
+/// or kills the session. Attaches a debugger to `nes` if there is none.
+pub fn serve(nes: &mut Nes, listener: &TcpListener) -> Result<(), String> {
+    let (stream, _) = listener.accept().map_err(|err| format!("couldn't accept gdb: {}", err))?;
+    GdbStub::default().run(nes, stream).map_err(|err| format!("gdb connection failed: {}", err))
+}
+
+impl GdbStub {
+    fn run(&mut self, nes: &mut Nes, mut stream: TcpStream) -> std::io::Result<()> {
+        if nes.debugger().is_none() {
+            nes.attach_debugger(Debugger::new());
+        }
+        while let Some(packet) = self.read_packet(&mut stream)? {
+            let reply = match self.command(nes, &packet) {
+                Action::Reply(reply) => reply,
+                Action::Step => stop_reply(nes.step_into()),
+                Action::Continue => self.resume(nes, &mut stream)?,
+                Action::Detach => {
+                    send(&mut stream, "OK")?;
+                    return Ok(());
+                }
+                Action::Kill => return Ok(()),
+            };
+            send(&mut stream, &reply)?;
+        }
+        Ok(())
+    }
+
+    // runs until the debugger stops the console or gdb sends a break
+    fn resume(&mut self, nes: &mut Nes, stream: &mut TcpStream) -> std::io::Result<String> {
+        loop {
+            if let RunResult::Stopped(reason) = nes.run() {
+                return Ok(stop_reply(RunResult::Stopped(reason)));
+            }
+            stream.set_nonblocking(true)?;
+            let mut byte = [0];
+            let read = stream.read(&mut byte);
+            stream.set_nonblocking(false)?;
+            match read {
+                Ok(0) => return Err(ErrorKind::UnexpectedEof.into()),
+                Ok(_) if byte[0] == 0x03 => return Ok(format!("S{:02x}", SIGINT)),
+                Ok(_) => {}
+                Err(err) if err.kind() == ErrorKind::WouldBlock => {}
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    // the next `$data#checksum` packet, acknowledged, or None once gdb hangs up
+    fn read_packet(&mut self, stream: &mut TcpStream) -> std::io::Result<Option<String>> {
+        let mut byte = [0];
+        loop {
+            if stream.read(&mut byte)? == 0 {
+                return Ok(None);
+            }
+            if byte[0] != b'$' {
+                // acks, and breaks while stopped
+                continue;
+            }
+            let mut data = Vec::new();
+            loop {
+                if stream.read(&mut byte)? == 0 {
+                    return Ok(None);
+                }
+                if byte[0] == b'#' {
+                    break;
+                }
+                data.push(byte[0]);
+            }
+            let mut checksum = [0; 2];
+            stream.read_exact(&mut checksum)?;
+            let valid = std::str::from_utf8(&checksum).ok().and_then(|hex| u8::from_str_radix(hex, 16).ok()) == Some(checksum_of(&data));
+            if !self.no_ack {
+                stream.write_all(if valid { b"+" } else { b"-" })?;
+            }
+            if valid {
+                return Ok(Some(String::from_utf8_lossy(&data).into_owned()));
+            }
+        }
+    }
+
+    fn command(&mut self, nes: &mut Nes, packet: &str) -> Action {
+        let reply = |reply: &str| Action::Reply(reply.to_string());
+        let Some(kind) = packet.chars().next() else {
+            return reply("");
+        };
+        let args = &packet[kind.len_utf8()..];
+        match kind {
+            '?' => Action::Reply(format!("S{:02x}", SIGTRAP)),
+            'g' => {
+                let cpu = nes.cpu();
+                let [lo, hi] = cpu.program_counter.to_le_bytes();
+                Action::Reply(hex(&[cpu.register_a, cpu.register_x, cpu.register_y, cpu.status, cpu.stack_pointer, lo, hi]))
+            }
+            'G' => match unhex(args) {
+                Some(bytes) if bytes.len() == 7 => {
+                    let cpu = nes.cpu_mut();
+                    cpu.register_a = bytes[0];
+                    cpu.register_x = bytes[1];
+                    cpu.register_y = bytes[2];
+                    cpu.status = bytes[3];
+                    cpu.stack_pointer = bytes[4];
+                    cpu.program_counter = u16::from_le_bytes([bytes[5], bytes[6]]);
+                    reply("OK")
+                }
+                _ => reply("E01"),
+            },
+            'p' => match u8::from_str_radix(args, 16) {
+                Ok(register) if register < 6 => {
+                    let cpu = nes.cpu();
+                    Action::Reply(match register {
+                        0 => hex(&[cpu.register_a]),
+                        1 => hex(&[cpu.register_x]),
+                        2 => hex(&[cpu.register_y]),
+                        3 => hex(&[cpu.status]),
+                        4 => hex(&[cpu.stack_pointer]),
+                        _ => hex(&cpu.program_counter.to_le_bytes()),
+                    })
+                }
+                _ => reply("E01"),
+            },
+            'P' => {
+                let parsed = args.split_once('=').and_then(|(register, value)| Some((u8::from_str_radix(register, 16).ok()?, unhex(value)?)));
+                let cpu = nes.cpu_mut();
+                match parsed {
+                    Some((0, value)) if value.len() == 1 => cpu.register_a = value[0],
+                    Some((1, value)) if value.len() == 1 => cpu.register_x = value[0],
+                    Some((2, value)) if value.len() == 1 => cpu.register_y = value[0],
+                    Some((3, value)) if value.len() == 1 => cpu.status = value[0],
+                    Some((4, value)) if value.len() == 1 => cpu.stack_pointer = value[0],
+                    Some((5, value)) if value.len() == 2 => cpu.program_counter = u16::from_le_bytes([value[0], value[1]]),
+                    _ => return reply("E01"),
+                }
+                reply("OK")
+            }
+            'm' => match addr_len(args) {
+                Some((addr, len)) => {
+                    let bus = &nes.cpu().bus;
+                    let bytes: Vec<u8> = (0..len).map(|i| bus.peek(addr.wrapping_add(i))).collect();
+                    Action::Reply(hex(&bytes))
+                }
+                None => reply("E01"),
+            },
+            'M' => {
+                let parsed = args.split_once(':').and_then(|(range, data)| Some((addr_len(range)?, unhex(data)?)));
+                match parsed {
+                    Some(((addr, len), data)) if data.len() == len as usize => {
+                        for (i, &byte) in data.iter().enumerate() {
+                            nes.cpu_mut().bus.mem_write(addr.wrapping_add(i as u16), byte);
+                        }
+                        reply("OK")
+                    }
+                    _ => reply("E01"),
+                }
+            }
+            'Z' | 'z' => self.breakpoint(nes, kind == 'Z', args),
+            'c' => Action::Continue,
+            's' => Action::Step,
+            'D' => Action::Detach,
+            'k' => Action::Kill,
+            'H' => reply("OK"),
+            _ if packet.starts_with("qSupported") => reply("PacketSize=4000;QStartNoAckMode+"),
+            _ if packet == "QStartNoAckMode" => {
+                self.no_ack = true;
+                reply("OK")
+            }
+            _ if packet == "qAttached" => reply("1"),
+            _ if packet == "qC" => reply("QC1"),
+            _ if packet == "qfThreadInfo" => reply("m1"),
+            _ if packet == "qsThreadInfo" => reply("l"),
+            // anything else isn't supported, which an empty reply says
+            _ => reply(""),
+        }
+    }
+
+    // Z0/Z1 are breakpoints, Z2/Z3/Z4 write, read and access watchpoints
+    fn breakpoint(&mut self, nes: &mut Nes, insert: bool, args: &str) -> Action {
+        let mut fields = args.split(',');
+        let (Some(kind), Some(addr), Some(len)) = (fields.next(), fields.next(), fields.next()) else {
+            return Action::Reply("E01".to_string());
+        };
+        let (Ok(addr), Ok(len)) = (u16::from_str_radix(addr, 16), u16::from_str_radix(len, 16)) else {
+            return Action::Reply("E01".to_string());
+        };
+        let Some(debugger) = nes.debugger_mut() else {
+            return Action::Reply("E01".to_string());
+        };
+        let watch = match kind {
+            "0" | "1" => {
+                if insert {
+                    debugger.add_breakpoint(addr);
+                } else {
+                    debugger.remove_breakpoint(addr);
+                }
+                return Action::Reply("OK".to_string());
+            }
+            "2" => WatchKind::Write,
+            "3" => WatchKind::Read,
+            "4" => WatchKind::ReadWrite,
+            _ => return Action::Reply(String::new()),
+        };
+        let key = (kind.chars().next().unwrap_or('2'), addr, len);
+        if insert {
+            let id = debugger.add_watchpoint(addr..=addr.saturating_add(len.max(1) - 1), watch);
+            self.watchpoints.insert(key, id);
+        } else if let Some(id) = self.watchpoints.remove(&key) {
+            debugger.remove_watchpoint(id);
+        }
+        Action::Reply("OK".to_string())
+    }
+}
+
+fn stop_reply(result: RunResult) -> String {
+    match result {
+        RunResult::Stopped(BreakReason::Watchpoint { addr, access, .. }) => {
+            let kind = if access == Access::Write { "watch" } else { "rwatch" };
+            format!("T{:02x}{}:{:04x};", SIGTRAP, kind, addr)
+        }
+        _ => format!("S{:02x}", SIGTRAP),
+    }
+}
+
+fn send(stream: &mut TcpStream, data: &str) -> std::io::Result<()> {
+    stream.write_all(packet(data).as_bytes())
+}
+
+fn packet(data: &str) -> String {
+    format!("${}#{:02x}", data, checksum_of(data.as_bytes()))
+}
+
+fn checksum_of(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn unhex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len()).step_by(2).map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok()).collect()
+}
+
+// "addr,len" in hex
+fn addr_len(text: &str) -> Option<(u16, u16)> {
+    let (addr, len) = text.split_once(',')?;
+    Some((u16::from_str_radix(addr, 16).ok()?, u16::from_str_radix(len, 16).ok()?))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::test_nes;
+
+    fn nes() -> Nes {
+        let mut nes = test_nes(
+            "
+                    .org $C000
+            reset:  lda #$42
+                    sta $0300
+                    jmp reset
+                    .org $FFFA
+                    .word reset, reset, reset
+            ",
+        );
+        nes.attach_debugger(Debugger::new());
+        nes
+    }
+
+    fn reply(stub: &mut GdbStub, nes: &mut Nes, packet: &str) -> String {
+        match stub.command(nes, packet) {
+            Action::Reply(reply) => reply,
+            action => panic!("{:?} for {}", action, packet),
+        }
+    }
+
+    #[test]
+    fn test_registers_and_memory() {
+        let mut nes = nes();
+        let mut stub = GdbStub::default();
+        assert_eq!(reply(&mut stub, &mut nes, "g"), "00000024fd00c0");
+        assert_eq!(reply(&mut stub, &mut nes, "G0102032440"), "E01");
+        assert_eq!(reply(&mut stub, &mut nes, "G01020324f034c1"), "OK");
+        assert_eq!(reply(&mut stub, &mut nes, "p5"), "34c1");
+        assert_eq!(reply(&mut stub, &mut nes, "P0=7f"), "OK");
+        assert_eq!(reply(&mut stub, &mut nes, "p0"), "7f");
+        assert_eq!(reply(&mut stub, &mut nes, "p6"), "E01");
+        assert_eq!(reply(&mut stub, &mut nes, "M10,2:beef"), "OK");
+        assert_eq!(reply(&mut stub, &mut nes, "m10,3"), "beef00");
+        assert_eq!(reply(&mut stub, &mut nes, "mc000,2"), "a942");
+        assert_eq!(reply(&mut stub, &mut nes, "vMustReplyEmpty"), "");
+        // what a byte that isn't UTF-8 turns into
+        assert_eq!(reply(&mut stub, &mut nes, "\u{fffd}00"), "");
+    }
+
+    #[test]
+    fn test_breakpoints_and_watchpoints() {
+        let mut nes = nes();
+        let mut stub = GdbStub::default();
+        assert_eq!(reply(&mut stub, &mut nes, "Z0,c005,1"), "OK");
+        assert_eq!(stub.command(&mut nes, "c"), Action::Continue);
+        assert_eq!(stop_reply(nes.run()), "S05");
+        assert_eq!(nes.cpu().program_counter, 0xc005);
+        assert_eq!(reply(&mut stub, &mut nes, "z0,c005,1"), "OK");
+
+        assert_eq!(reply(&mut stub, &mut nes, "Z2,300,1"), "OK");
+        assert_eq!(stop_reply(nes.run()), "T05watch:0300;");
+        assert_eq!(reply(&mut stub, &mut nes, "z2,300,1"), "OK");
+        assert!(nes.debugger().unwrap().watchpoints().is_empty());
+        assert_eq!(stop_reply(nes.step_into()), "S05");
+    }
+
+    #[test]
+    fn test_session() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let client = std::thread::spawn(move || {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            let mut exchange = |data: &str, expected: &str| {
+                stream.write_all(packet(data).as_bytes()).unwrap();
+                let mut buffer = vec![0; expected.len()];
+                stream.read_exact(&mut buffer).unwrap();
+                assert_eq!(String::from_utf8(buffer).unwrap(), expected);
+            };
+            exchange("?", &format!("+{}", packet("S05")));
+            exchange("Z0,c002,1", &format!("+{}", packet("OK")));
+            exchange("c", &format!("+{}", packet("S05")));
+            exchange("p5", &format!("+{}", packet("02c0")));
+            exchange("D", &format!("+{}", packet("OK")));
+        });
+        let mut nes = nes();
+        serve(&mut nes, &listener).unwrap();
+        client.join().unwrap();
+        assert!(nes.debugger().unwrap().has_breakpoint(0xc002));
+    }
+
+    #[test]
+    fn test_packets() {
+        assert_eq!(packet("OK"), "$OK#9a");
+        assert_eq!(unhex("0aFf"), Some(vec![0x0a, 0xff]));
+        assert_eq!(unhex("0a0"), None);
+        assert_eq!(addr_len("c000,10"), Some((0xc000, 0x10)));
+    }
+}
@@ -0,0 +1,97 @@
+use alloc::collections::VecDeque;
+use core::fmt;
+use crate::cpu::Registers;
+
+/// One instruction as the CPU was about to run it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HistoryEntry {
+    pub opcode: u8,
+    pub registers: Registers,
+}
+
+impl fmt::Display for HistoryEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let r = &self.registers;
+        write!(f, "{:04X}  {:02X}  A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X}", r.program_counter, self.opcode, r.a, r.x, r.y, r.status, r.stack_pointer)
+    }
+}
+
+/// The last instructions the CPU ran, oldest first, for working out how
+/// it got into a crash. Keeps nothing until given a length.
+#[derive(Debug, Clone, Default)]
+pub struct History {
+    entries: VecDeque<HistoryEntry>,
+    len: usize,
+}
+
+impl History {
+    pub fn new(len: usize) -> Self {
+        History { entries: VecDeque::with_capacity(len), len }
+    }
+
+    /// Keeps the last `len` instructions from now on, 0 turning it off.
+    pub fn set_len(&mut self, len: usize) {
+        self.len = len;
+        while self.entries.len() > len {
+            self.entries.pop_front();
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.len > 0
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = &HistoryEntry> {
+        self.entries.iter()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    pub(crate) fn push(&mut self, entry: HistoryEntry) {
+        if self.len == 0 {
+            return;
+        }
+        if self.entries.len() == self.len {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+}
+
+// one entry per line
+impl fmt::Display for History {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for entry in &self.entries {
+            writeln!(f, "{}", entry)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn entry(program_counter: u16) -> HistoryEntry {
+        HistoryEntry { opcode: 0xea, registers: Registers { program_counter, ..Registers::default() } }
+    }
+
+    #[test]
+    fn test_ring() {
+        let mut history = History::default();
+        history.push(entry(0));
+        assert!(!history.is_enabled());
+        assert_eq!(history.entries().count(), 0);
+
+        history.set_len(3);
+        for pc in 0..5 {
+            history.push(entry(pc));
+        }
+        let pcs: Vec<u16> = history.entries().map(|entry| entry.registers.program_counter).collect();
+        assert_eq!(pcs, vec![2, 3, 4]);
+        history.set_len(1);
+        assert_eq!(history.to_string(), "0004  EA  A:00 X:00 Y:00 P:00 SP:00\n");
+    }
+}
@@ -0,0 +1,201 @@
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::ops::RangeInclusive;
+use crate::cpu::CPU;
+use crate::debugger::Access;
+
+pub type CpuCallback = Box<dyn FnMut(&mut CPU) + Send>;
+pub type MemoryCallback = Box<dyn FnMut(&mut MemoryAccess) + Send>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct HookId(usize);
+
+/// A read or write a memory hook sees. Changing `value` changes what the
+/// CPU reads, or what gets written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryAccess {
+    pub addr: u16,
+    pub access: Access,
+    pub value: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum When {
+    // before the instruction at this address, or every one
+    Instruction(Option<u16>),
+    FrameEnd,
+    Nmi,
+}
+
+/// Rust callbacks on what the CPU does, for tools that want what a Lua
+/// script gets without an interpreter. CPU callbacks get the whole console
+/// and can change any of it.
+#[derive(Default)]
+pub(crate) struct Hooks {
+    cpu: Vec<(HookId, When, CpuCallback)>,
+    memory: Vec<(HookId, RangeInclusive<u16>, MemoryCallback)>,
+    next_id: usize,
+}
+
+// callbacks can't be copied, so a cloned console starts out without hooks
+impl Clone for Hooks {
+    fn clone(&self) -> Self {
+        Hooks { cpu: Vec::new(), memory: Vec::new(), next_id: self.next_id }
+    }
+}
+
+impl Hooks {
+    fn next_id(&mut self) -> HookId {
+        let id = HookId(self.next_id);
+        self.next_id += 1;
+        id
+    }
+
+    // what the access ends up reading or writing
+    pub(crate) fn on_access(&mut self, addr: u16, value: u8, access: Access) -> u8 {
+        let mut seen = MemoryAccess { addr, access, value };
+        for (_, range, callback) in &mut self.memory {
+            if range.contains(&addr) {
+                callback(&mut seen);
+            }
+        }
+        seen.value
+    }
+
+    pub(crate) fn has_memory(&self) -> bool {
+        !self.memory.is_empty()
+    }
+}
+
+impl CPU {
+    /// Calls `callback` before the instruction at `addr` runs, or before
+    /// every one without an address. Changing the program counter there
+    /// runs a different instruction instead.
+    pub fn add_instruction_hook<F>(&mut self, addr: Option<u16>, callback: F) -> HookId
+    where
+        F: FnMut(&mut CPU) + Send + 'static,
+    {
+        self.push_cpu_hook(When::Instruction(addr), Box::new(callback))
+    }
+
+    /// Calls `callback` on every read and write in `range`, as it happens.
+    pub fn add_memory_hook<F>(&mut self, range: RangeInclusive<u16>, callback: F) -> HookId
+    where
+        F: FnMut(&mut MemoryAccess) + Send + 'static,
+    {
+        let hooks = &mut self.bus.hooks;
+        let id = hooks.next_id();
+        hooks.memory.push((id, range, Box::new(callback)));
+        id
+    }
+
+    /// Calls `callback` each time the PPU finishes a frame.
+    pub fn add_frame_hook<F>(&mut self, callback: F) -> HookId
+    where
+        F: FnMut(&mut CPU) + Send + 'static,
+    {
+        self.push_cpu_hook(When::FrameEnd, Box::new(callback))
+    }
+
+    /// Calls `callback` when the CPU takes an NMI, with the program counter
+    /// on the handler.
+    pub fn add_nmi_hook<F>(&mut self, callback: F) -> HookId
+    where
+        F: FnMut(&mut CPU) + Send + 'static,
+    {
+        self.push_cpu_hook(When::Nmi, Box::new(callback))
+    }
+
+    pub fn remove_cpu_hook(&mut self, id: HookId) -> bool {
+        let hooks = &mut self.bus.hooks;
+        let before = hooks.cpu.len() + hooks.memory.len();
+        hooks.cpu.retain(|(other, _, _)| *other != id);
+        hooks.memory.retain(|(other, _, _)| *other != id);
+        before != hooks.cpu.len() + hooks.memory.len()
+    }
+
+    fn push_cpu_hook(&mut self, when: When, callback: CpuCallback) -> HookId {
+        let hooks = &mut self.bus.hooks;
+        let id = hooks.next_id();
+        hooks.cpu.push((id, when, callback));
+        id
+    }
+
+    pub(crate) fn run_instruction_hooks(&mut self) {
+        let pc = self.program_counter;
+        if self.bus.hooks.cpu.iter().any(|(_, when, _)| matches!(when, When::Instruction(addr) if addr.is_none_or(|addr| addr == pc))) {
+            self.run_cpu_hooks(|when| matches!(when, When::Instruction(addr) if addr.is_none_or(|addr| addr == pc)));
+        }
+    }
+
+    pub(crate) fn run_frame_hooks(&mut self) {
+        self.run_cpu_hooks(|when| when == When::FrameEnd);
+    }
+
+    pub(crate) fn run_nmi_hooks(&mut self) {
+        self.run_cpu_hooks(|when| when == When::Nmi);
+    }
+
+    fn run_cpu_hooks(&mut self, wanted: impl Fn(When) -> bool) {
+        if self.bus.hooks.cpu.is_empty() {
+            return;
+        }
+        // callbacks get the whole CPU, so the list is moved out while they run
+        let mut hooks = core::mem::take(&mut self.bus.hooks.cpu);
+        for (_, when, callback) in hooks.iter_mut() {
+            if wanted(*when) {
+                callback(self);
+            }
+        }
+        // keep whatever the callbacks registered in the meantime
+        hooks.append(&mut self.bus.hooks.cpu);
+        self.bus.hooks.cpu = hooks;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_instruction_hooks() {
+        let mut cpu = CPU::new();
+        // LDA #$01; NOP; NOP; BRK
+        cpu.load(vec![0xa9, 0x01, 0xea, 0xea, 0x00]);
+        cpu.reset();
+        let seen = Arc::new(AtomicUsize::new(0));
+        let counter = seen.clone();
+        cpu.add_instruction_hook(None, move |_| {
+            counter.fetch_add(1, Ordering::Relaxed);
+        });
+        // skips the first NOP
+        let skip = cpu.add_instruction_hook(Some(0x8002), |cpu| cpu.program_counter = 0x8003);
+        cpu.run();
+        assert_eq!(seen.load(Ordering::Relaxed), 3);
+        assert!(cpu.remove_cpu_hook(skip));
+        assert!(!cpu.remove_cpu_hook(skip));
+    }
+
+    #[test]
+    fn test_memory_hooks() {
+        let mut cpu = CPU::new();
+        let writes = Arc::new(AtomicUsize::new(0));
+        let counter = writes.clone();
+        cpu.add_memory_hook(0x10..=0x1f, move |access| {
+            if access.access == Access::Write {
+                counter.fetch_add(1, Ordering::Relaxed);
+                access.value += 1;
+            } else {
+                access.value *= 2;
+            }
+        });
+        cpu.bus.mem_write(0x10, 5);
+        cpu.bus.mem_write(0x20, 5);
+        assert_eq!(cpu.bus.peek(0x10), 6);
+        assert_eq!(cpu.bus.mem_read(0x10), 12);
+        assert_eq!(cpu.bus.mem_read(0x20), 5);
+        assert_eq!(writes.load(Ordering::Relaxed), 1);
+    }
+}
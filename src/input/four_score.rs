@@ -0,0 +1,62 @@
+// identifies the adapter after the two controllers of each port have been read
+const SIGNATURES: [u8; 2] = [0b0001_0000, 0b0010_0000];
+
+/// Four Score / Satellite multiplexer. Each port shifts out a 24 bit
+/// report: its first controller, its second controller, then a signature
+/// byte games check for before trusting controllers 3 and 4.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FourScore {
+    strobe: bool,
+    reports: [u32; 2],
+    indices: [u8; 2],
+}
+
+impl FourScore {
+    pub fn new() -> Self {
+        FourScore::default()
+    }
+
+    /// Strobe write, latching the buttons of all four controllers in
+    /// `Joypad::buttons()` format.
+    pub fn write(&mut self, data: u8, buttons: [u8; 4]) {
+        self.strobe = data & 1 == 1;
+        if self.strobe {
+            for port in 0..2 {
+                self.reports[port] =
+                    buttons[port] as u32 | (buttons[port + 2] as u32) << 8 | (SIGNATURES[port] as u32) << 16;
+                self.indices[port] = 0;
+            }
+        }
+    }
+
+    pub fn read(&mut self, port: usize) -> u8 {
+        let index = self.indices[port];
+        if index > 23 {
+            return 1;
+        }
+        if !self.strobe {
+            self.indices[port] += 1;
+        }
+        ((self.reports[port] >> index) & 1) as u8
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn report(four_score: &mut FourScore, port: usize) -> u32 {
+        (0..24).fold(0, |report, bit| report | (four_score.read(port) as u32) << bit)
+    }
+
+    #[test]
+    fn test_reports_and_signatures() {
+        let mut four_score = FourScore::new();
+        four_score.write(1, [0x01, 0x02, 0x80, 0x40]);
+        four_score.write(0, [0; 4]);
+        assert_eq!(report(&mut four_score, 0), 0x10_80_01);
+        assert_eq!(report(&mut four_score, 1), 0x20_40_02);
+        assert_eq!(four_score.read(0), 1);
+    }
+}
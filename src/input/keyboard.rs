@@ -0,0 +1,193 @@
+/// Keys of the Family BASIC keyboard.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Key {
+    F1, F2, F3, F4, F5, F6, F7, F8,
+    Num1, Num2, Num3, Num4, Num5, Num6, Num7, Num8, Num9, Num0,
+    A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z,
+    Minus, Caret, Yen, Stop, Escape, At, LeftBracket, Return, Ctr, Semicolon, Colon, RightBracket, Kana,
+    LeftShift, Comma, Period, Slash, Underscore, RightShift, Grph, Space,
+    ClrHome, Ins, Del, Up, Left, Right, Down,
+}
+
+// 9 rows of two 4-key columns, in the order bits 1-4 of $4017 report them
+const MATRIX: [[Key; 8]; 9] = {
+    use Key::*;
+    [
+        [RightBracket, LeftBracket, Return, F8, Stop, Yen, RightShift, Kana],
+        [Semicolon, Colon, At, F7, Caret, Minus, Slash, Underscore],
+        [K, L, O, F6, Num0, P, Comma, Period],
+        [J, U, I, F5, Num8, Num9, N, M],
+        [H, G, Y, F4, Num6, Num7, V, B],
+        [D, R, T, F3, Num4, Num5, C, F],
+        [A, S, W, F2, Num3, E, Z, X],
+        [Ctr, Q, Escape, F1, Num2, Num1, Grph, LeftShift],
+        [Left, Right, Up, ClrHome, Ins, Del, Space, Down],
+    ]
+};
+
+impl Key {
+    fn index(self) -> usize {
+        MATRIX.iter().flatten().position(|key| *key == self).unwrap()
+    }
+
+    /// Translates a host key, named like the W3C `KeyboardEvent.code` values
+    /// that browsers and winit use, by position on a US/JIS layout.
+    pub fn from_host_code(code: &str) -> Option<Key> {
+        use Key::*;
+        if let Some(letter) = code.strip_prefix("Key") {
+            let letters = [A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z];
+            return match letter.as_bytes() {
+                [c @ b'A'..=b'Z'] => Some(letters[(c - b'A') as usize]),
+                _ => None,
+            };
+        }
+        if let Some(digit) = code.strip_prefix("Digit") {
+            let digits = [Num0, Num1, Num2, Num3, Num4, Num5, Num6, Num7, Num8, Num9];
+            return match digit.as_bytes() {
+                [c @ b'0'..=b'9'] => Some(digits[(c - b'0') as usize]),
+                _ => None,
+            };
+        }
+        let key = match code {
+            "F1" => F1, "F2" => F2, "F3" => F3, "F4" => F4,
+            "F5" => F5, "F6" => F6, "F7" => F7, "F8" => F8,
+            "Minus" => Minus,
+            "Equal" => Caret,
+            "IntlYen" | "Backslash" => Yen,
+            "Pause" | "End" => Stop,
+            "Escape" => Escape,
+            "BracketLeft" => At,
+            "BracketRight" => LeftBracket,
+            "Enter" | "NumpadEnter" => Return,
+            "ControlLeft" | "ControlRight" => Ctr,
+            "Semicolon" => Semicolon,
+            "Quote" => Colon,
+            "Backquote" => RightBracket,
+            "AltRight" | "KanaMode" => Kana,
+            "ShiftLeft" => LeftShift,
+            "ShiftRight" => RightShift,
+            "Comma" => Comma,
+            "Period" => Period,
+            "Slash" => Slash,
+            "IntlRo" => Underscore,
+            "AltLeft" => Grph,
+            "Space" => Space,
+            "Home" => ClrHome,
+            "Insert" => Ins,
+            "Delete" | "Backspace" => Del,
+            "ArrowUp" => Up,
+            "ArrowLeft" => Left,
+            "ArrowRight" => Right,
+            "ArrowDown" => Down,
+            _ => return None,
+        };
+        Some(key)
+    }
+}
+
+/// Family BASIC keyboard on the Famicom expansion port. Writes to $4016
+/// pick a row and column of the key matrix and $4017 reads back four keys
+/// of it at a time.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Keyboard {
+    // bit n is key n of the matrix, row by row
+    keys: u128,
+    enabled: bool,
+    row: usize,
+    column: usize,
+}
+
+impl Keyboard {
+    pub fn new() -> Self {
+        Keyboard::default()
+    }
+
+    pub fn set_key(&mut self, key: Key, pressed: bool) {
+        if pressed {
+            self.keys |= 1 << key.index();
+        } else {
+            self.keys &= !(1 << key.index());
+        }
+    }
+
+    pub fn is_pressed(&self, key: Key) -> bool {
+        self.keys & (1 << key.index()) != 0
+    }
+
+    /// Releases every key, for when the host window loses focus.
+    pub fn release_all(&mut self) {
+        self.keys = 0;
+    }
+
+    pub fn write(&mut self, data: u8) {
+        let column = (data >> 1) as usize & 1;
+        // the row advances when the column select goes back to 0
+        if self.column == 1 && column == 0 {
+            self.row = (self.row + 1).min(MATRIX.len());
+        }
+        self.column = column;
+        if data & 1 == 1 {
+            self.row = 0;
+        }
+        self.enabled = data & 0b100 != 0;
+    }
+
+    /// Bits 1-4 of $4017, 0 for pressed. Past the last row every key reads released.
+    pub fn read(&self) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+        if self.row >= MATRIX.len() {
+            return 0b0001_1110;
+        }
+        let first = self.row * 8 + self.column * 4;
+        let pressed = (self.keys >> first) as u8 & 0b1111;
+        !pressed << 1 & 0b0001_1110
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_scan_matrix() {
+        let mut keyboard = Keyboard::new();
+        keyboard.set_key(Key::Return, true);
+        keyboard.set_key(Key::X, true);
+        keyboard.write(0b101);
+        assert_eq!(keyboard.read(), 0b0001_0110);
+        keyboard.write(0b110);
+        assert_eq!(keyboard.read(), 0b0001_1110);
+
+        // rows 1-6 have nothing pressed, X is the last key of row 6's second column
+        for _ in 0..6 {
+            keyboard.write(0b100);
+            keyboard.write(0b110);
+        }
+        assert_eq!(keyboard.read(), 0b0000_1110);
+
+        // rows 7 and 8, then past the end of the matrix
+        keyboard.write(0b100);
+        keyboard.write(0b110);
+        keyboard.write(0b100);
+        keyboard.write(0b110);
+        keyboard.write(0b100);
+        assert_eq!(keyboard.read(), 0b0001_1110);
+
+        keyboard.write(0b000);
+        assert_eq!(keyboard.read(), 0);
+    }
+
+    #[test]
+    fn test_host_codes() {
+        assert_eq!(Key::from_host_code("KeyQ"), Some(Key::Q));
+        assert_eq!(Key::from_host_code("Digit0"), Some(Key::Num0));
+        assert_eq!(Key::from_host_code("Enter"), Some(Key::Return));
+        assert_eq!(Key::from_host_code("ArrowDown"), Some(Key::Down));
+        assert_eq!(Key::from_host_code("KeyAA"), None);
+        assert_eq!(Key::from_host_code("MetaLeft"), None);
+    }
+}
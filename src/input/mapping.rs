@@ -0,0 +1,241 @@
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use crate::bus::Bus;
+use crate::input::keyboard::Key;
+use crate::input::{ExpansionDevice, InputConfig};
+use crate::joypad::Button;
+
+/// Something on the host a player presses: a keyboard key named by its
+/// W3C `KeyboardEvent.code`, or a gamepad button or axis direction
+/// numbered the way the frontend's gamepad library does.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum HostInput {
+    Key(String),
+    GamepadButton { gamepad: u8, button: u8 },
+    GamepadAxis { gamepad: u8, axis: u8, positive: bool },
+}
+
+/// A keyboard key as the frontends see it, for naming it the way
+/// `HostInput::Key` does. Each frontend maps its own key type onto these.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HostKey {
+    /// A to Z.
+    Letter(char),
+    /// 0 to 9 on the main row.
+    Digit(u8),
+    /// F1 to F24.
+    Function(u8),
+    ArrowUp,
+    ArrowDown,
+    ArrowLeft,
+    ArrowRight,
+    Enter,
+    Space,
+    Backspace,
+    ShiftLeft,
+    ShiftRight,
+    ControlLeft,
+    ControlRight,
+    AltLeft,
+    AltRight,
+    Minus,
+    Equal,
+    BracketLeft,
+    BracketRight,
+    Backslash,
+    Semicolon,
+    Quote,
+    Backquote,
+    Comma,
+    Period,
+    Slash,
+}
+
+impl HostKey {
+    /// The W3C `KeyboardEvent.code` input profiles name keys by.
+    pub fn code(self) -> String {
+        let code = match self {
+            HostKey::Letter(c) => return format!("Key{}", c.to_ascii_uppercase()),
+            HostKey::Digit(digit) => return format!("Digit{}", digit),
+            HostKey::Function(n) => return format!("F{}", n),
+            HostKey::ArrowUp => "ArrowUp",
+            HostKey::ArrowDown => "ArrowDown",
+            HostKey::ArrowLeft => "ArrowLeft",
+            HostKey::ArrowRight => "ArrowRight",
+            HostKey::Enter => "Enter",
+            HostKey::Space => "Space",
+            HostKey::Backspace => "Backspace",
+            HostKey::ShiftLeft => "ShiftLeft",
+            HostKey::ShiftRight => "ShiftRight",
+            HostKey::ControlLeft => "ControlLeft",
+            HostKey::ControlRight => "ControlRight",
+            HostKey::AltLeft => "AltLeft",
+            HostKey::AltRight => "AltRight",
+            HostKey::Minus => "Minus",
+            HostKey::Equal => "Equal",
+            HostKey::BracketLeft => "BracketLeft",
+            HostKey::BracketRight => "BracketRight",
+            HostKey::Backslash => "Backslash",
+            HostKey::Semicolon => "Semicolon",
+            HostKey::Quote => "Quote",
+            HostKey::Backquote => "Backquote",
+            HostKey::Comma => "Comma",
+            HostKey::Period => "Period",
+            HostKey::Slash => "Slash",
+        };
+        code.to_string()
+    }
+}
+
+/// What a host input drives on the console side.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Target {
+    /// A standard controller button, players 0 to 3.
+    Joypad { player: u8, button: Button },
+    ZapperTrigger,
+    PaddleFire,
+    /// Power Pad / Family Trainer button, 1 to 12.
+    PowerPad(u8),
+    FamilyKeyboard(Key),
+    Microphone,
+}
+
+/// Devices plugged into the console and the host inputs bound to them,
+/// kept together so a frontend can save and load it as a profile.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct InputProfile {
+    pub name: String,
+    pub ports: InputConfig,
+    pub expansion: ExpansionDevice,
+    pub bindings: Vec<(HostInput, Target)>,
+}
+
+impl Default for InputProfile {
+    /// One controller on the keyboard: arrows, Z for B, X for A, right
+    /// shift for select and enter for start.
+    fn default() -> Self {
+        let keys = [
+            ("KeyX", Button::A),
+            ("KeyZ", Button::B),
+            ("ShiftRight", Button::Select),
+            ("Enter", Button::Start),
+            ("ArrowUp", Button::Up),
+            ("ArrowDown", Button::Down),
+            ("ArrowLeft", Button::Left),
+            ("ArrowRight", Button::Right),
+        ];
+        InputProfile {
+            name: "default".to_string(),
+            ports: InputConfig::default(),
+            expansion: ExpansionDevice::default(),
+            bindings: keys
+                .iter()
+                .map(|(code, button)| (HostInput::Key(code.to_string()), Target::Joypad { player: 0, button: *button }))
+                .collect(),
+        }
+    }
+}
+
+impl InputProfile {
+    /// Adds a binding; one host input can drive several targets.
+    pub fn bind(&mut self, input: HostInput, target: Target) {
+        if !self.bindings.contains(&(input.clone(), target)) {
+            self.bindings.push((input, target));
+        }
+    }
+
+    pub fn unbind(&mut self, input: &HostInput) {
+        self.bindings.retain(|(bound, _)| bound != input);
+    }
+
+    pub fn unbind_target(&mut self, target: Target) {
+        self.bindings.retain(|(_, bound)| *bound != target);
+    }
+
+    pub fn targets<'a>(&'a self, input: &'a HostInput) -> impl Iterator<Item = Target> + 'a {
+        self.bindings.iter().filter(move |(bound, _)| bound == input).map(|(_, target)| *target)
+    }
+
+    /// Plugs the profile's devices into the console.
+    pub fn apply(&self, bus: &mut Bus) {
+        bus.set_input_config(self.ports);
+        bus.set_expansion_device(self.expansion);
+    }
+
+    /// Forwards a host press or release to whatever it is bound to.
+    /// Returns false for inputs the profile doesn't use.
+    pub fn handle(&self, bus: &mut Bus, input: &HostInput, pressed: bool) -> bool {
+        let mut handled = false;
+        for target in self.targets(input) {
+            handled = true;
+            match target {
+                Target::Joypad { player, button } => {
+                    if let Some(joypad) = bus.joypad_mut(player as usize) {
+                        joypad.set_button(button, pressed);
+                    }
+                }
+                Target::ZapperTrigger => bus.zapper.set_trigger(pressed),
+                Target::PaddleFire => bus.paddle.set_fire(pressed),
+                Target::PowerPad(button) => bus.power_pad.set_button(button, pressed),
+                Target::FamilyKeyboard(key) => bus.keyboard.set_key(key, pressed),
+                Target::Microphone => bus.microphone.set_triggered(pressed),
+            }
+        }
+        handled
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::input::PortDevice;
+
+    #[test]
+    fn test_handle_routes_to_devices() {
+        let mut bus = Bus::new();
+        let mut profile = InputProfile { ports: InputConfig::Ports(PortDevice::Joypad, PortDevice::Zapper), ..Default::default() };
+        profile.bind(HostInput::GamepadButton { gamepad: 0, button: 5 }, Target::ZapperTrigger);
+        profile.bind(HostInput::Key("KeyX".to_string()), Target::Joypad { player: 1, button: Button::A });
+        profile.apply(&mut bus);
+        assert_eq!(bus.input_config(), profile.ports);
+
+        assert!(profile.handle(&mut bus, &HostInput::Key("KeyX".to_string()), true));
+        assert!(bus.joypad1.is_pressed(Button::A));
+        assert!(bus.joypad2.is_pressed(Button::A));
+        assert!(profile.handle(&mut bus, &HostInput::GamepadButton { gamepad: 0, button: 5 }, true));
+        assert_eq!(bus.mem_read(0x4017) & 0b1_0000, 0b1_0000);
+        assert!(!profile.handle(&mut bus, &HostInput::Key("KeyQ".to_string()), true));
+
+        profile.unbind_target(Target::Joypad { player: 0, button: Button::A });
+        profile.handle(&mut bus, &HostInput::Key("KeyX".to_string()), false);
+        assert!(bus.joypad1.is_pressed(Button::A));
+        assert!(!bus.joypad2.is_pressed(Button::A));
+    }
+
+    #[test]
+    fn test_host_key_codes() {
+        assert_eq!(HostKey::Letter('x').code(), "KeyX");
+        assert_eq!(HostKey::Digit(7).code(), "Digit7");
+        assert_eq!(HostKey::Function(12).code(), "F12");
+        assert_eq!(HostKey::ShiftRight.code(), "ShiftRight");
+        // the default profile's keys are among them
+        let named = [HostKey::Letter('X'), HostKey::Letter('Z'), HostKey::ShiftRight, HostKey::Enter, HostKey::ArrowUp, HostKey::ArrowDown, HostKey::ArrowLeft, HostKey::ArrowRight];
+        for (key, (input, _)) in named.iter().zip(&InputProfile::default().bindings) {
+            assert_eq!(*input, HostInput::Key(key.code()));
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_profile_round_trip() {
+        let mut profile = InputProfile { expansion: ExpansionDevice::FamilyKeyboard, ..Default::default() };
+        profile.bind(HostInput::GamepadAxis { gamepad: 1, axis: 0, positive: false }, Target::FamilyKeyboard(Key::Left));
+        let saved = serde_json::to_string(&profile).unwrap();
+        let loaded: InputProfile = serde_json::from_str(&saved).unwrap();
+        assert_eq!(loaded, profile);
+    }
+}
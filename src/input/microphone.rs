@@ -0,0 +1,52 @@
+// level above which the mic's comparator reports sound
+const THRESHOLD: f32 = 0.25;
+
+/// Microphone built into the Famicom's second controller, seen by the
+/// console as one bit on $4016 that is set while it picks up sound.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Microphone {
+    level: f32,
+}
+
+impl Microphone {
+    pub fn new() -> Self {
+        Microphone::default()
+    }
+
+    /// Feeds the loudness picked up by the host microphone, 0.0 to 1.0.
+    pub fn set_level(&mut self, level: f32) {
+        self.level = level.clamp(0.0, 1.0);
+    }
+
+    /// For frontends that blow into the mic with a button instead.
+    pub fn set_triggered(&mut self, triggered: bool) {
+        self.level = if triggered { 1.0 } else { 0.0 };
+    }
+
+    pub fn level(&self) -> f32 {
+        self.level
+    }
+
+    /// Bit 2 of $4016.
+    pub fn read(&self) -> u8 {
+        ((self.level >= THRESHOLD) as u8) << 2
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_level_threshold() {
+        let mut mic = Microphone::new();
+        assert_eq!(mic.read(), 0);
+        mic.set_level(0.1);
+        assert_eq!(mic.read(), 0);
+        mic.set_level(0.6);
+        assert_eq!(mic.read(), 0b100);
+        mic.set_triggered(false);
+        assert_eq!(mic.read(), 0);
+    }
+}
@@ -0,0 +1,47 @@
+pub mod four_score;
+pub mod keyboard;
+pub mod mapping;
+pub mod microphone;
+pub mod paddle;
+pub mod power_pad;
+pub mod zapper;
+
+/// What is plugged into the controller ports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum InputConfig {
+    /// One device in each port.
+    Ports(PortDevice, PortDevice),
+    /// A Four Score adapter taking up both ports, with controllers 1 and 3
+    /// read through $4016 and 2 and 4 through $4017.
+    FourScore,
+}
+
+impl Default for InputConfig {
+    fn default() -> Self {
+        InputConfig::Ports(PortDevice::Joypad, PortDevice::Joypad)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PortDevice {
+    Empty,
+    Joypad,
+    Zapper,
+    ArkanoidPaddle,
+    PowerPad,
+}
+
+/// What is plugged into the Famicom expansion port, read through the
+/// upper bits of $4016/$4017 alongside the controllers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ExpansionDevice {
+    #[default]
+    None,
+    /// The Power Pad mat sold as the Family Trainer.
+    FamilyTrainer,
+    /// The Family BASIC keyboard.
+    FamilyKeyboard,
+}
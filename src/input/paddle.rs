@@ -0,0 +1,98 @@
+use crate::math;
+
+// range the potentiometer covers from one end of the knob to the other
+const POSITION_MIN: u8 = 0x62;
+const POSITION_MAX: u8 = 0xf2;
+
+/// Arkanoid "Vaus" controller, NES version: a knob whose position is
+/// latched by the strobe and shifted out MSB first, plus a fire button.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Paddle {
+    position: u8,
+    fire: bool,
+    strobe: bool,
+    shift: u8,
+}
+
+impl Default for Paddle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Paddle {
+    pub fn new() -> Self {
+        Paddle {
+            position: POSITION_MIN,
+            fire: false,
+            strobe: false,
+            shift: 0,
+        }
+    }
+
+    pub fn position(&self) -> u8 {
+        self.position
+    }
+
+    /// Raw potentiometer reading, clamped to what the hardware produces.
+    pub fn set_position(&mut self, position: u8) {
+        self.position = position.clamp(POSITION_MIN, POSITION_MAX);
+    }
+
+    /// Position from 0.0 (turned all the way left) to 1.0, for mouse or
+    /// analog stick input.
+    pub fn set_position_normalized(&mut self, position: f32) {
+        let range = (POSITION_MAX - POSITION_MIN) as f32;
+        self.position = POSITION_MIN + math::roundf(position.clamp(0.0, 1.0) * range) as u8;
+    }
+
+    pub fn set_fire(&mut self, pressed: bool) {
+        self.fire = pressed;
+    }
+
+    pub fn write(&mut self, data: u8) {
+        self.strobe = data & 1 == 1;
+        if self.strobe {
+            self.shift = self.position;
+        }
+    }
+
+    /// Bit 4 is the position, inverted, and bit 3 the fire button.
+    pub fn read(&mut self) -> u8 {
+        let bit = !(self.shift >> 7) & 1;
+        if !self.strobe {
+            self.shift <<= 1;
+        }
+        bit << 4 | (self.fire as u8) << 3
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_position_is_shifted_out_inverted() {
+        let mut paddle = Paddle::new();
+        paddle.set_position(0b1010_1100);
+        paddle.set_fire(true);
+        paddle.write(1);
+        paddle.write(0);
+        let bits: Vec<u8> = (0..8).map(|_| paddle.read()).collect();
+        let value = bits.iter().fold(0u8, |value, bits| value << 1 | (bits >> 4));
+        assert_eq!(!value, 0b1010_1100);
+        assert!(bits.iter().all(|bits| bits & 0b1000 != 0));
+    }
+
+    #[test]
+    fn test_normalized_position() {
+        let mut paddle = Paddle::new();
+        paddle.set_position_normalized(0.0);
+        assert_eq!(paddle.position(), POSITION_MIN);
+        paddle.set_position_normalized(1.0);
+        assert_eq!(paddle.position(), POSITION_MAX);
+        paddle.set_position(0xff);
+        assert_eq!(paddle.position(), POSITION_MAX);
+    }
+}
@@ -0,0 +1,110 @@
+// buttons in the order they are shifted out on bit 3 and bit 4 of $4017
+const SERIAL_ORDER_D3: [u8; 8] = [2, 1, 5, 9, 6, 10, 11, 7];
+const SERIAL_ORDER_D4: [u8; 4] = [4, 3, 12, 8];
+
+/// Power Pad floor mat with its 12 buttons numbered like on side B. On the
+/// NES it sits in a controller port and shifts out two bits per read; the
+/// Famicom's Family Trainer version plugs into the expansion port and is
+/// scanned a row of four buttons at a time.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PowerPad {
+    // bit n - 1 is button n
+    buttons: u16,
+    strobe: bool,
+    shift_d3: u16,
+    shift_d4: u16,
+    // Family Trainer rows, low bit selects
+    row_select: u8,
+}
+
+impl PowerPad {
+    pub fn new() -> Self {
+        PowerPad::default()
+    }
+
+    /// Presses or releases a button, 1 to 12.
+    pub fn set_button(&mut self, button: u8, pressed: bool) {
+        if !(1..=12).contains(&button) {
+            return;
+        }
+        if pressed {
+            self.buttons |= 1 << (button - 1);
+        } else {
+            self.buttons &= !(1 << (button - 1));
+        }
+    }
+
+    pub fn is_pressed(&self, button: u8) -> bool {
+        (1..=12).contains(&button) && self.buttons & (1 << (button - 1)) != 0
+    }
+
+    pub fn write(&mut self, data: u8) {
+        self.strobe = data & 1 == 1;
+        self.row_select = data & 0b111;
+        if self.strobe {
+            self.shift_d3 = self.serialize(&SERIAL_ORDER_D3);
+            self.shift_d4 = self.serialize(&SERIAL_ORDER_D4);
+        }
+    }
+
+    fn serialize(&self, order: &[u8]) -> u16 {
+        // the bits after the real buttons read as pressed
+        order.iter().enumerate().fold(0xffffu16 << order.len(), |shift, (i, button)| {
+            shift | (self.is_pressed(*button) as u16) << i
+        })
+    }
+
+    /// Controller port read: bit 3 and bit 4 each carry a serial stream, 1 for pressed.
+    pub fn read(&mut self) -> u8 {
+        let data = ((self.shift_d3 & 1) as u8) << 3 | ((self.shift_d4 & 1) as u8) << 4;
+        if !self.strobe {
+            self.shift_d3 = (self.shift_d3 >> 1) | 0x8000;
+            self.shift_d4 = (self.shift_d4 >> 1) | 0x8000;
+        }
+        data
+    }
+
+    /// Family Trainer read of $4017: bits 1-4 hold the selected row, 0 for pressed.
+    pub fn read_family_trainer(&self) -> u8 {
+        let row = match self.row_select {
+            s if s & 0b001 == 0 => [4, 3, 2, 1],
+            s if s & 0b010 == 0 => [8, 7, 6, 5],
+            s if s & 0b100 == 0 => [12, 11, 10, 9],
+            _ => return 0b0001_1110,
+        };
+        row.iter().enumerate().fold(0, |data, (i, button)| data | (!self.is_pressed(*button) as u8) << (i + 1))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_serial_streams() {
+        let mut pad = PowerPad::new();
+        pad.set_button(1, true);
+        pad.set_button(12, true);
+        pad.write(1);
+        pad.write(0);
+        let reads: Vec<u8> = (0..10).map(|_| pad.read()).collect();
+        let d3: Vec<u8> = reads.iter().map(|r| (r >> 3) & 1).collect();
+        let d4: Vec<u8> = reads.iter().map(|r| (r >> 4) & 1).collect();
+        assert_eq!(d3, vec![0, 1, 0, 0, 0, 0, 0, 0, 1, 1]);
+        assert_eq!(d4, vec![0, 0, 1, 0, 1, 1, 1, 1, 1, 1]);
+    }
+
+    #[test]
+    fn test_family_trainer_rows() {
+        let mut pad = PowerPad::new();
+        pad.set_button(2, true);
+        pad.set_button(11, true);
+        pad.write(0b110);
+        assert_eq!(pad.read_family_trainer(), 0b0001_0110);
+        pad.write(0b101);
+        assert_eq!(pad.read_family_trainer(), 0b0001_1110);
+        pad.write(0b011);
+        assert_eq!(pad.read_family_trainer(), 0b0001_1010);
+    }
+}
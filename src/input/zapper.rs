@@ -0,0 +1,38 @@
+/// Zapper light gun. The frontend aims it at a screen pixel; the photodiode
+/// reports light while that pixel was drawn bright within the last few
+/// scanlines, like the real sensor responds to the beam passing under it.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Zapper {
+    aim: Option<(usize, usize)>,
+    trigger: bool,
+}
+
+impl Zapper {
+    pub fn new() -> Self {
+        Zapper::default()
+    }
+
+    /// Points the gun at a pixel of the 256x240 picture.
+    pub fn aim(&mut self, x: usize, y: usize) {
+        self.aim = Some((x, y));
+    }
+
+    /// Points the gun away from the screen, which is how games get reloaded.
+    pub fn aim_offscreen(&mut self) {
+        self.aim = None;
+    }
+
+    pub fn aimed_at(&self) -> Option<(usize, usize)> {
+        self.aim
+    }
+
+    pub fn set_trigger(&mut self, pulled: bool) {
+        self.trigger = pulled;
+    }
+
+    /// Bit 3 is low while light is sensed, bit 4 high while the trigger is pulled.
+    pub fn read(&self, light: bool) -> u8 {
+        (!light as u8) << 3 | (self.trigger as u8) << 4
+    }
+}
@@ -0,0 +1,240 @@
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Button {
+    A,
+    B,
+    Select,
+    Start,
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+impl Button {
+    pub const ALL: [Button; 8] = [
+        Button::A,
+        Button::B,
+        Button::Select,
+        Button::Start,
+        Button::Up,
+        Button::Down,
+        Button::Left,
+        Button::Right,
+    ];
+
+    // position in the report, A goes out first
+    fn bit(&self) -> u8 {
+        1 << (*self as u8)
+    }
+}
+
+/// Buttons held on one controller, A in bit 0 through Right in bit 7 like
+/// `Joypad::buttons()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ButtonState(pub u8);
+
+impl ButtonState {
+    pub fn with(self, button: Button) -> Self {
+        ButtonState(self.0 | button.bit())
+    }
+
+    pub fn is_pressed(&self, button: Button) -> bool {
+        self.0 & button.bit() != 0
+    }
+}
+
+impl From<&[Button]> for ButtonState {
+    fn from(buttons: &[Button]) -> Self {
+        buttons.iter().fold(ButtonState::default(), |state, button| state.with(*button))
+    }
+}
+
+pub const DEFAULT_TURBO_RATE: u8 = 2;
+
+/// Standard controller: a 4021 shift register that latches the buttons
+/// while strobe is high and shifts them out one per read of $4016/$4017.
+///
+/// Buttons flagged as turbo are reported pressed and released in turns
+/// while held, switching every `turbo_rate` frames.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Joypad {
+    strobe: bool,
+    button_index: u8,
+    button_status: u8,
+    turbo: u8,
+    turbo_rate: u8,
+    turbo_frames: u8,
+    turbo_on: bool,
+}
+
+impl Default for Joypad {
+    fn default() -> Self {
+        Joypad {
+            strobe: false,
+            button_index: 0,
+            button_status: 0,
+            turbo: 0,
+            turbo_rate: DEFAULT_TURBO_RATE,
+            turbo_frames: 0,
+            turbo_on: true,
+        }
+    }
+}
+
+impl Joypad {
+    pub fn new() -> Self {
+        Joypad::default()
+    }
+
+    pub fn write(&mut self, data: u8) {
+        self.strobe = data & 1 == 1;
+        if self.strobe {
+            self.button_index = 0;
+        }
+    }
+
+    pub fn read(&mut self) -> u8 {
+        // an official controller reads 1 once all 8 buttons have been shifted out
+        if self.button_index > 7 {
+            return 1;
+        }
+        let response = (self.report() >> self.button_index) & 1;
+        if !self.strobe {
+            self.button_index += 1;
+        }
+        response
+    }
+
+    pub fn set_button(&mut self, button: Button, pressed: bool) {
+        if pressed {
+            self.button_status |= button.bit();
+        } else {
+            self.button_status &= !button.bit();
+        }
+    }
+
+    pub fn is_pressed(&self, button: Button) -> bool {
+        self.button_status & button.bit() != 0
+    }
+
+    /// All buttons as one byte, A in bit 0 through Right in bit 7.
+    pub fn buttons(&self) -> u8 {
+        self.button_status
+    }
+
+    pub fn set_buttons(&mut self, buttons: u8) {
+        self.button_status = buttons;
+    }
+
+    pub fn set_turbo(&mut self, button: Button, turbo: bool) {
+        if turbo {
+            self.turbo |= button.bit();
+        } else {
+            self.turbo &= !button.bit();
+        }
+    }
+
+    pub fn is_turbo(&self, button: Button) -> bool {
+        self.turbo & button.bit() != 0
+    }
+
+    pub fn turbo_rate(&self) -> u8 {
+        self.turbo_rate
+    }
+
+    /// Frames a turbo button stays pressed, and then released, at least 1.
+    pub fn set_turbo_rate(&mut self, frames: u8) {
+        self.turbo_rate = frames.max(1);
+        self.turbo_frames = self.turbo_frames.min(self.turbo_rate - 1);
+    }
+
+    /// Buttons as the console sees them, with turbo applied.
+    pub fn report(&self) -> u8 {
+        if self.turbo_on {
+            self.button_status
+        } else {
+            self.button_status & !self.turbo
+        }
+    }
+
+    /// Advances the turbo timing, called once per frame.
+    pub fn end_frame(&mut self) {
+        self.turbo_frames += 1;
+        if self.turbo_frames >= self.turbo_rate {
+            self.turbo_frames = 0;
+            self.turbo_on = !self.turbo_on;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn read_report(joypad: &mut Joypad) -> Vec<u8> {
+        (0..10).map(|_| joypad.read()).collect()
+    }
+
+    #[test]
+    fn test_strobe_and_shift_out() {
+        let mut joypad = Joypad::new();
+        joypad.set_button(Button::A, true);
+        joypad.set_button(Button::Start, true);
+        joypad.set_button(Button::Left, true);
+        joypad.write(1);
+        joypad.write(0);
+        assert_eq!(read_report(&mut joypad), vec![1, 0, 0, 1, 0, 0, 1, 0, 1, 1]);
+
+        // reading again without strobing keeps returning 1
+        assert_eq!(joypad.read(), 1);
+        joypad.write(1);
+        joypad.write(0);
+        assert_eq!(joypad.read(), 1);
+        assert_eq!(joypad.read(), 0);
+    }
+
+    #[test]
+    fn test_strobe_high_keeps_returning_a() {
+        let mut joypad = Joypad::new();
+        joypad.set_button(Button::A, true);
+        joypad.write(1);
+        assert_eq!(joypad.read(), 1);
+        assert_eq!(joypad.read(), 1);
+        joypad.set_button(Button::A, false);
+        assert_eq!(joypad.read(), 0);
+    }
+
+    #[test]
+    fn test_release_button() {
+        let mut joypad = Joypad::new();
+        joypad.set_button(Button::Right, true);
+        assert!(joypad.is_pressed(Button::Right));
+        assert_eq!(joypad.buttons(), 0b1000_0000);
+        joypad.set_button(Button::Right, false);
+        assert!(!joypad.is_pressed(Button::Right));
+    }
+
+    #[test]
+    fn test_turbo_toggles_every_rate_frames() {
+        let mut joypad = Joypad::new();
+        joypad.set_turbo(Button::B, true);
+        joypad.set_turbo_rate(3);
+        joypad.set_button(Button::A, true);
+        joypad.set_button(Button::B, true);
+        let mut reports = Vec::new();
+        for _ in 0..8 {
+            joypad.write(1);
+            joypad.write(0);
+            reports.push(joypad.read() | joypad.read() << 1);
+            joypad.end_frame();
+        }
+        assert_eq!(reports, vec![0b11, 0b11, 0b11, 0b01, 0b01, 0b01, 0b11, 0b11]);
+
+        // A is held steadily, and releasing B stops the turbo from pressing it
+        joypad.set_button(Button::B, false);
+        assert_eq!(joypad.report(), 0b01);
+    }
+}
@@ -1,5 +1,72 @@
-pub mod cpu;
-pub mod ops;
+//! Without the default `std` feature the console itself (CPU, PPU, APU,
+//! bus and cartridges) builds as `no_std` with `alloc`. Whatever touches
+//! files, threads or the clock needs `std`.
+
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+extern crate alloc;
 
 #[macro_use]
-extern crate lazy_static;
+mod diag;
+
+pub mod achievements;
+pub mod apu;
+pub mod asm;
+#[cfg(feature = "cpal")]
+pub mod audio;
+pub mod blip;
+pub mod bus;
+mod bytes;
+pub mod cartridge;
+pub mod cheats;
+pub mod clock;
+#[cfg(feature = "config")]
+pub mod config;
+pub mod cpu;
+#[cfg(feature = "debug-server")]
+pub mod debug_server;
+pub mod debugger;
+pub mod disasm;
+pub mod events;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod frame;
+#[cfg(feature = "gdb")]
+pub mod gdb;
+pub mod history;
+pub mod hooks;
+pub mod input;
+pub mod joypad;
+mod math;
+#[cfg(feature = "libretro")]
+pub mod libretro;
+#[cfg(feature = "lua")]
+pub mod lua;
+pub mod movie;
+pub mod nes;
+#[cfg(feature = "netplay")]
+pub mod netplay;
+pub mod ntsc;
+pub mod ops;
+pub mod overlay;
+pub mod ppu;
+pub mod profiler;
+pub mod ram_watch;
+#[cfg(feature = "std")]
+pub mod recording;
+pub mod region;
+pub mod rewind;
+pub mod rng;
+pub mod romdb;
+#[cfg(feature = "serde")]
+pub mod slots;
+pub mod symbols;
+#[cfg(feature = "std")]
+pub mod testing;
+#[cfg(feature = "std")]
+pub mod thread;
+pub mod trace;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+#[cfg(feature = "std")]
+pub mod wav;
@@ -0,0 +1,550 @@
+//! A libretro core, so RetroArch and other libretro frontends can run the
+//! emulator. Build it as a shared library with
+//!
+//! ```text
+//! cargo rustc --release --lib --features libretro --crate-type cdylib
+//! ```
+//!
+//! and rename `target/release/libnessie.so` (or `nessie.dll`,
+//! `libnessie.dylib`) to `nessie_libretro.so` in the frontend's cores
+//! directory. Both controller ports take a RetroPad; its B and A buttons
+//! are the NES B and A.
+//!
+//! libretro calls every function here from one thread, so the console
+//! lives in a thread local.
+
+// the libretro API documents what each of these expects from its caller
+#![allow(clippy::missing_safety_doc)]
+
+use crate::blip::DcBlocker;
+use crate::cartridge::Cartridge;
+use crate::cheats::{Cheat, Cheats};
+use crate::frame::{PixelFormat, HEIGHT, WIDTH};
+use crate::joypad::Button;
+use crate::nes::{Nes, ResetKind};
+use crate::region::Region;
+use std::cell::RefCell;
+use std::ffi::{c_char, c_uint, c_void, CStr, CString};
+use std::ptr;
+
+const API_VERSION: c_uint = 1;
+const SAMPLE_RATE: u32 = 48000;
+
+const ENVIRONMENT_SET_PIXEL_FORMAT: c_uint = 10;
+const ENVIRONMENT_SET_INPUT_DESCRIPTORS: c_uint = 11;
+const ENVIRONMENT_GET_LOG_INTERFACE: c_uint = 27;
+const PIXEL_FORMAT_RGB565: c_uint = 2;
+
+const DEVICE_JOYPAD: c_uint = 1;
+const REGION_NTSC: c_uint = 0;
+const REGION_PAL: c_uint = 1;
+const MEMORY_SAVE_RAM: c_uint = 0;
+const MEMORY_SYSTEM_RAM: c_uint = 2;
+const LOG_WARN: c_uint = 2;
+const LOG_ERROR: c_uint = 3;
+
+// RetroPad button ids for each NES button
+const JOYPAD: [(Button, c_uint, &CStr); 8] = [
+    (Button::A, 8, c"A"),
+    (Button::B, 0, c"B"),
+    (Button::Select, 2, c"Select"),
+    (Button::Start, 3, c"Start"),
+    (Button::Up, 4, c"Up"),
+    (Button::Down, 5, c"Down"),
+    (Button::Left, 6, c"Left"),
+    (Button::Right, 7, c"Right"),
+];
+
+type EnvironmentFn = unsafe extern "C" fn(cmd: c_uint, data: *mut c_void) -> bool;
+type VideoRefreshFn = unsafe extern "C" fn(data: *const c_void, width: c_uint, height: c_uint, pitch: usize);
+type AudioSampleFn = unsafe extern "C" fn(left: i16, right: i16);
+type AudioSampleBatchFn = unsafe extern "C" fn(data: *const i16, frames: usize) -> usize;
+type InputPollFn = unsafe extern "C" fn();
+type InputStateFn = unsafe extern "C" fn(port: c_uint, device: c_uint, index: c_uint, id: c_uint) -> i16;
+type LogFn = unsafe extern "C" fn(level: c_uint, fmt: *const c_char, ...);
+
+#[repr(C)]
+pub struct SystemInfo {
+    pub library_name: *const c_char,
+    pub library_version: *const c_char,
+    pub valid_extensions: *const c_char,
+    pub need_fullpath: bool,
+    pub block_extract: bool,
+}
+
+#[repr(C)]
+pub struct GameGeometry {
+    pub base_width: c_uint,
+    pub base_height: c_uint,
+    pub max_width: c_uint,
+    pub max_height: c_uint,
+    pub aspect_ratio: f32,
+}
+
+#[repr(C)]
+pub struct SystemTiming {
+    pub fps: f64,
+    pub sample_rate: f64,
+}
+
+#[repr(C)]
+pub struct SystemAvInfo {
+    pub geometry: GameGeometry,
+    pub timing: SystemTiming,
+}
+
+#[repr(C)]
+pub struct GameInfo {
+    pub path: *const c_char,
+    pub data: *const c_void,
+    pub size: usize,
+    pub meta: *const c_char,
+}
+
+#[repr(C)]
+struct InputDescriptor {
+    port: c_uint,
+    device: c_uint,
+    index: c_uint,
+    id: c_uint,
+    description: *const c_char,
+}
+
+#[repr(C)]
+struct LogCallback {
+    log: Option<LogFn>,
+}
+
+#[derive(Default)]
+struct Core {
+    // boxed so the RAM pointers handed out stay put
+    nes: Option<Box<Nes>>,
+    environment: Option<EnvironmentFn>,
+    log: Option<LogFn>,
+    video_refresh: Option<VideoRefreshFn>,
+    audio_sample_batch: Option<AudioSampleBatchFn>,
+    input_poll: Option<InputPollFn>,
+    input_state: Option<InputStateFn>,
+    samples: Vec<f32>,
+    stereo: Vec<i16>,
+    dc: DcBlocker,
+}
+
+thread_local! {
+    static CORE: RefCell<Core> = RefCell::new(Core::default());
+}
+
+fn with_core<T>(f: impl FnOnce(&mut Core) -> T) -> T {
+    CORE.with(|core| f(&mut core.borrow_mut()))
+}
+
+fn with_nes<T>(default: T, f: impl FnOnce(&mut Nes) -> T) -> T {
+    with_core(|core| core.nes.as_deref_mut().map_or(default, f))
+}
+
+/// Logs through the frontend, or through `diag!` when it has no log
+/// interface. Not to be called from inside `with_core`.
+fn log(level: c_uint, message: &str) {
+    match (with_core(|core| core.log), CString::new(format!("nessie: {}\n", message))) {
+        (Some(log), Ok(message)) => unsafe { log(level, c"%s".as_ptr(), message.as_ptr()) },
+        _ if level == LOG_ERROR => {
+            diag!(error, "{}", message);
+        }
+        _ => {
+            diag!(warn, "{}", message);
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_api_version() -> c_uint {
+    API_VERSION
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_info(info: *mut SystemInfo) {
+    *info = SystemInfo {
+        library_name: c"NESsie".as_ptr(),
+        library_version: concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr() as *const c_char,
+        valid_extensions: c"nes|bin".as_ptr(),
+        need_fullpath: false,
+        block_extract: false,
+    };
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_av_info(info: *mut SystemAvInfo) {
+    let region = with_nes(Region::default(), |nes| nes.region());
+    *info = SystemAvInfo {
+        geometry: GameGeometry {
+            base_width: WIDTH as c_uint,
+            base_height: HEIGHT as c_uint,
+            max_width: WIDTH as c_uint,
+            max_height: HEIGHT as c_uint,
+            // NTSC pixels are 8:7
+            aspect_ratio: WIDTH as f32 * 8.0 / 7.0 / HEIGHT as f32,
+        },
+        timing: SystemTiming { fps: region.frame_rate(), sample_rate: SAMPLE_RATE as f64 },
+    };
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_set_environment(callback: EnvironmentFn) {
+    let mut interface = LogCallback { log: None };
+    let logs = callback(ENVIRONMENT_GET_LOG_INTERFACE, &mut interface as *mut LogCallback as *mut c_void);
+    with_core(|core| {
+        core.environment = Some(callback);
+        core.log = if logs { interface.log } else { None };
+    });
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_video_refresh(callback: VideoRefreshFn) {
+    with_core(|core| core.video_refresh = Some(callback));
+}
+
+// everything goes through the batch callback
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample(_callback: AudioSampleFn) {}
+
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample_batch(callback: AudioSampleBatchFn) {
+    with_core(|core| core.audio_sample_batch = Some(callback));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_poll(callback: InputPollFn) {
+    with_core(|core| core.input_poll = Some(callback));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_state(callback: InputStateFn) {
+    with_core(|core| core.input_state = Some(callback));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_init() {}
+
+#[no_mangle]
+pub extern "C" fn retro_deinit() {
+    with_core(|core| *core = Core::default());
+}
+
+// only the RetroPad is supported
+#[no_mangle]
+pub extern "C" fn retro_set_controller_port_device(_port: c_uint, _device: c_uint) {}
+
+#[no_mangle]
+pub extern "C" fn retro_reset() {
+    with_nes((), |nes| nes.reset(ResetKind::Soft));
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_load_game(game: *const GameInfo) -> bool {
+    let Some(game) = game.as_ref() else { return false };
+    if game.data.is_null() {
+        return false;
+    }
+    let cartridge = match Cartridge::new(std::slice::from_raw_parts(game.data as *const u8, game.size)) {
+        Ok(cartridge) => cartridge,
+        Err(err) => {
+            log(LOG_ERROR, &err);
+            return false;
+        }
+    };
+    let Some(environment) = with_core(|core| core.environment) else { return false };
+
+    let mut format = PIXEL_FORMAT_RGB565;
+    if !environment(ENVIRONMENT_SET_PIXEL_FORMAT, &mut format as *mut c_uint as *mut c_void) {
+        log(LOG_ERROR, "the frontend doesn't take RGB565 video");
+        return false;
+    }
+    let mut descriptors: Vec<InputDescriptor> = (0..2)
+        .flat_map(|port| JOYPAD.iter().map(move |(_, id, name)| InputDescriptor { port, device: DEVICE_JOYPAD, index: 0, id: *id, description: name.as_ptr() }))
+        .collect();
+    descriptors.push(InputDescriptor { port: 0, device: 0, index: 0, id: 0, description: ptr::null() });
+    environment(ENVIRONMENT_SET_INPUT_DESCRIPTORS, descriptors.as_mut_ptr() as *mut c_void);
+
+    let mut nes = Box::new(Nes::new());
+    nes.insert_cartridge(cartridge);
+    let bus = &mut nes.cpu_mut().bus;
+    bus.ppu.set_pixel_format(PixelFormat::Rgb565);
+    bus.apu.set_sample_rate(SAMPLE_RATE);
+    with_core(|core| core.nes = Some(nes));
+    true
+}
+
+#[no_mangle]
+pub extern "C" fn retro_load_game_special(_game_type: c_uint, _info: *const GameInfo, _num_info: usize) -> bool {
+    false
+}
+
+#[no_mangle]
+pub extern "C" fn retro_unload_game() {
+    with_core(|core| core.nes = None);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_region() -> c_uint {
+    match with_nes(Region::default(), |nes| nes.region()) {
+        Region::Ntsc => REGION_NTSC,
+        Region::Pal | Region::Dendy => REGION_PAL,
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_run() {
+    let core = with_core(|core| (core.input_poll, core.input_state, core.video_refresh, core.audio_sample_batch));
+    let (Some(input_poll), Some(input_state), Some(video_refresh), Some(audio_sample_batch)) = core else { return };
+
+    input_poll();
+    let pressed: Vec<Vec<bool>> = (0..2).map(|port| JOYPAD.iter().map(|(_, id, _)| input_state(port, DEVICE_JOYPAD, 0, *id) != 0).collect()).collect();
+    let (video, audio) = with_core(|core| {
+        let Some(nes) = core.nes.as_deref_mut() else { return (None, Vec::new()) };
+        for (port, pressed) in pressed.iter().enumerate() {
+            if let Some(joypad) = nes.joypad_mut(port) {
+                for ((button, _, _), pressed) in JOYPAD.iter().zip(pressed) {
+                    joypad.set_button(*button, *pressed);
+                }
+            }
+        }
+        nes.run_frame();
+        let video = nes.frame().data().to_vec();
+
+        nes.audio(&mut core.samples);
+        core.stereo.clear();
+        for sample in core.samples.drain(..) {
+            let sample = (core.dc.filter(sample) * i16::MAX as f32).clamp(i16::MIN as f32, i16::MAX as f32) as i16;
+            core.stereo.extend_from_slice(&[sample, sample]);
+        }
+        (Some(video), std::mem::take(&mut core.stereo))
+    });
+
+    if let Some(video) = video {
+        video_refresh(video.as_ptr() as *const c_void, WIDTH as c_uint, HEIGHT as c_uint, WIDTH * PixelFormat::Rgb565.bytes_per_pixel());
+    }
+    let mut written = 0;
+    while written < audio.len() / 2 {
+        let frames = audio_sample_batch(audio[written * 2..].as_ptr(), audio.len() / 2 - written);
+        if frames == 0 {
+            break;
+        }
+        written += frames;
+    }
+    with_core(|core| core.stereo = audio);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_serialize_size() -> usize {
+    with_nes(0, |nes| nes.save_state().len())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_serialize(data: *mut c_void, size: usize) -> bool {
+    let Some(state) = with_nes(None, |nes| Some(nes.save_state())) else { return false };
+    if data.is_null() || state.len() > size {
+        return false;
+    }
+    let out = std::slice::from_raw_parts_mut(data as *mut u8, size);
+    out[..state.len()].copy_from_slice(&state);
+    out[state.len()..].fill(0);
+    true
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_unserialize(data: *const c_void, size: usize) -> bool {
+    if data.is_null() {
+        return false;
+    }
+    let state = std::slice::from_raw_parts(data as *const u8, size);
+    match with_nes(None, |nes| Some(nes.load_state(state))) {
+        Some(Ok(())) => true,
+        Some(Err(err)) => {
+            log(LOG_ERROR, &err);
+            false
+        }
+        None => false,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_reset() {
+    with_nes((), |nes| nes.set_cheats(Cheats::default()));
+}
+
+/// Takes Game Genie and RAM codes as `Cheat::parse` reads them, several
+/// joined with `+` as RetroArch's cheat files have them.
+#[no_mangle]
+pub unsafe extern "C" fn retro_cheat_set(_index: c_uint, enabled: bool, code: *const c_char) {
+    if !enabled || code.is_null() {
+        return;
+    }
+    let code = CStr::from_ptr(code).to_string_lossy();
+    let errors = with_nes(Vec::new(), |nes| {
+        let mut errors = Vec::new();
+        for part in code.split('+') {
+            match Cheat::parse(part.trim()) {
+                Ok(cheat) => nes.cheats_mut().add(cheat),
+                Err(err) => errors.push(err),
+            }
+        }
+        errors
+    });
+    for err in errors {
+        log(LOG_WARN, &err);
+    }
+}
+
+/// Battery RAM only for games that have a battery, so the frontend
+/// doesn't write save files for the rest.
+#[no_mangle]
+pub extern "C" fn retro_get_memory_data(id: c_uint) -> *mut c_void {
+    with_nes(ptr::null_mut(), |nes| {
+        let bus = &mut nes.cpu_mut().bus;
+        match id {
+            MEMORY_SAVE_RAM if bus.has_battery() => bus.prg_ram_mut().as_mut_ptr() as *mut c_void,
+            MEMORY_SYSTEM_RAM => bus.ram_mut().as_mut_ptr() as *mut c_void,
+            _ => ptr::null_mut(),
+        }
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_size(id: c_uint) -> usize {
+    with_nes(0, |nes| {
+        let bus = &nes.cpu().bus;
+        match id {
+            MEMORY_SAVE_RAM if bus.has_battery() => bus.prg_ram().len(),
+            MEMORY_SYSTEM_RAM => bus.ram().len(),
+            _ => 0,
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::asm::assemble;
+    use crate::cartridge::test::test_rom;
+    use std::cell::Cell;
+
+    thread_local! {
+        static VIDEO: Cell<Option<(c_uint, c_uint, usize)>> = const { Cell::new(None) };
+        static AUDIO: Cell<usize> = const { Cell::new(0) };
+        static START: Cell<bool> = const { Cell::new(false) };
+        static LOGGED: RefCell<Vec<(c_uint, String)>> = const { RefCell::new(Vec::new()) };
+    }
+
+    unsafe extern "C" fn environment(cmd: c_uint, _data: *mut c_void) -> bool {
+        cmd == ENVIRONMENT_SET_PIXEL_FORMAT || cmd == ENVIRONMENT_SET_INPUT_DESCRIPTORS
+    }
+
+    // stands in for the frontend's printf-style logger; x86-64 passes the
+    // first variadic arguments the way it passes fixed ones
+    unsafe extern "C" fn log(level: c_uint, fmt: *const c_char, message: *const c_char) {
+        assert_eq!(CStr::from_ptr(fmt), c"%s");
+        LOGGED.with_borrow_mut(|logged| logged.push((level, CStr::from_ptr(message).to_string_lossy().into_owned())));
+    }
+
+    unsafe extern "C" fn environment_with_log(cmd: c_uint, data: *mut c_void) -> bool {
+        if cmd == ENVIRONMENT_GET_LOG_INTERFACE {
+            let log = std::mem::transmute::<unsafe extern "C" fn(c_uint, *const c_char, *const c_char), LogFn>(log);
+            *(data as *mut LogCallback) = LogCallback { log: Some(log) };
+            return true;
+        }
+        environment(cmd, data)
+    }
+
+    unsafe extern "C" fn video_refresh(data: *const c_void, width: c_uint, height: c_uint, pitch: usize) {
+        assert!(!data.is_null());
+        VIDEO.set(Some((width, height, pitch)));
+    }
+
+    unsafe extern "C" fn audio_sample_batch(_data: *const i16, frames: usize) -> usize {
+        AUDIO.set(AUDIO.get() + frames);
+        frames
+    }
+
+    unsafe extern "C" fn input_poll() {}
+
+    unsafe extern "C" fn input_state(port: c_uint, _device: c_uint, _index: c_uint, id: c_uint) -> i16 {
+        (port == 0 && id == 3 && START.get()) as i16
+    }
+
+    // copies the controller to $00 every frame
+    fn load() {
+        let source = "
+                    .org $C000
+            reset:  lda #$C0
+                    sta $2000
+            idle:   jmp idle
+            nmi:    lda #1
+                    sta $4016
+                    lda #0
+                    sta $4016
+                    ldx #8
+            read:   lda $4016
+                    lsr a
+                    rol $00
+                    dex
+                    bne read
+                    rti
+                    .org $FFFA
+                    .word nmi, reset, reset
+            ";
+        let rom = test_rom(&assemble(source).unwrap().bytes);
+        unsafe { retro_set_environment(environment) };
+        retro_set_video_refresh(video_refresh);
+        retro_set_audio_sample_batch(audio_sample_batch);
+        retro_set_input_poll(input_poll);
+        retro_set_input_state(input_state);
+        retro_init();
+        let game = GameInfo { path: ptr::null(), data: rom.as_ptr() as *const c_void, size: rom.len(), meta: ptr::null() };
+        assert!(unsafe { retro_load_game(&game) });
+    }
+
+    #[test]
+    fn test_run() {
+        load();
+        START.set(true);
+        unsafe { retro_run() };
+        unsafe { retro_run() };
+        assert_eq!(VIDEO.get(), Some((256, 240, 512)));
+        assert!(AUDIO.get() > 1500);
+        assert_eq!(retro_get_memory_size(MEMORY_SYSTEM_RAM), 2048);
+        assert_eq!(retro_get_memory_size(MEMORY_SAVE_RAM), 0);
+        let ram = retro_get_memory_data(MEMORY_SYSTEM_RAM) as *const u8;
+        assert_eq!(unsafe { *ram }, 0b0001_0000);
+        retro_deinit();
+    }
+
+    #[test]
+    fn test_serialize() {
+        load();
+        unsafe { retro_run() };
+        let mut state = vec![0u8; retro_serialize_size()];
+        assert!(unsafe { retro_serialize(state.as_mut_ptr() as *mut c_void, state.len()) });
+        assert!(!unsafe { retro_serialize(state.as_mut_ptr() as *mut c_void, 10) });
+        let frame = with_nes(0, |nes| nes.frame().number());
+        unsafe { retro_run() };
+        assert!(unsafe { retro_unserialize(state.as_ptr() as *const c_void, state.len()) });
+        assert_eq!(with_nes(0, |nes| nes.frame().number()), frame);
+        retro_deinit();
+    }
+
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn test_logs_through_the_frontend() {
+        load();
+        unsafe { retro_set_environment(environment_with_log) };
+        let rom = [0u8; 16];
+        let game = GameInfo { path: ptr::null(), data: rom.as_ptr() as *const c_void, size: rom.len(), meta: ptr::null() };
+        assert!(!unsafe { retro_load_game(&game) });
+        unsafe { retro_cheat_set(0, true, c"not a cheat".as_ptr()) };
+        let logged = LOGGED.take();
+        assert_eq!(logged.len(), 2);
+        assert!(logged[0].0 == LOG_ERROR && logged[0].1.starts_with("nessie: "));
+        assert!(logged[1].0 == LOG_WARN && logged[1].1.ends_with('\n'));
+        retro_deinit();
+    }
+}
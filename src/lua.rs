@@ -0,0 +1,411 @@
+use crate::joypad::Button;
+use crate::nes::{Nes, ResetKind};
+use crate::overlay::{Color, Overlay};
+use mlua::{Function, Lua, RegistryKey, Table, Thread, ThreadStatus, Value};
+use std::cell::{Ref, RefCell, RefMut};
+use std::rc::Rc;
+
+// FCEUX's names for the buttons, in `Button::ALL` order
+const BUTTONS: [&str; 8] = ["A", "B", "select", "start", "up", "down", "left", "right"];
+
+const WHITE: Color = [0xff, 0xff, 0xff, 0xff];
+const BLACK: Color = [0, 0, 0, 0xff];
+
+// `frameadvance` has to yield from Lua, a Rust function can't
+const PRELUDE: &str = "function emu.frameadvance() coroutine.yield() end";
+
+#[derive(Default)]
+struct State {
+    overlay: Overlay,
+    output: Vec<String>,
+    // buttons `joypad.set` forces on or off for the next frame, by player
+    input: [[Option<bool>; 8]; 2],
+}
+
+/// A Lua script driving a console, with the parts of FCEUX's API most
+/// scripts use so they run unchanged:
+///
+/// - `emu.frameadvance`, `framecount`, `registerbefore`, `registerafter`,
+///   `softreset`, `poweron`, `message` and `print`
+/// - `memory.readbyte`, `readbytesigned`, `readword`, `readwordsigned`,
+///   `readbyterange`, `writebyte`, `getregister` and `setregister`
+/// - `joypad.get`/`read` and `set`, with players from 1
+/// - `gui.pixel`, `line`, `box`/`drawbox`/`rect` and `text`, on `overlay`
+///
+/// The script's main chunk runs as a coroutine that `emu.frameadvance`
+/// suspends until the next `frame_advance` call.
+pub struct Script {
+    lua: Lua,
+    nes: Rc<RefCell<Nes>>,
+    state: Rc<RefCell<State>>,
+    main: Option<RegistryKey>,
+}
+
+impl Script {
+    pub fn new(nes: Nes, source: &str, name: &str) -> Result<Script, String> {
+        let lua = Lua::new();
+        let nes = Rc::new(RefCell::new(nes));
+        let state = Rc::new(RefCell::new(State::default()));
+        install(&lua, &nes, &state).map_err(|err| err.to_string())?;
+        let main = lua.load(source).set_name(name).into_function().and_then(|main| lua.create_thread(main)).and_then(|thread| lua.create_registry_value(thread)).map_err(|err| err.to_string())?;
+        Ok(Script { lua, nes, state, main: Some(main) })
+    }
+
+    pub fn nes(&self) -> Ref<'_, Nes> {
+        self.nes.borrow()
+    }
+
+    pub fn nes_mut(&mut self) -> RefMut<'_, Nes> {
+        self.nes.borrow_mut()
+    }
+
+    /// Stops the script, handing back the console.
+    pub fn into_nes(self) -> Nes {
+        let Script { lua, nes, .. } = self;
+        // the API functions hold the other references
+        drop(lua);
+        Rc::try_unwrap(nes).ok().expect("only the script holds the console").into_inner()
+    }
+
+    /// Whether the main chunk hasn't returned yet. Callbacks it registered
+    /// keep running after it does.
+    pub fn is_running(&self) -> bool {
+        self.main.is_some()
+    }
+
+    /// What the script drew for the last frame.
+    pub fn overlay(&self) -> Ref<'_, Overlay> {
+        Ref::map(self.state.borrow(), |state| &state.overlay)
+    }
+
+    /// Lines from `print` and `emu.message` since the last call.
+    pub fn take_output(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.state.borrow_mut().output)
+    }
+
+    /// Runs the script up to its next `emu.frameadvance`, then a frame with
+    /// the `registerbefore` and `registerafter` callbacks around it. An
+    /// error in the script stops its main chunk and is returned.
+    pub fn frame_advance(&mut self) -> Result<(), String> {
+        self.state.borrow_mut().overlay.clear();
+        if let Some(key) = &self.main {
+            let thread: Thread = self.lua.registry_value(key).map_err(|err| err.to_string())?;
+            let resumed = thread.resume::<_, ()>(());
+            if thread.status() != ThreadStatus::Resumable {
+                self.main = None;
+            }
+            resumed.map_err(|err| err.to_string())?;
+        }
+        self.callback("before")?;
+
+        let input = std::mem::take(&mut self.state.borrow_mut().input);
+        let mut nes = self.nes.borrow_mut();
+        for (player, forced) in input.iter().enumerate() {
+            if let Some(joypad) = nes.joypad_mut(player) {
+                for (button, pressed) in Button::ALL.iter().zip(forced) {
+                    if let Some(pressed) = pressed {
+                        joypad.set_button(*button, *pressed);
+                    }
+                }
+            }
+        }
+        nes.run_frame();
+        drop(nes);
+
+        self.callback("after")
+    }
+
+    fn callback(&self, name: &str) -> Result<(), String> {
+        match self.lua.named_registry_value::<Option<Function>>(name) {
+            Ok(Some(callback)) => callback.call::<_, ()>(()).map_err(|err| err.to_string()),
+            Ok(None) => Ok(()),
+            Err(err) => Err(err.to_string()),
+        }
+    }
+}
+
+fn install(lua: &Lua, nes: &Rc<RefCell<Nes>>, state: &Rc<RefCell<State>>) -> mlua::Result<()> {
+    let globals = lua.globals();
+
+    let emu = lua.create_table()?;
+    let console = nes.clone();
+    emu.set("framecount", lua.create_function(move |_, ()| Ok(console.borrow().frame().number()))?)?;
+    emu.set("emulating", lua.create_function(|_, ()| Ok(true))?)?;
+    for (name, key) in [("registerbefore", "before"), ("registerafter", "after")] {
+        emu.set(name, lua.create_function(move |lua, callback: Option<Function>| lua.set_named_registry_value(key, callback))?)?;
+    }
+    let console = nes.clone();
+    emu.set(
+        "softreset",
+        lua.create_function(move |_, ()| {
+            console.borrow_mut().reset(ResetKind::Soft);
+            Ok(())
+        })?,
+    )?;
+    let console = nes.clone();
+    emu.set(
+        "poweron",
+        lua.create_function(move |_, ()| {
+            console.borrow_mut().reset(ResetKind::Hard);
+            Ok(())
+        })?,
+    )?;
+    let output = state.clone();
+    let print = lua.create_function(move |lua, values: mlua::Variadic<Value>| {
+        let tostring: Function = lua.globals().get("tostring")?;
+        let line = values.into_iter().map(|value| tostring.call::<_, String>(value)).collect::<mlua::Result<Vec<_>>>()?.join("\t");
+        output.borrow_mut().output.push(line);
+        Ok(())
+    })?;
+    emu.set("message", print.clone())?;
+    emu.set("print", print.clone())?;
+    globals.set("print", print)?;
+    globals.set("emu", emu)?;
+    globals.set("FCEU", globals.get::<_, Table>("emu")?)?;
+
+    let memory = lua.create_table()?;
+    let console = nes.clone();
+    memory.set("readbyte", lua.create_function(move |_, addr: i64| Ok(console.borrow().cpu().bus.peek(addr as u16)))?)?;
+    let console = nes.clone();
+    memory.set("readbytesigned", lua.create_function(move |_, addr: i64| Ok(console.borrow().cpu().bus.peek(addr as u16) as i8))?)?;
+    let console = nes.clone();
+    memory.set("readword", lua.create_function(move |_, (low, high): (i64, Option<i64>)| Ok(read_word(&console.borrow(), low, high)))?)?;
+    let console = nes.clone();
+    memory.set("readwordsigned", lua.create_function(move |_, (low, high): (i64, Option<i64>)| Ok(read_word(&console.borrow(), low, high) as i16))?)?;
+    let console = nes.clone();
+    memory.set(
+        "readbyterange",
+        lua.create_function(move |lua, (addr, len): (i64, i64)| {
+            let nes = console.borrow();
+            let bytes: Vec<u8> = (0..len.max(0)).map(|offset| nes.cpu().bus.peek((addr + offset) as u16)).collect();
+            lua.create_string(&bytes)
+        })?,
+    )?;
+    let console = nes.clone();
+    memory.set(
+        "writebyte",
+        lua.create_function(move |_, (addr, value): (i64, i64)| {
+            console.borrow_mut().cpu_mut().bus.mem_write(addr as u16, value as u8);
+            Ok(())
+        })?,
+    )?;
+    let console = nes.clone();
+    memory.set(
+        "getregister",
+        lua.create_function(move |_, name: String| {
+            let nes = console.borrow();
+            let cpu = nes.cpu();
+            let value = match name.to_ascii_lowercase().as_str() {
+                "a" => cpu.register_a as u16,
+                "x" => cpu.register_x as u16,
+                "y" => cpu.register_y as u16,
+                "s" | "sp" => cpu.stack_pointer as u16,
+                "p" => cpu.status as u16,
+                "pc" => cpu.program_counter,
+                _ => return Err(mlua::Error::RuntimeError(format!("unknown register {:?}", name))),
+            };
+            Ok(value)
+        })?,
+    )?;
+    let console = nes.clone();
+    memory.set(
+        "setregister",
+        lua.create_function(move |_, (name, value): (String, i64)| {
+            let mut nes = console.borrow_mut();
+            let cpu = nes.cpu_mut();
+            match name.to_ascii_lowercase().as_str() {
+                "a" => cpu.register_a = value as u8,
+                "x" => cpu.register_x = value as u8,
+                "y" => cpu.register_y = value as u8,
+                "s" | "sp" => cpu.stack_pointer = value as u8,
+                "p" => cpu.status = value as u8,
+                "pc" => cpu.program_counter = value as u16,
+                _ => return Err(mlua::Error::RuntimeError(format!("unknown register {:?}", name))),
+            }
+            Ok(())
+        })?,
+    )?;
+    globals.set("memory", memory)?;
+
+    let joypad = lua.create_table()?;
+    let console = nes.clone();
+    let read = lua.create_function(move |lua, player: usize| {
+        let buttons = lua.create_table()?;
+        let mut nes = console.borrow_mut();
+        let joypad = player.checked_sub(1).and_then(|player| nes.joypad_mut(player)).ok_or_else(|| mlua::Error::RuntimeError(format!("no joypad {}", player)))?;
+        for (button, name) in Button::ALL.iter().zip(BUTTONS) {
+            if joypad.is_pressed(*button) {
+                buttons.set(name, true)?;
+            }
+        }
+        Ok(buttons)
+    })?;
+    joypad.set("get", read.clone())?;
+    joypad.set("read", read)?;
+    let input = state.clone();
+    joypad.set(
+        "set",
+        lua.create_function(move |_, (player, buttons): (usize, Table)| {
+            let mut state = input.borrow_mut();
+            let forced = player.checked_sub(1).and_then(|player| state.input.get_mut(player)).ok_or_else(|| mlua::Error::RuntimeError(format!("no joypad {}", player)))?;
+            for (slot, name) in forced.iter_mut().zip(BUTTONS) {
+                // nil leaves the button to the player, like FCEUX
+                *slot = buttons.get::<_, Option<bool>>(name)?;
+            }
+            Ok(())
+        })?,
+    )?;
+    joypad.set("write", joypad.get::<_, Function>("set")?)?;
+    globals.set("joypad", joypad)?;
+
+    let gui = lua.create_table()?;
+    let draw = state.clone();
+    gui.set(
+        "pixel",
+        lua.create_function(move |_, (x, y, color): (i32, i32, Value)| {
+            draw.borrow_mut().overlay.pixel(x, y, parse_color(color, WHITE)?);
+            Ok(())
+        })?,
+    )?;
+    let draw = state.clone();
+    gui.set(
+        "line",
+        lua.create_function(move |_, (x1, y1, x2, y2, color): (i32, i32, i32, i32, Value)| {
+            draw.borrow_mut().overlay.line(x1, y1, x2, y2, parse_color(color, WHITE)?);
+            Ok(())
+        })?,
+    )?;
+    let draw = state.clone();
+    let rect = lua.create_function(move |_, (x1, y1, x2, y2, fill, outline): (i32, i32, i32, i32, Value, Value)| {
+        let fill = parse_color(fill, [0xff, 0xff, 0xff, 0x3f])?;
+        let outline = parse_color(outline, [fill[0], fill[1], fill[2], 0xff])?;
+        draw.borrow_mut().overlay.rect(x1, y1, x2, y2, fill, outline);
+        Ok(())
+    })?;
+    gui.set("box", rect.clone())?;
+    gui.set("drawbox", rect.clone())?;
+    gui.set("rect", rect)?;
+    let draw = state.clone();
+    gui.set(
+        "text",
+        lua.create_function(move |lua, (x, y, text, color, background): (i32, i32, Value, Value, Value)| {
+            let text = lua.coerce_string(text)?.map_or_else(String::new, |text| text.to_string_lossy().into_owned());
+            draw.borrow_mut().overlay.text(x, y, &text, parse_color(color, WHITE)?, parse_color(background, BLACK)?);
+            Ok(())
+        })?,
+    )?;
+    globals.set("gui", gui)?;
+
+    lua.load(PRELUDE).set_name("prelude").exec()
+}
+
+// FCEUX takes a word as one address or as the addresses of its two bytes
+fn read_word(nes: &Nes, low: i64, high: Option<i64>) -> u16 {
+    let high = high.unwrap_or(low + 1);
+    u16::from_le_bytes([nes.cpu().bus.peek(low as u16), nes.cpu().bus.peek(high as u16)])
+}
+
+/// A color as scripts give them: a name like `"red"`, `"#rrggbb"` or
+/// `"#rrggbbaa"`, or a number `0xRRGGBBAA`. `nil` means `default`.
+fn parse_color(value: Value, default: Color) -> mlua::Result<Color> {
+    let bad = |value: &dyn std::fmt::Debug| mlua::Error::RuntimeError(format!("bad color {:?}", value));
+    match value {
+        Value::Nil => Ok(default),
+        Value::Integer(number) => Ok((number as u32).to_be_bytes()),
+        Value::Number(number) => Ok((number as u32).to_be_bytes()),
+        Value::String(name) => {
+            let name = name.to_str()?.to_ascii_lowercase();
+            let color = match name.as_str() {
+                "white" => WHITE,
+                "black" => BLACK,
+                "red" => [0xff, 0, 0, 0xff],
+                "green" => [0, 0xff, 0, 0xff],
+                "blue" => [0, 0, 0xff, 0xff],
+                "yellow" => [0xff, 0xff, 0, 0xff],
+                "orange" => [0xff, 0x80, 0, 0xff],
+                "purple" => [0x80, 0, 0x80, 0xff],
+                "gray" | "grey" => [0x7f, 0x7f, 0x7f, 0xff],
+                "clear" => [0; 4],
+                _ => {
+                    let hex = name.strip_prefix('#').ok_or_else(|| bad(&name))?;
+                    let number = u32::from_str_radix(hex, 16).map_err(|_| bad(&name))?;
+                    match hex.len() {
+                        6 => (number << 8 | 0xff).to_be_bytes(),
+                        8 => number.to_be_bytes(),
+                        _ => return Err(bad(&name)),
+                    }
+                }
+            };
+            Ok(color)
+        }
+        value => Err(bad(&value)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::test_nes;
+
+    fn console() -> Nes {
+        test_nes(
+            "
+                    .org $C000
+            reset:  jmp reset
+                    .org $FFFA
+                    .word reset, reset, reset
+            ",
+        )
+    }
+
+    #[test]
+    fn test_script() {
+        let source = r#"
+            local frames = 0
+            emu.registerafter(function() frames = frames + 1; memory.writebyte(0x11, frames) end)
+            memory.writebyte(0x10, 0x42)
+            print("pc", memory.getregister("pc") >= 0xc000)
+            while true do
+                joypad.set(1, {A = true, right = true})
+                gui.box(0, 0, 10, 10, "red")
+                gui.text(1, 2, emu.framecount())
+                emu.frameadvance()
+                memory.writebyte(0x12, memory.readword(0x10, 0x11) >> 8)
+            end
+        "#;
+        let mut script = Script::new(console(), source, "test").unwrap();
+        script.frame_advance().unwrap();
+        script.frame_advance().unwrap();
+        assert!(script.is_running());
+        assert_eq!(script.take_output(), vec!["pc\ttrue"]);
+        assert_eq!(script.overlay().texts()[0].text, "1");
+        assert!(!script.overlay().is_empty());
+
+        let mut nes = script.into_nes();
+        assert_eq!((nes.cpu().bus.peek(0x10), nes.cpu().bus.peek(0x11), nes.cpu().bus.peek(0x12)), (0x42, 2, 1));
+        let joypad = nes.joypad_mut(0).unwrap();
+        assert!(joypad.is_pressed(Button::A) && joypad.is_pressed(Button::Right) && !joypad.is_pressed(Button::B));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(Script::new(console(), "while do", "broken").is_err());
+        let mut script = Script::new(console(), "emu.frameadvance() memory.getregister('q')", "bad register").unwrap();
+        script.frame_advance().unwrap();
+        assert!(script.frame_advance().unwrap_err().contains("unknown register"));
+        assert!(!script.is_running());
+        script.frame_advance().unwrap();
+    }
+
+    #[test]
+    fn test_colors() {
+        assert_eq!(parse_color(Value::Integer(0x11223344), WHITE).unwrap(), [0x11, 0x22, 0x33, 0x44]);
+        assert_eq!(parse_color(Value::Nil, BLACK).unwrap(), BLACK);
+        let lua = Lua::new();
+        let color = |text: &str| parse_color(Value::String(lua.create_string(text).unwrap()), WHITE);
+        assert_eq!(color("#102030").unwrap(), [0x10, 0x20, 0x30, 0xff]);
+        assert_eq!(color("Red").unwrap(), [0xff, 0, 0, 0xff]);
+        assert!(color("#12").is_err());
+        assert!(color("mauve").is_err());
+    }
+}
@@ -0,0 +1,100 @@
+//! Float functions `core` doesn't have. With std they're the usual ones;
+//! without it they're computed here, close enough for filter tables and
+//! rounding but not to the last bit.
+
+#[cfg(feature = "std")]
+pub(crate) fn sin(x: f64) -> f64 {
+    x.sin()
+}
+
+#[cfg(not(feature = "std"))]
+pub(crate) fn sin(x: f64) -> f64 {
+    sin_series(x)
+}
+
+#[cfg(feature = "std")]
+pub(crate) fn cos(x: f64) -> f64 {
+    x.cos()
+}
+
+#[cfg(not(feature = "std"))]
+pub(crate) fn cos(x: f64) -> f64 {
+    sin_series(x + core::f64::consts::FRAC_PI_2)
+}
+
+#[cfg(feature = "std")]
+pub(crate) fn cosf(x: f32) -> f32 {
+    x.cos()
+}
+
+#[cfg(not(feature = "std"))]
+pub(crate) fn cosf(x: f32) -> f32 {
+    cos(x as f64) as f32
+}
+
+#[cfg(feature = "std")]
+pub(crate) fn roundf(x: f32) -> f32 {
+    x.round()
+}
+
+// halves away from zero like `f32::round`; only for values that fit an i64
+#[cfg(not(feature = "std"))]
+pub(crate) fn roundf(x: f32) -> f32 {
+    if x < 0.0 {
+        (x - 0.5) as i64 as f32
+    } else {
+        (x + 0.5) as i64 as f32
+    }
+}
+
+#[cfg(feature = "std")]
+pub(crate) fn ceil(x: f64) -> f64 {
+    x.ceil()
+}
+
+// only for values that fit an i64
+#[cfg(not(feature = "std"))]
+pub(crate) fn ceil(x: f64) -> f64 {
+    let whole = x as i64 as f64;
+    if whole < x {
+        whole + 1.0
+    } else {
+        whole
+    }
+}
+
+// Taylor series around 0 after folding `x` into -pi/2..pi/2, where the
+// terms up to x^17 leave an error below 1e-9
+#[cfg(any(not(feature = "std"), test))]
+fn sin_series(x: f64) -> f64 {
+    use core::f64::consts::{FRAC_PI_2, PI, TAU};
+    let turns = x / TAU;
+    let turns = if turns < 0.0 { turns - 0.5 } else { turns + 0.5 } as i64 as f64;
+    let mut x = x - turns * TAU;
+    if x > FRAC_PI_2 {
+        x = PI - x;
+    } else if x < -FRAC_PI_2 {
+        x = -PI - x;
+    }
+    let square = x * x;
+    let mut term = x;
+    let mut sum = x;
+    for n in 1..9 {
+        term *= -square / ((2 * n) * (2 * n + 1)) as f64;
+        sum += term;
+    }
+    sum
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_sin_series() {
+        for step in -2000..2000 {
+            let x = step as f64 / 100.0;
+            assert!((sin_series(x) - x.sin()).abs() < 1e-9, "sin({})", x);
+        }
+    }
+}
@@ -0,0 +1,245 @@
+use super::{Movie, MovieFrame, MovieStart};
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use crate::input::InputConfig;
+use crate::joypad::Button;
+use crate::region::Region;
+
+// NesHawk's names and mnemonics for controller buttons, in log order
+const BUTTONS: [(Button, &str, char); 8] = [
+    (Button::Up, "Up", 'U'),
+    (Button::Down, "Down", 'D'),
+    (Button::Left, "Left", 'L'),
+    (Button::Right, "Right", 'R'),
+    (Button::Start, "Start", 'S'),
+    (Button::Select, "Select", 's'),
+    (Button::B, "B", 'B'),
+    (Button::A, "A", 'A'),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Column {
+    Reset,
+    Power,
+    Button(usize, Button),
+    Ignored,
+}
+
+fn column_from_name(name: &str) -> Column {
+    match name {
+        "Reset" => Column::Reset,
+        "Power" => Column::Power,
+        _ => {
+            let Some((player, button)) = name.strip_prefix('P').and_then(|n| n.split_once(' ')) else {
+                return Column::Ignored;
+            };
+            let player: usize = match player.parse() {
+                Ok(player @ 1..=4) => player,
+                _ => return Column::Ignored,
+            };
+            match BUTTONS.iter().find(|(_, n, _)| *n == button) {
+                Some((button, _, _)) => Column::Button(player - 1, *button),
+                None => Column::Ignored,
+            }
+        }
+    }
+}
+
+impl Movie {
+    /// Reads the "Input Log.txt" and "Header.txt" of a BizHawk .bk2 movie.
+    /// Only standard controllers starting from power on are supported.
+    pub fn from_bk2_text(header: &str, input_log: &str) -> Result<Movie, String> {
+        let mut movie = Movie::new(Region::Ntsc, InputConfig::default(), MovieStart::PowerOn);
+        for line in header.lines() {
+            let (key, value) = line.trim_end_matches('\r').split_once(' ').unwrap_or((line, ""));
+            match key {
+                "Platform" if value != "NES" => return Err(format!("bk2 movie is for {}, not the NES", value)),
+                "PAL" => movie.region = if value == "True" || value == "1" { Region::Pal } else { Region::Ntsc },
+                "StartsFromSavestate" | "StartsFromSaveRam" if value == "True" => {
+                    return Err("movies starting from a savestate aren't supported".to_string())
+                }
+                _ => {}
+            }
+        }
+
+        let mut columns: Option<Vec<Vec<Column>>> = None;
+        let mut players = 0;
+        for (number, line) in input_log.lines().enumerate() {
+            let err = |what: &str| format!("bk2 input log line {}: {}", number + 1, what);
+            let line = line.trim_end_matches('\r');
+            if let Some(key) = line.strip_prefix("LogKey:") {
+                let groups: Vec<Vec<Column>> = key
+                    .split('#')
+                    .filter(|group| !group.is_empty())
+                    .map(|group| group.split('|').filter(|name| !name.is_empty()).map(column_from_name).collect())
+                    .collect();
+                players = groups.iter().flatten().filter_map(|c| match c {
+                    Column::Button(player, _) => Some(player + 1),
+                    _ => None,
+                }).max().unwrap_or(0);
+                columns = Some(groups);
+                continue;
+            }
+            if !line.starts_with('|') {
+                continue;
+            }
+            let columns = columns.as_ref().ok_or_else(|| err("input before the LogKey"))?;
+            let fields: Vec<&str> = line.split('|').filter(|field| !field.is_empty()).collect();
+            if fields.len() != columns.len() {
+                return Err(err("wrong number of fields"));
+            }
+            let mut frame = MovieFrame::default();
+            for (field, group) in fields.iter().zip(columns) {
+                if field.chars().count() != group.len() {
+                    return Err(err("wrong number of buttons"));
+                }
+                for (c, column) in field.chars().zip(group) {
+                    if c == '.' {
+                        continue;
+                    }
+                    match column {
+                        Column::Reset => frame.reset = true,
+                        Column::Power if !movie.frames.is_empty() => {
+                            return Err(err("power cycling in the middle of a movie isn't supported"))
+                        }
+                        Column::Button(player, button) => frame.buttons[*player] |= 1 << *button as u8,
+                        Column::Power | Column::Ignored => {}
+                    }
+                }
+            }
+            movie.frames.push(frame);
+        }
+        if columns.is_none() {
+            return Err("bk2 input log has no LogKey".to_string());
+        }
+        if players > 2 {
+            movie.ports = InputConfig::FourScore;
+        }
+        Ok(movie)
+    }
+
+    /// The "Header.txt" and "Input Log.txt" of a BizHawk .bk2 movie.
+    pub fn to_bk2_text(&self) -> (String, String) {
+        let pal = if self.region == Region::Pal { "True" } else { "False" };
+        let header = format!("MovieVersion BizHawk v2.0.0\nPlatform NES\nCore NesHawk\nPAL {}\nrerecordCount 0\n", pal);
+
+        let players = match self.ports {
+            InputConfig::FourScore => 4,
+            InputConfig::Ports(..) => 2,
+        };
+        let mut log = String::from("[Input]\nLogKey:#Reset|Power|");
+        for player in 1..=players {
+            log.push('#');
+            for (_, name, _) in BUTTONS {
+                log.push_str(&format!("P{} {}|", player, name));
+            }
+        }
+        log.push('\n');
+        for frame in &self.frames {
+            log.push('|');
+            log.push(if frame.reset { 'r' } else { '.' });
+            log.push('.');
+            for buttons in &frame.buttons[..players] {
+                log.push('|');
+                for (button, _, mnemonic) in BUTTONS {
+                    log.push(if buttons & (1 << button as u8) != 0 { mnemonic } else { '.' });
+                }
+            }
+            log.push_str("|\n");
+        }
+        log.push_str("[/Input]\n");
+        (header, log)
+    }
+
+    /// Reads a BizHawk .bk2 movie from the bytes of its zip file.
+    #[cfg(feature = "bk2")]
+    pub fn from_bk2(data: &[u8]) -> Result<Movie, String> {
+        use std::io::Read;
+
+        let mut archive = zip::ZipArchive::new(std::io::Cursor::new(data)).map_err(|e| format!("can't open bk2: {}", e))?;
+        let mut read = |name: &str| {
+            let mut text = String::new();
+            archive
+                .by_name(name)
+                .map_err(|e| format!("can't find {} in bk2: {}", name, e))?
+                .read_to_string(&mut text)
+                .map_err(|e| format!("can't read {} in bk2: {}", name, e))?;
+            Ok::<String, String>(text)
+        };
+        let header = read("Header.txt")?;
+        let input_log = read("Input Log.txt")?;
+        Movie::from_bk2_text(&header, &input_log)
+    }
+
+    /// Writes the movie as the bytes of a BizHawk .bk2 zip file.
+    #[cfg(feature = "bk2")]
+    pub fn to_bk2(&self) -> Result<Vec<u8>, String> {
+        use std::io::Write;
+
+        let (header, input_log) = self.to_bk2_text();
+        let mut writer = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        for (name, text) in [("Header.txt", header), ("Input Log.txt", input_log)] {
+            writer
+                .start_file(name, zip::write::SimpleFileOptions::default())
+                .and_then(|_| writer.write_all(text.as_bytes()).map_err(Into::into))
+                .map_err(|e| format!("can't write {} to bk2: {}", name, e))?;
+        }
+        let cursor = writer.finish().map_err(|e| format!("can't write bk2: {}", e))?;
+        Ok(cursor.into_inner())
+    }
+
+    #[cfg(feature = "bk2")]
+    pub fn load_bk2<P: AsRef<std::path::Path>>(path: P) -> Result<Movie, String> {
+        let data = std::fs::read(path.as_ref()).map_err(|e| format!("can't read {}: {}", path.as_ref().display(), e))?;
+        Movie::from_bk2(&data)
+    }
+
+    #[cfg(feature = "bk2")]
+    pub fn save_bk2<P: AsRef<std::path::Path>>(&self, path: P) -> Result<(), String> {
+        std::fs::write(path.as_ref(), self.to_bk2()?).map_err(|e| format!("can't write {}: {}", path.as_ref().display(), e))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::input::PortDevice;
+
+    const INPUT_LOG: &str = "[Input]
+LogKey:#Reset|Power|#P1 Up|P1 Down|P1 Left|P1 Right|P1 Start|P1 Select|P1 B|P1 A|#P2 Up|P2 Down|P2 Left|P2 Right|P2 Start|P2 Select|P2 B|P2 A|
+|.P|........|........|
+|..|...RS...|.......A|
+|r.|........|......B.|
+[/Input]
+";
+
+    #[test]
+    fn test_parse_input_log() {
+        let movie = Movie::from_bk2_text("MovieVersion BizHawk v2.0.0\nPlatform NES\n", INPUT_LOG).unwrap();
+        assert_eq!(movie.ports, InputConfig::Ports(PortDevice::Joypad, PortDevice::Joypad));
+        assert_eq!(movie.frames.len(), 3);
+        assert_eq!(movie.frames[1].buttons, [0b1000_1000, 0b0000_0001, 0, 0]);
+        assert!(movie.frames[2].reset);
+        assert_eq!(movie.frames[2].buttons[1], 0b10);
+
+        assert!(Movie::from_bk2_text("Platform SNES\n", INPUT_LOG).is_err());
+    }
+
+    #[test]
+    fn test_text_round_trip() {
+        let mut movie = Movie::new(Region::Pal, InputConfig::FourScore, MovieStart::PowerOn);
+        movie.frames.push(MovieFrame { buttons: [1, 2, 4, 0b1000_0000], reset: false });
+        movie.frames.push(MovieFrame { buttons: [0; 4], reset: true });
+        let (header, input_log) = movie.to_bk2_text();
+        assert_eq!(Movie::from_bk2_text(&header, &input_log), Ok(movie));
+    }
+
+    #[cfg(feature = "bk2")]
+    #[test]
+    fn test_zip_round_trip() {
+        let movie = Movie::from_bk2_text("Platform NES\n", INPUT_LOG).unwrap();
+        let data = movie.to_bk2().unwrap();
+        assert_eq!(Movie::from_bk2(&data), Ok(movie));
+    }
+}
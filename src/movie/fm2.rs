@@ -0,0 +1,135 @@
+use super::{Movie, MovieFrame, MovieStart};
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use crate::input::{InputConfig, PortDevice};
+use crate::region::Region;
+#[cfg(feature = "std")]
+use std::fs;
+#[cfg(feature = "std")]
+use std::path::Path;
+
+// FCEUX input commands
+const COMMAND_SOFT_RESET: u32 = 1;
+const COMMAND_POWER: u32 = 2;
+// FCEUX port devices
+const SI_NONE: &str = "0";
+const SI_GAMEPAD: &str = "1";
+
+// pad fields list the buttons Right first, the reverse of Joypad::buttons()
+fn pad_from_fm2(field: &str) -> Result<u8, String> {
+    if field.chars().count() != 8 {
+        return Err(format!("bad controller field {:?}", field));
+    }
+    // FCEUX takes anything but a space or dot as pressed
+    Ok(field.chars().fold(0, |buttons, c| buttons << 1 | !matches!(c, ' ' | '.') as u8))
+}
+
+impl Movie {
+    /// Reads an FCEUX .fm2 movie. Only text movies with standard
+    /// controllers, optionally through a Four Score, starting from power on
+    /// are supported.
+    pub fn from_fm2(text: &str) -> Result<Movie, String> {
+        let mut movie = Movie::new(Region::Ntsc, InputConfig::default(), MovieStart::PowerOn);
+        let mut version = None;
+        let (mut port0, mut port1) = (SI_GAMEPAD, SI_GAMEPAD);
+        for (number, line) in text.lines().enumerate() {
+            let err = |what: String| format!("fm2 line {}: {}", number + 1, what);
+            let line = line.trim_end_matches('\r');
+            if line.starts_with('|') {
+                let fields: Vec<&str> = line.split('|').collect();
+                let commands: u32 = fields[1].parse().map_err(|_| err(format!("bad commands {:?}", fields[1])))?;
+                if commands & COMMAND_POWER != 0 && !movie.frames.is_empty() {
+                    return Err(err("power cycling in the middle of a movie isn't supported".to_string()));
+                }
+                let pads = match movie.ports {
+                    InputConfig::FourScore => 4,
+                    InputConfig::Ports(..) => 2,
+                };
+                let mut frame = MovieFrame { buttons: [0; 4], reset: commands & COMMAND_SOFT_RESET != 0 };
+                for (pad, buttons) in frame.buttons.iter_mut().take(pads).enumerate() {
+                    let field = fields.get(pad + 2).ok_or_else(|| err("missing controller".to_string()))?;
+                    if !field.is_empty() {
+                        *buttons = pad_from_fm2(field).map_err(err)?;
+                    }
+                }
+                movie.frames.push(frame);
+                continue;
+            }
+
+            let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+            match key {
+                "version" => version = Some(value.to_string()),
+                "palFlag" => movie.region = if value == "1" { Region::Pal } else { Region::Ntsc },
+                "fourscore" if value == "1" => movie.ports = InputConfig::FourScore,
+                "port0" => port0 = if value == SI_NONE { SI_NONE } else { SI_GAMEPAD },
+                "port1" => port1 = if value == SI_NONE { SI_NONE } else { SI_GAMEPAD },
+                "binary" if value == "1" => return Err(err("binary input logs aren't supported".to_string())),
+                "savestate" if !value.is_empty() => {
+                    return Err(err("movies starting from a savestate aren't supported".to_string()))
+                }
+                _ => {}
+            }
+            // anything but a gamepad or nothing in a port
+            if (key == "port0" || key == "port1") && value != SI_NONE && value != SI_GAMEPAD {
+                return Err(err(format!("unsupported device {} in {}", value, key)));
+            }
+        }
+        if version.as_deref() != Some("3") {
+            return Err("not an fm2 version 3 movie".to_string());
+        }
+        if movie.ports != InputConfig::FourScore {
+            let device = |port| if port == SI_NONE { PortDevice::Empty } else { PortDevice::Joypad };
+            movie.ports = InputConfig::Ports(device(port0), device(port1));
+        }
+        Ok(movie)
+    }
+
+    #[cfg(feature = "std")]
+    pub fn load_fm2<P: AsRef<Path>>(path: P) -> Result<Movie, String> {
+        let text = fs::read_to_string(path.as_ref())
+            .map_err(|e| format!("can't read {}: {}", path.as_ref().display(), e))?;
+        Movie::from_fm2(&text)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const MOVIE: &str = "version 3
+emuVersion 22020
+rerecordCount 5
+palFlag 0
+romFilename smb
+romChecksum base64:jjYwGG411HcjG/j9UOVM3Q==
+guid 00000000-0000-0000-0000-000000000000
+fourscore 0
+port0 1
+port1 1
+port2 0
+comment author someone
+|2|........|........||
+|0|.......A|........||
+|0|R..UT...|.L....B.||
+|1|........|........||
+";
+
+    #[test]
+    fn test_parse_movie() {
+        let movie = Movie::from_fm2(MOVIE).unwrap();
+        assert_eq!(movie.region, Region::Ntsc);
+        assert_eq!(movie.ports, InputConfig::Ports(PortDevice::Joypad, PortDevice::Joypad));
+        assert_eq!(movie.frames.len(), 4);
+        assert_eq!(movie.frames[1].buttons, [0b0000_0001, 0, 0, 0]);
+        assert_eq!(movie.frames[2].buttons, [0b1001_1000, 0b0100_0010, 0, 0]);
+        assert!(movie.frames[3].reset);
+    }
+
+    #[test]
+    fn test_reject_unsupported_movies() {
+        assert!(Movie::from_fm2(&MOVIE.replace("port1 1", "port1 2")).is_err());
+        assert!(Movie::from_fm2(&MOVIE.replace("version 3", "version 2")).is_err());
+        assert!(Movie::from_fm2(&format!("{}|2|........|........||\n", MOVIE)).is_err());
+    }
+}
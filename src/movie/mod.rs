@@ -0,0 +1,378 @@
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use crate::bus::Bus;
+use crate::input::{InputConfig, PortDevice};
+use crate::joypad::Button;
+use crate::nes::{Nes, ResetKind};
+use crate::region::Region;
+#[cfg(feature = "std")]
+use std::fs;
+#[cfg(feature = "std")]
+use std::path::Path;
+
+pub mod bk2;
+pub mod fm2;
+
+const HEADER: &str = "nessie movie 1";
+// button letters of a frame line, Right first like FCEUX writes them
+const BUTTON_LETTERS: [(Button, char); 8] = [
+    (Button::Right, 'R'),
+    (Button::Left, 'L'),
+    (Button::Down, 'D'),
+    (Button::Up, 'U'),
+    (Button::Start, 'T'),
+    (Button::Select, 'S'),
+    (Button::B, 'B'),
+    (Button::A, 'A'),
+];
+
+/// Where a movie starts from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MovieStart {
+    PowerOn,
+    /// A savestate taken when recording began, kept as opaque bytes.
+    Savestate(Vec<u8>),
+}
+
+/// Input for one frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MovieFrame {
+    /// Controllers 1 to 4 in `Joypad::buttons()` format.
+    pub buttons: [u8; 4],
+    /// Soft reset pressed at the start of the frame.
+    pub reset: bool,
+}
+
+/// Controller input recorded frame by frame, together with what is needed
+/// to replay it: the console region, the devices plugged in and the state
+/// recording started from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Movie {
+    pub region: Region,
+    pub ports: InputConfig,
+    pub start: MovieStart,
+    pub frames: Vec<MovieFrame>,
+}
+
+impl Movie {
+    pub fn new(region: Region, ports: InputConfig, start: MovieStart) -> Self {
+        Movie { region, ports, start, frames: Vec::new() }
+    }
+
+    /// A movie starting now on this console.
+    pub fn from_bus(bus: &Bus, start: MovieStart) -> Self {
+        Movie::new(bus.region(), bus.input_config(), start)
+    }
+
+    /// Appends the input the console sees this frame, turbo and resets
+    /// included. Call it once per frame, after the frontend has set the
+    /// buttons and before the frame runs.
+    pub fn record_frame(&mut self, nes: &Nes) {
+        let bus = &nes.cpu().bus;
+        let buttons = [bus.joypad1.report(), bus.joypad2.report(), bus.joypad3.report(), bus.joypad4.report()];
+        self.frames.push(MovieFrame { buttons, reset: nes.was_reset() });
+    }
+
+    /// Sets the controllers to the input of a frame and presses reset if
+    /// it was pressed then, returning false once the movie has run out.
+    /// Turbo is switched off since the recorded input already has it
+    /// applied.
+    pub fn play_frame(&self, frame: usize, nes: &mut Nes) -> bool {
+        let Some(input) = self.frames.get(frame) else {
+            return false;
+        };
+        if input.reset {
+            nes.reset(ResetKind::Soft);
+        }
+        let bus = &mut nes.cpu_mut().bus;
+        for (player, buttons) in input.buttons.iter().enumerate() {
+            let joypad = bus.joypad_mut(player).unwrap();
+            for button in Button::ALL {
+                joypad.set_turbo(button, false);
+            }
+            joypad.set_buttons(*buttons);
+        }
+        true
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = format!("{}\nregion {}\nports {}\n", HEADER, region_name(self.region), ports_name(self.ports));
+        match &self.start {
+            MovieStart::PowerOn => text.push_str("start power-on\n"),
+            MovieStart::Savestate(data) => {
+                let hex: String = data.iter().map(|b| format!("{:02x}", b)).collect();
+                text.push_str(&format!("start savestate {}\n", hex));
+            }
+        }
+        for frame in &self.frames {
+            text.push(if frame.reset { 'r' } else { '.' });
+            for buttons in frame.buttons {
+                text.push('|');
+                text.push_str(&buttons_to_text(buttons));
+            }
+            text.push('\n');
+        }
+        text
+    }
+
+    pub fn from_text(text: &str) -> Result<Movie, String> {
+        let mut lines = text.lines().enumerate();
+        if lines.next().map(|(_, line)| line.trim()) != Some(HEADER) {
+            return Err("not a NESsie movie".to_string());
+        }
+        let mut movie = Movie::new(Region::default(), InputConfig::default(), MovieStart::PowerOn);
+        for (number, line) in lines {
+            let err = |what: &str| format!("line {}: {}", number + 1, what);
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            if line.contains('|') {
+                let mut fields = line.split('|');
+                let reset = fields.next() == Some("r");
+                let mut frame = MovieFrame { buttons: [0; 4], reset };
+                for buttons in frame.buttons.iter_mut() {
+                    *buttons = buttons_from_text(fields.next().ok_or_else(|| err("missing controller"))?)
+                        .ok_or_else(|| err("bad buttons"))?;
+                }
+                movie.frames.push(frame);
+                continue;
+            }
+            let (key, value) = line.split_once(' ').ok_or_else(|| err("expected a setting"))?;
+            match key {
+                "region" => movie.region = region_from_name(value).ok_or_else(|| err("unknown region"))?,
+                "ports" => movie.ports = ports_from_name(value).ok_or_else(|| err("unknown ports"))?,
+                "start" => {
+                    movie.start = match value.split_once(' ') {
+                        None if value == "power-on" => MovieStart::PowerOn,
+                        Some(("savestate", hex)) => MovieStart::Savestate(hex_to_bytes(hex).ok_or_else(|| err("bad savestate"))?),
+                        _ => return Err(err("unknown start")),
+                    }
+                }
+                _ => return Err(err("unknown setting")),
+            }
+        }
+        Ok(movie)
+    }
+
+    #[cfg(feature = "std")]
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Movie, String> {
+        let text = fs::read_to_string(path.as_ref())
+            .map_err(|e| format!("can't read {}: {}", path.as_ref().display(), e))?;
+        Movie::from_text(&text)
+    }
+
+    #[cfg(feature = "std")]
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        fs::write(path.as_ref(), self.to_text())
+            .map_err(|e| format!("can't write {}: {}", path.as_ref().display(), e))
+    }
+}
+
+/// Plays a movie back one frame at a time and notes the first frame where
+/// input was pressed but the game never read the controllers, which is
+/// where playback has most likely drifted from the recording.
+#[derive(Debug, Clone)]
+pub struct MoviePlayer {
+    movie: Movie,
+    frame: usize,
+    desync: Option<usize>,
+}
+
+impl MoviePlayer {
+    pub fn new(movie: Movie) -> Self {
+        MoviePlayer { movie, frame: 0, desync: None }
+    }
+
+    pub fn movie(&self) -> &Movie {
+        &self.movie
+    }
+
+    /// The frame that plays next.
+    pub fn frame(&self) -> usize {
+        self.frame
+    }
+
+    pub fn finished(&self) -> bool {
+        self.frame >= self.movie.frames.len()
+    }
+
+    /// Sets the input for the next frame, before it runs. Returns false
+    /// once the movie has run out.
+    pub fn begin_frame(&mut self, nes: &mut Nes) -> bool {
+        nes.cpu_mut().bus.take_input_polled();
+        self.movie.play_frame(self.frame, nes)
+    }
+
+    /// Checks the frame that just ran consumed its input.
+    pub fn end_frame(&mut self, nes: &mut Nes) {
+        let pressed = self.movie.frames.get(self.frame).is_some_and(|input| input.buttons.iter().any(|b| *b != 0));
+        if pressed && !nes.cpu_mut().bus.take_input_polled() && self.desync.is_none() {
+            self.desync = Some(self.frame);
+        }
+        self.frame += 1;
+    }
+
+    /// The first frame whose input went unread.
+    pub fn desync(&self) -> Option<usize> {
+        self.desync
+    }
+}
+
+fn buttons_to_text(buttons: u8) -> String {
+    BUTTON_LETTERS
+        .iter()
+        .map(|(button, letter)| if buttons & (1 << *button as u8) != 0 { *letter } else { '.' })
+        .collect()
+}
+
+fn buttons_from_text(text: &str) -> Option<u8> {
+    if text.chars().count() != BUTTON_LETTERS.len() {
+        return None;
+    }
+    text.chars().zip(BUTTON_LETTERS.iter()).try_fold(0, |buttons, (c, (button, letter))| match c {
+        '.' | ' ' => Some(buttons),
+        _ if c == *letter => Some(buttons | 1 << *button as u8),
+        _ => None,
+    })
+}
+
+fn hex_to_bytes(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 == 1 {
+        return None;
+    }
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok()).collect()
+}
+
+pub(crate) fn region_name(region: Region) -> &'static str {
+    match region {
+        Region::Ntsc => "ntsc",
+        Region::Pal => "pal",
+        Region::Dendy => "dendy",
+    }
+}
+
+pub(crate) fn region_from_name(name: &str) -> Option<Region> {
+    [Region::Ntsc, Region::Pal, Region::Dendy].into_iter().find(|region| region_name(*region) == name)
+}
+
+const PORT_DEVICES: [(PortDevice, &str); 5] = [
+    (PortDevice::Empty, "empty"),
+    (PortDevice::Joypad, "joypad"),
+    (PortDevice::Zapper, "zapper"),
+    (PortDevice::ArkanoidPaddle, "paddle"),
+    (PortDevice::PowerPad, "power-pad"),
+];
+
+fn ports_name(ports: InputConfig) -> String {
+    let device_name = |device| PORT_DEVICES.iter().find(|(d, _)| *d == device).unwrap().1;
+    match ports {
+        InputConfig::FourScore => "four-score".to_string(),
+        InputConfig::Ports(first, second) => format!("{} {}", device_name(first), device_name(second)),
+    }
+}
+
+fn ports_from_name(name: &str) -> Option<InputConfig> {
+    if name == "four-score" {
+        return Some(InputConfig::FourScore);
+    }
+    let device = |name| PORT_DEVICES.iter().find(|(_, n)| *n == name).map(|(d, _)| *d);
+    let (first, second) = name.split_once(' ')?;
+    Some(InputConfig::Ports(device(first)?, device(second)?))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::test_rom;
+    use crate::cartridge::Cartridge;
+
+    #[test]
+    fn test_record_and_play_back() {
+        let mut nes = Nes::new();
+        let mut movie = Movie::from_bus(&nes.cpu().bus, MovieStart::PowerOn);
+        nes.cpu_mut().bus.joypad1.set_button(Button::A, true);
+        movie.record_frame(&nes);
+        nes.cpu_mut().bus.joypad1.set_button(Button::A, false);
+        nes.cpu_mut().bus.joypad2.set_button(Button::Left, true);
+        movie.record_frame(&nes);
+
+        let mut replay = Nes::new();
+        assert!(movie.play_frame(0, &mut replay));
+        assert!(replay.cpu().bus.joypad1.is_pressed(Button::A));
+        assert!(movie.play_frame(1, &mut replay));
+        assert!(!replay.cpu().bus.joypad1.is_pressed(Button::A));
+        assert!(replay.cpu().bus.joypad2.is_pressed(Button::Left));
+        assert!(!movie.play_frame(2, &mut replay));
+    }
+
+    #[test]
+    fn test_resets() {
+        // JMP $C000
+        let mut prg = vec![0xea; 0x4000];
+        prg[..3].copy_from_slice(&[0x4c, 0x00, 0xc0]);
+        prg[0x3ffc..].copy_from_slice(&[0x00, 0xc0, 0x00, 0xc0]);
+        let mut nes = Nes::new();
+        nes.insert_cartridge(Cartridge::new(&test_rom(&prg)).unwrap());
+        let mut movie = Movie::from_bus(&nes.cpu().bus, MovieStart::PowerOn);
+        nes.reset(ResetKind::Soft);
+        movie.record_frame(&nes);
+        nes.run_frame();
+        movie.record_frame(&nes);
+        nes.run_frame();
+        assert!(movie.frames[0].reset);
+        assert!(!movie.frames[1].reset);
+
+        let mut replay = Nes::new();
+        replay.insert_cartridge(Cartridge::new(&test_rom(&prg)).unwrap());
+        let stack_pointer = replay.cpu().stack_pointer;
+        let mut player = MoviePlayer::new(movie);
+        assert!(player.begin_frame(&mut replay));
+        assert_eq!(replay.cpu().stack_pointer, stack_pointer.wrapping_sub(3));
+        replay.run_frame();
+        player.end_frame(&mut replay);
+        // and only in the frame it was pressed in
+        assert!(player.begin_frame(&mut replay));
+        assert_eq!(replay.cpu().stack_pointer, stack_pointer.wrapping_sub(3));
+    }
+
+    #[test]
+    fn test_player_reports_unread_input() {
+        let mut movie = Movie::new(Region::Ntsc, InputConfig::default(), MovieStart::PowerOn);
+        movie.frames.push(MovieFrame { buttons: [1, 0, 0, 0], reset: false });
+        movie.frames.push(MovieFrame { buttons: [0; 4], reset: false });
+        movie.frames.push(MovieFrame { buttons: [1, 0, 0, 0], reset: false });
+        let mut nes = Nes::new();
+        let mut player = MoviePlayer::new(movie);
+
+        assert!(player.begin_frame(&mut nes));
+        nes.cpu_mut().bus.mem_read(0x4016);
+        player.end_frame(&mut nes);
+        // nothing pressed, so not reading is fine
+        assert!(player.begin_frame(&mut nes));
+        player.end_frame(&mut nes);
+        assert_eq!(player.desync(), None);
+        assert!(player.begin_frame(&mut nes));
+        player.end_frame(&mut nes);
+        assert_eq!(player.desync(), Some(2));
+        assert!(player.finished());
+        assert!(!player.begin_frame(&mut nes));
+    }
+
+    #[test]
+    fn test_text_round_trip() {
+        let mut movie = Movie::new(Region::Pal, InputConfig::FourScore, MovieStart::Savestate(vec![0x12, 0xab]));
+        movie.frames.push(MovieFrame { buttons: [0b1000_0001, 0, 0b1000, 0], reset: false });
+        movie.frames.push(MovieFrame { buttons: [0; 4], reset: true });
+        let text = movie.to_text();
+        assert!(text.contains(".|R......A|........|....T...|........\n"));
+        assert_eq!(Movie::from_text(&text), Ok(movie));
+
+        assert!(Movie::from_text("nessie movie 1\nregion mars\n").is_err());
+        assert!(Movie::from_text("nessie movie 1\n.|RL|........|........|........\n").is_err());
+    }
+}
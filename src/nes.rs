@@ -0,0 +1,1373 @@
+use alloc::boxed::Box;
+#[cfg(feature = "std")]
+use alloc::format;
+use alloc::string::String;
+#[cfg(feature = "std")]
+use alloc::string::ToString;
+use alloc::vec::Vec;
+use core::time::Duration;
+use crate::math;
+use crate::cartridge::{crc32, Cartridge};
+use crate::cheats::{Cheat, Cheats};
+use crate::cpu::{Registers, CPU};
+use crate::debugger::{BreakReason, Debugger, RunResult};
+use crate::events::{ConsoleEvent, SubscriptionId};
+use crate::frame::Frame;
+use crate::joypad::{ButtonState, Joypad};
+use crate::profiler::Profiler;
+use crate::ram_watch::RamWatch;
+#[cfg(feature = "std")]
+use crate::recording::Recorder;
+use crate::region::Region;
+use crate::rewind::Rewind;
+use crate::romdb::RomDatabase;
+use crate::symbols::Symbols;
+#[cfg(feature = "std")]
+use std::fs;
+#[cfg(feature = "std")]
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResetKind {
+    /// The reset button: RAM and most chip state survive.
+    Soft,
+    /// Turning the power off and on again.
+    Hard,
+}
+
+// start of every save state, followed by the format version as a little
+// endian u32 and then the console
+#[cfg(feature = "serde")]
+const STATE_MAGIC: &[u8; 8] = b"NESSIEST";
+/// Version of the save state format `Nes::save_state` writes. Bumped
+/// whenever the saved state changes shape; older versions stay loadable.
+#[cfg(feature = "serde")]
+pub const STATE_VERSION: u32 = 1;
+
+// frames of lateness `run_for` catches up on at normal speed; beyond that,
+// as after the host stalled, the time is dropped rather than run in a burst
+const MAX_PACING_FRAMES: f64 = 8.0;
+
+/// Where `Nes::run_frames` left the console, compact enough to compare
+/// against a known good run in CI.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RunReport {
+    /// Number of the last frame finished.
+    pub frame: u64,
+    /// CRC-32 of the last frame's color indices, so it doesn't depend on the palette.
+    pub frame_hash: u32,
+    /// CRC-32 of internal RAM.
+    pub ram_hash: u32,
+    pub registers: Registers,
+    pub cycles: u64,
+}
+
+/// How fast `Nes::run_for` runs the console compared to the real thing.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Speed {
+    /// Real time times the factor: 2.0 fast-forwards, 0.5 is slow motion.
+    Multiplier(f64),
+    /// As many frames as the host manages in the time given.
+    Uncapped,
+    /// Only the frames asked for with `step_frame`.
+    FrameStep,
+}
+
+impl Default for Speed {
+    fn default() -> Self {
+        Speed::Multiplier(1.0)
+    }
+}
+
+/// A copy of the whole console kept in memory, for going back to it with
+/// `Nes::restore`. Cheap enough to take every frame.
+#[derive(Clone)]
+pub struct Snapshot {
+    cpu: CPU,
+}
+
+impl Snapshot {
+    /// Frame number at the time the snapshot was taken.
+    pub fn frame_number(&self) -> u64 {
+        self.cpu.bus.ppu.frame().number()
+    }
+
+    /// Internal RAM at the time, for comparing with `debugger::memory::compare`.
+    pub fn ram(&self) -> &[u8] {
+        self.cpu.bus.ram()
+    }
+}
+
+/// The whole console: CPU, PPU, APU, cartridge and controllers wired
+/// together behind one type.
+pub struct Nes {
+    cpu: CPU,
+    paused: bool,
+    speed: Speed,
+    // emulated time owed to the host, in seconds
+    pacing_debt: f64,
+    steps_pending: u32,
+    // frames emulated ahead of the real console, and the copy that ran them
+    run_ahead: usize,
+    ahead: Option<Box<CPU>>,
+    rewind: Option<Rewind>,
+    // the reset button was pressed since the last frame ran
+    soft_reset: bool,
+    rom_crc: Option<u32>,
+    // region the inserted game asks for, unless overridden
+    detected_region: Region,
+    region_override: Option<Region>,
+    rom_database: RomDatabase,
+    ram_watch: Option<RamWatch>,
+    #[cfg(feature = "std")]
+    recorder: Option<Recorder>,
+}
+
+impl Default for Nes {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Nes {
+    pub fn new() -> Self {
+        Nes {
+            cpu: CPU::new(),
+            paused: false,
+            speed: Speed::default(),
+            pacing_debt: 0.0,
+            steps_pending: 0,
+            run_ahead: 0,
+            ahead: None,
+            rewind: None,
+            soft_reset: false,
+            rom_crc: None,
+            detected_region: Region::Ntsc,
+            region_override: None,
+            rom_database: RomDatabase::new(),
+            ram_watch: None,
+            #[cfg(feature = "std")]
+            recorder: None,
+        }
+    }
+
+    /// Plugs in a game and powers the console on with it.
+    /// The region comes from the header, else the ROM database, else NTSC,
+    /// unless it's overridden.
+    pub fn insert_cartridge(&mut self, cartridge: Cartridge) {
+        let crc = cartridge.crc32();
+        self.rom_crc = Some(crc);
+        self.detected_region = cartridge.region.or_else(|| self.rom_database.region(crc)).unwrap_or_default();
+        self.cpu.bus.set_region(self.region_override.unwrap_or(self.detected_region));
+        self.cpu.bus.insert_cartridge(cartridge);
+        self.reset(ResetKind::Hard);
+    }
+
+    pub fn region(&self) -> Region {
+        self.cpu.bus.region()
+    }
+
+    /// Forces a region whatever the game asks for, or with `None` goes back
+    /// to the detected one. Takes effect right away.
+    pub fn set_region_override(&mut self, region: Option<Region>) {
+        self.region_override = region;
+        self.cpu.bus.set_region(region.unwrap_or(self.detected_region));
+        self.ahead = None;
+    }
+
+    pub fn region_override(&self) -> Option<Region> {
+        self.region_override
+    }
+
+    /// Games to look regions up in when the header doesn't say. Used by
+    /// the next `insert_cartridge`.
+    pub fn set_rom_database(&mut self, database: RomDatabase) {
+        self.rom_database = database;
+    }
+
+    /// `Cartridge::crc32` of the game inserted, if any.
+    pub fn rom_crc(&self) -> Option<u32> {
+        self.rom_crc
+    }
+
+    /// Writes battery-backed PRG RAM to a .sav file other emulators can
+    /// read, making the directory it goes in if there isn't one.
+    #[cfg(feature = "std")]
+    pub fn save_battery<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        if !self.cpu.bus.has_battery() {
+            return Err("the cartridge has no battery".to_string());
+        }
+        if let Some(dir) = path.as_ref().parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir).map_err(|e| format!("can't create {}: {}", dir.display(), e))?;
+        }
+        fs::write(path.as_ref(), self.cpu.bus.prg_ram()).map_err(|e| format!("can't write {}: {}", path.as_ref().display(), e))
+    }
+
+    /// Reads a .sav file, ours or another emulator's, into PRG RAM.
+    #[cfg(feature = "std")]
+    pub fn load_battery<P: AsRef<Path>>(&mut self, path: P) -> Result<(), String> {
+        let data = fs::read(path.as_ref()).map_err(|e| format!("can't read {}: {}", path.as_ref().display(), e))?;
+        self.cpu.bus.load_prg_ram(&data)
+    }
+
+    /// Restarts the game from its reset vector. Audio not collected yet is dropped.
+    pub fn reset(&mut self, kind: ResetKind) {
+        diag!(info, ?kind, "reset");
+        match kind {
+            ResetKind::Soft => {
+                self.cpu.bus.ppu.reset();
+                self.cpu.bus.apu.reset();
+                self.cpu.soft_reset();
+                self.soft_reset = true;
+            }
+            ResetKind::Hard => {
+                self.cpu.bus.power_cycle();
+                self.cpu.reset();
+            }
+        }
+        self.cpu.bus.apu.flush_samples();
+        self.ahead = None;
+        if let Some(rewind) = &mut self.rewind {
+            rewind.clear();
+        }
+        if let Some(debugger) = self.debugger_mut() {
+            debugger.clear_call_stack();
+        }
+    }
+
+    /// Whether the reset button was pressed since the last frame ran, for
+    /// movies to record.
+    pub fn was_reset(&self) -> bool {
+        self.soft_reset
+    }
+
+    /// Takes a copy of the console. PPU and CPU hooks aren't part of it.
+    pub fn snapshot(&self) -> Snapshot {
+        let mut cpu = self.cpu.clone();
+        cpu.bus.apu.flush_samples();
+        Snapshot { cpu }
+    }
+
+    /// Puts the console back to where it was at `snapshot`. PPU and CPU hooks stay
+    /// as they are now, and audio not collected yet and rewind copies are dropped.
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        self.replace_cpu(snapshot.cpu.clone());
+        if let Some(rewind) = &mut self.rewind {
+            rewind.clear();
+        }
+    }
+
+    // swaps in a saved console, keeping the frontend's hooks, subscribers,
+    // debugger, tracer and profiler
+    fn replace_cpu(&mut self, mut cpu: CPU) {
+        cpu.bus.ppu.swap_hooks(&mut self.cpu.bus.ppu);
+        core::mem::swap(&mut cpu.tracer, &mut self.cpu.tracer);
+        core::mem::swap(&mut cpu.profiler, &mut self.cpu.profiler);
+        core::mem::swap(&mut cpu.bus.subscribers, &mut self.cpu.bus.subscribers);
+        core::mem::swap(&mut cpu.bus.debugger, &mut self.cpu.bus.debugger);
+        core::mem::swap(&mut cpu.bus.hooks, &mut self.cpu.bus.hooks);
+        core::mem::swap(&mut cpu.bus.ppu.events, &mut self.cpu.bus.ppu.events);
+        core::mem::swap(&mut cpu.bus.cheats, &mut self.cpu.bus.cheats);
+        core::mem::swap(&mut cpu.bus.apu.tap, &mut self.cpu.bus.apu.tap);
+        #[cfg(feature = "std")]
+        core::mem::swap(&mut cpu.bus.apu.capture, &mut self.cpu.bus.apu.capture);
+        self.cpu = cpu;
+        self.ahead = None;
+        if let Some(debugger) = self.debugger_mut() {
+            debugger.clear_call_stack();
+        }
+        if let Some(profiler) = self.profiler_mut() {
+            profiler.clear_call_stack();
+        }
+        self.cpu.bus.subscribers.emit(ConsoleEvent::StateLoaded);
+    }
+
+    /// Calls `callback` with every event the console raises from now on:
+    /// frames, vblank, IRQs, lag frames and state loads. Frames run ahead
+    /// don't raise any.
+    pub fn subscribe<F>(&mut self, callback: F) -> SubscriptionId
+    where
+        F: FnMut(ConsoleEvent) + Send + 'static,
+    {
+        self.cpu.bus.subscribers.subscribe(Box::new(callback))
+    }
+
+    pub fn unsubscribe(&mut self, id: SubscriptionId) -> bool {
+        self.cpu.bus.subscribers.unsubscribe(id)
+    }
+
+    /// Logs every instruction from now on as a line in nestest.log format,
+    /// for diffing against other emulators.
+    pub fn start_trace<F>(&mut self, callback: F)
+    where
+        F: FnMut(&str) + Send + 'static,
+    {
+        self.cpu.set_tracer(Some(Box::new(callback)));
+    }
+
+    /// Shows labels from `symbols` in trace lines, and hands them to the
+    /// debugger and profiler attached, if any.
+    pub fn set_symbols(&mut self, symbols: Symbols) {
+        if let Some(profiler) = self.profiler_mut() {
+            profiler.set_symbols(&symbols);
+        }
+        if let Some(debugger) = self.debugger_mut() {
+            debugger.set_symbols(symbols.clone());
+        }
+        self.cpu.set_trace_symbols(Some(symbols));
+    }
+
+    pub fn stop_trace(&mut self) {
+        self.cpu.set_tracer(None);
+    }
+
+    /// Starts keeping a copy of the console every `interval` frames, up to
+    /// `capacity` of them, for `rewind`. `capacity` 0 turns rewinding off.
+    pub fn set_rewind(&mut self, capacity: usize, interval: u64) {
+        self.rewind = (capacity > 0).then(|| Rewind::new(capacity, interval));
+    }
+
+    pub fn rewind_buffer(&self) -> Option<&Rewind> {
+        self.rewind.as_ref()
+    }
+
+    /// Steps back at least `frames` frames, to the newest kept copy that
+    /// far back. Returns how many frames back the console went, 0 if no
+    /// copy is old enough.
+    pub fn rewind(&mut self, frames: u64) -> u64 {
+        let current = self.cpu.bus.ppu.frame().number();
+        let Some(cpu) = self.rewind.as_mut().and_then(|rewind| rewind.rewind(current, frames)) else {
+            return 0;
+        };
+        self.replace_cpu(cpu);
+        current - self.cpu.bus.ppu.frame().number()
+    }
+
+    // runs one frame of the real console
+    fn emulate_frame(&mut self) -> Option<BreakReason> {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!("frame", number = self.cpu.bus.ppu.frame().number() + 1).entered();
+        let stop = self.cpu.run_to_frame_end();
+        self.soft_reset = false;
+        if stop.is_none() {
+            if let Some(rewind) = &mut self.rewind {
+                rewind.capture(&self.cpu);
+            }
+            self.cpu.bus.apply_ram_cheats();
+            if let Some(watch) = &mut self.ram_watch {
+                watch.update(&self.cpu);
+            }
+            #[cfg(feature = "std")]
+            if let Some(recorder) = &mut self.recorder {
+                let samples = self.cpu.bus.apu.tap.0.as_mut().map(core::mem::take).unwrap_or_default();
+                recorder.record(self.cpu.bus.ppu.frame(), &samples);
+            }
+        }
+        stop
+    }
+
+    /// The whole console state (CPU, PPU, APU, cartridge, RAM and timers)
+    /// as bytes for `load_state`. PPU hooks, the palette and audio output
+    /// settings belong to the frontend and aren't saved.
+    #[cfg(feature = "serde")]
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut data = STATE_MAGIC.to_vec();
+        data.extend_from_slice(&STATE_VERSION.to_le_bytes());
+        bincode::serialize_into(&mut data, &self.cpu).expect("console state always serializes");
+        data
+    }
+
+    /// CRC-32 of the console's state without the frontend's picture and
+    /// sound settings, so consoles running in step agree on it whatever
+    /// pixel format, palette or sample rate each one uses.
+    #[cfg(feature = "serde")]
+    pub fn state_hash(&self) -> u32 {
+        let mut cpu = self.cpu.clone();
+        cpu.bus.ppu.clear_output();
+        cpu.bus.apu.clear_output();
+        crc32(bincode::serialize(&cpu).expect("console state always serializes"))
+    }
+
+    /// Goes back to a state from `save_state`. States written by a newer
+    /// version than this one understands are refused rather than misread.
+    #[cfg(feature = "serde")]
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), String> {
+        let data = data.strip_prefix(STATE_MAGIC).ok_or("not a save state")?;
+        if data.len() < 4 {
+            return Err("save state is cut short".to_string());
+        }
+        let (version, data) = data.split_at(4);
+        let version = u32::from_le_bytes(version.try_into().unwrap());
+        if version > STATE_VERSION {
+            return Err(format!("save state is version {}, this build reads up to {}", version, STATE_VERSION));
+        }
+        let mut cpu: CPU = bincode::deserialize(data).map_err(|e| format!("can't read save state: {}", e))?;
+
+        let bus = &mut cpu.bus;
+        bus.ppu.chr_ram = self.cpu.bus.ppu.chr_ram;
+        bus.ppu.set_palette(self.cpu.bus.ppu.palette().clone());
+        bus.ppu.set_pixel_format(self.cpu.bus.ppu.frame().format());
+        bus.apu.set_region(bus.region());
+        bus.apu.set_sample_rate(self.cpu.bus.apu.sample_rate());
+        self.replace_cpu(cpu);
+        if let Some(rewind) = &mut self.rewind {
+            rewind.clear();
+        }
+        diag!(info, version, "state loaded");
+        Ok(())
+    }
+
+    pub fn run_ahead(&self) -> usize {
+        self.run_ahead
+    }
+
+    /// Run-ahead hides the game's own input lag: after each frame a copy of
+    /// the console runs `frames` more with the controllers held as they are,
+    /// and its picture is shown instead. The real console, its audio and
+    /// PPU hooks never see those frames. Costs `frames` extra frames of
+    /// emulation per frame; 1 or 2 covers most games.
+    pub fn set_run_ahead(&mut self, frames: usize) {
+        self.run_ahead = frames;
+        self.ahead = None;
+    }
+
+    fn run_ahead_frames(&mut self) {
+        if self.run_ahead == 0 {
+            return;
+        }
+        let mut ahead = Box::new(self.cpu.clone());
+        for _ in 0..self.run_ahead {
+            ahead.run_frame();
+        }
+        self.ahead = Some(ahead);
+    }
+
+    /// Stops emulation: `run_frame` keeps returning the last frame and
+    /// audio not collected yet is dropped, so resuming doesn't play stale sound.
+    pub fn pause(&mut self) {
+        self.paused = true;
+        self.cpu.bus.apu.flush_samples();
+    }
+
+    pub fn resume(&mut self) {
+        self.paused = false;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Seeds everything hardware leaves to chance at power on, and powers
+    /// the console back on with it. See `Bus::set_seed`.
+    pub fn set_seed(&mut self, seed: Option<u64>) {
+        self.cpu.bus.set_seed(seed);
+        self.reset(ResetKind::Hard);
+    }
+
+    pub fn speed(&self) -> Speed {
+        self.speed
+    }
+
+    pub fn set_speed(&mut self, speed: Speed) {
+        self.speed = speed;
+        self.pacing_debt = 0.0;
+        self.steps_pending = 0;
+    }
+
+    /// Queues one frame for the next `run_for` while in `Speed::FrameStep`.
+    pub fn step_frame(&mut self) {
+        self.steps_pending += 1;
+    }
+
+    /// Paces the console: call it with the host time that passed since the
+    /// last call, and it runs however many frames are due at the current
+    /// speed. Away from normal speed, audio is cut to what fits in the time
+    /// that passed so fast-forwarding doesn't pile it up. Returns the
+    /// number of frames finished; a debugger break ends the run early.
+    pub fn run_for(&mut self, elapsed: Duration) -> usize {
+        if self.paused {
+            return 0;
+        }
+        let pending = self.cpu.bus.apu.pending_samples();
+        let frame_time = 1.0 / self.cpu.bus.region().frame_rate();
+        let mut frames = 0;
+        match self.speed {
+            Speed::Multiplier(factor) => {
+                let factor = factor.max(0.0);
+                self.pacing_debt = (self.pacing_debt + elapsed.as_secs_f64() * factor).min(frame_time * MAX_PACING_FRAMES * factor.max(1.0));
+                while self.pacing_debt >= frame_time {
+                    self.pacing_debt -= frame_time;
+                    if self.emulate_frame().is_some() {
+                        self.pacing_debt = 0.0;
+                        break;
+                    }
+                    frames += 1;
+                }
+            }
+            Speed::Uncapped => {
+                #[cfg(all(feature = "std", not(all(target_arch = "wasm32", target_os = "unknown"))))]
+                let done = {
+                    let start = std::time::Instant::now();
+                    move |_frames: usize| start.elapsed() >= elapsed
+                };
+                // no clock without std, nor in a browser without going
+                // through JavaScript, so there it counts as eight times
+                // normal speed
+                #[cfg(not(all(feature = "std", not(all(target_arch = "wasm32", target_os = "unknown")))))]
+                let done = move |frames: usize| frames as f64 * frame_time >= elapsed.as_secs_f64() * 8.0;
+                loop {
+                    if self.emulate_frame().is_some() {
+                        break;
+                    }
+                    frames += 1;
+                    if done(frames) {
+                        break;
+                    }
+                }
+            }
+            Speed::FrameStep => {
+                while self.steps_pending > 0 {
+                    self.steps_pending -= 1;
+                    if self.emulate_frame().is_some() {
+                        self.steps_pending = 0;
+                        break;
+                    }
+                    frames += 1;
+                }
+            }
+        }
+        if self.speed != Speed::Multiplier(1.0) {
+            let real_time = math::ceil(elapsed.as_secs_f64() * self.cpu.bus.apu.sample_rate() as f64) as usize;
+            self.cpu.bus.apu.truncate_samples(pending + real_time);
+        }
+        // only the last frame is shown, so only it needs running ahead
+        if frames > 0 {
+            self.run_ahead_frames();
+        }
+        frames
+    }
+
+    /// Runs `frames` frames as fast as possible and reports where the
+    /// console ended up. Needs no video or audio output, for regression
+    /// tests and compatibility sweeps.
+    pub fn run_frames(&mut self, frames: u64) -> RunReport {
+        for _ in 0..frames {
+            self.run_frame();
+        }
+        let bus = &self.cpu.bus;
+        let frame = bus.ppu.frame();
+        RunReport {
+            frame: frame.number(),
+            frame_hash: frame.hash(),
+            ram_hash: crc32(bus.ram().iter().copied()),
+            registers: self.cpu.registers(),
+            cycles: bus.clock().cpu_cycles(),
+        }
+    }
+
+    /// Emulates exactly one video frame and returns it. Stops early if the
+    /// debugger breaks; `run` tells why.
+    pub fn run_frame(&mut self) -> &Frame {
+        self.run();
+        self.frame()
+    }
+
+    /// Emulates until the current frame is finished, or until the attached
+    /// debugger stops the console. Does nothing while paused.
+    pub fn run(&mut self) -> RunResult {
+        if self.paused {
+            return RunResult::Completed;
+        }
+        match self.emulate_frame() {
+            Some(reason) => RunResult::Stopped(reason),
+            None => {
+                self.run_ahead_frames();
+                RunResult::Completed
+            }
+        }
+    }
+
+    /// Has the console stop where `debugger` says, replacing the one
+    /// attached before. Snapshots and frames run ahead don't carry it.
+    pub fn attach_debugger(&mut self, debugger: Debugger) {
+        self.cpu.bus.debugger.0 = Some(Box::new(debugger));
+    }
+
+    pub fn detach_debugger(&mut self) -> Option<Debugger> {
+        self.cpu.bus.debugger.0.take().map(|debugger| *debugger)
+    }
+
+    pub fn debugger(&self) -> Option<&Debugger> {
+        self.cpu.bus.debugger.0.as_deref()
+    }
+
+    pub fn debugger_mut(&mut self) -> Option<&mut Debugger> {
+        self.cpu.bus.debugger.0.as_deref_mut()
+    }
+
+    /// Counts instructions with `profiler` from now on, replacing the one
+    /// attached before.
+    pub fn attach_profiler(&mut self, profiler: Profiler) {
+        self.cpu.profiler = Some(Box::new(profiler));
+    }
+
+    pub fn detach_profiler(&mut self) -> Option<Profiler> {
+        self.cpu.profiler.take().map(|profiler| *profiler)
+    }
+
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.cpu.profiler.as_deref()
+    }
+
+    pub fn profiler_mut(&mut self) -> Option<&mut Profiler> {
+        self.cpu.profiler.as_deref_mut()
+    }
+
+    /// Checks the values on `watch` after every frame, see `RamWatch::changes`.
+    pub fn set_ram_watch(&mut self, watch: Option<RamWatch>) {
+        self.ram_watch = watch;
+    }
+
+    pub fn ram_watch(&self) -> Option<&RamWatch> {
+        self.ram_watch.as_ref()
+    }
+
+    pub fn ram_watch_mut(&mut self) -> Option<&mut RamWatch> {
+        self.ram_watch.as_mut()
+    }
+
+    /// Records every frame the console finishes from now on, and the audio
+    /// if `recorder` takes it, until `stop_recording`.
+    #[cfg(feature = "std")]
+    pub fn start_recording(&mut self, recorder: Recorder) -> Result<(), String> {
+        if self.recorder.is_some() {
+            return Err("already recording".to_string());
+        }
+        self.cpu.bus.apu.tap.0 = recorder.has_audio().then(Vec::new);
+        self.recorder = Some(recorder);
+        Ok(())
+    }
+
+    /// Finishes the recording and returns the number of frames in it.
+    #[cfg(feature = "std")]
+    pub fn stop_recording(&mut self) -> Result<u64, String> {
+        let recorder = self.recorder.take().ok_or("not recording")?;
+        self.cpu.bus.apu.tap.0 = None;
+        let frames = recorder.frames();
+        recorder.finish()?;
+        Ok(frames)
+    }
+
+    #[cfg(feature = "std")]
+    pub fn recorder(&self) -> Option<&Recorder> {
+        self.recorder.as_ref()
+    }
+
+    /// Decodes a Game Genie code and turns it on, see `Cheat::from_game_genie`.
+    pub fn add_game_genie(&mut self, code: &str) -> Result<(), String> {
+        self.cpu.bus.cheats.add(Cheat::from_game_genie(code)?);
+        Ok(())
+    }
+
+    /// A Game Genie or RAM cheat, turned on, see `Cheat::parse`.
+    pub fn add_cheat(&mut self, code: &str) -> Result<(), String> {
+        self.cpu.bus.cheats.add(Cheat::parse(code)?);
+        Ok(())
+    }
+
+    /// Swaps in another set of cheats, like the ones `Cheats::load` read for this game.
+    pub fn set_cheats(&mut self, cheats: Cheats) {
+        self.cpu.bus.cheats = cheats;
+    }
+
+    /// Holds `addr` at the value it has now, see `Cheats::freeze`.
+    pub fn freeze(&mut self, addr: u16) -> Result<u8, String> {
+        let value = self.cpu.bus.peek(addr);
+        self.cpu.bus.cheats.freeze(addr, value)?;
+        Ok(value)
+    }
+
+    pub fn cheats(&self) -> &Cheats {
+        &self.cpu.bus.cheats
+    }
+
+    /// For turning codes off and on, or removing them.
+    pub fn cheats_mut(&mut self) -> &mut Cheats {
+        &mut self.cpu.bus.cheats
+    }
+
+    /// Runs the next instruction and stops there, see `Debugger::step_into`.
+    /// These step functions attach a debugger if there is none.
+    pub fn step_into(&mut self) -> RunResult {
+        self.arm_debugger(|debugger, _| debugger.step_into());
+        self.run()
+    }
+
+    /// Runs the next instruction, or the whole subroutine it calls, and stops.
+    pub fn step_over(&mut self) -> RunResult {
+        self.arm_debugger(Debugger::step_over);
+        self.run()
+    }
+
+    /// Runs until the current subroutine or interrupt handler returns.
+    pub fn step_out(&mut self) -> RunResult {
+        self.arm_debugger(Debugger::step_out);
+        self.run()
+    }
+
+    /// Runs until the CPU gets to another source line, see `Debugger::step_line`.
+    pub fn step_line(&mut self) -> RunResult {
+        self.arm_debugger(Debugger::step_line);
+        self.run()
+    }
+
+    /// Runs until the CPU gets to `addr`.
+    pub fn run_to(&mut self, addr: u16) -> RunResult {
+        self.arm_debugger(|debugger, _| debugger.run_to(addr));
+        self.run()
+    }
+
+    fn arm_debugger(&mut self, arm: impl FnOnce(&mut Debugger, &CPU)) {
+        let mut debugger = self.cpu.bus.debugger.0.take().unwrap_or_default();
+        arm(&mut debugger, &self.cpu);
+        self.cpu.bus.debugger.0 = Some(debugger);
+    }
+
+    /// Sets controllers 1 and 2 and runs one frame with them, for TAS tools
+    /// and agents driving the console frame by frame.
+    pub fn advance_frame_with_input(&mut self, p1: ButtonState, p2: ButtonState) -> &Frame {
+        self.cpu.bus.joypad1.set_buttons(p1.0);
+        self.cpu.bus.joypad2.set_buttons(p2.0);
+        self.run_frame()
+    }
+
+    /// The last completed picture, from the frames run ahead if run-ahead is on.
+    pub fn frame(&self) -> &Frame {
+        match &self.ahead {
+            Some(ahead) => ahead.bus.ppu.frame(),
+            None => self.cpu.bus.ppu.frame(),
+        }
+    }
+
+    /// Writes the frame on screen to a PNG file, see `Frame::to_png` for
+    /// the bytes without a file.
+    #[cfg(feature = "image")]
+    pub fn screenshot<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        let png = self.frame().to_png()?;
+        fs::write(path.as_ref(), png).map_err(|e| format!("can't write {}: {}", path.as_ref().display(), e))
+    }
+
+    /// Moves the audio produced so far into `out`, at the APU's sample rate.
+    pub fn audio(&mut self, out: &mut Vec<f32>) {
+        self.cpu.bus.apu.samples(out);
+    }
+
+    /// Controller of player 0 to 3, the last two through a Four Score.
+    pub fn joypad_mut(&mut self, player: usize) -> Option<&mut Joypad> {
+        self.cpu.bus.joypad_mut(player)
+    }
+
+    pub fn cpu(&self) -> &CPU {
+        &self.cpu
+    }
+
+    pub fn cpu_mut(&mut self) -> &mut CPU {
+        &mut self.cpu
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::test_rom;
+    use crate::joypad::Button;
+
+    // counts NMIs at $10 and copies controller 1's first button to $11
+    fn nmi_counter() -> Nes {
+        let program = crate::asm::assemble(
+            "
+                    .org $C000
+            reset:  sei
+                    lda #$80
+                    sta $2000
+            idle:   jmp idle
+                    .org $C100
+            nmi:    inc $10
+                    lda #$01
+                    sta $4016
+                    lda #$00
+                    sta $4016
+                    lda $4016
+                    sta $11
+                    rti
+                    .org $FFFA
+                    .word nmi, reset, reset
+            ",
+        )
+        .unwrap();
+        let prg = program.bytes;
+
+        let mut nes = Nes::new();
+        nes.insert_cartridge(Cartridge::new(&test_rom(&prg)).unwrap());
+        nes
+    }
+
+    #[test]
+    fn test_recording() {
+        let path = std::env::temp_dir().join(format!("nessie-nes-recording-{}.rgba", std::process::id()));
+        let mut nes = nmi_counter();
+        assert!(nes.stop_recording().is_err());
+        nes.start_recording(Recorder::raw(&path, Some(nes.cpu().bus.apu.sample_rate())).unwrap()).unwrap();
+        assert!(nes.start_recording(Recorder::raw(&path, None).unwrap()).is_err());
+        nes.run_frames(3);
+        let mut audio = Vec::new();
+        nes.audio(&mut audio);
+        assert_eq!(nes.stop_recording(), Ok(3));
+        nes.run_frame();
+
+        let video = fs::read(&path).unwrap();
+        let wav = fs::read(path.with_extension("wav")).unwrap();
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(path.with_extension("wav")).unwrap();
+        assert_eq!(video.len(), 3 * 256 * 240 * 4);
+        // the frontend still got all of the audio
+        assert_eq!(wav.len(), 44 + audio.len() * 4);
+    }
+
+    #[test]
+    fn test_ram_watch() {
+        use crate::ram_watch::{Format, WatchEntry};
+        let mut nes = nmi_counter();
+        let mut watch = RamWatch::new();
+        watch.add(WatchEntry::new("nmis", 0x10, 1, Format::Unsigned).unwrap());
+        nes.set_ram_watch(Some(watch));
+        nes.run_frame();
+        nes.run_frame();
+        let changes = nes.ram_watch().unwrap().changes();
+        assert_eq!(changes.len(), 1);
+        assert_eq!((changes[0].name.as_str(), changes[0].new - changes[0].old), ("nmis", 1));
+    }
+
+    #[test]
+    fn test_cheats() {
+        use crate::cheats::CheatKind;
+        let mut nes = nmi_counter();
+        // INC $10 in the NMI handler becomes DEC $10
+        nes.cheats_mut().add(Cheat { code: "DEC".to_string(), kind: CheatKind::GameGenie, addr: 0xc100, value: 0xc6, compare: Some(0xe6), label: String::new(), group: None });
+        nes.run_frame();
+        nes.run_frame();
+        assert_eq!(nes.cpu_mut().bus.mem_read(0x10), 0xff);
+        assert!(nes.cheats_mut().set_enabled("DEC", false));
+        nes.run_frame();
+        assert_eq!(nes.cpu_mut().bus.mem_read(0x10), 0x00);
+        assert!(nes.add_game_genie("SXIOPO").is_ok());
+        assert!(nes.add_game_genie("SXIOP").is_err());
+        assert_eq!(nes.cheats().iter().filter(|(_, enabled)| *enabled).count(), 1);
+
+        // held at 9 whatever the NMI handler does
+        nes.add_cheat("0010:09").unwrap();
+        nes.run_frame();
+        assert_eq!(nes.cpu_mut().bus.mem_read(0x10), 0x09);
+        nes.cheats_mut().clear();
+
+        // INC $10 stores 9 again however often it runs
+        assert_eq!(nes.freeze(0x0810), Ok(0x09));
+        nes.run_frame();
+        assert_eq!(nes.cpu().bus.peek(0x10), 0x09);
+        nes.cheats_mut().unfreeze(0x10);
+        nes.run_frame();
+        assert_eq!(nes.cpu().bus.peek(0x10), 0x0a);
+    }
+
+    #[test]
+    fn test_cpu_hooks() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+        let mut nes = nmi_counter();
+        let frames = Arc::new(AtomicUsize::new(0));
+        let counter = frames.clone();
+        nes.cpu_mut().add_frame_hook(move |_| {
+            counter.fetch_add(1, Ordering::Relaxed);
+        });
+        // the handler's INC $10 counts by 2
+        nes.cpu_mut().add_nmi_hook(|cpu| cpu.bus.mem_write(0x10, cpu.bus.peek(0x10) + 1));
+        let snapshot = nes.snapshot();
+        nes.run_frame();
+        nes.run_frame();
+        nes.restore(&snapshot);
+        nes.run_frame();
+        nes.run_frame();
+        assert_eq!(frames.load(Ordering::Relaxed), 4);
+        assert_eq!(nes.cpu().bus.peek(0x10), 2);
+    }
+
+    #[test]
+    fn test_event_log() {
+        use crate::ppu::events::PpuEventKind;
+        let mut nes = nmi_counter();
+        nes.cpu_mut().bus.ppu.set_event_log(true);
+        let snapshot = nes.snapshot();
+        nes.restore(&snapshot);
+        for _ in 0..3 {
+            nes.run_frame();
+        }
+        let kinds: Vec<_> = nes.cpu().bus.ppu.last_frame_events().iter().map(|event| event.kind).collect();
+        // the NMI handler strobes the controllers
+        assert_eq!(kinds, vec![PpuEventKind::Nmi, PpuEventKind::RegisterWrite { addr: 0x4016, value: 1 }, PpuEventKind::RegisterWrite { addr: 0x4016, value: 0 }]);
+        assert!(nes.cpu().bus.ppu.last_frame_events().iter().all(|event| event.scanline >= 241));
+    }
+
+    #[test]
+    fn test_run_frames() {
+        let mut nes = nmi_counter();
+        assert_eq!(nes.cpu().program_counter, 0xc000);
+        for number in 1..=3 {
+            assert_eq!(nes.run_frame().number(), number);
+        }
+        // the NMI of a frame is taken right after it completes
+        assert_eq!(nes.cpu_mut().bus.mem_read(0x10), 2);
+
+        nes.joypad_mut(0).unwrap().set_button(Button::A, true);
+        nes.run_frame();
+        nes.run_frame();
+        assert_eq!(nes.cpu_mut().bus.mem_read(0x11) & 1, 1);
+
+        let mut samples = Vec::new();
+        nes.audio(&mut samples);
+        assert!(samples.len() > 1000);
+    }
+
+    #[test]
+    fn test_run_frame_is_one_frame_of_cycles() {
+        let mut nes = nmi_counter();
+        nes.run_frame();
+        let mut lengths = Vec::new();
+        for _ in 0..6 {
+            let start = nes.cpu().bus.cycles();
+            nes.run_frame();
+            lengths.push(nes.cpu().bus.cycles() - start);
+        }
+        // 89342 dots is 29780.67 CPU cycles, give or take the instruction a frame ends in
+        assert!(lengths.iter().all(|cycles| (29_775..=29_787).contains(cycles)), "{:?}", lengths);
+        let total: usize = lengths.iter().sum();
+        assert!((total as i64 - 29_781 * 6).abs() <= 10, "{}", total);
+    }
+
+    #[test]
+    fn test_pause_and_resume() {
+        let mut nes = nmi_counter();
+        nes.run_frame();
+        nes.pause();
+        let mut samples = Vec::new();
+        nes.audio(&mut samples);
+        assert!(samples.is_empty());
+        let cycles = nes.cpu().bus.cycles();
+        assert_eq!(nes.run_frame().number(), 1);
+        assert_eq!(nes.cpu().bus.cycles(), cycles);
+
+        nes.resume();
+        assert_eq!(nes.run_frame().number(), 2);
+    }
+
+    #[test]
+    fn test_soft_and_hard_reset() {
+        let mut nes = nmi_counter();
+        for _ in 0..3 {
+            nes.run_frame();
+        }
+        nes.reset(ResetKind::Soft);
+        assert_eq!(nes.cpu().program_counter, 0xc000);
+        // RAM survives, and the NMI is off again until the game enables it
+        assert_eq!(nes.cpu_mut().bus.mem_read(0x10), 2);
+        assert_eq!(nes.cpu().bus.ppu.ctrl, 0);
+        assert_eq!(nes.frame().number(), 3);
+
+        nes.reset(ResetKind::Hard);
+        assert_eq!(nes.cpu_mut().bus.mem_read(0x10), 0);
+        assert_eq!(nes.cpu().stack_pointer, 0xfd);
+        nes.run_frame();
+        assert_eq!(nes.frame().number(), 4);
+    }
+
+    #[test]
+    fn test_seeded_runs_are_identical() {
+        let run = |seed| {
+            let mut nes = nmi_counter();
+            nes.set_seed(seed);
+            let ram: Vec<u8> = (0x200..0x800).map(|addr| nes.cpu_mut().bus.mem_read(addr)).collect();
+            for _ in 0..3 {
+                nes.run_frame();
+            }
+            (ram, nes.cpu().bus.cycles(), nes.cpu().bus.ppu.oam_data, nes.frame().pixels().to_vec())
+        };
+        assert_eq!(run(Some(7)), run(Some(7)));
+        assert_ne!(run(Some(7)).0, run(Some(8)).0);
+        assert!(run(None).0.iter().all(|&byte| byte == 0));
+    }
+
+    #[test]
+    fn test_snapshot_and_restore() {
+        let mut nes = nmi_counter();
+        nes.run_frame();
+        let snapshot = nes.snapshot();
+        assert_eq!(snapshot.frame_number(), 1);
+        for _ in 0..3 {
+            nes.run_frame();
+        }
+        let cycles = nes.cpu().bus.cycles();
+        let counted = nes.cpu_mut().bus.mem_read(0x10);
+
+        nes.restore(&snapshot);
+        assert_eq!(nes.frame().number(), 1);
+        for _ in 0..3 {
+            nes.run_frame();
+        }
+        assert_eq!(nes.cpu().bus.cycles(), cycles);
+        assert_eq!(nes.cpu_mut().bus.mem_read(0x10), counted);
+    }
+
+    #[test]
+    fn test_run_ahead_shows_future_frame() {
+        let mut nes = nmi_counter();
+        let hook_calls = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let calls = hook_calls.clone();
+        nes.cpu_mut().bus.ppu.add_scanline_hook(0, move |_| {
+            calls.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        });
+        nes.set_run_ahead(2);
+        assert_eq!(nes.run_frame().number(), 3);
+        assert_eq!(nes.run_frame().number(), 4);
+        // the real console is only two frames in
+        assert_eq!(nes.cpu().bus.ppu.frame().number(), 2);
+        assert_eq!(nes.cpu_mut().bus.mem_read(0x10), 1);
+        assert_eq!(hook_calls.load(std::sync::atomic::Ordering::Relaxed), 2);
+
+        nes.set_run_ahead(0);
+        assert_eq!(nes.run_frame().number(), 3);
+    }
+
+    #[test]
+    fn test_rewind() {
+        let mut nes = nmi_counter();
+        assert_eq!(nes.rewind(1), 0);
+        nes.set_rewind(10, 2);
+        let mut counts = vec![0];
+        for _ in 0..30 {
+            nes.run_frame();
+            counts.push(nes.cpu_mut().bus.mem_read(0x10));
+        }
+        assert_eq!(nes.rewind_buffer().unwrap().len(), 10);
+
+        // goes back to a kept frame at or before the one asked for
+        assert_eq!(nes.rewind(5), 6);
+        assert_eq!(nes.frame().number(), 24);
+        assert_eq!(nes.cpu_mut().bus.mem_read(0x10), counts[24]);
+        let mut fresh = nmi_counter();
+        for _ in 0..24 {
+            fresh.run_frame();
+        }
+        assert_eq!(nes.frame().pixels(), fresh.frame().pixels());
+        assert_eq!(nes.cpu().bus.cycles(), fresh.cpu().bus.cycles());
+
+        // not at all past the oldest copy
+        assert_eq!(nes.rewind(100), 0);
+        assert_eq!(nes.frame().number(), 24);
+        assert_eq!(nes.rewind(12), 12);
+        assert_eq!(nes.frame().number(), 12);
+        nes.run_frame();
+        assert_eq!(nes.cpu_mut().bus.mem_read(0x10), counts[13]);
+    }
+
+    #[test]
+    fn test_restore_drops_rewind_copies() {
+        let mut nes = nmi_counter();
+        nes.set_rewind(5, 1);
+        nes.run_frame();
+        nes.run_frame();
+        let snapshot = nes.snapshot();
+        for _ in 0..20 {
+            nes.run_frame();
+        }
+        nes.restore(&snapshot);
+        assert!(nes.rewind_buffer().unwrap().is_empty());
+        assert_eq!(nes.rewind(1), 0);
+        assert_eq!(nes.frame().number(), 2);
+    }
+
+    #[test]
+    fn test_trace_survives_restore() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+        let lines = Arc::new(AtomicUsize::new(0));
+        let mut nes = nmi_counter();
+        let counter = lines.clone();
+        nes.start_trace(move |_| {
+            counter.fetch_add(1, Ordering::Relaxed);
+        });
+        let snapshot = nes.snapshot();
+        nes.run_frame();
+        nes.restore(&snapshot);
+        let traced = lines.load(Ordering::Relaxed);
+        assert!(nes.cpu().is_tracing());
+        nes.run_frame();
+        assert!(lines.load(Ordering::Relaxed) > traced);
+    }
+
+    #[test]
+    fn test_profiler_survives_restore() {
+        let mut nes = nmi_counter();
+        let snapshot = nes.snapshot();
+        nes.attach_profiler(Profiler::new());
+        nes.run_frame();
+        let counted = nes.profiler().unwrap().instructions();
+        nes.restore(&snapshot);
+        assert_eq!(nes.profiler().map(Profiler::instructions), Some(counted));
+        nes.run_frame();
+        assert!(nes.profiler().unwrap().instructions() > counted);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_save_and_load_state() {
+        let mut nes = nmi_counter();
+        nes.set_seed(Some(3));
+        for _ in 0..3 {
+            nes.run_frame();
+        }
+        let state = nes.save_state();
+        for _ in 0..3 {
+            nes.run_frame();
+        }
+        let cycles = nes.cpu().bus.cycles();
+        let pixels = nes.frame().pixels().to_vec();
+
+        let mut other = Nes::new();
+        other.load_state(&state).unwrap();
+        assert_eq!(other.frame().number(), 3);
+        for _ in 0..3 {
+            other.run_frame();
+        }
+        assert_eq!(other.cpu().bus.cycles(), cycles);
+        assert_eq!(other.frame().pixels(), &pixels[..]);
+        assert_eq!(other.cpu_mut().bus.mem_read(0x10), nes.cpu_mut().bus.mem_read(0x10));
+
+        let mut newer = state.clone();
+        newer[8..12].copy_from_slice(&(STATE_VERSION + 1).to_le_bytes());
+        assert!(other.load_state(&newer).unwrap_err().contains("version"));
+        assert!(other.load_state(&state[..100]).is_err());
+        assert!(other.load_state(b"garbage").is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_state_hash_leaves_out_output_settings() {
+        let mut nes = nmi_counter();
+        let mut other = nmi_counter();
+        other.cpu_mut().bus.ppu.set_pixel_format(crate::frame::PixelFormat::Rgb565);
+        other.cpu_mut().bus.apu.set_sample_rate(32000);
+        other.cpu_mut().bus.apu.set_dynamic_rate_control(Some(0.005));
+        nes.run_frames(3);
+        other.run_frames(3);
+        assert_ne!(crc32(nes.save_state()), crc32(other.save_state()));
+        assert_eq!(nes.state_hash(), other.state_hash());
+        nes.run_frame();
+        assert_ne!(nes.state_hash(), other.state_hash());
+    }
+
+    #[test]
+    fn test_battery_save_round_trip() {
+        let mut nes = nmi_counter();
+        let path = std::env::temp_dir().join(format!("nessie-battery-{}.sav", std::process::id()));
+        assert!(nes.save_battery(&path).is_err());
+
+        let mut prg = vec![0xea; 0x4000];
+        prg[0x3ffc..].copy_from_slice(&[0x00, 0xc0, 0x00, 0xc0]);
+        let mut rom = test_rom(&prg);
+        rom[6] |= 0b10;
+        nes.insert_cartridge(Cartridge::new(&rom).unwrap());
+        nes.cpu_mut().bus.mem_write(0x6000, 0x12);
+        nes.cpu_mut().bus.mem_write(0x7fff, 0x34);
+        nes.save_battery(&path).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().len(), 0x2000);
+
+        nes.insert_cartridge(Cartridge::new(&rom).unwrap());
+        assert_eq!(nes.cpu_mut().bus.mem_read(0x6000), 0);
+        nes.load_battery(&path).unwrap();
+        assert_eq!(nes.cpu_mut().bus.mem_read(0x6000), 0x12);
+        assert_eq!(nes.cpu_mut().bus.mem_read(0x7fff), 0x34);
+        fs::remove_file(&path).unwrap();
+
+        assert!(nes.cpu_mut().bus.load_prg_ram(&[0; 0x2001]).is_err());
+    }
+
+    #[test]
+    fn test_run_frames_report() {
+        let mut nes = nmi_counter();
+        let report = nes.run_frames(10);
+        assert_eq!(report.frame, 10);
+        assert_eq!(report.cycles, nes.cpu().bus.cycles() as u64);
+        assert_eq!(report.registers.program_counter, nes.cpu().program_counter);
+
+        let mut again = nmi_counter();
+        assert_eq!(again.run_frames(10), report);
+        let later = again.run_frames(1);
+        // the NMI counter in RAM moved on
+        assert_ne!(later.ram_hash, report.ram_hash);
+    }
+
+    #[test]
+    fn test_region_detection_and_override() {
+        let mut prg = vec![0xea; 0x4000];
+        prg[0x3ffc..].copy_from_slice(&[0x00, 0xc0, 0x00, 0xc0]);
+        let plain = Cartridge::new(&test_rom(&prg)).unwrap();
+        let mut nes = Nes::new();
+        nes.insert_cartridge(plain.clone());
+        assert_eq!(nes.region(), Region::Ntsc);
+
+        let mut database = RomDatabase::new();
+        database.insert(plain.crc32(), Region::Pal);
+        nes.set_rom_database(database);
+        nes.insert_cartridge(plain.clone());
+        assert_eq!(nes.region(), Region::Pal);
+        assert_eq!(nes.cpu().bus.ppu.region(), Region::Pal);
+
+        // the header wins over the database
+        let mut rom = test_rom(&prg);
+        rom[7] |= 0b1000;
+        rom[12] = 3;
+        nes.insert_cartridge(Cartridge::new(&rom).unwrap());
+        assert_eq!(nes.region(), Region::Dendy);
+
+        nes.set_region_override(Some(Region::Ntsc));
+        assert_eq!(nes.region(), Region::Ntsc);
+        nes.insert_cartridge(plain);
+        assert_eq!(nes.region(), Region::Ntsc);
+        nes.set_region_override(None);
+        assert_eq!(nes.region(), Region::Pal);
+        let cycles = nes.run_frames(2).cycles;
+        assert!((33_000..34_000).contains(&(nes.run_frames(1).cycles - cycles)));
+    }
+
+    #[test]
+    fn test_overclock_scanlines() {
+        let frame = |overclock: u16| {
+            let mut nes = nmi_counter();
+            nes.cpu_mut().bus.ppu.config.overclock_scanlines = overclock;
+            let start = nes.run_frames(2).cycles;
+            let mut samples = Vec::new();
+            nes.audio(&mut samples);
+            samples.clear();
+            let cycles = nes.run_frames(10).cycles - start;
+            nes.audio(&mut samples);
+            (cycles, samples.len())
+        };
+        let (cycles, samples) = frame(0);
+        let (overclocked, overclocked_samples) = frame(30);
+        // 30 lines of 341 dots are 3410 extra CPU cycles a frame
+        assert!((overclocked - cycles).abs_diff(34_100) < 20, "{} {}", cycles, overclocked);
+        assert!(samples.abs_diff(overclocked_samples) < 5, "{} {}", samples, overclocked_samples);
+    }
+
+    #[test]
+    fn test_subscribe_to_events() {
+        use std::sync::{Arc, Mutex};
+
+        let mut nes = nmi_counter();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let seen = events.clone();
+        let id = nes.subscribe(move |event| seen.lock().unwrap().push(event));
+        nes.run_frame();
+        nes.run_frame();
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![
+                ConsoleEvent::VblankStart,
+                ConsoleEvent::FrameComplete(1),
+                ConsoleEvent::LagFrame(1),
+                // the game never turns off the frame IRQ
+                ConsoleEvent::ApuFrameIrq,
+                ConsoleEvent::VblankStart,
+                ConsoleEvent::FrameComplete(2),
+            ]
+        );
+
+        events.lock().unwrap().clear();
+        let snapshot = nes.snapshot();
+        nes.restore(&snapshot);
+        assert_eq!(*events.lock().unwrap(), vec![ConsoleEvent::StateLoaded]);
+
+        assert!(nes.unsubscribe(id));
+        nes.run_frame();
+        assert_eq!(events.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_trace_log() {
+        use std::sync::{Arc, Mutex};
+
+        let mut nes = nmi_counter();
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        let log = lines.clone();
+        nes.start_trace(move |line| log.lock().unwrap().push(line.to_string()));
+        nes.cpu_mut().step();
+        nes.cpu_mut().step();
+        nes.stop_trace();
+        nes.run_frame();
+
+        let lines = lines.lock().unwrap();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("C000  78        SEI"), "{}", lines[0]);
+        assert!(lines[1].starts_with("C001  A9 80     LDA #$80"), "{}", lines[1]);
+        assert!(lines[1].ends_with("P:24 SP:FD PPU:  0,  6 CYC:2"), "{}", lines[1]);
+    }
+
+    #[test]
+    fn test_run_for_paces_frames() {
+        let mut nes = nmi_counter();
+        assert_eq!(nes.run_for(Duration::from_millis(100)), 6);
+        // the leftover fraction of a frame carries over
+        assert_eq!(nes.run_for(Duration::from_millis(10)), 0);
+        assert_eq!(nes.run_for(Duration::from_millis(10)), 1);
+
+        nes.set_speed(Speed::Multiplier(3.0));
+        let mut samples = Vec::new();
+        nes.audio(&mut samples);
+        assert_eq!(nes.run_for(Duration::from_millis(100)), 18);
+        samples.clear();
+        nes.audio(&mut samples);
+        assert!(samples.len() <= 4410, "{}", samples.len());
+
+        // a stalled host doesn't get a burst of frames after
+        nes.set_speed(Speed::Multiplier(1.0));
+        assert_eq!(nes.run_for(Duration::from_secs(10)), 8);
+        assert_eq!(nes.run_for(Duration::from_millis(10)), 0);
+    }
+
+    #[test]
+    fn test_frame_step_and_uncapped() {
+        let mut nes = nmi_counter();
+        nes.set_speed(Speed::FrameStep);
+        assert_eq!(nes.run_for(Duration::from_secs(1)), 0);
+        nes.step_frame();
+        assert_eq!(nes.run_for(Duration::ZERO), 1);
+        assert_eq!(nes.frame().number(), 1);
+
+        nes.set_speed(Speed::Uncapped);
+        assert!(nes.run_for(Duration::ZERO) >= 1);
+    }
+
+    #[test]
+    fn test_advance_frame_with_input() {
+        let mut nes = nmi_counter();
+        nes.run_frame();
+        let number = nes.advance_frame_with_input(ButtonState::default().with(Button::A), ButtonState::default()).number();
+        assert_eq!(number, 2);
+        nes.run_frame();
+        assert_eq!(nes.cpu_mut().bus.mem_read(0x11) & 1, 1);
+
+        // runs like any other frame, cheats and all
+        assert_eq!(nes.freeze(0x10), Ok(2));
+        nes.advance_frame_with_input(ButtonState::default(), ButtonState::default());
+        assert_eq!(nes.cpu().bus.peek(0x10), 2);
+    }
+}
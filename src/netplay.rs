@@ -0,0 +1,386 @@
+//! Two consoles kept in lockstep over TCP. Emulation is deterministic, so
+//! the same game fed the same buttons on the same frames stays the same on
+//! both ends, and only the buttons have to travel.
+//!
+//! The host plays controller 1 and the peer that joins controller 2. On
+//! joining, the host sends the game's CRC and its console as it is, so both
+//! start from the same state. Every frame's buttons are sent `delay` frames
+//! ahead of when they're used, which hides that much latency; a frame whose
+//! buttons haven't come in yet isn't run and the frontend just tries again
+//! on its next tick. Both ends hash their state now and then to catch a
+//! desync.
+
+use crate::nes::Nes;
+use std::collections::btree_map::Entry;
+use std::collections::BTreeMap;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+const MAGIC: &[u8; 6] = b"NESNET";
+const VERSION: u8 = 1;
+// frames between state hashes, about a second
+const HASH_INTERVAL: u64 = 60;
+// how long the handshake may take before giving up on the other end
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+const INPUT: u8 = 1;
+const HASH: u8 = 2;
+const BYE: u8 = 3;
+
+/// How the link to the other console is doing, for the frontend to show.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// The last frame asked for ran.
+    Running,
+    /// Waiting for the other end's buttons for the next frame.
+    Stalled,
+    /// The other end left or the connection broke; nothing runs anymore.
+    Disconnected(String),
+}
+
+/// One end of a netplay session, from `host` or `join`.
+pub struct Netplay {
+    stream: TcpStream,
+    player: usize,
+    delay: u64,
+    // frames run since the session started
+    frame: u64,
+    local: BTreeMap<u64, u8>,
+    remote: BTreeMap<u64, u8>,
+    // hashes of frames the other end hasn't reported on yet, ours and theirs
+    local_hashes: BTreeMap<u64, u32>,
+    remote_hashes: BTreeMap<u64, u32>,
+    desync: Option<u64>,
+    state: ConnectionState,
+    incoming: Vec<u8>,
+    outgoing: Vec<u8>,
+}
+
+impl Netplay {
+    /// Waits for a peer on `listener` and sends it the console as it is
+    /// now, the game already inserted. Buttons are used `delay` frames
+    /// after they're given to `advance`.
+    pub fn host(listener: &TcpListener, nes: &Nes, delay: u64) -> Result<Netplay, String> {
+        let crc = nes.rom_crc().ok_or("no cartridge inserted")?;
+        let (mut stream, _) = listener.accept().map_err(|err| format!("couldn't accept a peer: {}", err))?;
+        let handshake = (|| {
+            stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+            let state = nes.save_state();
+            let mut hello = MAGIC.to_vec();
+            hello.push(VERSION);
+            hello.extend_from_slice(&delay.to_le_bytes());
+            hello.extend_from_slice(&crc.to_le_bytes());
+            hello.extend_from_slice(&(state.len() as u32).to_le_bytes());
+            hello.extend_from_slice(&state);
+            stream.write_all(&hello)?;
+            let mut reply = [0; 1];
+            stream.read_exact(&mut reply)?;
+            Ok(reply[0])
+        })();
+        match handshake.map_err(|err: std::io::Error| format!("handshake failed: {}", err))? {
+            0 => Netplay::new(stream, 0, delay),
+            _ => Err("the peer is running a different game".to_string()),
+        }
+    }
+
+    /// Connects to a host and takes over its console state. `nes` has to
+    /// have the same game inserted.
+    pub fn join<A: ToSocketAddrs>(addr: A, nes: &mut Nes) -> Result<Netplay, String> {
+        let mut stream = TcpStream::connect(addr).map_err(|err| format!("couldn't connect: {}", err))?;
+        stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT)).map_err(|err| err.to_string())?;
+        let hello = (|| {
+            let mut header = [0; 6 + 1 + 8 + 4 + 4];
+            stream.read_exact(&mut header)?;
+            if header[..6] != MAGIC[..] || header[6] != VERSION {
+                return Err(std::io::Error::new(ErrorKind::InvalidData, "not a netplay host of this version"));
+            }
+            let delay = u64::from_le_bytes(header[7..15].try_into().unwrap());
+            let crc = u32::from_le_bytes(header[15..19].try_into().unwrap());
+            let mut state = vec![0; u32::from_le_bytes(header[19..23].try_into().unwrap()) as usize];
+            stream.read_exact(&mut state)?;
+            Ok((delay, crc, state))
+        })();
+        let (delay, crc, state) = hello.map_err(|err| format!("handshake failed: {}", err))?;
+        let same_game = nes.rom_crc() == Some(crc);
+        stream.write_all(&[if same_game { 0 } else { 1 }]).map_err(|err| format!("handshake failed: {}", err))?;
+        if !same_game {
+            return Err(format!("the host is running another game, CRC {:08x}", crc));
+        }
+        nes.load_state(&state)?;
+        Netplay::new(stream, 1, delay)
+    }
+
+    fn new(stream: TcpStream, player: usize, delay: u64) -> Result<Netplay, String> {
+        stream.set_read_timeout(None).and_then(|_| stream.set_nodelay(true)).and_then(|_| stream.set_nonblocking(true)).map_err(|err| err.to_string())?;
+        // nobody pressed anything in the frames before the first buttons arrive
+        let nothing: BTreeMap<u64, u8> = (0..delay).map(|frame| (frame, 0)).collect();
+        Ok(Netplay {
+            stream,
+            player,
+            delay,
+            frame: 0,
+            local: nothing.clone(),
+            remote: nothing,
+            local_hashes: BTreeMap::new(),
+            remote_hashes: BTreeMap::new(),
+            desync: None,
+            state: ConnectionState::Running,
+            incoming: Vec::new(),
+            outgoing: Vec::new(),
+        })
+    }
+
+    /// The controller this end plays, 0 for the host and 1 for the peer.
+    pub fn player(&self) -> usize {
+        self.player
+    }
+
+    pub fn delay(&self) -> u64 {
+        self.delay
+    }
+
+    /// Frames run since the session started.
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    pub fn state(&self) -> &ConnectionState {
+        &self.state
+    }
+
+    /// The first frame after which the two consoles were found to differ.
+    /// They keep running, but what each player sees is no longer the same.
+    pub fn desync(&self) -> Option<u64> {
+        self.desync
+    }
+
+    /// Call once a frontend tick with the buttons held on this end, A in
+    /// bit 0 through Right in bit 7. Runs the next frame if the other
+    /// end's buttons for it are in and returns whether it did.
+    pub fn advance(&mut self, nes: &mut Nes, buttons: u8) -> bool {
+        if let ConnectionState::Disconnected(_) = self.state {
+            return false;
+        }
+        // the buttons of a stalled frame were sent on the first try
+        let send_frame = self.frame + self.delay;
+        if let Entry::Vacant(entry) = self.local.entry(send_frame) {
+            entry.insert(buttons);
+            self.outgoing.push(INPUT);
+            self.outgoing.extend_from_slice(&send_frame.to_le_bytes());
+            self.outgoing.push(buttons);
+        }
+        if let Err(err) = self.flush().and_then(|_| self.receive()) {
+            self.state = ConnectionState::Disconnected(err);
+            return false;
+        }
+        let Some(remote) = self.remote.remove(&self.frame) else {
+            self.state = ConnectionState::Stalled;
+            return false;
+        };
+        let local = self.local.remove(&self.frame).unwrap_or(0);
+        for (player, buttons) in [(self.player, local), (1 - self.player, remote)] {
+            if let Some(joypad) = nes.joypad_mut(player) {
+                joypad.set_buttons(buttons);
+            }
+        }
+        nes.run_frame();
+        if (self.frame + 1).is_multiple_of(HASH_INTERVAL) {
+            let hash = nes.state_hash();
+            self.local_hashes.insert(self.frame, hash);
+            self.outgoing.push(HASH);
+            self.outgoing.extend_from_slice(&self.frame.to_le_bytes());
+            self.outgoing.extend_from_slice(&hash.to_le_bytes());
+            self.compare_hashes();
+        }
+        self.frame += 1;
+        self.state = match self.flush() {
+            Ok(()) => ConnectionState::Running,
+            Err(err) => ConnectionState::Disconnected(err),
+        };
+        true
+    }
+
+    /// Tells the other end this one is leaving.
+    pub fn close(mut self) {
+        self.outgoing.push(BYE);
+        let _ = self.stream.set_nonblocking(false);
+        let _ = self.stream.write_all(&self.outgoing);
+    }
+
+    // sends as much of what's queued as goes without waiting
+    fn flush(&mut self) -> Result<(), String> {
+        while !self.outgoing.is_empty() {
+            match self.stream.write(&self.outgoing) {
+                Ok(0) => return Err("the connection closed".to_string()),
+                Ok(written) => drop(self.outgoing.drain(..written)),
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(err) => return Err(err.to_string()),
+            }
+        }
+        Ok(())
+    }
+
+    // takes in whatever has arrived, without waiting
+    fn receive(&mut self) -> Result<(), String> {
+        let mut buffer = [0; 1024];
+        let hung_up = loop {
+            match self.stream.read(&mut buffer) {
+                Ok(0) => break true,
+                Ok(read) => self.incoming.extend_from_slice(&buffer[..read]),
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break false,
+                Err(err) => return Err(err.to_string()),
+            }
+        };
+        // a goodbye right before hanging up says more than the hang up
+        self.parse()?;
+        if hung_up {
+            return Err("the other end hung up".to_string());
+        }
+        Ok(())
+    }
+
+    fn parse(&mut self) -> Result<(), String> {
+        let mut at = 0;
+        while let Some(&kind) = self.incoming.get(at) {
+            let len = match kind {
+                INPUT => 1 + 8 + 1,
+                HASH => 1 + 8 + 4,
+                BYE => return Err("the other end left".to_string()),
+                _ => return Err(format!("unknown message {}", kind)),
+            };
+            let Some(message) = self.incoming.get(at..at + len) else { break };
+            let frame = u64::from_le_bytes(message[1..9].try_into().unwrap());
+            if kind == INPUT {
+                self.remote.insert(frame, message[9]);
+            } else {
+                self.remote_hashes.insert(frame, u32::from_le_bytes(message[9..13].try_into().unwrap()));
+            }
+            at += len;
+        }
+        self.incoming.drain(..at);
+        self.compare_hashes();
+        Ok(())
+    }
+
+    fn compare_hashes(&mut self) {
+        let frames: Vec<u64> = self.remote_hashes.keys().filter(|frame| self.local_hashes.contains_key(frame)).copied().collect();
+        for frame in frames {
+            if self.local_hashes.remove(&frame) != self.remote_hashes.remove(&frame) && self.desync.is_none() {
+                self.desync = Some(frame);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::asm::assemble;
+    use crate::cartridge::test::test_rom;
+    use crate::cartridge::Cartridge;
+    use crate::frame::PixelFormat;
+    use std::thread;
+
+    // keeps a sum of both controllers' buttons in RAM
+    fn console() -> Nes {
+        let program = "
+            .org $C000
+            reset: lda #$80
+                   sta $2000
+            loop:  jmp loop
+            nmi:   ldx #1
+                   stx $4016
+                   dex
+                   stx $4016
+                   ldy #8
+            read:  lda $4016
+                   and #1
+                   clc
+                   adc $10
+                   sta $10
+                   lda $4017
+                   and #1
+                   asl a
+                   clc
+                   adc $10
+                   sta $10
+                   dey
+                   bne read
+                   rti
+            .org $FFFA
+            .word nmi, reset, reset";
+        let mut nes = Nes::new();
+        nes.insert_cartridge(Cartridge::new(&test_rom(&assemble(program).unwrap().bytes)).unwrap());
+        nes
+    }
+
+    // runs `frames` frames with `buttons` held, ticking until each one runs
+    fn play(netplay: &mut Netplay, nes: &mut Nes, frames: u64, buttons: impl Fn(u64) -> u8) {
+        while netplay.frame() < frames {
+            if !netplay.advance(nes, buttons(netplay.frame())) {
+                assert!(!matches!(netplay.state(), ConnectionState::Disconnected(_)), "{:?}", netplay.state());
+                thread::sleep(Duration::from_millis(1));
+            }
+        }
+    }
+
+    #[test]
+    fn test_lockstep() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let peer = thread::spawn(move || {
+            // picture and sound set up like the libretro core's
+            let mut nes = console();
+            nes.cpu_mut().bus.ppu.set_pixel_format(PixelFormat::Rgb565);
+            nes.cpu_mut().bus.apu.set_sample_rate(32000);
+            let mut netplay = Netplay::join(addr, &mut nes).unwrap();
+            assert_eq!((netplay.player(), netplay.delay()), (1, 2));
+            play(&mut netplay, &mut nes, 70, |frame| if frame % 3 == 0 { 0b1 } else { 0 });
+            (nes.cpu().bus.peek(0x10), netplay.desync())
+        });
+
+        let mut nes = console();
+        nes.run_frames(5);
+        let mut netplay = Netplay::host(&listener, &nes, 2).unwrap();
+        play(&mut netplay, &mut nes, 70, |frame| (frame % 2) as u8);
+        let (peer_sum, peer_desync) = peer.join().unwrap();
+        assert_eq!(nes.cpu().bus.peek(0x10), peer_sum);
+        assert_ne!(peer_sum, 0);
+        assert_eq!((netplay.desync(), peer_desync), (None, None));
+        netplay.close();
+    }
+
+    #[test]
+    fn test_desync_and_disconnect() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let peer = thread::spawn(move || {
+            let mut nes = console();
+            let mut netplay = Netplay::join(addr, &mut nes).unwrap();
+            play(&mut netplay, &mut nes, 30, |_| 0);
+            // something outside the inputs changes the game on this end only
+            nes.cpu_mut().bus.mem_write(0x10, 0x55);
+            play(&mut netplay, &mut nes, 70, |_| 0);
+            netplay.close();
+        });
+
+        let mut nes = console();
+        let mut netplay = Netplay::host(&listener, &nes, 0).unwrap();
+        play(&mut netplay, &mut nes, 70, |_| 0);
+        peer.join().unwrap();
+        while !matches!(netplay.state(), ConnectionState::Disconnected(_)) {
+            netplay.advance(&mut nes, 0);
+        }
+        assert_eq!(netplay.desync(), Some(HASH_INTERVAL - 1));
+        assert_eq!(netplay.state(), &ConnectionState::Disconnected("the other end left".to_string()));
+
+        let mut other = Nes::new();
+        let mut program = vec![0xea; 0x4000];
+        program[0x3ffc] = 0x00;
+        other.insert_cartridge(Cartridge::new(&test_rom(&program)).unwrap());
+        let peer = thread::spawn(move || Netplay::join(addr, &mut other).err());
+        assert!(Netplay::host(&listener, &nes, 0).is_err());
+        assert!(peer.join().unwrap().unwrap().contains("another game"));
+    }
+}
@@ -0,0 +1,224 @@
+use alloc::vec;
+use alloc::vec::Vec;
+use core::f32::consts::PI;
+use crate::math;
+use crate::frame::{Frame, HEIGHT, WIDTH};
+
+pub const NTSC_WIDTH: usize = 602;
+
+// composite voltages of the 2C02 relative to sync, low and high for each luma level
+const LEVELS: [f32; 8] = [0.350, 0.518, 0.962, 1.550, 1.094, 1.506, 1.962, 1.962];
+const BLACK: f32 = 0.518;
+const WHITE: f32 = 1.962;
+const ATTENUATION: f32 = 0.746;
+
+// the PPU outputs 8 samples of a 12 phase color subcarrier per pixel
+const SAMPLES_PER_PIXEL: usize = 8;
+const PHASES: usize = 12;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NtscSettings {
+    /// Hue rotation in degrees.
+    pub hue: f32,
+    pub saturation: f32,
+    pub brightness: f32,
+    /// Move the subcarrier phase from frame to frame like the real signal
+    /// does; turning it off gives a stable picture with fixed artifacts.
+    pub crawl: bool,
+}
+
+impl Default for NtscSettings {
+    fn default() -> Self {
+        NtscSettings {
+            hue: 0.0,
+            saturation: 1.0,
+            brightness: 1.0,
+            crawl: true,
+        }
+    }
+}
+
+/// Post-processing stage that encodes a frame into a simulated composite
+/// signal and decodes it again, producing the color bleeding, fringing and
+/// dot crawl of a TV hooked up over composite. Output is 602x240 RGBA.
+pub struct NtscFilter {
+    pub settings: NtscSettings,
+    pixels: Vec<u8>,
+    samples: Vec<f32>,
+}
+
+impl Default for NtscFilter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// normalized level of the signal for a PPU color at one subcarrier phase
+fn signal(index: u16, phase: usize) -> f32 {
+    let color = (index & 0x0f) as usize;
+    let level = if color > 13 { 1 } else { ((index >> 4) & 0b11) as usize };
+    let emphasis = index >> 6;
+
+    let mut low = LEVELS[level];
+    let mut high = LEVELS[4 + level];
+    if color == 0 {
+        low = high;
+    }
+    if color > 12 {
+        high = low;
+    }
+
+    let in_phase = |color: usize| (color + phase) % PHASES < 6;
+    let mut value = if in_phase(color) { high } else { low };
+    if (emphasis & 0b001 != 0 && in_phase(0))
+        || (emphasis & 0b010 != 0 && in_phase(4))
+        || (emphasis & 0b100 != 0 && in_phase(8))
+    {
+        value *= ATTENUATION;
+    }
+
+    (value - BLACK) / (WHITE - BLACK)
+}
+
+impl NtscFilter {
+    pub fn new() -> Self {
+        NtscFilter {
+            settings: NtscSettings::default(),
+            pixels: vec![0; NTSC_WIDTH * HEIGHT * 4],
+            samples: vec![0.0; WIDTH * SAMPLES_PER_PIXEL],
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        NTSC_WIDTH
+    }
+
+    pub fn height(&self) -> usize {
+        HEIGHT
+    }
+
+    /// Runs the frame through the filter; the returned RGBA buffer is reused
+    /// by the next call.
+    pub fn apply(&mut self, frame: &Frame) -> &[u8] {
+        // reference waves for the I and Q axes, lined up with the color burst
+        let hue = self.settings.hue * PI / 180.0;
+        let mut wave_i = [0.0; PHASES];
+        let mut wave_q = [0.0; PHASES];
+        for p in 0..PHASES {
+            let angle = PI * p as f32 / 6.0 + hue;
+            wave_i[p] = math::cosf(angle + 93.0 * PI / 180.0);
+            wave_q[p] = math::cosf(angle + 3.0 * PI / 180.0);
+        }
+
+        // every scanline and every frame starts 4 samples further into the subcarrier
+        let frame_phase = if self.settings.crawl { (frame.number() as usize * 4) % PHASES } else { 0 };
+        let indices = frame.indices();
+        let count = self.samples.len();
+
+        for y in 0..HEIGHT {
+            let line_phase = (frame_phase + y * 4) % PHASES;
+            for x in 0..WIDTH {
+                let index = indices[y * WIDTH + x];
+                for s in 0..SAMPLES_PER_PIXEL {
+                    let pos = x * SAMPLES_PER_PIXEL + s;
+                    self.samples[pos] = signal(index, (line_phase + pos) % PHASES);
+                }
+            }
+
+            for col in 0..NTSC_WIDTH {
+                let center = ((col * 2 + 1) * count) / (NTSC_WIDTH * 2);
+                let (mut luma, mut i, mut q) = (0.0, 0.0, 0.0);
+                for k in 0..PHASES {
+                    let pos = (center + k).saturating_sub(PHASES / 2).min(count - 1);
+                    let level = self.samples[pos];
+                    let p = (line_phase + pos) % PHASES;
+                    luma += level;
+                    i += level * wave_i[p];
+                    q += level * wave_q[p];
+                }
+                let luma = luma / PHASES as f32;
+                let i = i / PHASES as f32 * 2.0 * self.settings.saturation;
+                let q = q / PHASES as f32 * 2.0 * self.settings.saturation;
+
+                let to_byte = |v: f32| math::roundf(v * self.settings.brightness * 255.0).clamp(0.0, 255.0) as u8;
+                let base = (y * NTSC_WIDTH + col) * 4;
+                self.pixels[base] = to_byte(luma + 0.956 * i + 0.621 * q);
+                self.pixels[base + 1] = to_byte(luma - 0.272 * i - 0.647 * q);
+                self.pixels[base + 2] = to_byte(luma - 1.106 * i + 1.703 * q);
+                self.pixels[base + 3] = 0xff;
+            }
+        }
+
+        &self.pixels
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn solid_frame(index: u16) -> Frame {
+        let mut frame = Frame::new();
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                frame.set_pixel_index(x, y, index);
+            }
+        }
+        frame
+    }
+
+    fn pixel(pixels: &[u8], x: usize, y: usize) -> (u8, u8, u8) {
+        let base = (y * NTSC_WIDTH + x) * 4;
+        (pixels[base], pixels[base + 1], pixels[base + 2])
+    }
+
+    #[test]
+    fn test_output_size() {
+        let mut filter = NtscFilter::new();
+        let pixels = filter.apply(&Frame::new());
+        assert_eq!(pixels.len(), 602 * 240 * 4);
+    }
+
+    #[test]
+    fn test_greys_stay_grey() {
+        let mut filter = NtscFilter::new();
+        let (r, g, b) = pixel(filter.apply(&solid_frame(0x20)), 300, 100);
+        assert_eq!((r, g, b), (0xff, 0xff, 0xff));
+        let (r, g, b) = pixel(filter.apply(&solid_frame(0x10)), 300, 100);
+        assert_eq!((r, g), (g, b));
+    }
+
+    #[test]
+    fn test_hues() {
+        let mut filter = NtscFilter::new();
+        let (r, g, b) = pixel(filter.apply(&solid_frame(0x16)), 300, 100);
+        assert!(r > g && r > b, "red came out as {:?}", (r, g, b));
+        let (r, g, b) = pixel(filter.apply(&solid_frame(0x12)), 300, 100);
+        assert!(b > r && b > g, "blue came out as {:?}", (r, g, b));
+        let (r, g, b) = pixel(filter.apply(&solid_frame(0x1a)), 300, 100);
+        assert!(g > r && g > b, "green came out as {:?}", (r, g, b));
+    }
+
+    #[test]
+    fn test_edges_fringe_and_crawl() {
+        let mut frame = Frame::new();
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                frame.set_pixel_index(x, y, if x % 2 == 0 { 0x30 } else { 0x0f });
+            }
+        }
+        let mut filter = NtscFilter::new();
+        let first = filter.apply(&frame).to_vec();
+        frame.finish();
+        let second = filter.apply(&frame).to_vec();
+        assert_ne!(first, second);
+
+        let (r, g, b) = pixel(&first, 300, 100);
+        assert!(r != g || g != b);
+
+        filter.settings.crawl = false;
+        let still = filter.apply(&frame).to_vec();
+        frame.finish();
+        assert_eq!(still, filter.apply(&frame));
+    }
+}
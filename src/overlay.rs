@@ -0,0 +1,145 @@
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use crate::frame::{Frame, HEIGHT, WIDTH};
+
+/// An RGBA color; alpha 0 is see-through.
+pub type Color = [u8; 4];
+
+/// Text for the frontend to draw over the picture in its own font.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Text {
+    pub x: i32,
+    pub y: i32,
+    pub text: String,
+    pub color: Color,
+    pub background: Color,
+}
+
+/// A see-through layer the size of the picture for scripts and tools to
+/// draw on, shown over each frame. Drawing off the edges is clipped.
+#[derive(Debug, Clone)]
+pub struct Overlay {
+    pixels: Vec<u8>,
+    texts: Vec<Text>,
+}
+
+impl Default for Overlay {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Overlay {
+    pub fn new() -> Self {
+        Overlay { pixels: vec![0; WIDTH * HEIGHT * 4], texts: Vec::new() }
+    }
+
+    /// RGBA, a row at a time like `Frame::pixels`.
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    pub fn texts(&self) -> &[Text] {
+        &self.texts
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.texts.is_empty() && self.pixels.chunks(4).all(|pixel| pixel[3] == 0)
+    }
+
+    pub fn clear(&mut self) {
+        self.pixels.fill(0);
+        self.texts.clear();
+    }
+
+    pub fn pixel(&mut self, x: i32, y: i32, color: Color) {
+        if (0..WIDTH as i32).contains(&x) && (0..HEIGHT as i32).contains(&y) {
+            let base = (y as usize * WIDTH + x as usize) * 4;
+            self.pixels[base..base + 4].copy_from_slice(&color);
+        }
+    }
+
+    pub fn line(&mut self, x1: i32, y1: i32, x2: i32, y2: i32, color: Color) {
+        // Bresenham
+        let (dx, dy) = ((x2 - x1).abs(), -(y2 - y1).abs());
+        let (step_x, step_y) = ((x2 - x1).signum(), (y2 - y1).signum());
+        let (mut x, mut y, mut error) = (x1, y1, dx + dy);
+        loop {
+            self.pixel(x, y, color);
+            if x == x2 && y == y2 {
+                break;
+            }
+            let doubled = 2 * error;
+            if doubled >= dy {
+                error += dy;
+                x += step_x;
+            }
+            if doubled <= dx {
+                error += dx;
+                y += step_y;
+            }
+        }
+    }
+
+    /// A box with corners at both points, filled with `fill` inside an `outline`.
+    pub fn rect(&mut self, x1: i32, y1: i32, x2: i32, y2: i32, fill: Color, outline: Color) {
+        let (left, right) = (x1.min(x2), x1.max(x2));
+        let (top, bottom) = (y1.min(y2), y1.max(y2));
+        for y in top + 1..bottom {
+            for x in left + 1..right {
+                self.pixel(x, y, fill);
+            }
+        }
+        self.line(left, top, right, top, outline);
+        self.line(left, bottom, right, bottom, outline);
+        self.line(left, top, left, bottom, outline);
+        self.line(right, top, right, bottom, outline);
+    }
+
+    pub fn text(&mut self, x: i32, y: i32, text: &str, color: Color, background: Color) {
+        self.texts.push(Text { x, y, text: text.to_string(), color, background });
+    }
+
+    /// Mixes the drawn pixels into an RGBA `frame`, by their alpha. Text is
+    /// left to the frontend.
+    pub fn blend_onto(&self, frame: &mut Frame) {
+        for (index, pixel) in self.pixels.chunks(4).enumerate() {
+            let alpha = pixel[3] as u16;
+            if alpha == 0 {
+                continue;
+            }
+            let (x, y) = (index % WIDTH, index / WIDTH);
+            let (r, g, b) = frame.pixel(x, y);
+            let mix = |over: u8, under: u8| ((over as u16 * alpha + under as u16 * (255 - alpha)) / 255) as u8;
+            frame.set_pixel(x, y, (mix(pixel[0], r), mix(pixel[1], g), mix(pixel[2], b)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const RED: Color = [0xff, 0, 0, 0xff];
+
+    #[test]
+    fn test_draw() {
+        let mut overlay = Overlay::new();
+        assert!(overlay.is_empty());
+        overlay.line(0, 0, 3, 1, RED);
+        let lit = |overlay: &Overlay| (0..HEIGHT as i32).flat_map(|y| (0..WIDTH as i32).map(move |x| (x, y))).filter(|&(x, y)| overlay.pixels[(y as usize * WIDTH + x as usize) * 4 + 3] != 0).collect::<Vec<_>>();
+        assert_eq!(lit(&overlay), vec![(0, 0), (1, 0), (2, 1), (3, 1)]);
+
+        overlay.clear();
+        overlay.rect(10, 10, 12, 12, [0; 4], RED);
+        assert_eq!(lit(&overlay).len(), 8);
+        overlay.pixel(-1, 500, RED);
+        assert_eq!(lit(&overlay).len(), 8);
+
+        let mut frame = Frame::new();
+        overlay.blend_onto(&mut frame);
+        assert_eq!(frame.pixel(10, 10), (0xff, 0, 0));
+        assert_eq!(frame.pixel(11, 11), (0, 0, 0));
+    }
+}
@@ -0,0 +1,310 @@
+use super::registers::*;
+use super::PPU;
+use alloc::vec;
+use alloc::vec::Vec;
+use crate::frame::{HEIGHT, WIDTH};
+
+pub const NAMETABLES_WIDTH: usize = 512;
+pub const NAMETABLES_HEIGHT: usize = 480;
+pub const PATTERN_TABLE_SIZE: usize = 128;
+
+/// All four nametables laid out 2x2, as the PPU would fetch them right now.
+pub struct NametableView {
+    /// RGBA pixels, 512x480.
+    pub pixels: Vec<u8>,
+    /// Top left corner of the visible screen inside the view; the 256x240
+    /// rectangle wraps around the edges.
+    pub scroll_x: u16,
+    pub scroll_y: u16,
+}
+
+/// One decoded OAM entry.
+pub struct SpriteInfo {
+    pub index: u8,
+    pub x: u8,
+    pub y: u8,
+    pub tile: u8,
+    pub palette: u8,
+    pub behind_background: bool,
+    pub flip_horizontal: bool,
+    pub flip_vertical: bool,
+    pub height: usize,
+    /// RGBA thumbnail, 8 pixels wide and `height` tall; transparent pixels have zero alpha.
+    pub pixels: Vec<u8>,
+}
+
+/// The frame being drawn, as far as the PPU has gotten.
+pub struct PartialFrame {
+    /// RGBA pixels, 256x240. Whatever the beam hasn't reached yet still shows
+    /// the previous frame, at half brightness.
+    pub pixels: Vec<u8>,
+    /// The next pixel the PPU is going to output, if it is in the visible area.
+    pub beam: Option<(usize, usize)>,
+}
+
+pub(crate) fn put_pixel(buffer: &mut [u8], width: usize, x: usize, y: usize, rgb: (u8, u8, u8)) {
+    let base = (y * width + x) * 4;
+    buffer[base] = rgb.0;
+    buffer[base + 1] = rgb.1;
+    buffer[base + 2] = rgb.2;
+    buffer[base + 3] = 0xff;
+}
+
+impl PPU {
+    // 2-bit color values of the 8x8 tile with its pattern at addr
+    pub(crate) fn tile_pixels(&self, addr: u16) -> [[u8; 8]; 8] {
+        let mut pixels = [[0; 8]; 8];
+        for (row, line) in pixels.iter_mut().enumerate() {
+            let lo = self.read_vram(addr + row as u16);
+            let hi = self.read_vram(addr + row as u16 + 8);
+            for (col, value) in line.iter_mut().enumerate() {
+                let bit = 7 - col;
+                *value = (((hi >> bit) & 1) << 1) | ((lo >> bit) & 1);
+            }
+        }
+        pixels
+    }
+
+    // RGB of a 2-bit tile value using one of the eight palettes
+    pub(crate) fn palette_rgb(&self, palette: u8, value: u8) -> (u8, u8, u8) {
+        let entry = if value == 0 { 0 } else { palette as u16 * 4 + value as u16 };
+        let color = self.read_vram(0x3f00 + entry);
+        self.palette.color(color as u16)
+    }
+
+    pub fn debug_nametables(&self) -> NametableView {
+        let mut pixels = vec![0; NAMETABLES_WIDTH * NAMETABLES_HEIGHT * 4];
+        let bank = if self.ctrl & CTRL_BACKGROUND_PATTERN != 0 { 0x1000 } else { 0 };
+
+        for table in 0..4u16 {
+            let base = 0x2000 + table * 0x400;
+            let origin_x = (table as usize % 2) * 256;
+            let origin_y = (table as usize / 2) * 240;
+            for tile_y in 0..30u16 {
+                for tile_x in 0..32u16 {
+                    let tile = self.read_vram(base + tile_y * 32 + tile_x) as u16;
+                    let attr = self.read_vram(base + 0x3c0 + (tile_y / 4) * 8 + tile_x / 4);
+                    let shift = ((tile_y % 4) / 2) * 4 + ((tile_x % 4) / 2) * 2;
+                    let palette = (attr >> shift) & 0b11;
+                    let tile = self.tile_pixels(bank + tile * 16);
+                    for (row, line) in tile.iter().enumerate() {
+                        for (col, value) in line.iter().enumerate() {
+                            let x = origin_x + tile_x as usize * 8 + col;
+                            let y = origin_y + tile_y as usize * 8 + row;
+                            put_pixel(&mut pixels, NAMETABLES_WIDTH, x, y, self.palette_rgb(palette, *value));
+                        }
+                    }
+                }
+            }
+        }
+
+        // t holds the scroll position the next frame starts from
+        let scroll_x = ((self.t & 0x001f) << 3) + self.fine_x as u16 + if self.t & 0x0400 != 0 { 256 } else { 0 };
+        let scroll_y = (((self.t >> 5) & 0x001f) << 3) + ((self.t >> 12) & 0b111) + if self.t & 0x0800 != 0 { 240 } else { 0 };
+
+        NametableView {
+            pixels,
+            scroll_x,
+            scroll_y,
+        }
+    }
+
+    /// Both pattern tables as 16x16 grids of tiles, 128x128 RGBA each,
+    /// colored with one of the eight palettes (4-7 are the sprite ones).
+    pub fn debug_pattern_tables(&self, palette_index: u8) -> [Vec<u8>; 2] {
+        let palette = palette_index & 0b111;
+        let render = |bank: u16| {
+            let mut pixels = vec![0; PATTERN_TABLE_SIZE * PATTERN_TABLE_SIZE * 4];
+            for tile in 0..256u16 {
+                let pattern = self.tile_pixels(bank + tile * 16);
+                for (row, line) in pattern.iter().enumerate() {
+                    for (col, value) in line.iter().enumerate() {
+                        let x = (tile as usize % 16) * 8 + col;
+                        let y = (tile as usize / 16) * 8 + row;
+                        put_pixel(&mut pixels, PATTERN_TABLE_SIZE, x, y, self.palette_rgb(palette, *value));
+                    }
+                }
+            }
+            pixels
+        };
+        [render(0x0000), render(0x1000)]
+    }
+
+    /// Everything output so far this frame, for looking at the picture while
+    /// the PPU is stopped in the middle of it.
+    pub fn debug_partial_frame(&self) -> PartialFrame {
+        let (drawn, beam) = if self.scanline < HEIGHT as u16 {
+            let x = (self.dot.saturating_sub(1) as usize).min(WIDTH);
+            let y = self.scanline as usize;
+            (y * WIDTH + x, if x < WIDTH { Some((x, y)) } else { None })
+        } else {
+            (WIDTH * HEIGHT, None)
+        };
+
+        let mut pixels = vec![0; WIDTH * HEIGHT * 4];
+        for (i, color) in self.back.iter().enumerate() {
+            let (r, g, b) = self.palette.color(*color);
+            let rgb = if i < drawn { (r, g, b) } else { (r / 2, g / 2, b / 2) };
+            put_pixel(&mut pixels, WIDTH, i % WIDTH, i / WIDTH, rgb);
+        }
+        PartialFrame { pixels, beam }
+    }
+
+    /// All 64 sprites as currently stored in OAM.
+    pub fn debug_sprites(&self) -> Vec<SpriteInfo> {
+        let height = self.sprite_height() as usize;
+        (0..64)
+            .map(|i| {
+                let entry = &self.oam_data[i * 4..i * 4 + 4];
+                let attr = entry[2];
+                let flip_horizontal = attr & 0b0100_0000 != 0;
+                let flip_vertical = attr & 0b1000_0000 != 0;
+                let palette = (attr & 0b11) + 4;
+
+                let mut pixels = vec![0; 8 * height * 4];
+                for y in 0..height {
+                    let row = if flip_vertical { height - 1 - y } else { y };
+                    let addr = self.sprite_pattern_addr(entry[1], row as u16);
+                    let lo = self.read_vram(addr);
+                    let hi = self.read_vram(addr + 8);
+                    for x in 0..8 {
+                        let bit = if flip_horizontal { x } else { 7 - x };
+                        let value = (((hi >> bit) & 1) << 1) | ((lo >> bit) & 1);
+                        if value != 0 {
+                            put_pixel(&mut pixels, 8, x, y, self.palette_rgb(palette, value));
+                        }
+                    }
+                }
+
+                SpriteInfo {
+                    index: i as u8,
+                    x: entry[3],
+                    y: entry[0],
+                    tile: entry[1],
+                    palette,
+                    behind_background: attr & 0b0010_0000 != 0,
+                    flip_horizontal,
+                    flip_vertical,
+                    height,
+                    pixels,
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ppu::Mirroring;
+
+    fn pixel(view: &[u8], width: usize, x: usize, y: usize) -> (u8, u8, u8) {
+        let base = (y * width + x) * 4;
+        (view[base], view[base + 1], view[base + 2])
+    }
+
+    #[test]
+    fn test_nametables_follow_mirroring() {
+        let mut chr = vec![0; 0x2000];
+        for i in 0..8 {
+            chr[16 + i] = 0xff;
+        }
+        let mut ppu = PPU::new(chr, Mirroring::Vertical);
+        ppu.palette_table[0] = 0x0f;
+        ppu.palette_table[1] = 0x30;
+        // top left tile of nametable 1, mirrored into nametable 3
+        ppu.vram[0x400] = 1;
+
+        let view = ppu.debug_nametables();
+        let colors = ppu.palette();
+        assert_eq!(view.pixels.len(), 512 * 480 * 4);
+        assert_eq!(pixel(&view.pixels, 512, 256, 0), colors.color(0x30));
+        assert_eq!(pixel(&view.pixels, 512, 256, 240), colors.color(0x30));
+        assert_eq!(pixel(&view.pixels, 512, 0, 0), colors.color(0x0f));
+    }
+
+    #[test]
+    fn test_pattern_tables() {
+        let mut chr = vec![0; 0x2000];
+        // tile $11 of the right table has its top row in color 3
+        chr[0x1000 + 0x11 * 16] = 0xff;
+        chr[0x1000 + 0x11 * 16 + 8] = 0xff;
+        let mut ppu = PPU::new(chr, Mirroring::Vertical);
+        ppu.palette_table[0] = 0x0f;
+        ppu.palette_table[0x13] = 0x16;
+
+        let [left, right] = ppu.debug_pattern_tables(4);
+        let colors = ppu.palette();
+        assert_eq!(left.len(), 128 * 128 * 4);
+        assert_eq!(pixel(&left, 128, 8, 8), colors.color(0x0f));
+        assert_eq!(pixel(&right, 128, 8, 8), colors.color(0x16));
+        assert_eq!(pixel(&right, 128, 15, 8), colors.color(0x16));
+        assert_eq!(pixel(&right, 128, 8, 9), colors.color(0x0f));
+    }
+
+    #[test]
+    fn test_sprites_are_decoded() {
+        let mut chr = vec![0; 0x2000];
+        // tile 2 has a single color 1 pixel in its top left corner
+        chr[2 * 16] = 0x80;
+        let mut ppu = PPU::new(chr, Mirroring::Vertical);
+        ppu.palette_table[0x15] = 0x27;
+        ppu.oam_data[4..8].copy_from_slice(&[0x30, 0x02, 0b0110_0001, 0x40]);
+
+        let sprites = ppu.debug_sprites();
+        assert_eq!(sprites.len(), 64);
+        let sprite = &sprites[1];
+        assert_eq!((sprite.x, sprite.y, sprite.tile, sprite.palette), (0x40, 0x30, 0x02, 5));
+        assert!(sprite.flip_horizontal);
+        assert!(!sprite.flip_vertical);
+        assert!(sprite.behind_background);
+        assert_eq!(sprite.height, 8);
+        // flipped, so the pixel ends up in the top right corner
+        assert_eq!(pixel(&sprite.pixels, 8, 7, 0), ppu.palette().color(0x27));
+        assert_eq!(sprite.pixels[3], 0);
+        assert_eq!(sprite.pixels[7 * 4 + 3], 0xff);
+    }
+
+    #[test]
+    fn test_tall_sprite_thumbnails() {
+        let mut ppu = PPU::new_empty_rom();
+        ppu.write_to_ctrl(CTRL_SPRITE_SIZE);
+        let sprites = ppu.debug_sprites();
+        assert_eq!(sprites[0].height, 16);
+        assert_eq!(sprites[0].pixels.len(), 8 * 16 * 4);
+    }
+
+    #[test]
+    fn test_partial_frame_stops_at_the_beam() {
+        let mut ppu = PPU::new_empty_rom();
+        ppu.palette_table[0] = 0x30;
+        ppu.write_to_mask(MASK_SHOW_BACKGROUND);
+        // run to scanline 100, dot 11: pixels 0-9 of that line are out
+        for _ in 0..100 {
+            ppu.tick(341);
+        }
+        ppu.tick(11);
+
+        let partial = ppu.debug_partial_frame();
+        let white = ppu.palette().color(0x30);
+        assert_eq!(partial.beam, Some((10, 100)));
+        assert_eq!(pixel(&partial.pixels, 256, 255, 99), white);
+        assert_eq!(pixel(&partial.pixels, 256, 9, 100), white);
+        assert_ne!(pixel(&partial.pixels, 256, 10, 100), white);
+
+        ppu.tick(300);
+        assert_eq!(ppu.debug_partial_frame().beam, None);
+    }
+
+    #[test]
+    fn test_nametable_scroll_rectangle() {
+        let mut ppu = PPU::new_empty_rom();
+        ppu.write_to_ctrl(0b11);
+        ppu.write_to_scroll(13);
+        ppu.write_to_scroll(42);
+
+        let view = ppu.debug_nametables();
+        assert_eq!(view.scroll_x, 256 + 13);
+        assert_eq!(view.scroll_y, 240 + 42);
+    }
+}
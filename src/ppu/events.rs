@@ -0,0 +1,92 @@
+use super::PPU;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+/// What happened, for an event viewer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PpuEventKind {
+    /// A CPU write to a PPU or APU/IO register, PPU ones folded onto $2000-$2007.
+    RegisterWrite { addr: u16, value: u8 },
+    /// The PPU raised NMI, at vblank or by enabling it during vblank.
+    Nmi,
+    /// The first sprite 0 hit of the frame.
+    SpriteZeroHit,
+    /// The CPU took an IRQ.
+    Irq,
+}
+
+/// An event and where the PPU was when it happened.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PpuEvent {
+    /// The pre-render line is the region's last, 261 on NTSC, not -1.
+    pub scanline: u16,
+    pub dot: u16,
+    pub kind: PpuEventKind,
+}
+
+/// Events of the frame being drawn and of the one before, for Mesen-style
+/// event viewers. Frames go from the start of the pre-render line, so the
+/// vblank a frame ends in stays with it.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct EventLog {
+    current: Vec<PpuEvent>,
+    last: Vec<PpuEvent>,
+}
+
+impl PPU {
+    /// Starts or stops recording events, see `last_frame_events`.
+    pub fn set_event_log(&mut self, enabled: bool) {
+        self.events = enabled.then(Box::default);
+    }
+
+    pub fn is_logging_events(&self) -> bool {
+        self.events.is_some()
+    }
+
+    /// Every event of the last whole frame, in order. Empty unless
+    /// `set_event_log` turned recording on.
+    pub fn last_frame_events(&self) -> &[PpuEvent] {
+        self.events.as_ref().map_or(&[], |log| &log.last)
+    }
+
+    /// The events so far of the frame being drawn.
+    pub fn frame_events(&self) -> &[PpuEvent] {
+        self.events.as_ref().map_or(&[], |log| &log.current)
+    }
+
+    pub(crate) fn record_event(&mut self, kind: PpuEventKind) {
+        let (scanline, dot) = (self.scanline, self.dot);
+        if let Some(log) = &mut self.events {
+            log.current.push(PpuEvent { scanline, dot, kind });
+        }
+    }
+
+    // at the start of the pre-render line
+    pub(crate) fn roll_event_log(&mut self) {
+        if let Some(log) = &mut self.events {
+            log.last = core::mem::take(&mut log.current);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ppu::registers::CTRL_GENERATE_NMI;
+
+    #[test]
+    fn test_event_log() {
+        let mut ppu = PPU::new_empty_rom();
+        ppu.record_event(PpuEventKind::Irq);
+        assert!(ppu.frame_events().is_empty());
+
+        ppu.set_event_log(true);
+        ppu.write_to_ctrl(CTRL_GENERATE_NMI);
+        // through vblank to the end of the pre-render line
+        for _ in 0..262 {
+            ppu.tick(341);
+        }
+        assert_eq!(ppu.last_frame_events(), &[PpuEvent { scanline: 241, dot: 1, kind: PpuEventKind::Nmi }]);
+        assert!(ppu.frame_events().is_empty());
+    }
+}
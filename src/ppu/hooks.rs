@@ -0,0 +1,170 @@
+use super::PPU;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+pub type DotCallback = Box<dyn FnMut(&mut PPU) + Send>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct HookId(usize);
+
+pub(crate) struct DotHook {
+    id: HookId,
+    scanline: u16,
+    dot: u16,
+    // ignores scanline and fires on all of them
+    every_scanline: bool,
+    callback: DotCallback,
+}
+
+#[derive(Default)]
+pub(crate) struct Hooks {
+    hooks: Vec<DotHook>,
+    next_id: usize,
+}
+
+// callbacks can't be copied, so a cloned PPU starts out without hooks
+impl Clone for Hooks {
+    fn clone(&self) -> Self {
+        Hooks { hooks: Vec::new(), next_id: self.next_id }
+    }
+}
+
+impl Hooks {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.hooks.is_empty()
+    }
+}
+
+impl PPU {
+    /// Calls `callback` every frame when the PPU is about to run the given dot.
+    pub fn add_dot_hook<F>(&mut self, scanline: u16, dot: u16, callback: F) -> HookId
+    where
+        F: FnMut(&mut PPU) + Send + 'static,
+    {
+        self.push_hook(scanline, dot, false, Box::new(callback))
+    }
+
+    /// Calls `callback` at the start (dot 0) of the given scanline.
+    pub fn add_scanline_hook<F>(&mut self, scanline: u16, callback: F) -> HookId
+    where
+        F: FnMut(&mut PPU) + Send + 'static,
+    {
+        self.push_hook(scanline, 0, false, Box::new(callback))
+    }
+
+    /// Calls `callback` on every scanline when the PPU is about to run the given dot.
+    pub fn add_every_scanline_hook<F>(&mut self, dot: u16, callback: F) -> HookId
+    where
+        F: FnMut(&mut PPU) + Send + 'static,
+    {
+        self.push_hook(0, dot, true, Box::new(callback))
+    }
+
+    /// Trades hooks with `other`, for putting a saved copy of the PPU in
+    /// place without losing the callbacks.
+    pub(crate) fn swap_hooks(&mut self, other: &mut PPU) {
+        core::mem::swap(&mut self.hooks, &mut other.hooks);
+    }
+
+    pub fn remove_hook(&mut self, id: HookId) -> bool {
+        let before = self.hooks.hooks.len();
+        self.hooks.hooks.retain(|hook| hook.id != id);
+        before != self.hooks.hooks.len()
+    }
+
+    fn push_hook(&mut self, scanline: u16, dot: u16, every_scanline: bool, callback: DotCallback) -> HookId {
+        let id = HookId(self.hooks.next_id);
+        self.hooks.next_id += 1;
+        self.hooks.hooks.push(DotHook {
+            id,
+            scanline,
+            dot,
+            every_scanline,
+            callback,
+        });
+        id
+    }
+
+    pub(crate) fn run_hooks(&mut self) {
+        let (scanline, dot) = (self.scanline, self.dot);
+        if !self.hooks.hooks.iter().any(|h| h.dot == dot && (h.every_scanline || h.scanline == scanline)) {
+            return;
+        }
+
+        // callbacks get the whole PPU, so the list is moved out while they run
+        let mut hooks = core::mem::take(&mut self.hooks.hooks);
+        for hook in hooks.iter_mut() {
+            if hook.dot == dot && (hook.every_scanline || hook.scanline == scanline) {
+                (hook.callback)(self);
+            }
+        }
+        // keep whatever the callbacks registered in the meantime
+        hooks.append(&mut self.hooks.hooks);
+        self.hooks.hooks = hooks;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    fn run_frame(ppu: &mut PPU) {
+        for _ in 0..262 {
+            ppu.tick(341);
+        }
+    }
+
+    #[test]
+    fn test_dot_hook_fires_once_per_frame() {
+        let mut ppu = PPU::new_empty_rom();
+        let seen = Arc::new(AtomicUsize::new(0));
+        let counter = seen.clone();
+        ppu.add_dot_hook(100, 5, move |ppu| {
+            assert_eq!((ppu.scanline(), ppu.dot()), (100, 5));
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+
+        run_frame(&mut ppu);
+        run_frame(&mut ppu);
+        assert_eq!(seen.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_every_scanline_hook() {
+        let mut ppu = PPU::new_empty_rom();
+        let seen = Arc::new(AtomicUsize::new(0));
+        let counter = seen.clone();
+        ppu.add_every_scanline_hook(0, move |_| {
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+
+        run_frame(&mut ppu);
+        assert_eq!(seen.load(Ordering::SeqCst), 262);
+    }
+
+    #[test]
+    fn test_hook_can_change_ppu_state() {
+        let mut ppu = PPU::new_empty_rom();
+        ppu.add_scanline_hook(120, |ppu| ppu.palette_table[0] = 0x30);
+        run_frame(&mut ppu);
+        assert_eq!(ppu.frame().pixel(0, 119), ppu.palette().color(0x00));
+        assert_eq!(ppu.frame().pixel(0, 120), ppu.palette().color(0x30));
+    }
+
+    #[test]
+    fn test_remove_hook() {
+        let mut ppu = PPU::new_empty_rom();
+        let seen = Arc::new(AtomicUsize::new(0));
+        let counter = seen.clone();
+        let id = ppu.add_scanline_hook(10, move |_| {
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+
+        assert!(ppu.remove_hook(id));
+        assert!(!ppu.remove_hook(id));
+        run_frame(&mut ppu);
+        assert_eq!(seen.load(Ordering::SeqCst), 0);
+    }
+}
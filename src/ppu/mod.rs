@@ -0,0 +1,1396 @@
+pub mod debug;
+pub mod events;
+pub mod hooks;
+pub mod palette;
+pub mod registers;
+
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+use crate::bytes;
+use crate::frame::{Frame, PixelFormat, HEIGHT, WIDTH};
+use crate::region::Region;
+use crate::rng::Rng;
+use events::{EventLog, PpuEventKind};
+use hooks::Hooks;
+use palette::Palette;
+use registers::*;
+
+pub const DOTS_PER_SCANLINE: u16 = 341;
+// NTSC frame layout, see Region for the others
+pub const SCANLINES_PER_FRAME: u16 = 262;
+pub const VBLANK_SCANLINE: u16 = 241;
+pub const PRE_RENDER_SCANLINE: u16 = 261;
+
+// roughly 600ms, after which an undriven bit of the data bus has discharged
+pub const DEFAULT_OPEN_BUS_DECAY_FRAMES: u64 = 36;
+
+// scanlines a Zapper's photodiode keeps seeing a pixel after the beam drew it
+const LIGHT_SENSE_LINES: usize = 20;
+// luma (0-255) a pixel needs to trip the photodiode
+const LIGHT_SENSE_LUMA: f32 = 128.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PpuConfig {
+    /// Frames a bit of the open bus latch holds its value without being
+    /// refreshed; `None` keeps it forever.
+    pub open_bus_decay: Option<u64>,
+    /// Copy the OAM row OAMADDR points at over the first row when rendering
+    /// starts with OAMADDR >= 8, like the 2C02G does.
+    pub oam_corruption: bool,
+    /// Sprites drawn per scanline, 8 on hardware; `None` draws all of them
+    /// to get rid of flicker.
+    pub sprite_limit: Option<usize>,
+    /// Scanlines the PPU and APU stand still for right after the NMI while
+    /// the CPU keeps going, giving games extra vblank time to cut slowdown.
+    /// 0 on hardware.
+    pub overclock_scanlines: u16,
+}
+
+impl Default for PpuConfig {
+    fn default() -> Self {
+        PpuConfig {
+            open_bus_decay: Some(DEFAULT_OPEN_BUS_DECAY_FRAMES),
+            oam_corruption: false,
+            sprite_limit: Some(8),
+            overclock_scanlines: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Mirroring {
+    Vertical,
+    Horizontal,
+    FourScreen,
+}
+
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PPU {
+    pub config: PpuConfig,
+    region: Region,
+    pub chr_rom: Vec<u8>,
+    /// The board has RAM instead of ROM for the pattern tables, so $2007
+    /// writes to $0000-$1FFF stick. It comes with the cartridge, so save
+    /// states leave it out and keep the one loaded.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub chr_ram: bool,
+    pub mirroring: Mirroring,
+    #[cfg_attr(feature = "serde", serde(with = "serde_big_array::BigArray"))]
+    pub vram: [u8; 0x1000],
+    pub palette_table: [u8; 32],
+    #[cfg_attr(feature = "serde", serde(with = "serde_big_array::BigArray"))]
+    pub oam_data: [u8; 256],
+    pub oam_addr: u8,
+
+    pub ctrl: u8,
+    pub mask: u8,
+    pub status: u8,
+
+    // loopy registers: current/temporary vram address, fine x scroll, write toggle
+    v: u16,
+    t: u16,
+    fine_x: u8,
+    w: bool,
+    data_buffer: u8,
+
+    // last value driven on the cpu <-> ppu data bus, and the frame each bit was last refreshed in
+    open_bus: u8,
+    open_bus_refreshed: [u64; 8],
+
+    scanline: u16,
+    dot: u16,
+    odd_frame: bool,
+    nmi_pending: bool,
+    // dots left of the overclock stall
+    overclock_dots: u32,
+
+    // background fetch latches and shifters
+    next_tile_id: u8,
+    next_tile_attr: u8,
+    next_tile_lo: u8,
+    next_tile_hi: u8,
+    shifter_pattern_lo: u16,
+    shifter_pattern_hi: u16,
+    shifter_attr_lo: u16,
+    shifter_attr_hi: u16,
+
+    // sprites found for the line being drawn
+    sprite_count: usize,
+    #[cfg_attr(feature = "serde", serde(with = "serde_big_array::BigArray"))]
+    sprite_x: [u8; 64],
+    #[cfg_attr(feature = "serde", serde(with = "serde_big_array::BigArray"))]
+    sprite_attr: [u8; 64],
+    #[cfg_attr(feature = "serde", serde(with = "serde_big_array::BigArray"))]
+    sprite_pattern_lo: [u8; 64],
+    #[cfg_attr(feature = "serde", serde(with = "serde_big_array::BigArray"))]
+    sprite_pattern_hi: [u8; 64],
+    sprite_zero_on_line: bool,
+
+    // color indices of the picture being drawn, with emphasis bits above bit 6
+    back: Vec<u16>,
+    frame: Frame,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    palette: Palette,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    hooks: Hooks,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub(crate) events: Option<Box<EventLog>>,
+}
+
+impl PPU {
+    pub fn new(chr_rom: Vec<u8>, mirroring: Mirroring) -> Self {
+        PPU {
+            config: PpuConfig::default(),
+            region: Region::Ntsc,
+            chr_rom,
+            chr_ram: false,
+            mirroring,
+            vram: [0; 0x1000],
+            palette_table: [0; 32],
+            oam_data: [0; 256],
+            oam_addr: 0,
+            ctrl: 0,
+            mask: 0,
+            status: 0,
+            v: 0,
+            t: 0,
+            fine_x: 0,
+            w: false,
+            data_buffer: 0,
+            open_bus: 0,
+            open_bus_refreshed: [0; 8],
+            scanline: 0,
+            dot: 0,
+            odd_frame: false,
+            nmi_pending: false,
+            overclock_dots: 0,
+            next_tile_id: 0,
+            next_tile_attr: 0,
+            next_tile_lo: 0,
+            next_tile_hi: 0,
+            shifter_pattern_lo: 0,
+            shifter_pattern_hi: 0,
+            shifter_attr_lo: 0,
+            shifter_attr_hi: 0,
+            sprite_count: 0,
+            sprite_x: [0; 64],
+            sprite_attr: [0; 64],
+            sprite_pattern_lo: [0; 64],
+            sprite_pattern_hi: [0; 64],
+            sprite_zero_on_line: false,
+            back: vec![0; WIDTH * HEIGHT],
+            frame: Frame::new(),
+            palette: Palette::default(),
+            hooks: Hooks::default(),
+            events: None,
+        }
+    }
+
+    pub fn new_empty_rom() -> Self {
+        PPU::new(vec![0; 0x2000], Mirroring::Horizontal)
+    }
+
+    /// Soft reset: PPUCTRL, PPUMASK, the write toggle and the read buffer
+    /// are cleared. Memory and the position in the frame are kept.
+    pub fn reset(&mut self) {
+        self.ctrl = 0;
+        self.mask = 0;
+        self.w = false;
+        self.data_buffer = 0;
+        self.odd_frame = false;
+    }
+
+    /// Power cycle: everything but the cartridge, configuration, palette,
+    /// hooks and the last finished frame goes back to power-on state.
+    pub fn power_cycle(&mut self) {
+        let mut ppu = PPU::new(core::mem::take(&mut self.chr_rom), self.mirroring);
+        ppu.chr_ram = self.chr_ram;
+        ppu.config = self.config;
+        ppu.region = self.region;
+        core::mem::swap(&mut ppu.frame, &mut self.frame);
+        core::mem::swap(&mut ppu.palette, &mut self.palette);
+        core::mem::swap(&mut ppu.hooks, &mut self.hooks);
+        *self = ppu;
+    }
+
+    /// Fills what the PPU doesn't clear on power on with noise, like real
+    /// chips: nametables, palette, OAM and the open bus latch, whose bits
+    /// get different ages so they decay at different times.
+    pub fn randomize(&mut self, rng: &mut Rng) {
+        rng.fill(&mut self.vram);
+        rng.fill(&mut self.palette_table);
+        for entry in self.palette_table.iter_mut() {
+            *entry &= 0x3f;
+        }
+        rng.fill(&mut self.oam_data);
+        self.open_bus = rng.next_u64() as u8;
+        let now = self.frame.number();
+        let frames = self.config.open_bus_decay.unwrap_or(1);
+        for refreshed in self.open_bus_refreshed.iter_mut() {
+            *refreshed = now.saturating_sub(rng.below(frames));
+        }
+    }
+
+    pub fn region(&self) -> Region {
+        self.region
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+        if self.scanline >= region.scanlines_per_frame() {
+            self.scanline = 0;
+        }
+    }
+
+    pub fn scanline(&self) -> u16 {
+        self.scanline
+    }
+
+    pub fn dot(&self) -> u16 {
+        self.dot
+    }
+
+    /// The last completed picture.
+    pub fn frame(&self) -> &Frame {
+        &self.frame
+    }
+
+    /// Whether a light sensor pointed at the pixel sees light right now: it
+    /// has to be bright and drawn within the last few scanlines.
+    pub fn light_at(&self, x: usize, y: usize) -> bool {
+        if x >= WIDTH || y >= HEIGHT || !self.rendering_enabled() {
+            return false;
+        }
+        // how far the beam has gotten, in pixels since the top of the picture
+        let beam = if self.scanline < HEIGHT as u16 {
+            self.scanline as usize * WIDTH + (self.dot.saturating_sub(1) as usize).min(WIDTH)
+        } else {
+            (self.scanline as usize) * WIDTH
+        };
+        let pixel = y * WIDTH + x;
+        if pixel >= beam || beam - pixel > LIGHT_SENSE_LINES * WIDTH {
+            return false;
+        }
+        let (r, g, b) = self.palette.color(self.back[pixel]);
+        0.299 * r as f32 + 0.587 * g as f32 + 0.114 * b as f32 >= LIGHT_SENSE_LUMA
+    }
+
+    /// Moves nametables, palette, OAM, CHR and the pictures out into `out`.
+    pub(crate) fn take_memory(&mut self, out: &mut Vec<u8>) {
+        bytes::write_block(out, &core::mem::take(&mut self.chr_rom));
+        out.extend_from_slice(&self.vram);
+        out.extend_from_slice(&self.palette_table);
+        out.extend_from_slice(&self.oam_data);
+        for color in core::mem::take(&mut self.back) {
+            out.extend_from_slice(&color.to_le_bytes());
+        }
+        self.frame.take_memory(out);
+    }
+
+    /// Puts back what `take_memory` took.
+    pub(crate) fn put_memory(&mut self, data: &mut &[u8]) {
+        self.chr_rom = bytes::read_block(data).to_vec();
+        bytes::read_into(data, &mut self.vram);
+        bytes::read_into(data, &mut self.palette_table);
+        bytes::read_into(data, &mut self.oam_data);
+        let back = bytes::read_bytes(data, WIDTH * HEIGHT * 2);
+        self.back = back.chunks(2).map(|pair| u16::from_le_bytes([pair[0], pair[1]])).collect();
+        let palette = &self.palette;
+        self.frame.put_memory(data, |index| palette.color(index));
+    }
+
+    /// Drops the picture in the palette's colors and the pixel format,
+    /// keeping the PPU colors drawn.
+    #[cfg(feature = "serde")]
+    pub(crate) fn clear_output(&mut self) {
+        self.frame.clear_output();
+    }
+
+    /// Format of `frame().data()`; conversion happens once as each frame completes.
+    pub fn set_pixel_format(&mut self, format: PixelFormat) {
+        self.frame.set_format(format);
+    }
+
+    pub fn palette(&self) -> &Palette {
+        &self.palette
+    }
+
+    /// Switches the colors used for frames finished from now on.
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+    }
+
+    pub fn poll_nmi(&mut self) -> bool {
+        core::mem::take(&mut self.nmi_pending)
+    }
+
+    fn rendering_enabled(&self) -> bool {
+        self.mask & (MASK_SHOW_BACKGROUND | MASK_SHOW_SPRITES) != 0
+    }
+
+    // true while the PPU is busy fetching for the visible picture
+    fn rendering_active(&self) -> bool {
+        self.rendering_enabled() && (self.scanline < 240 || self.scanline == self.region.pre_render_scanline())
+    }
+
+    // register interface
+
+    pub fn write_to_ctrl(&mut self, value: u8) {
+        let before_nmi = self.ctrl & CTRL_GENERATE_NMI != 0;
+        self.ctrl = value;
+        self.t = (self.t & !0x0c00) | ((value as u16 & 0b11) << 10);
+        if !before_nmi && value & CTRL_GENERATE_NMI != 0 && self.status & STATUS_VBLANK != 0 {
+            self.nmi_pending = true;
+            self.record_event(PpuEventKind::Nmi);
+        }
+    }
+
+    pub fn write_to_mask(&mut self, value: u8) {
+        self.mask = value;
+    }
+
+    /// Value returned by reads of write-only registers.
+    pub fn open_bus(&self) -> u8 {
+        self.open_bus
+    }
+
+    // drives the bits selected by mask, the rest keep their latched value
+    fn refresh_open_bus(&mut self, value: u8, mask: u8) {
+        self.open_bus = (self.open_bus & !mask) | (value & mask);
+        let now = self.frame.number();
+        for (bit, refreshed) in self.open_bus_refreshed.iter_mut().enumerate() {
+            if mask & (1 << bit) != 0 {
+                *refreshed = now;
+            }
+        }
+    }
+
+    fn decay_open_bus(&mut self) {
+        if let Some(frames) = self.config.open_bus_decay {
+            let now = self.frame.number();
+            for (bit, refreshed) in self.open_bus_refreshed.iter().enumerate() {
+                if now - refreshed >= frames {
+                    self.open_bus &= !(1 << bit);
+                }
+            }
+        }
+    }
+
+    /// Any write to a PPU register fills the open bus latch.
+    pub fn latch_open_bus(&mut self, value: u8) {
+        self.refresh_open_bus(value, 0xff);
+    }
+
+    pub fn read_status(&mut self) -> u8 {
+        // only the top three bits are driven, the rest come from the latch
+        let data = (self.status & 0xe0) | (self.open_bus & 0x1f);
+        self.refresh_open_bus(data, 0xe0);
+        self.status &= !STATUS_VBLANK;
+        self.w = false;
+        data
+    }
+
+    pub fn write_to_oam_addr(&mut self, value: u8) {
+        self.oam_addr = value;
+    }
+
+    pub fn write_to_oam_data(&mut self, value: u8) {
+        if self.rendering_active() {
+            // the write is dropped, but the address takes a glitchy bump of the sprite index
+            self.oam_addr = self.oam_addr.wrapping_add(4);
+            return;
+        }
+        self.oam_data[self.oam_addr as usize] = value;
+        self.oam_addr = self.oam_addr.wrapping_add(1);
+    }
+
+    pub fn read_oam_data(&mut self) -> u8 {
+        let data = if self.rendering_active() && (1..=64).contains(&self.dot) {
+            // secondary OAM is being cleared, which reads back as $ff
+            0xff
+        } else if self.oam_addr & 0b11 == 2 {
+            // bits 2-4 of the attribute byte don't exist
+            self.oam_data[self.oam_addr as usize] & 0xe3
+        } else {
+            self.oam_data[self.oam_addr as usize]
+        };
+        self.refresh_open_bus(data, 0xff);
+        data
+    }
+
+    pub fn write_oam_dma(&mut self, data: &[u8; 256]) {
+        for x in data.iter() {
+            self.write_to_oam_data(*x);
+        }
+    }
+
+    pub fn write_to_scroll(&mut self, value: u8) {
+        if !self.w {
+            self.t = (self.t & !0x001f) | (value as u16 >> 3);
+            self.fine_x = value & 0b111;
+        } else {
+            self.t = (self.t & !0x73e0) | ((value as u16 & 0b111) << 12) | ((value as u16 & 0xf8) << 2);
+        }
+        self.w = !self.w;
+    }
+
+    pub fn write_to_ppu_addr(&mut self, value: u8) {
+        if !self.w {
+            self.t = (self.t & 0x00ff) | ((value as u16 & 0x3f) << 8);
+        } else {
+            self.t = (self.t & 0xff00) | value as u16;
+            self.v = self.t;
+        }
+        self.w = !self.w;
+    }
+
+    pub fn write_to_data(&mut self, value: u8) {
+        let addr = self.v;
+        self.write_vram(addr, value);
+        self.increment_vram_addr();
+    }
+
+    pub fn read_data(&mut self) -> u8 {
+        let addr = self.v & 0x3fff;
+        self.increment_vram_addr();
+
+        if addr >= 0x3f00 {
+            // palette reads are immediate, the buffer picks up the nametable underneath
+            self.data_buffer = self.read_vram(addr - 0x1000);
+            // palette entries are six bits wide, the top two come from the latch
+            let data = (self.read_vram(addr) & 0x3f) | (self.open_bus & 0xc0);
+            self.refresh_open_bus(data, 0x3f);
+            data
+        } else {
+            let result = self.data_buffer;
+            self.data_buffer = self.read_vram(addr);
+            self.refresh_open_bus(result, 0xff);
+            result
+        }
+    }
+
+    fn increment_vram_addr(&mut self) {
+        let step = if self.ctrl & CTRL_VRAM_INCREMENT != 0 { 32 } else { 1 };
+        self.v = self.v.wrapping_add(step) & 0x7fff;
+    }
+
+    // ppu address space
+
+    fn mirror_vram_addr(&self, addr: u16) -> usize {
+        let index = (addr & 0x0fff) as usize;
+        let table = index / 0x400;
+        let offset = index % 0x400;
+        let table = match self.mirroring {
+            Mirroring::Vertical => table & 1,
+            Mirroring::Horizontal => table >> 1,
+            Mirroring::FourScreen => table,
+        };
+        table * 0x400 + offset
+    }
+
+    fn palette_index(addr: u16) -> usize {
+        let index = (addr & 0x1f) as usize;
+        // sprite backdrop entries mirror the background ones
+        match index {
+            0x10 | 0x14 | 0x18 | 0x1c => index - 0x10,
+            _ => index,
+        }
+    }
+
+    pub fn read_vram(&self, addr: u16) -> u8 {
+        let addr = addr & 0x3fff;
+        match addr {
+            0..=0x1fff => self.chr_rom[addr as usize % self.chr_rom.len()],
+            0x2000..=0x3eff => self.vram[self.mirror_vram_addr(addr)],
+            _ => self.palette_table[Self::palette_index(addr)],
+        }
+    }
+
+    pub fn write_vram(&mut self, addr: u16, value: u8) {
+        let addr = addr & 0x3fff;
+        match addr {
+            0..=0x1fff => {
+                if self.chr_ram {
+                    let len = self.chr_rom.len();
+                    self.chr_rom[addr as usize % len] = value;
+                }
+            }
+            0x2000..=0x3eff => {
+                let index = self.mirror_vram_addr(addr);
+                self.vram[index] = value;
+            }
+            _ => self.palette_table[Self::palette_index(addr)] = value & 0x3f,
+        }
+    }
+
+    // timing
+
+    /// Advances the PPU by the given number of dots.
+    pub fn tick(&mut self, dots: u16) {
+        for _ in 0..dots {
+            self.step_dot();
+        }
+    }
+
+    /// The PPU is standing still for overclock scanlines.
+    pub fn is_overclocking(&self) -> bool {
+        self.overclock_dots > 0
+    }
+
+    fn step_dot(&mut self) {
+        if self.overclock_dots > 0 {
+            self.overclock_dots -= 1;
+            return;
+        }
+        if !self.hooks.is_empty() {
+            self.run_hooks();
+        }
+
+        let visible = self.scanline < 240;
+        let pre_render = self.scanline == self.region.pre_render_scanline();
+
+        if pre_render && self.dot == 0 && self.events.is_some() {
+            self.roll_event_log();
+        }
+        if pre_render && self.dot == 1 {
+            self.status &= !(STATUS_VBLANK | STATUS_SPRITE_ZERO_HIT | STATUS_SPRITE_OVERFLOW);
+        }
+
+        if self.rendering_enabled() && (visible || pre_render) {
+            if pre_render && self.dot == 1 && self.config.oam_corruption && self.oam_addr >= 8 {
+                let row = (self.oam_addr & 0xf8) as usize;
+                self.oam_data.copy_within(row..row + 8, 0);
+            }
+            if (257..=320).contains(&self.dot) {
+                self.oam_addr = 0;
+            }
+            self.background_cycle(pre_render);
+            if self.dot == 257 {
+                if visible {
+                    self.evaluate_sprites();
+                } else {
+                    self.sprite_count = 0;
+                    self.sprite_zero_on_line = false;
+                }
+            }
+        }
+
+        if visible && (1..=256).contains(&self.dot) {
+            self.render_pixel();
+        }
+
+        if self.scanline == VBLANK_SCANLINE && self.dot == 1 {
+            self.finish_frame();
+        }
+        if self.scanline == self.region.vblank_scanline() && self.dot == 1 {
+            self.status |= STATUS_VBLANK;
+            diag!(debug, frame = self.frame.number(), nmi = self.ctrl & CTRL_GENERATE_NMI != 0, "vblank");
+            if self.ctrl & CTRL_GENERATE_NMI != 0 {
+                self.nmi_pending = true;
+                self.record_event(PpuEventKind::Nmi);
+            }
+            self.overclock_dots = self.config.overclock_scanlines as u32 * DOTS_PER_SCANLINE as u32;
+        }
+
+        self.dot += 1;
+        // odd frames drop the last dot of the pre-render line while rendering
+        if pre_render
+            && self.dot == DOTS_PER_SCANLINE - 1
+            && self.odd_frame
+            && self.rendering_enabled()
+            && self.region.skips_odd_frame_dot()
+        {
+            self.dot += 1;
+        }
+        if self.dot == DOTS_PER_SCANLINE {
+            self.dot = 0;
+            self.scanline += 1;
+            if self.scanline == self.region.scanlines_per_frame() {
+                self.scanline = 0;
+                self.odd_frame = !self.odd_frame;
+            }
+        }
+    }
+
+    fn finish_frame(&mut self) {
+        for (i, color) in self.back.iter().enumerate() {
+            let rgb = self.palette.color(*color);
+            self.frame.set_pixel(i % WIDTH, i / WIDTH, rgb);
+            self.frame.set_pixel_index(i % WIDTH, i / WIDTH, *color);
+        }
+        self.frame.finish();
+        self.decay_open_bus();
+    }
+
+    fn background_cycle(&mut self, pre_render: bool) {
+        let dot = self.dot;
+
+        if (2..=257).contains(&dot) || (322..=337).contains(&dot) {
+            self.shift_background();
+            match (dot - 1) % 8 {
+                0 => {
+                    self.load_background_shifters();
+                    self.next_tile_id = self.read_vram(0x2000 | (self.v & 0x0fff));
+                }
+                2 => {
+                    let addr = 0x23c0 | (self.v & 0x0c00) | ((self.v >> 4) & 0x38) | ((self.v >> 2) & 0x07);
+                    let mut attr = self.read_vram(addr);
+                    if self.coarse_y() & 0x02 != 0 {
+                        attr >>= 4;
+                    }
+                    if self.coarse_x() & 0x02 != 0 {
+                        attr >>= 2;
+                    }
+                    self.next_tile_attr = attr & 0b11;
+                }
+                4 => {
+                    let addr = self.background_pattern_addr();
+                    self.next_tile_lo = self.read_vram(addr);
+                }
+                6 => {
+                    let addr = self.background_pattern_addr() + 8;
+                    self.next_tile_hi = self.read_vram(addr);
+                }
+                7 => self.increment_scroll_x(),
+                _ => {}
+            }
+        }
+
+        if dot == 256 {
+            self.increment_scroll_y();
+        }
+        if dot == 257 {
+            self.load_background_shifters();
+            // copy horizontal position from t
+            self.v = (self.v & !0x041f) | (self.t & 0x041f);
+        }
+        if pre_render && (280..=304).contains(&dot) {
+            // copy vertical position from t
+            self.v = (self.v & !0x7be0) | (self.t & 0x7be0);
+        }
+        if dot == 338 || dot == 340 {
+            self.next_tile_id = self.read_vram(0x2000 | (self.v & 0x0fff));
+        }
+    }
+
+    fn coarse_x(&self) -> u16 {
+        self.v & 0x001f
+    }
+
+    fn coarse_y(&self) -> u16 {
+        (self.v >> 5) & 0x001f
+    }
+
+    fn fine_y(&self) -> u16 {
+        (self.v >> 12) & 0b111
+    }
+
+    fn background_pattern_addr(&self) -> u16 {
+        let table = if self.ctrl & CTRL_BACKGROUND_PATTERN != 0 { 0x1000 } else { 0 };
+        table + (self.next_tile_id as u16) * 16 + self.fine_y()
+    }
+
+    fn increment_scroll_x(&mut self) {
+        if self.coarse_x() == 31 {
+            self.v &= !0x001f;
+            self.v ^= 0x0400;
+        } else {
+            self.v += 1;
+        }
+    }
+
+    fn increment_scroll_y(&mut self) {
+        if self.fine_y() < 7 {
+            self.v += 0x1000;
+            return;
+        }
+        self.v &= !0x7000;
+        let mut y = self.coarse_y();
+        if y == 29 {
+            y = 0;
+            self.v ^= 0x0800;
+        } else if y == 31 {
+            y = 0;
+        } else {
+            y += 1;
+        }
+        self.v = (self.v & !0x03e0) | (y << 5);
+    }
+
+    fn load_background_shifters(&mut self) {
+        self.shifter_pattern_lo = (self.shifter_pattern_lo & 0xff00) | self.next_tile_lo as u16;
+        self.shifter_pattern_hi = (self.shifter_pattern_hi & 0xff00) | self.next_tile_hi as u16;
+        let attr_lo = if self.next_tile_attr & 0b01 != 0 { 0xff } else { 0 };
+        let attr_hi = if self.next_tile_attr & 0b10 != 0 { 0xff } else { 0 };
+        self.shifter_attr_lo = (self.shifter_attr_lo & 0xff00) | attr_lo;
+        self.shifter_attr_hi = (self.shifter_attr_hi & 0xff00) | attr_hi;
+    }
+
+    fn shift_background(&mut self) {
+        if self.mask & MASK_SHOW_BACKGROUND != 0 {
+            self.shifter_pattern_lo <<= 1;
+            self.shifter_pattern_hi <<= 1;
+            self.shifter_attr_lo <<= 1;
+            self.shifter_attr_hi <<= 1;
+        }
+    }
+
+    fn sprite_height(&self) -> u16 {
+        if self.ctrl & CTRL_SPRITE_SIZE != 0 { 16 } else { 8 }
+    }
+
+    // pattern address of the given row of a sprite, counted from its unflipped top
+    fn sprite_pattern_addr(&self, tile: u8, row: u16) -> u16 {
+        if self.sprite_height() == 16 {
+            let table = (tile as u16 & 1) * 0x1000;
+            let tile = (tile & 0xfe) as u16 + if row >= 8 { 1 } else { 0 };
+            table + tile * 16 + (row & 0b111)
+        } else {
+            let table = if self.ctrl & CTRL_SPRITE_PATTERN != 0 { 0x1000 } else { 0 };
+            table + tile as u16 * 16 + row
+        }
+    }
+
+    // finds the sprites covering the next scanline and fetches their patterns
+    fn evaluate_sprites(&mut self) {
+        let height = self.sprite_height();
+        let line = self.scanline;
+        let limit = self.config.sprite_limit.unwrap_or(64);
+        let mut found = 0;
+        self.sprite_count = 0;
+        self.sprite_zero_on_line = false;
+
+        for i in 0..64 {
+            let y = self.oam_data[i * 4] as u16;
+            if line < y || line - y >= height {
+                continue;
+            }
+            found += 1;
+            // the flag reflects the hardware limit whatever the configured one is
+            if found > 8 {
+                self.status |= STATUS_SPRITE_OVERFLOW;
+            }
+            if self.sprite_count >= limit {
+                break;
+            }
+
+            let tile = self.oam_data[i * 4 + 1];
+            let attr = self.oam_data[i * 4 + 2];
+            let mut row = line - y;
+            if attr & 0b1000_0000 != 0 {
+                row = height - 1 - row;
+            }
+            let addr = self.sprite_pattern_addr(tile, row);
+            let mut lo = self.read_vram(addr);
+            let mut hi = self.read_vram(addr + 8);
+            if attr & 0b0100_0000 != 0 {
+                lo = lo.reverse_bits();
+                hi = hi.reverse_bits();
+            }
+
+            let n = self.sprite_count;
+            self.sprite_x[n] = self.oam_data[i * 4 + 3];
+            self.sprite_attr[n] = attr;
+            self.sprite_pattern_lo[n] = lo;
+            self.sprite_pattern_hi[n] = hi;
+            if i == 0 {
+                self.sprite_zero_on_line = true;
+            }
+            self.sprite_count += 1;
+        }
+    }
+
+    fn render_pixel(&mut self) {
+        let x = (self.dot - 1) as usize;
+        let y = self.scanline as usize;
+
+        let mut bg_pixel = 0;
+        let mut bg_palette = 0;
+        if self.mask & MASK_SHOW_BACKGROUND != 0 && (x >= 8 || self.mask & MASK_SHOW_BACKGROUND_LEFT != 0) {
+            let mux = 0x8000 >> self.fine_x;
+            let p0 = (self.shifter_pattern_lo & mux != 0) as u8;
+            let p1 = (self.shifter_pattern_hi & mux != 0) as u8;
+            bg_pixel = (p1 << 1) | p0;
+            let a0 = (self.shifter_attr_lo & mux != 0) as u8;
+            let a1 = (self.shifter_attr_hi & mux != 0) as u8;
+            bg_palette = (a1 << 1) | a0;
+        }
+
+        let mut sprite_pixel = 0;
+        let mut sprite_palette = 0;
+        let mut sprite_behind = false;
+        let mut sprite_zero = false;
+        if self.mask & MASK_SHOW_SPRITES != 0 && (x >= 8 || self.mask & MASK_SHOW_SPRITES_LEFT != 0) {
+            for i in 0..self.sprite_count {
+                let offset = x as i16 - self.sprite_x[i] as i16;
+                if !(0..8).contains(&offset) {
+                    continue;
+                }
+                let bit = 7 - offset as u8;
+                let p0 = (self.sprite_pattern_lo[i] >> bit) & 1;
+                let p1 = (self.sprite_pattern_hi[i] >> bit) & 1;
+                let pixel = (p1 << 1) | p0;
+                if pixel == 0 {
+                    continue;
+                }
+                sprite_pixel = pixel;
+                sprite_palette = (self.sprite_attr[i] & 0b11) + 4;
+                sprite_behind = self.sprite_attr[i] & 0b0010_0000 != 0;
+                sprite_zero = i == 0 && self.sprite_zero_on_line;
+                break;
+            }
+        }
+
+        let palette_addr = match (bg_pixel, sprite_pixel) {
+            (0, 0) => 0,
+            (0, _) => sprite_palette * 4 + sprite_pixel,
+            (_, 0) => bg_palette * 4 + bg_pixel,
+            _ => {
+                if sprite_zero && x != 255 && self.status & STATUS_SPRITE_ZERO_HIT == 0 {
+                    self.status |= STATUS_SPRITE_ZERO_HIT;
+                    self.record_event(PpuEventKind::SpriteZeroHit);
+                }
+                if sprite_behind {
+                    bg_palette * 4 + bg_pixel
+                } else {
+                    sprite_palette * 4 + sprite_pixel
+                }
+            }
+        };
+
+        // with rendering off the backdrop shows, or the palette entry v points at
+        let palette_addr = if !self.rendering_enabled() && self.v & 0x3f00 == 0x3f00 {
+            self.v
+        } else {
+            0x3f00 + palette_addr as u16
+        };
+
+        let mut color = self.read_vram(palette_addr);
+        if self.mask & MASK_GREYSCALE != 0 {
+            color &= 0x30;
+        }
+        let mut emphasis = ((self.mask & MASK_EMPHASIS) >> 5) as u16;
+        if self.region.swaps_emphasis_red_green() {
+            emphasis = (emphasis & 0b100) | ((emphasis & 0b001) << 1) | ((emphasis & 0b010) >> 1);
+        }
+        let emphasis = emphasis << 6;
+        self.back[y * WIDTH + x] = color as u16 | emphasis;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // advances to the given position from wherever the PPU currently is
+    fn run_to(ppu: &mut PPU, scanline: u16, dot: u16) {
+        while ppu.scanline != scanline || ppu.dot != dot {
+            ppu.tick(1);
+        }
+    }
+
+    #[test]
+    fn test_ppu_vram_writes() {
+        let mut ppu = PPU::new_empty_rom();
+        ppu.write_to_ppu_addr(0x23);
+        ppu.write_to_ppu_addr(0x05);
+        ppu.write_to_data(0x66);
+
+        assert_eq!(ppu.vram[0x0305], 0x66);
+    }
+
+    #[test]
+    fn test_ppu_vram_reads_are_buffered() {
+        let mut ppu = PPU::new_empty_rom();
+        ppu.write_to_ctrl(0);
+        ppu.vram[0x0305] = 0x66;
+
+        ppu.write_to_ppu_addr(0x23);
+        ppu.write_to_ppu_addr(0x05);
+
+        ppu.read_data(); // load into buffer
+        assert_eq!(ppu.v, 0x2306);
+        assert_eq!(ppu.read_data(), 0x66);
+    }
+
+    #[test]
+    fn test_ppu_vram_reads_step_32() {
+        let mut ppu = PPU::new_empty_rom();
+        ppu.write_to_ctrl(0b100);
+        ppu.vram[0x01ff] = 0x66;
+        ppu.vram[0x01ff + 32] = 0x77;
+        ppu.vram[0x01ff + 64] = 0x88;
+
+        ppu.write_to_ppu_addr(0x21);
+        ppu.write_to_ppu_addr(0xff);
+
+        ppu.read_data(); // load into buffer
+        assert_eq!(ppu.read_data(), 0x66);
+        assert_eq!(ppu.read_data(), 0x77);
+        assert_eq!(ppu.read_data(), 0x88);
+    }
+
+    // Horizontal: https://wiki.nesdev.com/w/index.php/Mirroring
+    //   [0x2000 A ] [0x2400 a ]
+    //   [0x2800 B ] [0x2C00 b ]
+    #[test]
+    fn test_vram_horizontal_mirror() {
+        let mut ppu = PPU::new_empty_rom();
+        ppu.write_to_ppu_addr(0x24);
+        ppu.write_to_ppu_addr(0x05);
+        ppu.write_to_data(0x66); // write to a
+
+        ppu.write_to_ppu_addr(0x28);
+        ppu.write_to_ppu_addr(0x05);
+        ppu.write_to_data(0x77); // write to B
+
+        ppu.write_to_ppu_addr(0x20);
+        ppu.write_to_ppu_addr(0x05);
+        ppu.read_data(); // load into buffer
+        assert_eq!(ppu.read_data(), 0x66); // read from A
+
+        ppu.write_to_ppu_addr(0x2C);
+        ppu.write_to_ppu_addr(0x05);
+        ppu.read_data(); // load into buffer
+        assert_eq!(ppu.read_data(), 0x77); // read from b
+    }
+
+    // Vertical: https://wiki.nesdev.com/w/index.php/Mirroring
+    //   [0x2000 A ] [0x2400 B ]
+    //   [0x2800 a ] [0x2C00 b ]
+    #[test]
+    fn test_vram_vertical_mirror() {
+        let mut ppu = PPU::new(vec![0; 0x2000], Mirroring::Vertical);
+        ppu.write_to_ppu_addr(0x20);
+        ppu.write_to_ppu_addr(0x05);
+        ppu.write_to_data(0x66); // write to A
+
+        ppu.write_to_ppu_addr(0x2C);
+        ppu.write_to_ppu_addr(0x05);
+        ppu.write_to_data(0x77); // write to b
+
+        ppu.write_to_ppu_addr(0x28);
+        ppu.write_to_ppu_addr(0x05);
+        ppu.read_data(); // load into buffer
+        assert_eq!(ppu.read_data(), 0x66); // read from a
+
+        ppu.write_to_ppu_addr(0x24);
+        ppu.write_to_ppu_addr(0x05);
+        ppu.read_data(); // load into buffer
+        assert_eq!(ppu.read_data(), 0x77); // read from B
+    }
+
+    #[test]
+    fn test_palette_backdrop_mirrors() {
+        let mut ppu = PPU::new_empty_rom();
+        ppu.write_to_ppu_addr(0x3f);
+        ppu.write_to_ppu_addr(0x10);
+        ppu.write_to_data(0x21);
+        assert_eq!(ppu.palette_table[0], 0x21);
+    }
+
+    #[test]
+    fn test_read_status_resets_latch() {
+        let mut ppu = PPU::new_empty_rom();
+        ppu.vram[0x0305] = 0x66;
+
+        ppu.write_to_ppu_addr(0x21);
+        ppu.write_to_ppu_addr(0x23);
+        ppu.write_to_ppu_addr(0x05);
+
+        ppu.read_data(); // load into buffer
+        assert_ne!(ppu.read_data(), 0x66);
+
+        ppu.read_status();
+
+        ppu.write_to_ppu_addr(0x23);
+        ppu.write_to_ppu_addr(0x05);
+
+        ppu.read_data(); // load into buffer
+        assert_eq!(ppu.read_data(), 0x66);
+    }
+
+    #[test]
+    fn test_vblank_sets_status_and_nmi() {
+        let mut ppu = PPU::new_empty_rom();
+        ppu.write_to_ctrl(CTRL_GENERATE_NMI);
+        run_to(&mut ppu, VBLANK_SCANLINE, 1);
+        assert!(!ppu.poll_nmi());
+        ppu.tick(1);
+        assert!(ppu.status & STATUS_VBLANK != 0);
+        assert!(ppu.poll_nmi());
+        assert!(!ppu.poll_nmi());
+        assert_eq!(ppu.frame().number(), 1);
+
+        run_to(&mut ppu, PRE_RENDER_SCANLINE, 2);
+        assert!(ppu.status & STATUS_VBLANK == 0);
+    }
+
+    #[test]
+    fn test_three_hundred_forty_one_dots_per_line() {
+        let mut ppu = PPU::new_empty_rom();
+        ppu.tick(DOTS_PER_SCANLINE * 10 + 5);
+        assert_eq!(ppu.scanline(), 10);
+        assert_eq!(ppu.dot(), 5);
+    }
+
+    fn run_frames(ppu: &mut PPU, frames: u64) {
+        let target = ppu.frame().number() + frames;
+        while ppu.frame().number() < target {
+            ppu.tick(1);
+        }
+    }
+
+    #[test]
+    fn test_oam_read_write() {
+        let mut ppu = PPU::new_empty_rom();
+        ppu.write_to_oam_addr(0x10);
+        ppu.write_to_oam_data(0x66);
+        ppu.write_to_oam_data(0x77);
+
+        ppu.write_to_oam_addr(0x10);
+        assert_eq!(ppu.read_oam_data(), 0x66);
+
+        ppu.write_to_oam_addr(0x11);
+        assert_eq!(ppu.read_oam_data(), 0x77);
+    }
+
+    #[test]
+    fn test_oam_attribute_reads_drop_unused_bits() {
+        let mut ppu = PPU::new_empty_rom();
+        ppu.write_to_oam_addr(0x02);
+        ppu.write_to_oam_data(0xff);
+        ppu.write_to_oam_addr(0x02);
+        assert_eq!(ppu.read_oam_data(), 0xe3);
+    }
+
+    #[test]
+    fn test_oam_dma() {
+        let mut ppu = PPU::new_empty_rom();
+
+        let mut data = [0x66; 256];
+        data[0] = 0x77;
+        data[255] = 0x88;
+
+        ppu.write_to_oam_addr(0x10);
+        ppu.write_oam_dma(&data);
+
+        ppu.write_to_oam_addr(0xf); //wrap around
+        assert_eq!(ppu.read_oam_data(), 0x88);
+
+        ppu.write_to_oam_addr(0x10);
+        assert_eq!(ppu.read_oam_data(), 0x77);
+
+        ppu.write_to_oam_addr(0x11);
+        assert_eq!(ppu.read_oam_data(), 0x66);
+    }
+
+    #[test]
+    fn test_oam_writes_during_rendering_are_ignored() {
+        let mut ppu = PPU::new_empty_rom();
+        ppu.write_to_mask(MASK_SHOW_SPRITES);
+        run_to(&mut ppu, 10, 100);
+        ppu.write_to_oam_addr(0x01);
+        ppu.write_to_oam_data(0x66);
+        assert_eq!(ppu.oam_data[0x01], 0);
+        assert_eq!(ppu.oam_addr, 0x05);
+    }
+
+    #[test]
+    fn test_oam_addr_resets_during_sprite_fetches() {
+        let mut ppu = PPU::new_empty_rom();
+        ppu.write_to_mask(MASK_SHOW_SPRITES);
+        run_to(&mut ppu, 10, 100);
+        ppu.write_to_oam_addr(0x20);
+        run_to(&mut ppu, 10, 300);
+        assert_eq!(ppu.oam_addr, 0);
+    }
+
+    #[test]
+    fn test_oam_corruption_when_rendering_starts() {
+        let mut ppu = PPU::new_empty_rom();
+        ppu.config.oam_corruption = true;
+        for i in 0..8 {
+            ppu.oam_data[0x48 + i] = 0x50 + i as u8;
+        }
+        run_to(&mut ppu, PRE_RENDER_SCANLINE, 0);
+        ppu.write_to_oam_addr(0x4a);
+        ppu.write_to_mask(MASK_SHOW_SPRITES);
+        ppu.tick(2);
+        assert_eq!(&ppu.oam_data[0..8], &[0x50, 0x51, 0x52, 0x53, 0x54, 0x55, 0x56, 0x57]);
+    }
+
+    #[test]
+    fn test_no_oam_corruption_by_default() {
+        let mut ppu = PPU::new_empty_rom();
+        ppu.oam_data[0x48] = 0x50;
+        run_to(&mut ppu, PRE_RENDER_SCANLINE, 0);
+        ppu.write_to_oam_addr(0x4a);
+        ppu.write_to_mask(MASK_SHOW_SPRITES);
+        ppu.tick(2);
+        assert_eq!(ppu.oam_data[0], 0);
+    }
+
+    // nine solid sprites side by side on the same lines
+    fn ppu_with_nine_sprites() -> PPU {
+        let mut chr = vec![0; 0x2000];
+        for i in 0..8 {
+            chr[16 + i] = 0xff;
+        }
+        let mut ppu = PPU::new(chr, Mirroring::Vertical);
+        ppu.oam_data = [0xff; 256];
+        for i in 0..9 {
+            ppu.oam_data[i * 4..i * 4 + 4].copy_from_slice(&[20, 1, 0, i as u8 * 10 + 20]);
+        }
+        ppu.palette_table[0] = 0x0f;
+        ppu.palette_table[0x11] = 0x30;
+        ppu.write_to_mask(MASK_SHOW_SPRITES);
+        ppu
+    }
+
+    #[test]
+    fn test_sprite_limit() {
+        let mut ppu = ppu_with_nine_sprites();
+        run_to(&mut ppu, VBLANK_SCANLINE, 2);
+        assert!(ppu.status & STATUS_SPRITE_OVERFLOW != 0);
+        assert_eq!(ppu.frame().pixel(90, 24), ppu.palette().color(0x30));
+        assert_eq!(ppu.frame().pixel(100, 24), ppu.palette().color(0x0f));
+    }
+
+    #[test]
+    fn test_sprite_limit_disabled() {
+        let mut ppu = ppu_with_nine_sprites();
+        ppu.config.sprite_limit = None;
+        run_to(&mut ppu, VBLANK_SCANLINE, 2);
+        assert!(ppu.status & STATUS_SPRITE_OVERFLOW != 0);
+        assert_eq!(ppu.frame().pixel(100, 24), ppu.palette().color(0x30));
+    }
+
+    #[test]
+    fn test_write_only_registers_read_open_bus() {
+        let mut ppu = PPU::new_empty_rom();
+        ppu.latch_open_bus(0x5a);
+        assert_eq!(ppu.open_bus(), 0x5a);
+    }
+
+    #[test]
+    fn test_status_low_bits_come_from_open_bus() {
+        let mut ppu = PPU::new_empty_rom();
+        ppu.status = STATUS_VBLANK;
+        ppu.latch_open_bus(0x1f);
+        assert_eq!(ppu.read_status(), 0x9f);
+        assert_eq!(ppu.open_bus(), 0x9f);
+    }
+
+    #[test]
+    fn test_palette_reads_keep_top_bits_of_open_bus() {
+        let mut ppu = PPU::new_empty_rom();
+        ppu.palette_table[1] = 0x2a;
+        ppu.write_to_ppu_addr(0x3f);
+        ppu.write_to_ppu_addr(0x01);
+        ppu.latch_open_bus(0xc0);
+        assert_eq!(ppu.read_data(), 0xea);
+    }
+
+    #[test]
+    fn test_open_bus_decays() {
+        let mut ppu = PPU::new_empty_rom();
+        ppu.latch_open_bus(0xff);
+        run_frames(&mut ppu, DEFAULT_OPEN_BUS_DECAY_FRAMES - 1);
+        assert_eq!(ppu.open_bus(), 0xff);
+
+        // refreshing the top bits keeps them alive
+        ppu.status = STATUS_VBLANK | STATUS_SPRITE_ZERO_HIT | STATUS_SPRITE_OVERFLOW;
+        ppu.read_status();
+        run_frames(&mut ppu, 1);
+        assert_eq!(ppu.open_bus(), 0xe0);
+    }
+
+    #[test]
+    fn test_open_bus_decay_can_be_disabled() {
+        let mut ppu = PPU::new_empty_rom();
+        ppu.config.open_bus_decay = None;
+        ppu.latch_open_bus(0xff);
+        run_frames(&mut ppu, DEFAULT_OPEN_BUS_DECAY_FRAMES * 2);
+        assert_eq!(ppu.open_bus(), 0xff);
+    }
+
+    #[test]
+    fn test_pal_frame_timing() {
+        let mut ppu = PPU::new_empty_rom();
+        ppu.set_region(Region::Pal);
+        ppu.write_to_mask(MASK_SHOW_BACKGROUND);
+        let full = DOTS_PER_SCANLINE as usize * 312;
+
+        // no dot is ever skipped
+        assert_eq!(dots_until_next_frame(&mut ppu), full);
+        assert_eq!(dots_until_next_frame(&mut ppu), full);
+
+        // vblank lasts until the pre-render line at 311
+        run_to(&mut ppu, VBLANK_SCANLINE, 2);
+        assert!(ppu.status & STATUS_VBLANK != 0);
+        run_to(&mut ppu, 300, 0);
+        assert!(ppu.status & STATUS_VBLANK != 0);
+        run_to(&mut ppu, 311, 2);
+        assert!(ppu.status & STATUS_VBLANK == 0);
+    }
+
+    #[test]
+    fn test_dendy_vblank_starts_late() {
+        let mut ppu = PPU::new_empty_rom();
+        ppu.set_region(Region::Dendy);
+        ppu.write_to_ctrl(CTRL_GENERATE_NMI);
+        assert_eq!(dots_until_next_frame(&mut ppu), DOTS_PER_SCANLINE as usize * 312);
+        assert!(ppu.poll_nmi());
+
+        // the picture is done at 241 but vblank waits another 50 lines
+        run_to(&mut ppu, VBLANK_SCANLINE, 2);
+        assert_eq!(ppu.frame().number(), 2);
+        assert!(ppu.status & STATUS_VBLANK == 0);
+        assert!(!ppu.poll_nmi());
+        run_to(&mut ppu, 291, 2);
+        assert!(ppu.status & STATUS_VBLANK != 0);
+        assert!(ppu.poll_nmi());
+    }
+
+    #[test]
+    fn test_pal_swaps_red_and_green_emphasis() {
+        let mut ppu = PPU::new_empty_rom();
+        ppu.set_region(Region::Pal);
+        ppu.write_to_mask(0b0010_0000);
+        run_to(&mut ppu, VBLANK_SCANLINE, 2);
+        assert_eq!(ppu.frame().indices()[0] >> 6, 0b010);
+
+        let mut ppu = PPU::new_empty_rom();
+        ppu.write_to_mask(0b0010_0000);
+        run_to(&mut ppu, VBLANK_SCANLINE, 2);
+        assert_eq!(ppu.frame().indices()[0] >> 6, 0b001);
+    }
+
+    #[test]
+    fn test_light_sense_follows_the_beam() {
+        let mut ppu = PPU::new_empty_rom();
+        ppu.palette_table[0] = 0x30;
+        ppu.write_to_mask(MASK_SHOW_BACKGROUND);
+        run_to(&mut ppu, 100, 50);
+        assert!(ppu.light_at(10, 100));
+        assert!(!ppu.light_at(60, 100));
+        assert!(ppu.light_at(60, 90));
+        assert!(!ppu.light_at(10, 70));
+
+        run_to(&mut ppu, 130, 0);
+        assert!(!ppu.light_at(10, 100));
+
+        // dark pixels never register
+        let mut ppu = PPU::new_empty_rom();
+        ppu.palette_table[0] = 0x0f;
+        ppu.write_to_mask(MASK_SHOW_BACKGROUND);
+        run_to(&mut ppu, 100, 50);
+        assert!(!ppu.light_at(10, 100));
+    }
+
+    fn dots_until_next_frame(ppu: &mut PPU) -> usize {
+        let mut dots = 0;
+        loop {
+            ppu.tick(1);
+            dots += 1;
+            if ppu.scanline == 0 && ppu.dot == 0 {
+                return dots;
+            }
+        }
+    }
+
+    #[test]
+    fn test_odd_frames_skip_a_dot_while_rendering() {
+        let mut ppu = PPU::new_empty_rom();
+        ppu.write_to_mask(MASK_SHOW_BACKGROUND);
+        let full = DOTS_PER_SCANLINE as usize * SCANLINES_PER_FRAME as usize;
+
+        assert_eq!(dots_until_next_frame(&mut ppu), full);
+        assert_eq!(dots_until_next_frame(&mut ppu), full - 1);
+        assert_eq!(dots_until_next_frame(&mut ppu), full);
+    }
+
+    #[test]
+    fn test_no_dot_skip_with_rendering_disabled() {
+        let mut ppu = PPU::new_empty_rom();
+        let full = DOTS_PER_SCANLINE as usize * SCANLINES_PER_FRAME as usize;
+
+        assert_eq!(dots_until_next_frame(&mut ppu), full);
+        assert_eq!(dots_until_next_frame(&mut ppu), full);
+    }
+
+    #[test]
+    fn test_mid_frame_palette_change() {
+        let mut ppu = PPU::new_empty_rom();
+        ppu.palette_table[0] = 0x01;
+        run_to(&mut ppu, 100, 0);
+        ppu.palette_table[0] = 0x02;
+        run_to(&mut ppu, VBLANK_SCANLINE, 2);
+
+        assert_eq!(ppu.frame().pixel(0, 99), palette::SYSTEM_PALETTE[0x01]);
+        assert_eq!(ppu.frame().pixel(0, 100), palette::SYSTEM_PALETTE[0x02]);
+    }
+
+    #[test]
+    fn test_set_palette() {
+        let mut ppu = PPU::new_empty_rom();
+        ppu.palette_table[0] = 0x16;
+        ppu.set_palette(Palette::builtin(palette::BuiltinPalette::Monochrome));
+        run_to(&mut ppu, VBLANK_SCANLINE, 2);
+        let (r, g, b) = ppu.frame().pixel(0, 0);
+        assert_eq!(r, g);
+        assert_eq!(g, b);
+    }
+
+    #[test]
+    fn test_mid_frame_scroll_split() {
+        let mut chr = vec![0; 0x2000];
+        // tile 1 is solid color 1
+        for i in 0..8 {
+            chr[16 + i] = 0xff;
+        }
+        let mut ppu = PPU::new(chr, Mirroring::Vertical);
+        ppu.palette_table[1] = 0x30;
+        ppu.palette_table[0] = 0x0f;
+        // nametable 1 is filled with tile 1, nametable 0 stays blank
+        for i in 0..0x3c0 {
+            ppu.vram[0x400 + i] = 1;
+        }
+        ppu.write_to_mask(MASK_SHOW_BACKGROUND | MASK_SHOW_BACKGROUND_LEFT);
+
+        run_to(&mut ppu, 120, 0);
+        // switch to the second nametable for the lower half
+        ppu.write_to_ctrl(0b01);
+        run_to(&mut ppu, VBLANK_SCANLINE, 2);
+
+        let frame = ppu.frame();
+        assert_eq!(frame.pixel(100, 60), palette::SYSTEM_PALETTE[0x0f]);
+        assert_eq!(frame.pixel(100, 200), palette::SYSTEM_PALETTE[0x30]);
+    }
+
+    #[test]
+    fn test_sprite_zero_hit() {
+        let mut chr = vec![0; 0x2000];
+        for i in 0..8 {
+            chr[16 + i] = 0xff;
+        }
+        let mut ppu = PPU::new(chr, Mirroring::Vertical);
+        for i in 0..0x3c0 {
+            ppu.vram[i] = 1;
+        }
+        ppu.oam_data[0] = 50;
+        ppu.oam_data[1] = 1;
+        ppu.oam_data[3] = 40;
+        ppu.write_to_mask(MASK_SHOW_BACKGROUND | MASK_SHOW_SPRITES);
+
+        run_to(&mut ppu, 50, 0);
+        assert!(ppu.status & STATUS_SPRITE_ZERO_HIT == 0);
+        run_to(&mut ppu, 52, 0);
+        assert!(ppu.status & STATUS_SPRITE_ZERO_HIT != 0);
+    }
+}
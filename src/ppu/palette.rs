@@ -0,0 +1,168 @@
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use std::fs;
+#[cfg(feature = "std")]
+use std::path::Path;
+
+// 2C02 colors as seen on a typical NTSC set
+pub static SYSTEM_PALETTE: [(u8, u8, u8); 64] = [
+    (0x80, 0x80, 0x80), (0x00, 0x3D, 0xA6), (0x00, 0x12, 0xB0), (0x44, 0x00, 0x96),
+    (0xA1, 0x00, 0x5E), (0xC7, 0x00, 0x28), (0xBA, 0x06, 0x00), (0x8C, 0x17, 0x00),
+    (0x5C, 0x2F, 0x00), (0x10, 0x45, 0x00), (0x05, 0x4A, 0x00), (0x00, 0x47, 0x2E),
+    (0x00, 0x41, 0x66), (0x00, 0x00, 0x00), (0x05, 0x05, 0x05), (0x05, 0x05, 0x05),
+    (0xC7, 0xC7, 0xC7), (0x00, 0x77, 0xFF), (0x21, 0x55, 0xFF), (0x82, 0x37, 0xFA),
+    (0xEB, 0x2F, 0xB5), (0xFF, 0x29, 0x50), (0xFF, 0x22, 0x00), (0xD6, 0x32, 0x00),
+    (0xC4, 0x62, 0x00), (0x35, 0x80, 0x00), (0x05, 0x8F, 0x00), (0x00, 0x8A, 0x55),
+    (0x00, 0x99, 0xCC), (0x21, 0x21, 0x21), (0x09, 0x09, 0x09), (0x09, 0x09, 0x09),
+    (0xFF, 0xFF, 0xFF), (0x0F, 0xD7, 0xFF), (0x69, 0xA2, 0xFF), (0xD4, 0x80, 0xFF),
+    (0xFF, 0x45, 0xF3), (0xFF, 0x61, 0x8B), (0xFF, 0x88, 0x33), (0xFF, 0x9C, 0x12),
+    (0xFA, 0xBC, 0x20), (0x9F, 0xE3, 0x0E), (0x2B, 0xF0, 0x35), (0x0C, 0xF0, 0xA4),
+    (0x05, 0xFB, 0xFF), (0x5E, 0x5E, 0x5E), (0x0D, 0x0D, 0x0D), (0x0D, 0x0D, 0x0D),
+    (0xFF, 0xFF, 0xFF), (0xA6, 0xFC, 0xFF), (0xB3, 0xEC, 0xFF), (0xDA, 0xAB, 0xEB),
+    (0xFF, 0xA8, 0xF9), (0xFF, 0xAB, 0xB3), (0xFF, 0xD2, 0xB0), (0xFF, 0xEF, 0xA6),
+    (0xFF, 0xF7, 0x9C), (0xD7, 0xE8, 0x95), (0xA6, 0xED, 0xAF), (0xA2, 0xF2, 0xDA),
+    (0x99, 0xFF, 0xFC), (0xDD, 0xDD, 0xDD), (0x11, 0x11, 0x11), (0x11, 0x11, 0x11),
+];
+
+// how much the channels left out of an emphasis bit are dimmed
+const EMPHASIS_ATTENUATION: f32 = 0.816;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BuiltinPalette {
+    Ntsc,
+    Monochrome,
+}
+
+/// Maps a color index, with the emphasis bits above bit 6, to RGB.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Palette {
+    colors: Vec<(u8, u8, u8)>,
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette::builtin(BuiltinPalette::Ntsc)
+    }
+}
+
+impl Palette {
+    pub fn builtin(kind: BuiltinPalette) -> Self {
+        match kind {
+            BuiltinPalette::Ntsc => Palette::from_colors(&SYSTEM_PALETTE),
+            BuiltinPalette::Monochrome => {
+                let grey: Vec<(u8, u8, u8)> = SYSTEM_PALETTE
+                    .iter()
+                    .map(|(r, g, b)| {
+                        let luma = (0.299 * *r as f32 + 0.587 * *g as f32 + 0.114 * *b as f32) as u8;
+                        (luma, luma, luma)
+                    })
+                    .collect();
+                Palette::from_colors(&grey)
+            }
+        }
+    }
+
+    /// Parses a .pal file: 64 colors, or 512 when the file carries its own
+    /// colors for every combination of emphasis bits.
+    pub fn from_pal_bytes(data: &[u8]) -> Result<Palette, String> {
+        if data.len() != 64 * 3 && data.len() != 512 * 3 {
+            return Err(format!("palette must be 192 or 1536 bytes, got {}", data.len()));
+        }
+        let colors: Vec<(u8, u8, u8)> = data.chunks(3).map(|c| (c[0], c[1], c[2])).collect();
+        Ok(Palette::from_colors(&colors))
+    }
+
+    #[cfg(feature = "std")]
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Palette, String> {
+        let data = fs::read(path.as_ref())
+            .map_err(|e| format!("can't read {}: {}", path.as_ref().display(), e))?;
+        Palette::from_pal_bytes(&data)
+    }
+
+    fn from_colors(colors: &[(u8, u8, u8)]) -> Self {
+        if colors.len() == 512 {
+            return Palette { colors: colors.to_vec() };
+        }
+
+        let mut all = Vec::with_capacity(512);
+        for emphasis in 0..8 {
+            for (i, (r, g, b)) in colors.iter().enumerate() {
+                // the blacks in columns $e and $f aren't affected
+                if emphasis == 0 || i & 0x0e == 0x0e {
+                    all.push((*r, *g, *b));
+                    continue;
+                }
+                let dim = |value: u8, emphasized: bool| {
+                    if emphasized {
+                        value
+                    } else {
+                        (value as f32 * EMPHASIS_ATTENUATION) as u8
+                    }
+                };
+                all.push((
+                    dim(*r, emphasis & 0b001 != 0),
+                    dim(*g, emphasis & 0b010 != 0),
+                    dim(*b, emphasis & 0b100 != 0),
+                ));
+            }
+        }
+        Palette { colors: all }
+    }
+
+    pub fn color(&self, index: u16) -> (u8, u8, u8) {
+        self.colors[(index & 0x1ff) as usize]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_default_palette_is_ntsc() {
+        let palette = Palette::default();
+        assert_eq!(palette.color(0x30), SYSTEM_PALETTE[0x30]);
+    }
+
+    #[test]
+    fn test_load_64_color_pal() {
+        let mut data = vec![0; 192];
+        data[3] = 0x10;
+        data[4] = 0x20;
+        data[5] = 0x30;
+        let palette = Palette::from_pal_bytes(&data).unwrap();
+        assert_eq!(palette.color(0x01), (0x10, 0x20, 0x30));
+    }
+
+    #[test]
+    fn test_load_512_color_pal() {
+        let mut data = vec![0; 1536];
+        data[(0x40 + 0x01) * 3] = 0x77;
+        let palette = Palette::from_pal_bytes(&data).unwrap();
+        assert_eq!(palette.color(0x41), (0x77, 0, 0));
+    }
+
+    #[test]
+    fn test_rejects_bad_pal_size() {
+        assert!(Palette::from_pal_bytes(&[0; 100]).is_err());
+    }
+
+    #[test]
+    fn test_emphasis_dims_other_channels() {
+        let palette = Palette::default();
+        let (r, g, b) = palette.color(0x20 | 0b001 << 6);
+        assert_eq!(r, 0xff);
+        assert!(g < 0xff);
+        assert!(b < 0xff);
+    }
+
+    #[test]
+    fn test_monochrome_palette() {
+        let palette = Palette::builtin(BuiltinPalette::Monochrome);
+        let (r, g, b) = palette.color(0x16);
+        assert_eq!(r, g);
+        assert_eq!(g, b);
+    }
+}
@@ -0,0 +1,293 @@
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+use crate::ops;
+use crate::symbols::Symbols;
+
+const BRK: u8 = 0x00;
+const JSR: u8 = 0x20;
+
+/// An instruction's address, with the PRG ROM bank it was in for code
+/// running from ROM so code in different banks isn't counted together.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Location {
+    pub bank: Option<u16>,
+    pub addr: u16,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.bank {
+            Some(bank) => write!(f, "{:02X}:{:04X}", bank, self.addr),
+            None => write!(f, "{:04X}", self.addr),
+        }
+    }
+}
+
+/// Cycles spent in one routine, from `Profiler::routines`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RoutineProfile {
+    /// Where the routine starts, `None` for code outside of any.
+    pub entry: Option<u16>,
+    /// Its label, or its address without one.
+    pub name: String,
+    pub calls: u64,
+    /// Cycles spent in the routine itself.
+    pub self_cycles: u64,
+    /// Cycles spent in it and the routines it called.
+    pub inclusive_cycles: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Routine {
+    calls: u64,
+    self_cycles: u64,
+    inclusive_cycles: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Frame {
+    entry: u16,
+    // right after the return address was pushed
+    stack_pointer: u8,
+}
+
+/// Counts how often each opcode and each instruction address runs, and
+/// the cycles spent in each routine entered by JSR or an interrupt.
+/// Attach one with `Nes::attach_profiler`; it slows emulation down a bit.
+#[derive(Debug, Clone)]
+pub struct Profiler {
+    instructions: u64,
+    opcodes: [u64; 256],
+    locations: BTreeMap<Location, u64>,
+    labels: BTreeMap<u16, String>,
+    routines: BTreeMap<Option<u16>, Routine>,
+    // like the call stack, a routine is left once the stack pointer moves
+    // above its return address
+    frames: Vec<Frame>,
+    last_opcode: u8,
+    last_cycles: Option<u64>,
+    interrupted: bool,
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Profiler {
+            instructions: 0,
+            opcodes: [0; 256],
+            locations: BTreeMap::new(),
+            labels: BTreeMap::new(),
+            routines: BTreeMap::new(),
+            frames: Vec::new(),
+            last_opcode: 0xea,
+            last_cycles: None,
+            interrupted: false,
+        }
+    }
+
+    /// Names routines by their entry point. With labels, only calls to a
+    /// labeled address count as routines, and the cycles of the rest go to
+    /// the labeled routine they were called from. Without, every call does.
+    pub fn set_labels(&mut self, labels: BTreeMap<u16, String>) {
+        self.labels = labels;
+    }
+
+    pub fn set_symbols(&mut self, symbols: &Symbols) {
+        self.set_labels(symbols.labels().clone());
+    }
+
+    pub fn instructions(&self) -> u64 {
+        self.instructions
+    }
+
+    pub fn opcode_count(&self, opcode: u8) -> u64 {
+        self.opcodes[opcode as usize]
+    }
+
+    pub fn count_at(&self, location: Location) -> u64 {
+        self.locations.get(&location).copied().unwrap_or(0)
+    }
+
+    /// Counts and cycles are dropped, labels are kept.
+    pub fn clear(&mut self) {
+        let labels = core::mem::take(&mut self.labels);
+        *self = Profiler { labels, ..Profiler::new() };
+    }
+
+    /// Forgets the calls seen so far and where the clock was, for when the
+    /// console jumped elsewhere in time as on loading states. Counts stay.
+    pub fn clear_call_stack(&mut self) {
+        self.frames.clear();
+        self.last_opcode = 0xea;
+        self.last_cycles = None;
+        self.interrupted = false;
+    }
+
+    /// Cycle profile of every routine that ran, most self cycles first.
+    pub fn routines(&self) -> Vec<RoutineProfile> {
+        let mut routines: Vec<RoutineProfile> = self
+            .routines
+            .iter()
+            .map(|(&entry, routine)| RoutineProfile {
+                entry,
+                name: match entry {
+                    Some(addr) => self.labels.get(&addr).cloned().unwrap_or_else(|| format!("${:04X}", addr)),
+                    None => "(outside routines)".to_string(),
+                },
+                calls: routine.calls,
+                self_cycles: routine.self_cycles,
+                inclusive_cycles: routine.inclusive_cycles,
+            })
+            .collect();
+        routines.sort_by(|a, b| b.self_cycles.cmp(&a.self_cycles).then(a.entry.cmp(&b.entry)));
+        routines
+    }
+
+    /// Every opcode that ran and the `top` most run addresses, each most
+    /// run first.
+    pub fn report(&self, top: usize) -> ProfileReport {
+        let mut opcodes: Vec<(u8, u64)> = (0..=255).map(|opcode| (opcode, self.opcodes[opcode as usize])).filter(|&(_, count)| count > 0).collect();
+        opcodes.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        let mut hot: Vec<(Location, u64)> = self.locations.iter().map(|(&location, &count)| (location, count)).collect();
+        hot.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        hot.truncate(top);
+        ProfileReport { instructions: self.instructions, opcodes, hot }
+    }
+
+    // called before every instruction with the CPU's cycle count and stack
+    pub(crate) fn record(&mut self, opcode: u8, location: Location, cycles: u64, stack_pointer: u8) {
+        self.instructions += 1;
+        self.opcodes[opcode as usize] += 1;
+        *self.locations.entry(location).or_insert(0) += 1;
+
+        // what ran since the last instruction belongs to the routines it ran in
+        let spent = self.last_cycles.map_or(0, |last| cycles - last);
+        self.last_cycles = Some(cycles);
+        let top = self.frames.last().map(|frame| frame.entry);
+        self.routines.entry(top).or_default().self_cycles += spent;
+        let mut counted: Vec<Option<u16>> = Vec::with_capacity(self.frames.len() + 1);
+        for entry in core::iter::once(None).chain(self.frames.iter().map(|frame| Some(frame.entry))) {
+            if !counted.contains(&entry) {
+                counted.push(entry);
+                self.routines.entry(entry).or_default().inclusive_cycles += spent;
+            }
+        }
+
+        while self.frames.last().is_some_and(|frame| frame.stack_pointer < stack_pointer) {
+            self.frames.pop();
+        }
+        let called = matches!(self.last_opcode, JSR | BRK) || core::mem::take(&mut self.interrupted);
+        if called && (self.labels.is_empty() || self.labels.contains_key(&location.addr)) {
+            self.frames.push(Frame { entry: location.addr, stack_pointer });
+            self.routines.entry(Some(location.addr)).or_default().calls += 1;
+        }
+        self.last_opcode = opcode;
+    }
+
+    // the CPU took an NMI or IRQ, the next instruction is its handler's
+    pub(crate) fn on_interrupt(&mut self) {
+        self.interrupted = true;
+    }
+}
+
+/// Counts from `Profiler::report`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProfileReport {
+    pub instructions: u64,
+    pub opcodes: Vec<(u8, u64)>,
+    pub hot: Vec<(Location, u64)>,
+}
+
+impl fmt::Display for ProfileReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let share = |count: u64| count as f64 * 100.0 / self.instructions.max(1) as f64;
+        writeln!(f, "{} instructions", self.instructions)?;
+        writeln!(f, "opcodes:")?;
+        for &(opcode, count) in &self.opcodes {
+            let name = ops::opcode(opcode).map_or("???", |op| op.name);
+            writeln!(f, "  {:02X} {}  {:>10}  {:5.1}%", opcode, name, count, share(count))?;
+        }
+        writeln!(f, "hot addresses:")?;
+        for &(location, count) in &self.hot {
+            writeln!(f, "  {:>7}  {:>10}  {:5.1}%", location.to_string(), count, share(count))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::test_rom;
+    use crate::cartridge::Cartridge;
+    use crate::nes::Nes;
+
+    #[test]
+    fn test_counts() {
+        // loop: INX; BNE loop; JMP loop
+        let mut prg = vec![0xea; 0x4000];
+        prg[..6].copy_from_slice(&[0xe8, 0xd0, 0xfd, 0x4c, 0x00, 0xc0]);
+        prg[0x3ffc..].copy_from_slice(&[0x00, 0xc0, 0x00, 0xc0]);
+        let mut nes = Nes::new();
+        nes.insert_cartridge(Cartridge::new(&test_rom(&prg)).unwrap());
+        nes.attach_profiler(Profiler::new());
+        while nes.profiler().unwrap().instructions() < 256 * 2 + 1 {
+            nes.cpu_mut().step();
+        }
+
+        let profiler = nes.profiler().unwrap();
+        assert_eq!(profiler.opcode_count(0xe8), 256);
+        assert_eq!(profiler.opcode_count(0x4c), 1);
+        assert_eq!(profiler.count_at(Location { bank: Some(0), addr: 0xc000 }), 256);
+        assert_eq!(profiler.count_at(Location { bank: None, addr: 0xc000 }), 0);
+
+        let report = profiler.report(1);
+        assert_eq!(report.opcodes, vec![(0xd0, 256), (0xe8, 256), (0x4c, 1)]);
+        assert_eq!(report.hot, vec![(Location { bank: Some(0), addr: 0xc000 }, 256)]);
+        assert!(report.to_string().contains("  E8 INX         256   49.9%\n"));
+        assert!(report.to_string().contains("  00:C000         256   49.9%\n"));
+        assert!(nes.detach_profiler().is_some());
+    }
+
+    #[test]
+    fn test_routines() {
+        // main: JSR outer; JMP main, outer: JSR inner; JSR helper; RTS,
+        // inner: NOP; RTS, helper: RTS
+        let mut prg = vec![0xea; 0x4000];
+        prg[..6].copy_from_slice(&[0x20, 0x00, 0xc1, 0x4c, 0x00, 0xc0]);
+        prg[0x100..0x107].copy_from_slice(&[0x20, 0x00, 0xc2, 0x20, 0x00, 0xc3, 0x60]);
+        prg[0x200..0x202].copy_from_slice(&[0xea, 0x60]);
+        prg[0x300] = 0x60;
+        prg[0x3ffc..].copy_from_slice(&[0x00, 0xc0, 0x00, 0xc0]);
+        let mut nes = Nes::new();
+        nes.insert_cartridge(Cartridge::new(&test_rom(&prg)).unwrap());
+        let mut profiler = Profiler::new();
+        profiler.set_labels(BTreeMap::from([(0xc100, "outer".to_string()), (0xc200, "inner".to_string())]));
+        nes.attach_profiler(profiler);
+        // twice round main, then the next instruction's cycles aren't counted yet
+        for _ in 0..2 * 8 + 1 {
+            nes.cpu_mut().step();
+        }
+
+        let routines = nes.profiler().unwrap().routines();
+        let find = |name: &str| routines.iter().find(|routine| routine.name == name).unwrap().clone();
+        // NOP 2 + RTS 6
+        let inner = find("inner");
+        assert_eq!((inner.calls, inner.self_cycles, inner.inclusive_cycles), (2, 16, 16));
+        // 2 JSRs, RTS, and the unlabeled helper's RTS: 6 * 4 a call
+        let outer = find("outer");
+        assert_eq!((outer.calls, outer.self_cycles, outer.inclusive_cycles), (2, 48, 64));
+        // JSR 6 + JMP 3 a round
+        let top = find("(outside routines)");
+        assert_eq!((top.entry, top.self_cycles, top.inclusive_cycles), (None, 18, 82));
+        assert_eq!(routines[0].name, "outer");
+    }
+}
@@ -0,0 +1,151 @@
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use crate::cpu::CPU;
+
+/// How a watched value is shown.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Format {
+    #[default]
+    Unsigned,
+    Signed,
+    Hex,
+    /// Binary coded decimal, a digit a nibble, as scores often are.
+    Bcd,
+}
+
+/// A named value in CPU memory, 1, 2 or 4 bytes little endian.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WatchEntry {
+    pub name: String,
+    pub addr: u16,
+    pub size: u8,
+    pub format: Format,
+}
+
+impl WatchEntry {
+    pub fn new(name: &str, addr: u16, size: u8, format: Format) -> Result<WatchEntry, String> {
+        if !matches!(size, 1 | 2 | 4) {
+            return Err(format!("{} bytes can't be watched, only 1, 2 or 4", size));
+        }
+        Ok(WatchEntry { name: name.to_string(), addr, size, format })
+    }
+
+    /// The raw value, read without side effects.
+    pub fn read(&self, cpu: &CPU) -> u32 {
+        (0..self.size as u16).rev().fold(0, |value, i| (value << 8) | cpu.bus.peek(self.addr.wrapping_add(i)) as u32)
+    }
+
+    pub fn format(&self, value: u32) -> String {
+        let bits = self.size as u32 * 8;
+        match self.format {
+            Format::Unsigned => value.to_string(),
+            Format::Signed => (((value << (32 - bits)) as i32) >> (32 - bits)).to_string(),
+            Format::Hex => format!("${:0width$X}", value, width = self.size as usize * 2),
+            // digits past 9 show as they are, as hex
+            Format::Bcd => format!("{:0width$X}", value, width = self.size as usize * 2),
+        }
+    }
+}
+
+/// A watched value that changed over the last frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    pub name: String,
+    pub old: u32,
+    pub new: u32,
+    /// `new`, formatted.
+    pub text: String,
+}
+
+/// A list of values in RAM to keep an eye on, checked once a frame. Hand
+/// it to `Nes::set_ram_watch`, or call `update` yourself.
+#[derive(Debug, Clone, Default)]
+pub struct RamWatch {
+    entries: Vec<(WatchEntry, Option<u32>)>,
+    changes: Vec<Change>,
+}
+
+impl RamWatch {
+    pub fn new() -> Self {
+        RamWatch::default()
+    }
+
+    /// Adds an entry, replacing the one with the same name.
+    pub fn add(&mut self, entry: WatchEntry) {
+        self.remove(&entry.name);
+        self.entries.push((entry, None));
+    }
+
+    pub fn remove(&mut self, name: &str) -> bool {
+        let len = self.entries.len();
+        self.entries.retain(|(entry, _)| entry.name != name);
+        self.entries.len() != len
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = &WatchEntry> {
+        self.entries.iter().map(|(entry, _)| entry)
+    }
+
+    /// Every entry with its value formatted, as a watch panel lists them.
+    pub fn values(&self, cpu: &CPU) -> Vec<(&WatchEntry, String)> {
+        self.entries.iter().map(|(entry, _)| (entry, entry.format(entry.read(cpu)))).collect()
+    }
+
+    /// Reads every entry and keeps the ones that changed since the last
+    /// update for `changes`. Entries don't count as changed on their first read.
+    pub fn update(&mut self, cpu: &CPU) {
+        self.changes.clear();
+        for (entry, last) in &mut self.entries {
+            let value = entry.read(cpu);
+            if let Some(old) = last.filter(|&old| old != value) {
+                self.changes.push(Change { name: entry.name.clone(), old, new: value, text: entry.format(value) });
+            }
+            *last = Some(value);
+        }
+    }
+
+    /// What changed at the last update.
+    pub fn changes(&self) -> &[Change] {
+        &self.changes
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_formats() {
+        let mut cpu = CPU::new();
+        cpu.bus.mem_write(0x10, 0x99);
+        cpu.bus.mem_write(0x11, 0x12);
+        let entry = |size, format| WatchEntry::new("x", 0x10, size, format).unwrap();
+        assert_eq!(entry(1, Format::Unsigned).format(entry(1, Format::Unsigned).read(&cpu)), "153");
+        assert_eq!(entry(1, Format::Signed).format(0x99), "-103");
+        assert_eq!(entry(2, Format::Signed).format(0x1299), "4761");
+        assert_eq!(entry(2, Format::Hex).read(&cpu), 0x1299);
+        assert_eq!(entry(2, Format::Hex).format(0x1299), "$1299");
+        assert_eq!(entry(4, Format::Bcd).format(0x1299), "00001299");
+        assert!(WatchEntry::new("x", 0, 3, Format::Hex).is_err());
+    }
+
+    #[test]
+    fn test_changes() {
+        let mut cpu = CPU::new();
+        let mut watch = RamWatch::new();
+        watch.add(WatchEntry::new("lives", 0x20, 1, Format::Unsigned).unwrap());
+        watch.add(WatchEntry::new("score", 0x21, 2, Format::Bcd).unwrap());
+        watch.update(&cpu);
+        assert!(watch.changes().is_empty());
+
+        cpu.bus.mem_write(0x21, 0x50);
+        watch.update(&cpu);
+        assert_eq!(watch.changes(), [Change { name: "score".to_string(), old: 0, new: 0x50, text: "0050".to_string() }]);
+        watch.update(&cpu);
+        assert!(watch.changes().is_empty());
+
+        assert!(watch.remove("lives"));
+        assert_eq!(watch.values(&cpu)[0].1, "0050");
+    }
+}
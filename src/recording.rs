@@ -0,0 +1,168 @@
+use crate::frame::{Frame, HEIGHT, WIDTH};
+use crate::wav::WavWriter;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+
+enum Video {
+    // RGBA frames back to back
+    Raw(BufWriter<File>),
+    // its stdin is piped, and closed by waiting for it
+    Ffmpeg(Child),
+}
+
+// an ffmpeg recording with audio goes to two files first, muxed at the end
+struct Mux {
+    video: PathBuf,
+    audio: PathBuf,
+    output: PathBuf,
+}
+
+/// Records gameplay to files, frame by frame. Start and stop one on a
+/// running console with `Nes::start_recording` and `Nes::stop_recording`.
+pub struct Recorder {
+    video: Video,
+    audio: Option<WavWriter<BufWriter<File>>>,
+    mux: Option<Mux>,
+    frames: u64,
+    // the first thing that went wrong; nothing more is recorded after it
+    error: Option<String>,
+}
+
+impl Recorder {
+    /// Writes every frame to `path` as raw 256x240 RGBA, 4 bytes a pixel,
+    /// and with a `sample_rate` the audio next to it as a .wav.
+    pub fn raw<P: AsRef<Path>>(path: P, sample_rate: Option<u32>) -> Result<Recorder, String> {
+        let path = path.as_ref();
+        let file = File::create(path).map_err(|err| format!("couldn't create {}: {}", path.display(), err))?;
+        let audio = sample_rate.map(|rate| WavWriter::create(path.with_extension("wav"), rate, 1)).transpose()?;
+        Ok(Recorder { video: Video::Raw(BufWriter::new(file)), audio, mux: None, frames: 0, error: None })
+    }
+
+    /// Pipes the frames to the `ffmpeg` on the PATH, which encodes them to
+    /// `path` in whatever format its extension names: .mp4, .gif, .apng
+    /// and so on. With a `sample_rate` the audio goes to a .wav that
+    /// `finish` muxes in, which needs a format that takes audio.
+    pub fn ffmpeg<P: AsRef<Path>>(path: P, frame_rate: f64, sample_rate: Option<u32>) -> Result<Recorder, String> {
+        let output = path.as_ref().to_path_buf();
+        let (video_path, mux) = match sample_rate {
+            Some(_) => {
+                let extension = output.extension().and_then(|ext| ext.to_str()).unwrap_or("mkv");
+                let video = output.with_extension(format!("video.{}", extension));
+                let mux = Mux { video: video.clone(), audio: output.with_extension("wav"), output };
+                (video, Some(mux))
+            }
+            None => (output, None),
+        };
+        let child = Command::new("ffmpeg")
+            .args(["-y", "-loglevel", "error", "-f", "rawvideo", "-pixel_format", "rgba"])
+            .args(["-video_size", &format!("{}x{}", WIDTH, HEIGHT), "-framerate", &frame_rate.to_string(), "-i", "-"])
+            .arg(&video_path)
+            .stdin(Stdio::piped())
+            .spawn()
+            .map_err(|err| format!("couldn't run ffmpeg: {}", err))?;
+        let audio = match (&mux, sample_rate) {
+            (Some(mux), Some(rate)) => Some(WavWriter::create(&mux.audio, rate, 1)?),
+            _ => None,
+        };
+        Ok(Recorder { video: Video::Ffmpeg(child), audio, mux, frames: 0, error: None })
+    }
+
+    /// Whether audio is recorded, so the console knows to keep a copy.
+    pub fn has_audio(&self) -> bool {
+        self.audio.is_some()
+    }
+
+    /// Frames recorded so far.
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Adds a frame and the audio that goes with it. Errors are kept for
+    /// `finish` to report.
+    pub fn record(&mut self, frame: &Frame, samples: &[f32]) {
+        if self.error.is_some() {
+            return;
+        }
+        let video = match &mut self.video {
+            Video::Raw(file) => file.write_all(frame.pixels()),
+            Video::Ffmpeg(child) => child.stdin.as_mut().expect("ffmpeg's stdin is piped").write_all(frame.pixels()),
+        };
+        let result = video.map_err(|err| format!("couldn't write frame {}: {}", self.frames, err));
+        let result = result.and_then(|_| self.audio.as_mut().map_or(Ok(()), |audio| audio.write(samples)));
+        match result {
+            Ok(()) => self.frames += 1,
+            Err(err) => self.error = Some(err),
+        }
+    }
+
+    /// Closes the files, waiting for ffmpeg to finish encoding and muxing.
+    pub fn finish(self) -> Result<(), String> {
+        let audio = self.audio.map(WavWriter::finish).transpose();
+        let video = match self.video {
+            Video::Raw(mut file) => file.flush().map_err(|err| format!("couldn't write video: {}", err)),
+            Video::Ffmpeg(child) => wait(child),
+        };
+        if let Some(err) = self.error {
+            return Err(err);
+        }
+        video?;
+        audio?;
+        match self.mux {
+            Some(mux) => mux.run(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Mux {
+    fn run(self) -> Result<(), String> {
+        let child = Command::new("ffmpeg")
+            .args(["-y", "-loglevel", "error", "-i"])
+            .arg(&self.video)
+            .arg("-i")
+            .arg(&self.audio)
+            .args(["-c:v", "copy", "-shortest"])
+            .arg(&self.output)
+            .spawn()
+            .map_err(|err| format!("couldn't run ffmpeg: {}", err))?;
+        wait(child)?;
+        let _ = fs::remove_file(&self.video);
+        let _ = fs::remove_file(&self.audio);
+        Ok(())
+    }
+}
+
+fn wait(mut child: Child) -> Result<(), String> {
+    let status = child.wait().map_err(|err| format!("ffmpeg didn't finish: {}", err))?;
+    if !status.success() {
+        return Err(format!("ffmpeg failed with {}", status));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_raw() {
+        let path = std::env::temp_dir().join(format!("nessie-recording-{}.rgba", std::process::id()));
+        let mut recorder = Recorder::raw(&path, Some(44100)).unwrap();
+        let mut frame = Frame::new();
+        frame.set_pixel(0, 0, (1, 2, 3));
+        recorder.record(&frame, &[0.5; 10]);
+        recorder.record(&frame, &[0.5; 5]);
+        assert_eq!(recorder.frames(), 2);
+        recorder.finish().unwrap();
+
+        let video = fs::read(&path).unwrap();
+        let audio = fs::read(path.with_extension("wav")).unwrap();
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(path.with_extension("wav")).unwrap();
+        assert_eq!(video.len(), 2 * WIDTH * HEIGHT * 4);
+        assert_eq!(&video[WIDTH * HEIGHT * 4..][..4], &[1, 2, 3, 0xff]);
+        assert_eq!(audio.len(), 44 + 15 * 4);
+    }
+}
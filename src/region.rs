@@ -0,0 +1,69 @@
+/// TV system the console is built for, which sets its clocks and frame timing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Region {
+    #[default]
+    Ntsc,
+    Pal,
+    /// PAL famiclones: PAL frame length, but vblank starts late so games
+    /// written for NTSC see about the same amount of vblank time.
+    Dendy,
+}
+
+impl Region {
+    pub fn scanlines_per_frame(&self) -> u16 {
+        match self {
+            Region::Ntsc => 262,
+            Region::Pal | Region::Dendy => 312,
+        }
+    }
+
+    /// The scanline where the vblank flag gets set and the NMI fires.
+    pub fn vblank_scanline(&self) -> u16 {
+        match self {
+            Region::Ntsc | Region::Pal => 241,
+            Region::Dendy => 291,
+        }
+    }
+
+    /// The scanline right before the first visible one, where flags are cleared
+    /// and the scroll position is reloaded.
+    pub fn pre_render_scanline(&self) -> u16 {
+        self.scanlines_per_frame() - 1
+    }
+
+    /// Master clock cycles per CPU cycle and per PPU dot.
+    pub fn master_clock_dividers(&self) -> (u64, u64) {
+        match self {
+            Region::Ntsc => (12, 4),
+            Region::Pal => (16, 5),
+            Region::Dendy => (15, 5),
+        }
+    }
+
+    pub fn cpu_clock_hz(&self) -> f64 {
+        match self {
+            Region::Ntsc => 1_789_773.0,
+            Region::Pal => 1_662_607.0,
+            Region::Dendy => 1_773_448.0,
+        }
+    }
+
+    pub fn frame_rate(&self) -> f64 {
+        match self {
+            Region::Ntsc => 60.0988,
+            Region::Pal | Region::Dendy => 50.0070,
+        }
+    }
+
+    /// Only the NTSC PPU shortens odd frames by a dot.
+    pub fn skips_odd_frame_dot(&self) -> bool {
+        matches!(self, Region::Ntsc)
+    }
+
+    /// The 2C07 and the famiclone PPUs wire the red and green emphasis bits
+    /// the other way around.
+    pub fn swaps_emphasis_red_green(&self) -> bool {
+        matches!(self, Region::Pal | Region::Dendy)
+    }
+}
@@ -0,0 +1,196 @@
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use crate::bytes::read_bytes;
+use crate::cpu::CPU;
+
+/// Steps back through gameplay. A copy of the console is kept every
+/// `interval` frames, up to `capacity` of them, oldest dropped first.
+///
+/// Copies are split in two: the small chip state is kept as is, while the
+/// memory (RAM, ROM, VRAM, the picture) only changes a little from one copy
+/// to the next, so all but the newest copy keep it as the XOR against the
+/// next newer one, run-length compressed.
+pub struct Rewind {
+    capacity: usize,
+    interval: u64,
+    entries: VecDeque<Entry>,
+    // memory of the newest entry, which the deltas are taken against
+    newest: Vec<u8>,
+}
+
+struct Entry {
+    frame: u64,
+    // console with its memory taken out
+    cpu: Box<CPU>,
+    // compressed XOR of this entry's memory and the next newer one's;
+    // empty for the newest entry
+    delta: Vec<u8>,
+}
+
+impl Rewind {
+    pub fn new(capacity: usize, interval: u64) -> Self {
+        Rewind {
+            capacity: capacity.max(1),
+            interval: interval.max(1),
+            entries: VecDeque::new(),
+            newest: Vec::new(),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn interval(&self) -> u64 {
+        self.interval
+    }
+
+    /// Copies held right now.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Bytes taken up by the compressed memory of all copies, the newest
+    /// one's uncompressed memory included.
+    pub fn memory_size(&self) -> usize {
+        self.newest.len() + self.entries.iter().map(|entry| entry.delta.len()).sum::<usize>()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.newest.clear();
+    }
+
+    /// Takes a copy of `cpu` if it's time for one.
+    pub(crate) fn capture(&mut self, cpu: &CPU) {
+        let frame = cpu.bus.ppu.frame().number();
+        if !frame.is_multiple_of(self.interval) || self.entries.back().is_some_and(|entry| entry.frame >= frame) {
+            return;
+        }
+        let mut copy = Box::new(cpu.clone());
+        copy.bus.apu.flush_samples();
+        let mut memory = Vec::with_capacity(self.newest.len());
+        copy.bus.take_memory(&mut memory);
+
+        if let Some(previous) = self.entries.back_mut() {
+            previous.delta = compress(&self.newest, &memory);
+        }
+        self.newest = memory;
+        self.entries.push_back(Entry { frame, cpu: copy, delta: Vec::new() });
+        if self.entries.len() > self.capacity {
+            self.entries.pop_front();
+        }
+    }
+
+    /// Goes back to the newest copy at least `frames` frames before
+    /// `current`, dropping everything newer. Gives that console back;
+    /// `None`, keeping every copy, if none is that old.
+    pub(crate) fn rewind(&mut self, current: u64, frames: u64) -> Option<CPU> {
+        let target = current.saturating_sub(frames);
+        if self.entries.front().is_none_or(|entry| entry.frame > target) {
+            return None;
+        }
+        while self.entries.len() > 1 && self.entries.back().is_some_and(|entry| entry.frame > target) {
+            self.entries.pop_back();
+            let previous = self.entries.back_mut().unwrap();
+            self.newest = decompress(&self.newest, &core::mem::take(&mut previous.delta));
+        }
+        let entry = self.entries.back()?;
+        let mut cpu = (*entry.cpu).clone();
+        cpu.bus.put_memory(&mut &self.newest[..]);
+        Some(cpu)
+    }
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(data: &mut &[u8]) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = read_bytes(data, 1)[0];
+        value |= ((byte & 0x7f) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return value;
+        }
+        shift += 7;
+    }
+}
+
+// XORs `old` against `new`, treating whichever is shorter as padded with
+// zeros, and packs the result as (zero run, literal run, literals) triples
+// after the length of `old`
+fn compress(old: &[u8], new: &[u8]) -> Vec<u8> {
+    let xor = |i: usize| old.get(i).copied().unwrap_or(0) ^ new.get(i).copied().unwrap_or(0);
+    let len = old.len().max(new.len());
+    let mut out = Vec::new();
+    write_varint(&mut out, old.len());
+    let mut i = 0;
+    while i < len {
+        let zeros_start = i;
+        while i < len && xor(i) == 0 {
+            i += 1;
+        }
+        let literals_start = i;
+        // a lone zero between changed bytes isn't worth a new triple
+        while i < len && (xor(i) != 0 || (i + 1 < len && xor(i + 1) != 0)) {
+            i += 1;
+        }
+        write_varint(&mut out, literals_start - zeros_start);
+        write_varint(&mut out, i - literals_start);
+        out.extend((literals_start..i).map(xor));
+    }
+    out
+}
+
+// undoes `compress`: gives `old` back from `new` and the delta
+fn decompress(new: &[u8], delta: &[u8]) -> Vec<u8> {
+    let mut data = delta;
+    let len = read_varint(&mut data);
+    let mut old = new.to_vec();
+    old.resize(len.max(new.len()), 0);
+    let mut i = 0;
+    while !data.is_empty() {
+        i += read_varint(&mut data);
+        let literals = read_varint(&mut data);
+        for byte in read_bytes(&mut data, literals) {
+            old[i] ^= byte;
+            i += 1;
+        }
+    }
+    old.truncate(len);
+    old
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_delta_round_trip() {
+        let old: Vec<u8> = (0..5000).map(|i| (i / 7) as u8).collect();
+        let mut new = old.clone();
+        new[10] ^= 0xff;
+        new[12] = 3;
+        new[4000] = 0;
+        let delta = compress(&old, &new);
+        assert!(delta.len() < 20, "{}", delta.len());
+        assert_eq!(decompress(&new, &delta), old);
+
+        // lengths can change, e.g. with another cartridge
+        let shorter = &old[..100];
+        assert_eq!(decompress(&new, &compress(shorter, &new)), shorter);
+        assert_eq!(decompress(shorter, &compress(&new, shorter)), new);
+    }
+}
@@ -0,0 +1,83 @@
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+use crate::region::Region;
+#[cfg(feature = "std")]
+use std::fs;
+#[cfg(feature = "std")]
+use std::path::Path;
+
+/// What's known about games beyond their header, looked up by
+/// `Cartridge::crc32`. Starts out empty; frontends fill it from a list such
+/// as one exported from NesCartDB.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RomDatabase {
+    regions: BTreeMap<u32, Region>,
+}
+
+impl RomDatabase {
+    pub fn new() -> Self {
+        RomDatabase::default()
+    }
+
+    pub fn insert(&mut self, crc: u32, region: Region) {
+        self.regions.insert(crc, region);
+    }
+
+    pub fn region(&self, crc: u32) -> Option<Region> {
+        self.regions.get(&crc).copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.regions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.regions.is_empty()
+    }
+
+    /// Reads lines of a hex CRC-32 and a region ("ntsc", "pal" or "dendy"),
+    /// like `1a2b3c4d pal`. Blank lines and lines starting with # are skipped.
+    pub fn from_text(text: &str) -> Result<RomDatabase, String> {
+        let mut database = RomDatabase::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let err = |what: &str| format!("ROM database line {}: {}", number + 1, what);
+            let (crc, region) = line.split_once(char::is_whitespace).ok_or_else(|| err("expected a CRC and a region"))?;
+            let crc = u32::from_str_radix(crc, 16).map_err(|_| err("bad CRC"))?;
+            let region = match region.trim().to_ascii_lowercase().as_str() {
+                "ntsc" => Region::Ntsc,
+                "pal" => Region::Pal,
+                "dendy" => Region::Dendy,
+                other => return Err(err(&format!("unknown region {}", other))),
+            };
+            database.insert(crc, region);
+        }
+        Ok(database)
+    }
+
+    #[cfg(feature = "std")]
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<RomDatabase, String> {
+        let text = fs::read_to_string(path.as_ref()).map_err(|e| format!("can't read {}: {}", path.as_ref().display(), e))?;
+        RomDatabase::from_text(&text)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let database = RomDatabase::from_text("# crc region\n\n1A2B3C4D pal\n00000001  Dendy\n").unwrap();
+        assert_eq!(database.len(), 2);
+        assert_eq!(database.region(0x1a2b3c4d), Some(Region::Pal));
+        assert_eq!(database.region(1), Some(Region::Dendy));
+        assert_eq!(database.region(2), None);
+        assert!(RomDatabase::from_text("xyz pal").is_err());
+        assert!(RomDatabase::from_text("12 secam").unwrap_err().contains("line 1"));
+    }
+}
@@ -0,0 +1,233 @@
+use crate::frame::{HEIGHT, WIDTH};
+use crate::nes::Nes;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// frames between auto-saves unless set otherwise, about a minute
+pub const DEFAULT_AUTO_SAVE_FRAMES: u64 = 3600;
+
+/// A shrunk copy of the picture at the time of a save, RGBA.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Thumbnail {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>,
+}
+
+/// Which slot a save is in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Slot {
+    Numbered(u8),
+    Auto,
+}
+
+/// What's known about a save without loading it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SlotInfo {
+    pub slot: Slot,
+    /// Seconds since the Unix epoch.
+    pub saved_at: u64,
+    pub frame: u64,
+    pub thumbnail: Option<Thumbnail>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SlotFile {
+    info: SlotInfo,
+    state: Vec<u8>,
+}
+
+/// Numbered save slots on disk, kept apart per game by `Nes::rom_crc`,
+/// plus an auto-save slot written every so many frames: F5/F7 behavior
+/// for frontends. Files go in one directory as `<crc>.slot<n>` and
+/// `<crc>.auto`.
+pub struct SlotManager {
+    dir: PathBuf,
+    selected: u8,
+    thumbnails: bool,
+    auto_save_frames: Option<u64>,
+    last_auto_save: Option<u64>,
+}
+
+impl SlotManager {
+    pub fn new<P: AsRef<Path>>(dir: P) -> Self {
+        SlotManager {
+            dir: dir.as_ref().to_path_buf(),
+            selected: 0,
+            thumbnails: true,
+            auto_save_frames: Some(DEFAULT_AUTO_SAVE_FRAMES),
+            last_auto_save: None,
+        }
+    }
+
+    pub fn selected(&self) -> u8 {
+        self.selected
+    }
+
+    /// Picks the slot `save_selected` and `load_selected` use.
+    pub fn select(&mut self, slot: u8) {
+        self.selected = slot;
+    }
+
+    /// Embeds a thumbnail of the picture in every save, on by default.
+    pub fn set_thumbnails(&mut self, thumbnails: bool) {
+        self.thumbnails = thumbnails;
+    }
+
+    /// Frames between auto-saves; `None` turns auto-saving off.
+    pub fn set_auto_save(&mut self, frames: Option<u64>) {
+        self.auto_save_frames = frames.map(|frames| frames.max(1));
+    }
+
+    fn path(&self, nes: &Nes, slot: Slot) -> Result<PathBuf, String> {
+        let crc = nes.rom_crc().ok_or("no cartridge inserted")?;
+        let name = match slot {
+            Slot::Numbered(n) => format!("{:08x}.slot{}", crc, n),
+            Slot::Auto => format!("{:08x}.auto", crc),
+        };
+        Ok(self.dir.join(name))
+    }
+
+    pub fn save(&self, nes: &Nes, slot: Slot) -> Result<SlotInfo, String> {
+        let path = self.path(nes, slot)?;
+        let saved_at = SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or(0);
+        let info = SlotInfo {
+            slot,
+            saved_at,
+            frame: nes.cpu().bus.ppu.frame().number(),
+            thumbnail: self.thumbnails.then(|| thumbnail(nes)),
+        };
+        let file = SlotFile { info: info.clone(), state: nes.save_state() };
+        let data = bincode::serialize(&file).map_err(|e| format!("can't encode save: {}", e))?;
+        fs::create_dir_all(&self.dir).map_err(|e| format!("can't create {}: {}", self.dir.display(), e))?;
+        fs::write(&path, data).map_err(|e| format!("can't write {}: {}", path.display(), e))?;
+        Ok(info)
+    }
+
+    pub fn load(&self, nes: &mut Nes, slot: Slot) -> Result<SlotInfo, String> {
+        let file = self.read(nes, slot)?.ok_or_else(|| format!("{:?} is empty", slot))?;
+        nes.load_state(&file.state)?;
+        Ok(file.info)
+    }
+
+    pub fn save_selected(&self, nes: &Nes) -> Result<SlotInfo, String> {
+        self.save(nes, Slot::Numbered(self.selected))
+    }
+
+    pub fn load_selected(&self, nes: &mut Nes) -> Result<SlotInfo, String> {
+        self.load(nes, Slot::Numbered(self.selected))
+    }
+
+    /// Info about the save in `slot`, `None` if it's empty.
+    pub fn info(&self, nes: &Nes, slot: Slot) -> Result<Option<SlotInfo>, String> {
+        Ok(self.read(nes, slot)?.map(|file| file.info))
+    }
+
+    /// The saves there are for the game in `nes`, numbered slots first.
+    pub fn list(&self, nes: &Nes) -> Vec<SlotInfo> {
+        (0..=u8::MAX)
+            .map(Slot::Numbered)
+            .chain([Slot::Auto])
+            .filter_map(|slot| self.info(nes, slot).ok().flatten())
+            .collect()
+    }
+
+    /// Call once a frame: writes the auto-save slot when it's due. Returns
+    /// whether it did.
+    pub fn auto_save(&mut self, nes: &Nes) -> Result<bool, String> {
+        let (Some(frames), Some(_)) = (self.auto_save_frames, nes.rom_crc()) else {
+            return Ok(false);
+        };
+        let now = nes.cpu().bus.ppu.frame().number();
+        match self.last_auto_save {
+            // the console went back in time, count from there
+            Some(last) if now < last => self.last_auto_save = Some(now),
+            Some(last) if now - last >= frames => {
+                self.save(nes, Slot::Auto)?;
+                self.last_auto_save = Some(now);
+                return Ok(true);
+            }
+            Some(_) => {}
+            None => self.last_auto_save = Some(now),
+        }
+        Ok(false)
+    }
+
+    fn read(&self, nes: &Nes, slot: Slot) -> Result<Option<SlotFile>, String> {
+        let path = self.path(nes, slot)?;
+        if !path.exists() {
+            return Ok(None);
+        }
+        let data = fs::read(&path).map_err(|e| format!("can't read {}: {}", path.display(), e))?;
+        let file = bincode::deserialize(&data).map_err(|e| format!("can't read {}: {}", path.display(), e))?;
+        Ok(Some(file))
+    }
+}
+
+// every other pixel of every other line
+fn thumbnail(nes: &Nes) -> Thumbnail {
+    let frame = nes.cpu().bus.ppu.frame();
+    let (width, height) = (WIDTH / 2, HEIGHT / 2);
+    let mut pixels = Vec::with_capacity(width * height * 4);
+    for y in 0..height {
+        for x in 0..width {
+            let (r, g, b) = frame.pixel(x * 2, y * 2);
+            pixels.extend_from_slice(&[r, g, b, 0xff]);
+        }
+    }
+    Thumbnail { width, height, pixels }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::test_nes;
+
+    fn console() -> Nes {
+        test_nes(
+            "
+                    .org $C000
+            reset:  inc $10
+                    jmp reset
+                    .org $FFFA
+                    .word reset, reset, reset
+            ",
+        )
+    }
+
+    #[test]
+    fn test_slots() {
+        let dir = std::env::temp_dir().join(format!("nessie-slots-{}", std::process::id()));
+        let mut slots = SlotManager::new(&dir);
+        let mut nes = console();
+        assert!(slots.load_selected(&mut nes).is_err());
+
+        nes.run_frame();
+        slots.select(3);
+        let info = slots.save_selected(&nes).unwrap();
+        assert_eq!(info.slot, Slot::Numbered(3));
+        assert_eq!(info.thumbnail.as_ref().unwrap().pixels.len(), 128 * 120 * 4);
+        let counter = nes.cpu_mut().bus.mem_read(0x10);
+
+        nes.run_frame();
+        assert_eq!(slots.load_selected(&mut nes).unwrap(), info);
+        assert_eq!(nes.cpu_mut().bus.mem_read(0x10), counter);
+        assert_eq!(slots.list(&nes), vec![info]);
+        assert!(Nes::new().rom_crc().is_none());
+
+        slots.set_auto_save(Some(2));
+        let saved: Vec<bool> = (0..4)
+            .map(|_| {
+                nes.run_frame();
+                slots.auto_save(&nes).unwrap()
+            })
+            .collect();
+        // counting starts at the first call
+        assert_eq!(saved, vec![false, false, true, false]);
+        assert_eq!(slots.info(&nes, Slot::Auto).unwrap().unwrap().frame, 4);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,194 @@
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use std::fs;
+#[cfg(feature = "std")]
+use std::path::Path;
+
+/// Names for CPU addresses, read from the label files assemblers and other
+/// emulators' debuggers write, so addresses show as `reset` or `nmi_handler`.
+/// An address keeps the first name it's given.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Symbols {
+    labels: BTreeMap<u16, String>,
+}
+
+impl Symbols {
+    pub fn new() -> Self {
+        Symbols::default()
+    }
+
+    /// Reads a label file by its extension: FCEUX `.nl`, Mesen `.mlb` or a
+    /// ca65/ld65 `.dbg`. Mesen labels PRG ROM by offset, so mapping them to
+    /// addresses takes the size of the game's PRG ROM.
+    #[cfg(feature = "std")]
+    pub fn load<P: AsRef<Path>>(path: P, prg_rom_size: usize) -> Result<Symbols, String> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).map_err(|err| format!("couldn't read {}: {}", path.display(), err))?;
+        let mut symbols = Symbols::new();
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("nl") => symbols.parse_nl(&text)?,
+            Some("mlb") => symbols.parse_mlb(&text, prg_rom_size)?,
+            Some("dbg") => symbols.parse_dbg(&text)?,
+            _ => return Err(format!("{} isn't a .nl, .mlb or .dbg file", path.display())),
+        }
+        Ok(symbols)
+    }
+
+    pub fn insert(&mut self, addr: u16, name: &str) {
+        self.labels.entry(addr).or_insert_with(|| name.to_string());
+    }
+
+    pub fn label(&self, addr: u16) -> Option<&str> {
+        self.labels.get(&addr).map(String::as_str)
+    }
+
+    pub fn address(&self, name: &str) -> Option<u16> {
+        self.labels.iter().find(|(_, label)| *label == name).map(|(&addr, _)| addr)
+    }
+
+    pub fn labels(&self) -> &BTreeMap<u16, String> {
+        &self.labels
+    }
+
+    pub fn len(&self) -> usize {
+        self.labels.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.labels.is_empty()
+    }
+
+    /// FCEUX name lists: `$C000#reset#comment`, or `$0300/10#buffer#` for
+    /// an array, which is named by its first address. Other lines are
+    /// comment continuations.
+    pub fn parse_nl(&mut self, text: &str) -> Result<(), String> {
+        for (number, line) in text.lines().enumerate() {
+            let Some(rest) = line.strip_prefix('$') else {
+                continue;
+            };
+            let mut fields = rest.split('#');
+            let addr = fields.next().unwrap_or("").split('/').next().unwrap_or("");
+            let addr = u16::from_str_radix(addr, 16).map_err(|_| format!("line {}: bad address {:?}", number + 1, addr))?;
+            match fields.next() {
+                Some(name) if !name.is_empty() => self.insert(addr, name),
+                Some(_) => {}
+                None => return Err(format!("line {}: expected $address#name#", number + 1)),
+            }
+        }
+        Ok(())
+    }
+
+    /// Mesen label files: `P:0123:reset` with a memory type, an address or
+    /// range in it, the label and an optional comment. PRG ROM offsets are
+    /// mapped the way NROM maps them.
+    pub fn parse_mlb(&mut self, text: &str, prg_rom_size: usize) -> Result<(), String> {
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim_end();
+            if line.is_empty() {
+                continue;
+            }
+            let mut fields = line.splitn(4, ':');
+            let (Some(kind), Some(addr), Some(name)) = (fields.next(), fields.next(), fields.next()) else {
+                return Err(format!("line {}: expected type:address:label", number + 1));
+            };
+            let addr = addr.split('-').next().unwrap_or("");
+            let offset = usize::from_str_radix(addr, 16).map_err(|_| format!("line {}: bad address {:?}", number + 1, addr))?;
+            if name.is_empty() {
+                continue;
+            }
+            let addrs: Vec<usize> = match kind {
+                "P" | "NesPrgRom" if prg_rom_size == 0x4000 => vec![0x8000 + offset, 0xc000 + offset],
+                "P" | "NesPrgRom" => vec![0x8000 + offset],
+                "R" | "NesInternalRam" => vec![offset],
+                "S" | "W" | "NesSaveRam" | "NesWorkRam" => vec![0x6000 + offset],
+                "G" | "NesMemory" | "Register" => vec![offset],
+                // labels in CHR and other memories have no CPU address
+                _ => continue,
+            };
+            for addr in addrs.into_iter().filter(|&addr| addr <= 0xffff) {
+                self.insert(addr as u16, name);
+            }
+        }
+        Ok(())
+    }
+
+    /// The `sym` lines of a debug file written by `ld65 --dbgfile`. Only
+    /// labels count, not constants, and cheap locals like `@loop` are left
+    /// out as their names repeat.
+    pub fn parse_dbg(&mut self, text: &str) -> Result<(), String> {
+        for (number, line) in text.lines().enumerate() {
+            let Some(rest) = line.strip_prefix("sym") else {
+                continue;
+            };
+            let mut name = None;
+            let mut value = None;
+            let mut label = true;
+            for field in rest.trim_start().split(',') {
+                match field.split_once('=') {
+                    Some(("name", quoted)) => name = Some(quoted.trim_matches('"')),
+                    Some(("val", val)) => {
+                        let parsed = match val.strip_prefix("0x") {
+                            Some(hex) => u32::from_str_radix(hex, 16),
+                            None => val.parse(),
+                        };
+                        value = Some(parsed.map_err(|_| format!("line {}: bad value {:?}", number + 1, val))?);
+                    }
+                    Some(("type", kind)) => label = kind == "lab",
+                    _ => {}
+                }
+            }
+            if let (Some(name), Some(value), true) = (name, value, label) {
+                if !name.starts_with('@') && value <= 0xffff {
+                    self.insert(value as u16, name);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_nl() {
+        let mut symbols = Symbols::new();
+        symbols.parse_nl("$C000#reset#Power on\n\\ more comment\n$0300/10#buffer#\n$C010##no name\n").unwrap();
+        assert_eq!(symbols.label(0xc000), Some("reset"));
+        assert_eq!(symbols.label(0x0300), Some("buffer"));
+        assert_eq!(symbols.len(), 2);
+        assert!(symbols.parse_nl("$XYZ#oops#").is_err());
+    }
+
+    #[test]
+    fn test_mlb() {
+        let mut symbols = Symbols::new();
+        let text = "P:0000:reset\nP:0010-001F:table:comment\nR:0010:frame_count\nW:0000:save\nG:2000:PPUCTRL\nC:0000:tiles\nP:0020::just a comment\n";
+        symbols.parse_mlb(text, 0x4000).unwrap();
+        assert_eq!(symbols.label(0x8000), Some("reset"));
+        assert_eq!(symbols.label(0xc000), Some("reset"));
+        assert_eq!(symbols.label(0xc010), Some("table"));
+        assert_eq!(symbols.label(0x0010), Some("frame_count"));
+        assert_eq!(symbols.label(0x6000), Some("save"));
+        assert_eq!(symbols.address("PPUCTRL"), Some(0x2000));
+        assert_eq!(symbols.len(), 7);
+        assert!(symbols.parse_mlb("P:reset", 0x4000).is_err());
+    }
+
+    #[test]
+    fn test_dbg() {
+        let text = "version\tmajor=2,minor=0\n\
+            sym\tid=0,name=\"reset\",addrsize=absolute,scope=0,def=1,val=0xC000,seg=0,type=lab\n\
+            sym\tid=1,name=\"@loop\",addrsize=absolute,scope=1,def=2,val=0xC003,seg=0,type=lab\n\
+            sym\tid=2,name=\"PPUCTRL\",addrsize=absolute,scope=0,def=3,val=0x2000,type=equ\n\
+            sym\tid=3,name=\"nmi\",addrsize=absolute,scope=0,def=4,val=49168,seg=0,type=lab\n";
+        let mut symbols = Symbols::new();
+        symbols.parse_dbg(text).unwrap();
+        assert_eq!(symbols.labels().iter().map(|(&addr, name)| (addr, name.as_str())).collect::<Vec<_>>(), vec![(0xc000, "reset"), (0xc010, "nmi")]);
+    }
+}
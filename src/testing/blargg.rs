@@ -0,0 +1,183 @@
+use crate::cartridge::Cartridge;
+use crate::nes::{Nes, ResetKind};
+
+// status byte, then a signature marking the rest as valid, then the text
+const STATUS: u16 = 0x6000;
+const SIGNATURE: [u8; 3] = [0xde, 0xb0, 0x61];
+const TEXT: u16 = 0x6004;
+const TEXT_END: u16 = 0x7fff;
+
+const RUNNING: u8 = 0x80;
+const NEEDS_RESET: u8 = 0x81;
+// the ROMs ask for the reset button to be held off for at least 100ms
+const RESET_DELAY_FRAMES: u64 = 6;
+
+/// What a finished test ROM reported.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TestResult {
+    /// 0 on success, otherwise the number of the failed check.
+    pub code: u8,
+    /// What the ROM printed, usually naming the failure.
+    pub text: String,
+}
+
+impl TestResult {
+    pub fn passed(&self) -> bool {
+        self.code == 0
+    }
+}
+
+/// Runs a blargg-style test ROM until it reports a result at $6000, for at
+/// most `frame_limit` frames, pressing reset whenever it asks for it. The
+/// ROM's own failure comes back as a result; the error is for a ROM that
+/// never reports one.
+pub fn run(cartridge: Cartridge, frame_limit: u64) -> Result<TestResult, String> {
+    let mut nes = Nes::new();
+    nes.insert_cartridge(cartridge);
+    let mut reset_at = None;
+    let mut reset_done = false;
+    for frame in 0..frame_limit {
+        nes.run_frame();
+        if !has_signature(&nes) {
+            continue;
+        }
+        match nes.cpu().bus.peek(STATUS) {
+            RUNNING => reset_done = false,
+            NEEDS_RESET => match reset_at {
+                None if !reset_done => reset_at = Some(frame + RESET_DELAY_FRAMES),
+                Some(at) if frame >= at => {
+                    nes.reset(ResetKind::Soft);
+                    reset_at = None;
+                    reset_done = true;
+                }
+                _ => {}
+            },
+            code => return Ok(TestResult { code, text: text(&nes) }),
+        }
+    }
+    if has_signature(&nes) {
+        Err(format!("no result after {} frames, output so far: {:?}", frame_limit, text(&nes)))
+    } else {
+        Err(format!("no test output after {} frames", frame_limit))
+    }
+}
+
+/// Like `run`, but turns anything but a pass into an error carrying the
+/// ROM's message, for use straight from a test.
+pub fn check(cartridge: Cartridge, frame_limit: u64) -> Result<(), String> {
+    let result = run(cartridge, frame_limit)?;
+    if result.passed() {
+        Ok(())
+    } else {
+        Err(format!("failed with code {}: {}", result.code, result.text.trim_end()))
+    }
+}
+
+fn has_signature(nes: &Nes) -> bool {
+    let bus = &nes.cpu().bus;
+    (0..3).all(|i| bus.peek(STATUS + 1 + i) == SIGNATURE[i as usize])
+}
+
+fn text(nes: &Nes) -> String {
+    let bus = &nes.cpu().bus;
+    let bytes: Vec<u8> = (TEXT..=TEXT_END).map(|addr| bus.peek(addr)).take_while(|&b| b != 0).collect();
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::test_rom;
+    use std::path::PathBuf;
+
+    fn store(program: &mut Vec<u8>, addr: u16, value: u8) {
+        program.extend_from_slice(&[0xa9, value, 0x8d, addr as u8, (addr >> 8) as u8]);
+    }
+
+    fn report(program: &mut Vec<u8>, code: u8, text: &str) {
+        for (i, &byte) in text.as_bytes().iter().chain(&[0]).enumerate() {
+            store(program, TEXT + i as u16, byte);
+        }
+        store(program, STATUS, code);
+    }
+
+    fn signature(program: &mut Vec<u8>) {
+        for (i, &byte) in SIGNATURE.iter().enumerate() {
+            store(program, STATUS + 1 + i as u16, byte);
+        }
+    }
+
+    fn halt(program: &mut Vec<u8>) {
+        let here = 0xc000 + program.len() as u16;
+        program.extend_from_slice(&[0x4c, here as u8, (here >> 8) as u8]);
+    }
+
+    fn cartridge(program: &[u8]) -> Cartridge {
+        let mut prg = vec![0xea; 0x4000];
+        prg[..program.len()].copy_from_slice(program);
+        prg[0x3ffc..].copy_from_slice(&[0x00, 0xc0, 0x00, 0xc0]);
+        Cartridge::new(&test_rom(&prg)).unwrap()
+    }
+
+    #[test]
+    fn test_pass_and_fail() {
+        let mut program = Vec::new();
+        signature(&mut program);
+        report(&mut program, 0, "Passed\n");
+        halt(&mut program);
+        let result = run(cartridge(&program), 10).unwrap();
+        assert!(result.passed());
+        assert_eq!(result.text, "Passed\n");
+
+        let mut program = Vec::new();
+        signature(&mut program);
+        report(&mut program, 3, "BRK\nFailed #3\n");
+        halt(&mut program);
+        assert_eq!(check(cartridge(&program), 10), Err("failed with code 3: BRK\nFailed #3".to_string()));
+    }
+
+    #[test]
+    fn test_presses_reset() {
+        // LDA $6000; CMP #$81; BNE ask; report a pass; ask: ask for a reset
+        let mut program = vec![0xad, 0x00, 0x60, 0xc9, NEEDS_RESET, 0xd0, 0x00];
+        let mut passed = Vec::new();
+        report(&mut passed, 0, "ok");
+        halt(&mut passed);
+        program[6] = passed.len() as u8;
+        program.extend(passed);
+        signature(&mut program);
+        store(&mut program, STATUS, NEEDS_RESET);
+        halt(&mut program);
+        assert_eq!(run(cartridge(&program), 20), Ok(TestResult { code: 0, text: "ok".to_string() }));
+    }
+
+    #[test]
+    fn test_no_output() {
+        let mut program = Vec::new();
+        halt(&mut program);
+        assert!(run(cartridge(&program), 3).is_err());
+
+        let mut program = Vec::new();
+        signature(&mut program);
+        report(&mut program, RUNNING, "working");
+        halt(&mut program);
+        assert!(run(cartridge(&program), 3).unwrap_err().contains("working"));
+    }
+
+    /// Runs every .nes file in the directory `BLARGG_DIR` names.
+    #[test]
+    #[ignore]
+    fn test_blargg_roms() {
+        let dir = PathBuf::from(std::env::var("BLARGG_DIR").expect("BLARGG_DIR isn't set"));
+        let mut paths: Vec<PathBuf> = std::fs::read_dir(dir).unwrap().map(|entry| entry.unwrap().path()).filter(|path| path.extension().is_some_and(|ext| ext == "nes")).collect();
+        paths.sort();
+        let failures: Vec<String> = paths
+            .iter()
+            .filter_map(|path| {
+                let outcome = Cartridge::load(path).and_then(|cartridge| check(cartridge, 60 * 60));
+                outcome.err().map(|err| format!("{}: {}", path.display(), err))
+            })
+            .collect();
+        assert!(failures.is_empty(), "{}", failures.join("\n"));
+    }
+}
@@ -0,0 +1,202 @@
+use crate::nes::Nes;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+/// Set to anything to have `check` write the frames it sees as the new
+/// goldens instead of comparing against them.
+pub const BLESS_VAR: &str = "NESSIE_BLESS";
+
+/// Frames a game is expected to show, by frame number, as `Frame::hash`es.
+/// Stored as text, one `frame hash` pair per line, so a changed golden
+/// shows up in review.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Goldens {
+    hashes: BTreeMap<u64, u32>,
+}
+
+impl Goldens {
+    /// Runs `nes` until each of `frames` is done and hashes it. Frames
+    /// already behind the console are skipped.
+    pub fn record(nes: &mut Nes, frames: &[u64]) -> Goldens {
+        let mut frames = frames.to_vec();
+        frames.sort_unstable();
+        let mut hashes = BTreeMap::new();
+        for number in frames {
+            while nes.frame().number() < number {
+                nes.run_frame();
+            }
+            if nes.frame().number() == number {
+                hashes.insert(number, nes.frame().hash());
+            }
+        }
+        Goldens { hashes }
+    }
+
+    pub fn parse(text: &str) -> Result<Goldens, String> {
+        let mut hashes = BTreeMap::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let parsed = line.split_once(' ').and_then(|(frame, hash)| Some((frame.parse().ok()?, u32::from_str_radix(hash.trim(), 16).ok()?)));
+            let (frame, hash) = parsed.ok_or_else(|| format!("line {}: expected a frame number and a hash, got {:?}", number + 1, line))?;
+            hashes.insert(frame, hash);
+        }
+        Ok(Goldens { hashes })
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Goldens, String> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).map_err(|err| format!("couldn't read {}: {}", path.display(), err))?;
+        Goldens::parse(&text)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        let path = path.as_ref();
+        fs::write(path, self.to_string()).map_err(|err| format!("couldn't write {}: {}", path.display(), err))
+    }
+
+    pub fn frames(&self) -> impl Iterator<Item = u64> + '_ {
+        self.hashes.keys().copied()
+    }
+
+    pub fn hash(&self, frame: u64) -> Option<u32> {
+        self.hashes.get(&frame).copied()
+    }
+
+    /// One line for every frame that differs from `expected` or is missing
+    /// from either.
+    pub fn diff(&self, expected: &Goldens) -> Vec<String> {
+        let mut frames: Vec<u64> = self.frames().chain(expected.frames()).collect();
+        frames.sort_unstable();
+        frames.dedup();
+        frames
+            .into_iter()
+            .filter_map(|frame| match (expected.hash(frame), self.hash(frame)) {
+                (Some(want), Some(got)) if want == got => None,
+                (Some(want), Some(got)) => Some(format!("frame {}: expected {:08x}, got {:08x}", frame, want, got)),
+                (Some(_), None) => Some(format!("frame {}: never reached", frame)),
+                (None, _) => Some(format!("frame {}: not in the goldens", frame)),
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for Goldens {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "# frame, CRC-32 of its color indices")?;
+        for (frame, hash) in &self.hashes {
+            writeln!(f, "{} {:08x}", frame, hash)?;
+        }
+        Ok(())
+    }
+}
+
+/// Runs `nes` through `frames` and compares them with the goldens at
+/// `path`, or writes them there if `NESSIE_BLESS` is set. A missing
+/// goldens file is an error until it's blessed.
+pub fn check<P: AsRef<Path>>(nes: &mut Nes, path: P, frames: &[u64]) -> Result<(), String> {
+    check_or_bless(nes, path.as_ref(), frames, std::env::var_os(BLESS_VAR).is_some())
+}
+
+fn check_or_bless(nes: &mut Nes, path: &Path, frames: &[u64], bless: bool) -> Result<(), String> {
+    let got = Goldens::record(nes, frames);
+    if bless {
+        return got.save(path);
+    }
+    if !path.exists() {
+        return Err(format!("no goldens at {}, run with {}=1 to write them", path.display(), BLESS_VAR));
+    }
+    let diff = got.diff(&Goldens::load(path)?);
+    if diff.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("{} doesn't match, run with {}=1 if the change is intended:\n{}", path.display(), BLESS_VAR, diff.join("\n")))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::asm::assemble;
+    use crate::cartridge::test::test_rom;
+    use crate::cartridge::Cartridge;
+    use std::path::PathBuf;
+
+    // sets the backdrop to `color` and turns the background on in the
+    // first vblank
+    fn backdrop(color: u8) -> Nes {
+        let source = format!(
+            "
+                    .org $C000
+            reset:  bit $2002
+            wait:   bit $2002
+                    bpl wait
+                    lda #$3F
+                    sta $2006
+                    lda #$00
+                    sta $2006
+                    lda #{}
+                    sta $2007
+                    lda #$08
+                    sta $2001
+            idle:   jmp idle
+                    .org $FFFA
+                    .word reset, reset, reset
+            ",
+            color
+        );
+        let mut nes = Nes::new();
+        nes.insert_cartridge(Cartridge::new(&test_rom(&assemble(&source).unwrap().bytes)).unwrap());
+        nes
+    }
+
+    #[test]
+    fn test_check_and_bless() {
+        let path = std::env::temp_dir().join(format!("nessie-golden-{}.txt", std::process::id()));
+        let _ = fs::remove_file(&path);
+        assert!(check_or_bless(&mut backdrop(0x21), &path, &[2, 4], false).unwrap_err().contains("no goldens"));
+        check_or_bless(&mut backdrop(0x21), &path, &[2, 4], true).unwrap();
+        let goldens = Goldens::load(&path).unwrap();
+        assert_eq!(goldens.frames().collect::<Vec<_>>(), vec![2, 4]);
+        assert_eq!(Goldens::parse(&goldens.to_string()), Ok(goldens));
+
+        check_or_bless(&mut backdrop(0x21), &path, &[2, 4], false).unwrap();
+        let err = check_or_bless(&mut backdrop(0x16), &path, &[2, 4, 6], false).unwrap_err();
+        fs::remove_file(&path).unwrap();
+        assert!(err.contains("frame 2: expected"));
+        assert!(err.contains("frame 6: not in the goldens"));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(Goldens::parse("# comment\n\n10 0badf00d\n").is_ok());
+        assert!(Goldens::parse("10\n").is_err());
+        assert!(Goldens::parse("ten 0badf00d\n").is_err());
+    }
+
+    /// Checks every .nes file in `GOLDEN_DIR` against the `.golden` file
+    /// next to it at a few frames; bless with `NESSIE_BLESS=1`.
+    #[test]
+    #[ignore]
+    fn test_golden_roms() {
+        let dir = PathBuf::from(std::env::var("GOLDEN_DIR").expect("GOLDEN_DIR isn't set"));
+        let mut paths: Vec<PathBuf> = fs::read_dir(dir).unwrap().map(|entry| entry.unwrap().path()).filter(|path| path.extension().is_some_and(|ext| ext == "nes")).collect();
+        paths.sort();
+        let failures: Vec<String> = paths
+            .iter()
+            .filter_map(|path| {
+                let outcome = Cartridge::load(path).and_then(|cartridge| {
+                    let mut nes = Nes::new();
+                    nes.insert_cartridge(cartridge);
+                    check(&mut nes, path.with_extension("golden"), &[60, 120, 300])
+                });
+                outcome.err().map(|err| format!("{}: {}", path.display(), err))
+            })
+            .collect();
+        assert!(failures.is_empty(), "{}", failures.join("\n"));
+    }
+}
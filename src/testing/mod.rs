@@ -0,0 +1,8 @@
+//! Harnesses that check the emulator against well-known test ROMs and
+//! against frames it rendered before. The ROMs themselves aren't shipped
+//! with the crate; point the ignored tests at them through the environment
+//! variables they name.
+
+pub mod blargg;
+pub mod golden;
+pub mod nestest;
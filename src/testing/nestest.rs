@@ -0,0 +1,137 @@
+use crate::cartridge::Cartridge;
+use crate::nes::Nes;
+use crate::trace;
+use std::fmt;
+
+// matching lines shown before the one that differs
+const CONTEXT_LINES: usize = 5;
+
+/// Where execution first stopped matching the reference log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    /// 1-based line of the reference log.
+    pub line: usize,
+    pub expected: String,
+    pub actual: String,
+    /// The lines that matched right before it.
+    pub context: Vec<String>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "diverged from the log at line {}:", self.line)?;
+        for line in &self.context {
+            writeln!(f, "           {}", line)?;
+        }
+        writeln!(f, "expected:  {}", self.expected)?;
+        write!(f, "actual:    {}", self.actual)
+    }
+}
+
+/// Runs nestest.nes in automation mode, from $C000 with the timing the
+/// reference log starts with, comparing every instruction against the
+/// lines of `log` (nestest.log), the unofficial opcodes from line 5004 on
+/// included. Returns how many lines matched when all of them do.
+pub fn run(cartridge: Cartridge, log: &str) -> Result<usize, Divergence> {
+    let mut nes = Nes::new();
+    nes.insert_cartridge(cartridge);
+    let cpu = nes.cpu_mut();
+    cpu.program_counter = 0xc000;
+    // the reset sequence the log accounts for
+    cpu.bus.tick(7);
+
+    let mut context = Vec::new();
+    let mut matched = 0;
+    for (number, expected) in log.lines().enumerate() {
+        let expected = expected.trim_end();
+        if expected.is_empty() {
+            continue;
+        }
+        let actual = trace::trace(nes.cpu());
+        if actual != expected {
+            return Err(Divergence { line: number + 1, expected: expected.to_string(), actual, context });
+        }
+        context.push(actual);
+        if context.len() > CONTEXT_LINES {
+            context.remove(0);
+        }
+        matched += 1;
+        nes.cpu_mut().step();
+    }
+    Ok(matched)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::test_rom;
+    use std::path::PathBuf;
+
+    fn rom() -> Cartridge {
+        let mut prg = vec![0xea; 0x4000];
+        let program = [
+            0xa2, 0x05, // LDX #$05
+            0xca, // DEX
+            0xd0, 0xfd, // BNE $C002
+            0x86, 0x10, // STX $10
+            0xa7, 0x10, // LAX $10, unofficial
+        ];
+        prg[..program.len()].copy_from_slice(&program);
+        prg[0x3ffc..].copy_from_slice(&[0x00, 0xc0, 0x00, 0xc0]);
+        Cartridge::new(&test_rom(&prg)).unwrap()
+    }
+
+    fn reference_log() -> Vec<String> {
+        let mut nes = Nes::new();
+        nes.insert_cartridge(rom());
+        nes.cpu_mut().bus.tick(7);
+        (0..14)
+            .map(|_| {
+                let line = trace::trace(nes.cpu());
+                nes.cpu_mut().step();
+                line
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_matching_log() {
+        let log = reference_log();
+        assert!(log[0].starts_with("C000  A2 05     LDX #$05"), "{}", log[0]);
+        assert!(log[0].ends_with("PPU:  0, 21 CYC:7"), "{}", log[0]);
+        assert_eq!(run(rom(), &log.join("\r\n")), Ok(14));
+    }
+
+    #[test]
+    fn test_reports_first_divergence() {
+        let mut log = reference_log();
+        log[9] = log[9].replace("A:00", "A:01");
+        let divergence = run(rom(), &log.join("\n")).unwrap_err();
+        assert_eq!(divergence.line, 10);
+        assert_eq!(divergence.context, log[4..9].to_vec());
+        assert!(divergence.to_string().contains("line 10"));
+    }
+
+    #[test]
+    fn test_compares_unofficial_opcodes() {
+        let mut log = reference_log();
+        assert!(log[12].starts_with("C007  A7 10    *LAX $10 = 00"), "{}", log[12]);
+        assert_eq!(run(rom(), &log.join("\n")), Ok(14));
+        log[13] = log[13].replace("A:00", "A:01");
+        assert_eq!(run(rom(), &log.join("\n")).unwrap_err().line, 14);
+    }
+
+    /// Needs nestest.nes and nestest.log in the directory `NESTEST_DIR`
+    /// names; neither ships with the crate, so the tests above only cover
+    /// the harness, against a log of a small program.
+    #[test]
+    #[ignore]
+    fn test_nestest() {
+        let dir = PathBuf::from(std::env::var("NESTEST_DIR").expect("NESTEST_DIR isn't set"));
+        let cartridge = Cartridge::load(dir.join("nestest.nes")).unwrap();
+        let log = std::fs::read_to_string(dir.join("nestest.log")).unwrap();
+        if let Err(divergence) = run(cartridge, &log) {
+            panic!("{}", divergence);
+        }
+    }
+}
@@ -0,0 +1,207 @@
+use crate::frame::Frame;
+use crate::joypad::ButtonState;
+use crate::nes::{Nes, ResetKind, Snapshot, Speed};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+// how long the thread sleeps when no frame is due
+const IDLE_WAIT: Duration = Duration::from_millis(1);
+
+/// What the emulator thread is told to do.
+pub enum Command {
+    /// Holds controller `player`'s buttons from now on.
+    SetButtons { player: usize, buttons: ButtonState },
+    Pause,
+    Resume,
+    Reset(ResetKind),
+    SetSpeed(Speed),
+    /// Runs one frame in `Speed::FrameStep`.
+    StepFrame,
+    /// Answered with `Event::Snapshot`.
+    TakeSnapshot,
+    Restore(Box<Snapshot>),
+    /// Answered with `Event::State`.
+    #[cfg(feature = "serde")]
+    SaveState,
+    /// Fails with `Event::Error`.
+    #[cfg(feature = "serde")]
+    LoadState(Vec<u8>),
+}
+
+/// What the emulator thread publishes.
+pub enum Event {
+    /// A finished picture, one per frame shown.
+    Frame(Box<Frame>),
+    /// Audio produced along with the frames since the last `Audio`.
+    Audio(Vec<f32>),
+    Snapshot(Box<Snapshot>),
+    #[cfg(feature = "serde")]
+    State(Vec<u8>),
+    Error(String),
+}
+
+/// Runs a console on a thread of its own at the pace `Nes::run_for`
+/// keeps, taking commands over a channel and sending back pictures and
+/// audio: the loop every GUI frontend ends up writing.
+pub struct EmulatorThread {
+    commands: Option<Sender<Command>>,
+    events: Receiver<Event>,
+    handle: Option<JoinHandle<Nes>>,
+}
+
+impl EmulatorThread {
+    pub fn spawn(nes: Nes) -> Self {
+        let (commands, command_rx) = mpsc::channel();
+        let (event_tx, events) = mpsc::channel();
+        let handle = thread::spawn(move || run(nes, command_rx, event_tx));
+        EmulatorThread { commands: Some(commands), events, handle: Some(handle) }
+    }
+
+    /// Queues a command. False if the thread is gone.
+    pub fn send(&self, command: Command) -> bool {
+        self.commands.as_ref().is_some_and(|commands| commands.send(command).is_ok())
+    }
+
+    pub fn events(&self) -> &Receiver<Event> {
+        &self.events
+    }
+
+    /// Stops the thread and gives the console back.
+    pub fn join(mut self) -> Nes {
+        self.stop().expect("emulator thread is only joined once")
+    }
+
+    fn stop(&mut self) -> Option<Nes> {
+        // hanging up is what tells the thread to stop
+        self.commands = None;
+        let handle = self.handle.take()?;
+        Some(handle.join().expect("emulator thread panicked"))
+    }
+}
+
+impl Drop for EmulatorThread {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+fn run(mut nes: Nes, commands: Receiver<Command>, events: Sender<Event>) -> Nes {
+    let mut last = Instant::now();
+    loop {
+        // take every queued command, and while paused wait for more
+        let mut waited = false;
+        loop {
+            let command = if nes.is_paused() {
+                waited = true;
+                match commands.recv() {
+                    Ok(command) => command,
+                    Err(_) => return nes,
+                }
+            } else {
+                match commands.try_recv() {
+                    Ok(command) => command,
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => return nes,
+                }
+            };
+            if let Some(event) = handle(&mut nes, command) {
+                if events.send(event).is_err() {
+                    return nes;
+                }
+            }
+        }
+        // time spent paused isn't owed
+        if waited {
+            last = Instant::now();
+        }
+
+        let now = Instant::now();
+        let frames = nes.run_for(now - last);
+        last = now;
+        if frames == 0 {
+            thread::sleep(IDLE_WAIT);
+            continue;
+        }
+        let mut audio = Vec::new();
+        nes.audio(&mut audio);
+        if events.send(Event::Frame(Box::new(nes.frame().clone()))).is_err() || events.send(Event::Audio(audio)).is_err() {
+            return nes;
+        }
+    }
+}
+
+fn handle(nes: &mut Nes, command: Command) -> Option<Event> {
+    match command {
+        Command::SetButtons { player, buttons } => {
+            if let Some(joypad) = nes.joypad_mut(player) {
+                joypad.set_buttons(buttons.0);
+            }
+        }
+        Command::Pause => nes.pause(),
+        Command::Resume => nes.resume(),
+        Command::Reset(kind) => nes.reset(kind),
+        Command::SetSpeed(speed) => nes.set_speed(speed),
+        Command::StepFrame => nes.step_frame(),
+        Command::TakeSnapshot => return Some(Event::Snapshot(Box::new(nes.snapshot()))),
+        Command::Restore(snapshot) => nes.restore(&snapshot),
+        #[cfg(feature = "serde")]
+        Command::SaveState => return Some(Event::State(nes.save_state())),
+        #[cfg(feature = "serde")]
+        Command::LoadState(data) => {
+            if let Err(e) = nes.load_state(&data) {
+                return Some(Event::Error(e));
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::test_rom;
+    use crate::cartridge::Cartridge;
+    use crate::joypad::Button;
+
+    fn assert_send<T: Send>() {}
+
+    #[test]
+    fn test_core_types_are_send() {
+        assert_send::<Nes>();
+        assert_send::<Snapshot>();
+        assert_send::<Command>();
+        assert_send::<Event>();
+    }
+
+    #[test]
+    fn test_thread_runs_frames_and_takes_commands() {
+        let mut prg = vec![0xea; 0x4000];
+        prg[..3].copy_from_slice(&[0x4c, 0x00, 0xc0]);
+        prg[0x3ffc..].copy_from_slice(&[0x00, 0xc0, 0x00, 0xc0]);
+        let mut nes = Nes::new();
+        nes.insert_cartridge(Cartridge::new(&test_rom(&prg)).unwrap());
+
+        let thread = EmulatorThread::spawn(nes);
+        thread.send(Command::SetSpeed(Speed::Uncapped));
+        thread.send(Command::SetButtons { player: 0, buttons: ButtonState::default().with(Button::Start) });
+        let frame = loop {
+            if let Event::Frame(frame) = thread.events().recv().unwrap() {
+                break frame;
+            }
+        };
+        assert!(frame.number() >= 1);
+
+        thread.send(Command::Pause);
+        thread.send(Command::TakeSnapshot);
+        let snapshot = loop {
+            if let Event::Snapshot(snapshot) = thread.events().recv().unwrap() {
+                break snapshot;
+            }
+        };
+        let mut nes = thread.join();
+        assert!(nes.is_paused());
+        assert!(nes.joypad_mut(0).unwrap().is_pressed(Button::Start));
+        assert_eq!(nes.frame().number(), snapshot.frame_number());
+    }
+}
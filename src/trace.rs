@@ -0,0 +1,138 @@
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use crate::cpu::{AddressingMode, CPU};
+use crate::disasm;
+use crate::ops;
+use crate::symbols::Symbols;
+
+/// The instruction `cpu` is about to run as a line of nestest.log, the
+/// format reference logs of most emulators come in:
+///
+/// `C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7`
+///
+/// Memory shown next to operands is read without side effects.
+pub fn trace(cpu: &CPU) -> String {
+    trace_line(cpu, None)
+}
+
+/// Like `trace`, with operand addresses replaced by their labels. Lines
+/// with labels won't line up with reference logs.
+pub fn trace_with_symbols(cpu: &CPU, symbols: &Symbols) -> String {
+    trace_line(cpu, Some(symbols))
+}
+
+fn trace_line(cpu: &CPU, symbols: Option<&Symbols>) -> String {
+    let bus = &cpu.bus;
+    let pc = cpu.program_counter;
+    let mut instruction = disasm::decode_with(|addr| bus.peek(addr), pc);
+    let bytes: Vec<String> = instruction.bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+    if let Some(symbols) = symbols {
+        instruction.symbolize(symbols);
+    }
+
+    let mut asm = instruction.to_string();
+    let op = ops::opcode(instruction.bytes[0]);
+    if let Some(op) = op {
+        let lo = instruction.bytes.get(1).copied().unwrap_or(0);
+        let word = u16::from_le_bytes([lo, instruction.bytes.get(2).copied().unwrap_or(0)]);
+        let peek_u16 = |addr: u16, wrap_page: bool| {
+            let hi_addr = if wrap_page { (addr & 0xff00) | (addr.wrapping_add(1) & 0x00ff) } else { addr.wrapping_add(1) };
+            u16::from_le_bytes([bus.peek(addr), bus.peek(hi_addr)])
+        };
+        let jump = op.name == "JMP" || op.name == "JSR";
+        match op.mode {
+            AddressingMode::ZeroPage => asm += &format!(" = {:02X}", bus.peek(lo as u16)),
+            AddressingMode::ZeroPage_X | AddressingMode::ZeroPage_Y => {
+                let index = if matches!(op.mode, AddressingMode::ZeroPage_X) { cpu.register_x } else { cpu.register_y };
+                let addr = lo.wrapping_add(index) as u16;
+                asm += &format!(" @ {:02X} = {:02X}", addr, bus.peek(addr));
+            }
+            AddressingMode::Absolute if !jump => asm += &format!(" = {:02X}", bus.peek(word)),
+            AddressingMode::Absolute_X | AddressingMode::Absolute_Y => {
+                let index = if matches!(op.mode, AddressingMode::Absolute_X) { cpu.register_x } else { cpu.register_y };
+                let addr = word.wrapping_add(index as u16);
+                asm += &format!(" @ {:04X} = {:02X}", addr, bus.peek(addr));
+            }
+            AddressingMode::Indirect_X => {
+                let pointer = lo.wrapping_add(cpu.register_x);
+                let addr = peek_u16(pointer as u16, true);
+                asm += &format!(" @ {:02X} = {:04X} = {:02X}", pointer, addr, bus.peek(addr));
+            }
+            AddressingMode::Indirect_Y => {
+                let base = peek_u16(lo as u16, true);
+                let addr = base.wrapping_add(cpu.register_y as u16);
+                asm += &format!(" = {:04X} @ {:04X} = {:02X}", base, addr, bus.peek(addr));
+            }
+            // JMP ($xxFF) takes the high byte from the start of the same page
+            AddressingMode::Indirect => asm += &format!(" = {:04X}", peek_u16(word, true)),
+            _ => {}
+        }
+    }
+
+    // the log marks unofficial opcodes with a * in front
+    format!(
+        "{:04X}  {:<9}{}{:<31} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} PPU:{:>3},{:>3} CYC:{}",
+        pc,
+        bytes.join(" "),
+        if op.is_some_and(|op| op.unofficial) { '*' } else { ' ' },
+        asm,
+        cpu.register_a,
+        cpu.register_x,
+        cpu.register_y,
+        cpu.status,
+        cpu.stack_pointer,
+        bus.ppu.scanline(),
+        bus.ppu.dot(),
+        bus.clock().cpu_cycles(),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn cpu_with(program: &[u8]) -> CPU {
+        let mut cpu = CPU::new();
+        cpu.load(program.to_vec());
+        cpu.reset();
+        cpu
+    }
+
+    #[test]
+    fn test_trace_line_format() {
+        let mut cpu = cpu_with(&[0x4c, 0xf5, 0xc5]);
+        cpu.program_counter = 0x8000;
+        cpu.bus.tick(7);
+        assert_eq!(
+            trace(&cpu),
+            "8000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7"
+        );
+    }
+
+    #[test]
+    fn test_symbols() {
+        let cpu = cpu_with(&[0x4c, 0xf5, 0xc5]);
+        let mut symbols = Symbols::new();
+        symbols.insert(0xc5f5, "main_loop");
+        assert!(trace_with_symbols(&cpu, &symbols).starts_with("8000  4C F5 C5  JMP main_loop                   A:00"));
+    }
+
+    #[test]
+    fn test_operand_values() {
+        let mut cpu = cpu_with(&[0xb1, 0x89]);
+        cpu.register_y = 0x10;
+        cpu.bus.mem_write(0x89, 0x00);
+        cpu.bus.mem_write(0x8a, 0x03);
+        cpu.bus.mem_write(0x0310, 0x5a);
+        assert!(trace(&cpu).contains("B1 89     LDA ($89),Y = 0300 @ 0310 = 5A  A:00"), "{}", trace(&cpu));
+
+        let mut cpu = cpu_with(&[0xea]);
+        assert!(trace(&cpu).starts_with("8000  EA        NOP                             A:00"), "{}", trace(&cpu));
+        cpu.bus.mem_write(0x8000, 0x02);
+        assert!(trace(&cpu).contains(".byte $02"));
+
+        let cpu = cpu_with(&[0x04, 0xa9]);
+        assert!(trace(&cpu).starts_with("8000  04 A9    *NOP $A9 = 00                    A:00"), "{}", trace(&cpu));
+    }
+}
@@ -0,0 +1,118 @@
+//! JavaScript bindings for running the emulator in a browser. Build with
+//!
+//! ```text
+//! cargo rustc --release --lib --target wasm32-unknown-unknown --features wasm --crate-type cdylib
+//! wasm-bindgen --target web --out-dir pkg target/wasm32-unknown-unknown/release/nessie.wasm
+//! ```
+//!
+//! and see `examples/web/index.html` for a page that plays a game on a
+//! canvas. Frames come out as a `Uint8Array` of RGBA pixels, ready for an
+//! `ImageData`, and audio as a `Float32Array`.
+
+use crate::cartridge::Cartridge;
+use crate::frame::{HEIGHT, WIDTH};
+use crate::input::mapping::{HostInput, InputProfile};
+use crate::nes::{Nes, ResetKind};
+use std::time::Duration;
+use wasm_bindgen::prelude::*;
+
+/// The console as JavaScript sees it, `new Nes()`.
+#[wasm_bindgen(js_name = Nes)]
+pub struct WebNes {
+    nes: Nes,
+    profile: InputProfile,
+}
+
+#[wasm_bindgen(js_class = Nes)]
+impl WebNes {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> WebNes {
+        WebNes { nes: Nes::new(), profile: InputProfile::default() }
+    }
+
+    /// Plugs in an iNES or NES 2.0 image and powers on.
+    #[wasm_bindgen(js_name = loadRom)]
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<(), String> {
+        self.nes.insert_cartridge(Cartridge::new(rom)?);
+        Ok(())
+    }
+
+    pub fn reset(&mut self, hard: bool) {
+        self.nes.reset(if hard { ResetKind::Hard } else { ResetKind::Soft });
+    }
+
+    #[wasm_bindgen(js_name = runFrame)]
+    pub fn run_frame(&mut self) {
+        self.nes.run_frame();
+    }
+
+    /// Runs the frames due after `ms` milliseconds, as from one
+    /// `requestAnimationFrame` to the next. Returns how many ran.
+    #[wasm_bindgen(js_name = runFor)]
+    pub fn run_for(&mut self, ms: f64) -> usize {
+        self.nes.run_for(Duration::from_secs_f64(ms.max(0.0) / 1000.0))
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn width(&self) -> usize {
+        WIDTH
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn height(&self) -> usize {
+        HEIGHT
+    }
+
+    #[wasm_bindgen(js_name = frameNumber)]
+    pub fn frame_number(&self) -> f64 {
+        self.nes.frame().number() as f64
+    }
+
+    /// The last frame, 4 bytes of RGBA a pixel.
+    pub fn frame(&self) -> Vec<u8> {
+        self.nes.frame().pixels().to_vec()
+    }
+
+    /// A `KeyboardEvent.code` going down or up, through the default key
+    /// bindings. False if nothing is bound to it.
+    pub fn key(&mut self, code: &str, pressed: bool) -> bool {
+        self.profile.handle(&mut self.nes.cpu_mut().bus, &HostInput::Key(code.to_string()), pressed)
+    }
+
+    /// Buttons held on controller 0 to 3, A in bit 0 through Right in bit 7.
+    #[wasm_bindgen(js_name = setButtons)]
+    pub fn set_buttons(&mut self, player: usize, buttons: u8) {
+        if let Some(joypad) = self.nes.joypad_mut(player) {
+            joypad.set_buttons(buttons);
+        }
+    }
+
+    /// Match it to the `AudioContext`'s `sampleRate`.
+    #[wasm_bindgen(js_name = setSampleRate)]
+    pub fn set_sample_rate(&mut self, rate: u32) {
+        self.nes.cpu_mut().bus.apu.set_sample_rate(rate);
+    }
+
+    /// Mono audio made since the last call, from 0.0 to 1.0.
+    pub fn audio(&mut self) -> Vec<f32> {
+        let mut samples = Vec::new();
+        self.nes.audio(&mut samples);
+        samples
+    }
+
+    #[wasm_bindgen(js_name = saveState)]
+    pub fn save_state(&self) -> Vec<u8> {
+        self.nes.save_state()
+    }
+
+    #[wasm_bindgen(js_name = loadState)]
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), String> {
+        self.nes.load_state(state)
+    }
+}
+
+impl Default for WebNes {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,91 @@
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+// WAVE_FORMAT_IEEE_FLOAT: samples are kept exactly as the APU made them
+const FLOAT_FORMAT: u16 = 3;
+const HEADER_LEN: u32 = 44;
+
+/// Writes 32-bit float samples to a .wav file. The sizes in the header are
+/// filled in by `finish`.
+pub struct WavWriter<W: Write + Seek> {
+    out: W,
+    channels: u16,
+    data_len: u32,
+}
+
+impl WavWriter<BufWriter<File>> {
+    pub fn create<P: AsRef<Path>>(path: P, sample_rate: u32, channels: u16) -> Result<Self, String> {
+        let path = path.as_ref();
+        let file = File::create(path).map_err(|err| format!("couldn't create {}: {}", path.display(), err))?;
+        WavWriter::new(BufWriter::new(file), sample_rate, channels)
+    }
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    pub fn new(mut out: W, sample_rate: u32, channels: u16) -> Result<Self, String> {
+        let block_align = channels * 4;
+        let mut header = Vec::with_capacity(HEADER_LEN as usize);
+        header.extend_from_slice(b"RIFF");
+        header.extend_from_slice(&(HEADER_LEN - 8).to_le_bytes());
+        header.extend_from_slice(b"WAVEfmt ");
+        header.extend_from_slice(&16u32.to_le_bytes());
+        header.extend_from_slice(&FLOAT_FORMAT.to_le_bytes());
+        header.extend_from_slice(&channels.to_le_bytes());
+        header.extend_from_slice(&sample_rate.to_le_bytes());
+        header.extend_from_slice(&(sample_rate * block_align as u32).to_le_bytes());
+        header.extend_from_slice(&block_align.to_le_bytes());
+        header.extend_from_slice(&32u16.to_le_bytes());
+        header.extend_from_slice(b"data");
+        header.extend_from_slice(&0u32.to_le_bytes());
+        out.write_all(&header).map_err(write_error)?;
+        Ok(WavWriter { out, channels, data_len: 0 })
+    }
+
+    pub fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    /// Appends samples, interleaved if there's more than one channel.
+    pub fn write(&mut self, samples: &[f32]) -> Result<(), String> {
+        let bytes: Vec<u8> = samples.iter().flat_map(|sample| sample.to_le_bytes()).collect();
+        self.out.write_all(&bytes).map_err(write_error)?;
+        self.data_len = self.data_len.saturating_add(bytes.len() as u32);
+        Ok(())
+    }
+
+    /// Fills in the header and hands back the writer.
+    pub fn finish(mut self) -> Result<W, String> {
+        self.out.seek(SeekFrom::Start(4)).map_err(write_error)?;
+        self.out.write_all(&(HEADER_LEN - 8 + self.data_len).to_le_bytes()).map_err(write_error)?;
+        self.out.seek(SeekFrom::Start(HEADER_LEN as u64 - 4)).map_err(write_error)?;
+        self.out.write_all(&self.data_len.to_le_bytes()).map_err(write_error)?;
+        self.out.seek(SeekFrom::End(0)).map_err(write_error)?;
+        self.out.flush().map_err(write_error)?;
+        Ok(self.out)
+    }
+}
+
+fn write_error(err: std::io::Error) -> String {
+    format!("couldn't write WAV: {}", err)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_header_and_data() {
+        let mut wav = WavWriter::new(Cursor::new(Vec::new()), 44100, 1).unwrap();
+        wav.write(&[0.5, 0.25]).unwrap();
+        let data = wav.finish().unwrap().into_inner();
+        assert_eq!(data.len(), 44 + 8);
+        assert_eq!(&data[0..4], b"RIFF");
+        assert_eq!(u32::from_le_bytes(data[4..8].try_into().unwrap()), 36 + 8);
+        assert_eq!(u16::from_le_bytes(data[20..22].try_into().unwrap()), FLOAT_FORMAT);
+        assert_eq!(u32::from_le_bytes(data[24..28].try_into().unwrap()), 44100);
+        assert_eq!(u32::from_le_bytes(data[40..44].try_into().unwrap()), 8);
+        assert_eq!(f32::from_le_bytes(data[44..48].try_into().unwrap()), 0.5);
+    }
+}